@@ -0,0 +1,86 @@
+//! `autogguf audit`: reconciles the quants asked for with what's on disk and what's published,
+//! for catching up on long publishing efforts where runs were interrupted or files replaced.
+
+use crate::{plan::human_bytes, verify, QuantLevel};
+use std::{fmt::Display, path::PathBuf};
+
+/// Where one quant stands, here and on the Hub.
+#[derive(Debug, Clone)]
+pub struct QuantAudit {
+    pub level: QuantLevel,
+    /// Total size of the local quant across its shards, if it's here.
+    pub local_bytes: Option<u64>,
+    /// Total size of the published quant across its shards, if it's on the Hub.
+    pub remote_bytes: Option<u64>,
+    /// What needs fixing, if anything.
+    pub problem: Option<String>,
+}
+
+impl QuantAudit {
+    pub fn passed(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+impl Display for QuantAudit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = self.level.to_string().to_uppercase();
+        if let Some(problem) = &self.problem {
+            return write!(f, "❌ {label}: {problem}");
+        }
+        match (self.local_bytes, self.remote_bytes) {
+            (Some(bytes), Some(_)) => {
+                write!(f, "✅ {label}: {} here and on the Hub", human_bytes(bytes))
+            }
+            (None, Some(bytes)) => write!(
+                f,
+                "✅ {label}: {} on the Hub, not kept here",
+                human_bytes(bytes)
+            ),
+            _ => write!(f, "✅ {label}"),
+        }
+    }
+}
+
+/// Audit the quant for `level` from its local `files`, all its shards if it was split, and the
+/// size of what's published for it, if anything is.
+pub(crate) async fn audit(
+    level: QuantLevel,
+    files: &[PathBuf],
+    remote_bytes: Option<u64>,
+) -> QuantAudit {
+    let mut local_bytes = None;
+    let mut problem = None;
+    if !files.is_empty() {
+        let mut bytes = 0;
+        for file in files {
+            bytes += tokio::fs::metadata(file)
+                .await
+                .map(|m| m.len())
+                .unwrap_or_default();
+        }
+        local_bytes = Some(bytes);
+        if let Err(e) = verify::tensor_count(files).await {
+            problem = Some(format!("corrupted here: {e}"));
+        }
+    }
+    problem = problem.or(match (local_bytes, remote_bytes) {
+        (None, None) => Some("missing here and on the Hub".to_string()),
+        (Some(bytes), None) => Some(format!(
+            "{} here, but not on the Hub yet",
+            human_bytes(bytes)
+        )),
+        (Some(local), Some(remote)) if local != remote => Some(format!(
+            "{} ({local} bytes) here, but {} ({remote} bytes) on the Hub",
+            human_bytes(local),
+            human_bytes(remote)
+        )),
+        _ => None,
+    });
+    QuantAudit {
+        level,
+        local_bytes,
+        remote_bytes,
+        problem,
+    }
+}
@@ -0,0 +1,783 @@
+//! The README.md model card uploaded alongside the GGUFs.
+
+use crate::{
+    checksums::{Checksum, CHECKSUMS_FILE},
+    convert::{Shard, CALIBRATION_URL},
+    embedding::Pooling,
+    eval::EvalResults,
+    gguf,
+    hub::{ModelInfo, UploadFile},
+    ipfs::Pinned,
+    llama::{self, LlamaLock},
+    lora::LoraAdapter,
+    memory::{self, KvCache},
+    mmproj, naming,
+    plan::human_bytes,
+    sign::Signing,
+    torrent::{Torrent, MAGNETS_FILE},
+    Precision, QuantLevel,
+};
+use clap::ValueEnum;
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+pub(crate) const MODEL_CARD_FILE: &str = "README.md";
+
+/// Where the importance matrix used for the i-quants came from.
+#[derive(Debug, Clone)]
+pub(crate) enum ImatrixSource {
+    /// Generated by `llama-imatrix` over `chunks` chunks of these calibration datasets, or the
+    /// default if `sources` is empty. With `per_dataset`, one imatrix per dataset, combined.
+    Generated {
+        sources: Vec<String>,
+        chunks: u32,
+        per_dataset: bool,
+    },
+    /// Fetched from this HuggingFace repo.
+    Downloaded(String),
+    /// Passed in with `--imatrix`, combined if there's more than one.
+    Provided(Vec<PathBuf>),
+}
+
+/// What the model card says beyond the files themselves, known before any stage runs but for
+/// the torrents and IPFS CIDs.
+#[derive(Debug, Clone)]
+pub(crate) struct CardInfo {
+    /// The source model on the Hub, or empty for a local model.
+    pub model_id: String,
+    /// The branch, tag or commit of `model_id` converted, if not `main`.
+    pub revision: Option<String>,
+    /// The HuggingFace user credited in `quantized_by`.
+    pub quantized_by: String,
+    pub llama_path: PathBuf,
+    /// Set when any requested quant needs an imatrix.
+    pub imatrix: Option<ImatrixSource>,
+    /// Quant levels made with `--output-tensor-type` or `--token-embedding-type` overrides.
+    pub tensor_types: Vec<TensorTypes>,
+    /// LoRA adapters converted alongside the model, with their GGUFs' names.
+    pub loras: Vec<(LoraAdapter, String)>,
+    /// The LoRA adapter merged into the model before quantizing.
+    pub merged_lora: Option<LoraAdapter>,
+    /// An embedding or reranker model's pooling.
+    pub pooling: Option<Pooling>,
+    /// Each quant's torrent, filled in at upload once they're hashed.
+    pub torrents: Vec<Torrent>,
+    /// The GGUFs pinned to IPFS, filled in at upload once they're added.
+    pub pins: Vec<Pinned>,
+}
+
+/// The tensor type overrides one quant level was made with.
+#[derive(Debug, Clone)]
+pub(crate) struct TensorTypes {
+    pub level: QuantLevel,
+    pub output: Option<String>,
+    pub token_embedding: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct CardFile {
+    name: String,
+    /// Where it is in the repo: the file, or for a split quant, its folder.
+    link: String,
+    /// Total size, across all shards.
+    bytes: u64,
+    bits_per_weight: Option<f64>,
+    /// Path in the repo and size of each shard, for a split quant.
+    shards: Vec<(String, u64)>,
+}
+
+#[derive(Debug, Clone)]
+struct ModelCard<'a> {
+    info: &'a CardInfo,
+    repo_id: &'a str,
+    source: &'a ModelInfo,
+    /// The llama.cpp the GGUFs were made with: as locked in the output dir, or else whatever's
+    /// checked out now.
+    llama: Option<LlamaLock>,
+    files: Vec<CardFile>,
+    /// The llamafile's path in the repo, if a quant was packaged as one.
+    llamafile: Option<String>,
+    /// A vision-language model's projectors' paths in the repo.
+    mmproj: Vec<String>,
+    checksums: &'a [Checksum],
+    signing: Option<&'a Signing>,
+    eval: EvalResults,
+    /// For estimating memory requirements, if the GGUFs' hyperparameters could be read.
+    kv_cache: Option<KvCache>,
+}
+
+impl CardInfo {
+    /// Write `README.md` for `repo_id` into `dir`, describing the GGUFs among `uploads`, with
+    /// `source`'s own card below. The frontmatter carries `source`'s license, languages, tags and
+    /// pipeline tag over, so the repo is discoverable on the Hub as a quantization of it.
+    pub async fn write(
+        &self,
+        dir: &Path,
+        uploads: &[UploadFile],
+        repo_id: &str,
+        source: &ModelInfo,
+        checksums: &[Checksum],
+        signing: Option<&Signing>,
+    ) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+        let mut files: Vec<CardFile> = vec![];
+        let mut kv_cache = None;
+        let llamafile = uploads
+            .iter()
+            .find(|file| file.path_in_repo.ends_with(".llamafile"))
+            .map(|file| file.path_in_repo.clone());
+        let mut mmproj: Vec<String> = uploads
+            .iter()
+            .map(|file| file.path_in_repo.clone())
+            .filter(|path| mmproj::is_mmproj(path))
+            .collect();
+        mmproj.sort();
+        for file in uploads {
+            let name = file
+                .local_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            // NOTE: adapters and projectors get their own sections, not rows alongside the model's
+            // files
+            if !name.ends_with(".gguf")
+                || self.loras.iter().any(|(_, file)| *file == name)
+                || mmproj::is_mmproj(&name)
+            {
+                continue;
+            }
+            let bytes = tokio::fs::metadata(&file.local_path).await?.len();
+            let path_in_repo = file.path_in_repo.clone();
+            // NOTE: every GGUF has the same hyperparameters, and a split quant's are in its
+            // first shard
+            if kv_cache.is_none() && Shard::parse(&name).is_none_or(|shard| shard.n == 1) {
+                kv_cache = gguf::validate(&file.local_path)
+                    .await
+                    .ok()
+                    .and_then(|header| KvCache::from_header(&header));
+            }
+            let Some(shard) = Shard::parse(&name) else {
+                files.push(CardFile {
+                    bits_per_weight: bits_per_weight(name.trim_end_matches(".gguf")),
+                    name,
+                    link: path_in_repo,
+                    bytes,
+                    shards: vec![],
+                });
+                continue;
+            };
+            // NOTE: a split quant is one row, however many shards it has
+            match files.iter_mut().find(|f| f.name == shard.stem) {
+                Some(file) => {
+                    file.bytes += bytes;
+                    file.shards.push((path_in_repo, bytes));
+                }
+                None => files.push(CardFile {
+                    name: shard.stem.to_string(),
+                    link: format!("{}/", shard.label()),
+                    bytes,
+                    bits_per_weight: bits_per_weight(shard.stem),
+                    shards: vec![(path_in_repo, bytes)],
+                }),
+            }
+        }
+        for file in &mut files {
+            file.shards.sort();
+        }
+        files.sort_by(|a, b| a.bytes.cmp(&b.bytes).then_with(|| a.name.cmp(&b.name)));
+
+        let card = ModelCard {
+            info: self,
+            repo_id,
+            source,
+            llama: match LlamaLock::load(dir).await {
+                Some(lock) => Some(lock),
+                None => llama::head_commit(&self.llama_path)
+                    .await
+                    .map(|commit| LlamaLock {
+                        git_ref: None,
+                        commit,
+                    }),
+            },
+            files,
+            llamafile,
+            mmproj,
+            checksums,
+            signing,
+            eval: EvalResults::load(dir).await,
+            kv_cache,
+        };
+        let path = dir.join(MODEL_CARD_FILE);
+        tokio::fs::write(&path, card.to_string()).await?;
+        Ok(path)
+    }
+}
+
+impl ModelCard<'_> {
+    /// The quant the usage example fetches: Q4_K_M, the usual default, if it's here, or else the
+    /// middling one by size.
+    fn usage_example(&self) -> Option<&CardFile> {
+        self.files
+            .iter()
+            .find(|file| {
+                naming::label(file.name.trim_end_matches(".gguf")).eq_ignore_ascii_case("q4_k_m")
+            })
+            .or_else(|| self.files.get(self.files.len() / 2))
+    }
+
+    /// Each file's weights plus its KV cache at a few context lengths, then the biggest file
+    /// that fits each common GPU size.
+    fn write_memory(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        kv_cache: &KvCache,
+    ) -> std::fmt::Result {
+        let contexts = kv_cache.contexts();
+        let header = |first: &str| {
+            let mut header = format!("| {first} |");
+            let mut rule = format!("| {} |", "-".repeat(first.len()));
+            for context in &contexts {
+                let column = format!(" {} context |", memory::context_label(*context));
+                rule.push_str(&format!(" {}: |", "-".repeat(column.len() - 4)));
+                header.push_str(&column);
+            }
+            format!("{header}\n{rule}")
+        };
+        writeln!(
+            f,
+            "Roughly how much memory each file needs to run fully offloaded to a GPU: its \
+             weights plus an f16 KV cache for the context. llama.cpp's compute buffers need a \
+             little more on top."
+        )?;
+        writeln!(f)?;
+        writeln!(f, "{}", header("File"))?;
+        for file in &self.files {
+            write!(f, "| {} |", file.name)?;
+            for context in &contexts {
+                let bytes = file.bytes + kv_cache.bytes(*context);
+                write!(f, " {} |", human_bytes(bytes))?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "The biggest file that fits each GPU, leaving {:.0}% of its memory free:",
+            memory::HEADROOM * 100.0
+        )?;
+        writeln!(f)?;
+        writeln!(f, "{}", header("GPU memory"))?;
+        for gib in memory::GPU_MEMORY_GIB {
+            write!(f, "| {gib} GB |")?;
+            for context in &contexts {
+                let usable = memory::usable_bytes(gib);
+                // NOTE: files are sorted by size, smallest first
+                let fits = self
+                    .files
+                    .iter()
+                    .rev()
+                    .find(|file| file.bytes + kv_cache.bytes(*context) <= usable);
+                match fits {
+                    Some(file) => {
+                        let label = naming::label(file.name.trim_end_matches(".gguf"));
+                        write!(f, " {} |", label.to_uppercase())?
+                    }
+                    None => write!(f, " - |")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// A model card without its YAML frontmatter, which belongs to its own repo.
+fn without_frontmatter(readme: &str) -> &str {
+    let body = readme
+        .strip_prefix("---\n")
+        .or_else(|| readme.strip_prefix("---\r\n"))
+        .and_then(|rest| rest.find("\n---").map(|end| &rest[end + 4..]))
+        .unwrap_or(readme);
+    body.trim()
+}
+
+/// Bits per weight for a GGUF named for its quant or precision.
+fn bits_per_weight(stem: &str) -> Option<f64> {
+    let level = naming::label(stem);
+    match level.parse::<QuantLevel>() {
+        Ok(q) => Some(q.bits_per_weight()),
+        Err(_) => Precision::from_str(level, true)
+            .ok()
+            .map(|p| p.bits_per_weight()),
+    }
+}
+
+impl Display for ModelCard<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let model_id = &self.info.model_id;
+        writeln!(f, "---")?;
+        if !model_id.is_empty() {
+            writeln!(f, "base_model: {model_id}")?;
+            writeln!(f, "base_model_relation: quantized")?;
+        }
+        if !self.info.quantized_by.is_empty() {
+            writeln!(f, "quantized_by: {}", self.info.quantized_by)?;
+        }
+        if let Some(license) = self.source.license() {
+            writeln!(f, "license: {license}")?;
+            let card_data = self.source.card_data.as_ref();
+            if let Some(name) = card_data.and_then(|c| c.license_name.as_ref()) {
+                writeln!(f, "license_name: {name}")?;
+            }
+            if let Some(link) = card_data.and_then(|c| c.license_link.as_ref()) {
+                writeln!(f, "license_link: {link}")?;
+            }
+        }
+        let languages = self.source.languages();
+        if !languages.is_empty() {
+            writeln!(f, "language:")?;
+            for language in languages {
+                writeln!(f, "- {language}")?;
+            }
+        }
+        if let Some(pipeline_tag) = &self.source.pipeline_tag {
+            writeln!(f, "pipeline_tag: {pipeline_tag}")?;
+        }
+        writeln!(f, "library_name: gguf")?;
+        writeln!(f, "tags:")?;
+        writeln!(f, "- gguf")?;
+        if self.info.imatrix.is_some() {
+            writeln!(f, "- imatrix")?;
+        }
+        for tag in self.source.card_tags() {
+            writeln!(f, "- {tag}")?;
+        }
+        writeln!(f, "---")?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "# {}",
+            self.repo_id.rsplit('/').next().unwrap_or(self.repo_id)
+        )?;
+        writeln!(f)?;
+        if model_id.is_empty() {
+            write!(f, "GGUF conversions")?;
+        } else {
+            write!(
+                f,
+                "GGUF conversions of [{model_id}](https://huggingface.co/{model_id})"
+            )?;
+            if let Some(revision) = &self.info.revision {
+                write!(
+                    f,
+                    " at revision [`{revision}`](https://huggingface.co/{model_id}/tree/{revision})"
+                )?;
+            }
+        }
+        match &self.info.merged_lora {
+            Some(LoraAdapter::Hub(repo_id)) => write!(
+                f,
+                " with the LoRA adapter [{repo_id}](https://huggingface.co/{repo_id}) merged in"
+            )?,
+            Some(adapter @ LoraAdapter::Local(_)) => {
+                write!(f, " with the LoRA adapter `{}` merged in", adapter.name())?
+            }
+            None => {}
+        }
+        write!(
+            f,
+            " for use with [llama.cpp](https://github.com/ggerganov/llama.cpp)"
+        )?;
+        match &self.llama {
+            Some(LlamaLock { git_ref, commit }) => {
+                let short = &commit[..commit.len().min(7)];
+                let link =
+                    format!("[`{short}`](https://github.com/ggerganov/llama.cpp/commit/{commit})");
+                match git_ref {
+                    Some(git_ref) => {
+                        writeln!(f, ", made with llama.cpp `{git_ref}` (commit {link}).")?
+                    }
+                    None => writeln!(f, ", made with llama.cpp commit {link}.")?,
+                }
+            }
+            None => writeln!(f, ".")?,
+        }
+        writeln!(f)?;
+
+        writeln!(f, "| File | Size | Bits per weight |")?;
+        writeln!(f, "| ---- | ---: | --------------: |")?;
+        for file in &self.files {
+            let bpw = match file.bits_per_weight {
+                Some(bpw) => format!("{bpw:.2}"),
+                None => "?".to_string(),
+            };
+            let shards = match file.shards.len() {
+                0 => String::new(),
+                n => format!(" ({n} shards)"),
+            };
+            writeln!(
+                f,
+                "| [{}](./{}){shards} | {} | {bpw} |",
+                file.name,
+                file.link,
+                human_bytes(file.bytes)
+            )?;
+        }
+
+        if let Some(file) = self.usage_example() {
+            let label = naming::label(file.name.trim_end_matches(".gguf")).to_uppercase();
+            writeln!(f)?;
+            writeln!(f, "## Usage")?;
+            writeln!(f)?;
+            writeln!(
+                f,
+                "llama.cpp can fetch a quant straight from this repo by its label, e.g. {label}:"
+            )?;
+            writeln!(f)?;
+            writeln!(f, "```sh")?;
+            match self.info.pooling {
+                None => {
+                    writeln!(f, "llama-cli -hf {}:{label}", self.repo_id)?;
+                    writeln!(f, "llama-server -hf {}:{label}", self.repo_id)?;
+                }
+                Some(Pooling::Rank) => {
+                    writeln!(f, "llama-server -hf {}:{label} --reranking", self.repo_id)?;
+                }
+                Some(_) => {
+                    writeln!(
+                        f,
+                        "llama-embedding -hf {}:{label} -p \"Hello, world!\"",
+                        self.repo_id
+                    )?;
+                    writeln!(f, "llama-server -hf {}:{label} --embedding", self.repo_id)?;
+                }
+            }
+            writeln!(f, "```")?;
+        }
+        if !self.mmproj.is_empty() {
+            let links: Vec<_> = self
+                .mmproj
+                .iter()
+                .map(|path| format!("[{path}](./{path})"))
+                .collect();
+            writeln!(f)?;
+            writeln!(f, "## Vision")?;
+            writeln!(f)?;
+            writeln!(
+                f,
+                "The model takes images too, through its vision projector, here as {}. llama.cpp \
+                 fetches it along with a quant by itself with `-hf`; otherwise, download one and \
+                 pass it with `--mmproj`.",
+                links.join(" and ")
+            )?;
+            if let Some(file) = self.usage_example() {
+                let label = naming::label(file.name.trim_end_matches(".gguf")).to_uppercase();
+                writeln!(f)?;
+                writeln!(f, "```sh")?;
+                writeln!(
+                    f,
+                    "llama-mtmd-cli -hf {}:{label} --image photo.jpg -p \"Describe this image.\"",
+                    self.repo_id
+                )?;
+                writeln!(f, "```")?;
+            }
+        }
+        let loras: Vec<_> = self
+            .info
+            .loras
+            .iter()
+            .filter(|(_, file)| self.checksums.iter().any(|c| c.path == *file))
+            .collect();
+        if !loras.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "## LoRA adapters")?;
+            writeln!(f)?;
+            writeln!(
+                f,
+                "These LoRA adapters of the model are here as GGUFs too, to load on top of any \
+                 quant:"
+            )?;
+            writeln!(f)?;
+            for (adapter, file) in &loras {
+                match adapter {
+                    LoraAdapter::Hub(repo_id) => writeln!(
+                        f,
+                        "- [{file}](./{file}), from [{repo_id}](https://huggingface.co/{repo_id})"
+                    )?,
+                    LoraAdapter::Local(_) => writeln!(f, "- [{file}](./{file})")?,
+                }
+            }
+            if let Some(file) = self.usage_example() {
+                let label = naming::label(file.name.trim_end_matches(".gguf")).to_uppercase();
+                writeln!(f)?;
+                writeln!(f, "Download one, then pass it with `--lora`:")?;
+                writeln!(f)?;
+                writeln!(f, "```sh")?;
+                writeln!(
+                    f,
+                    "llama-cli -hf {}:{label} --lora {}",
+                    self.repo_id, loras[0].1
+                )?;
+                writeln!(f, "```")?;
+            }
+        }
+
+        if let Some(llamafile) = &self.llamafile {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "[{llamafile}](./{llamafile}) runs the model with nothing to install: it's a \
+                 [llamafile](https://github.com/Mozilla-Ocho/llamafile), the quant bundled with \
+                 llama.cpp in a single executable for Linux, macOS and Windows. Download it, \
+                 then:"
+            )?;
+            writeln!(f)?;
+            writeln!(f, "```sh")?;
+            writeln!(f, "chmod +x {llamafile}")?;
+            writeln!(f, "./{llamafile}")?;
+            writeln!(f, "```")?;
+            writeln!(f)?;
+            writeln!(
+                f,
+                "On Windows, rename it to end in `.exe` first. Windows won't run executables over \
+                 4 GB, though."
+            )?;
+        }
+
+        let split: Vec<_> = self.files.iter().filter(|f| !f.shards.is_empty()).collect();
+        if !split.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "## Split files")?;
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Quants too big for a single file are split with `llama-gguf-split`. Download \
+                 every shard into the same directory and point llama.cpp at the first; it loads \
+                 the rest automatically."
+            )?;
+            for file in split {
+                writeln!(f)?;
+                writeln!(f, "**{}** ({} total)", file.name, human_bytes(file.bytes))?;
+                writeln!(f)?;
+                for (path, bytes) in &file.shards {
+                    writeln!(f, "- [{path}](./{path}) ({})", human_bytes(*bytes))?;
+                }
+            }
+        }
+
+        if !self.info.torrents.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "## Torrents")?;
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Each quant is also a torrent, web-seeded from this repo so it downloads even \
+                 with no other peers. Open its magnet link or `.torrent` in any BitTorrent \
+                 client; every magnet link is also in [{MAGNETS_FILE}](./{MAGNETS_FILE})."
+            )?;
+            writeln!(f)?;
+            writeln!(f, "| Quant | Torrent | Magnet link |")?;
+            writeln!(f, "| ----- | ------- | ----------- |")?;
+            for torrent in &self.info.torrents {
+                writeln!(
+                    f,
+                    "| {} | [{}](./{}) | [magnet]({}) |",
+                    torrent.label, torrent.path, torrent.path, torrent.magnet
+                )?;
+            }
+        }
+
+        if !self.info.pins.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "## IPFS")?;
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Every GGUF is also pinned to IPFS, so it can be fetched by its CID from any IPFS \
+                 node or gateway, e.g. `ipfs get <CID> -o <file>`."
+            )?;
+            writeln!(f)?;
+            writeln!(f, "| File | CID |")?;
+            writeln!(f, "| ---- | --- |")?;
+            for pinned in &self.info.pins {
+                let name = pinned.path.rsplit('/').next().unwrap_or(&pinned.path);
+                writeln!(
+                    f,
+                    "| {} | [`{}`](https://ipfs.io/ipfs/{}?filename={name}) |",
+                    pinned.path, pinned.cid, pinned.cid
+                )?;
+            }
+        }
+
+        if let Some(kv_cache) = &self.kv_cache {
+            writeln!(f)?;
+            writeln!(f, "## Memory requirements")?;
+            writeln!(f)?;
+            self.write_memory(f, kv_cache)?;
+        }
+
+        if !self.info.tensor_types.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "## Tensor type overrides")?;
+            writeln!(f)?;
+            writeln!(
+                f,
+                "These quants use different types for some tensors than `llama-quantize` picks \
+                 by default:"
+            )?;
+            writeln!(f)?;
+            writeln!(f, "| Quant | Output tensor | Token embeddings |")?;
+            writeln!(f, "| ----- | ------------- | ---------------- |")?;
+            for t in &self.info.tensor_types {
+                let label = |ggml_type: &Option<String>| match ggml_type {
+                    Some(ggml_type) => ggml_type.to_uppercase(),
+                    None => "default".to_string(),
+                };
+                writeln!(
+                    f,
+                    "| {} | {} | {} |",
+                    t.level.to_string().to_uppercase(),
+                    label(&t.output),
+                    label(&t.token_embedding)
+                )?;
+            }
+        }
+
+        if !self.eval.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "## Evaluation")?;
+            writeln!(f)?;
+            write!(f, "{}", self.eval)?;
+        }
+
+        if let Some(imatrix) = &self.info.imatrix {
+            writeln!(f)?;
+            writeln!(f, "## Importance matrix")?;
+            writeln!(f)?;
+            match imatrix {
+                ImatrixSource::Generated {
+                    sources, chunks, ..
+                } if sources.is_empty() => writeln!(
+                    f,
+                    "The IQ quants use an importance matrix generated with `llama-imatrix` over \
+                     {chunks} chunks of [this calibration dataset]({CALIBRATION_URL})."
+                )?,
+                ImatrixSource::Generated {
+                    sources,
+                    chunks,
+                    per_dataset,
+                } => {
+                    if *per_dataset {
+                        writeln!(
+                            f,
+                            "The IQ quants use an importance matrix combined from ones generated \
+                             with `llama-imatrix` over {chunks} chunks of each of these \
+                             calibration datasets:"
+                        )?;
+                    } else {
+                        writeln!(
+                            f,
+                            "The IQ quants use an importance matrix generated with `llama-imatrix` \
+                             over {chunks} chunks of these calibration datasets:"
+                        )?;
+                    }
+                    writeln!(f)?;
+                    write_sources(f, sources)?;
+                }
+                ImatrixSource::Downloaded(repo_id) => writeln!(
+                    f,
+                    "The IQ quants use the importance matrix published in \
+                     [{repo_id}](https://huggingface.co/{repo_id})."
+                )?,
+                ImatrixSource::Provided(paths) if paths.len() == 1 => writeln!(
+                    f,
+                    "The IQ quants use a provided importance matrix, `{}`.",
+                    paths[0].file_name().unwrap_or_default().to_string_lossy()
+                )?,
+                ImatrixSource::Provided(paths) => {
+                    writeln!(
+                        f,
+                        "The IQ quants use an importance matrix combined from these provided ones:"
+                    )?;
+                    writeln!(f)?;
+                    for path in paths {
+                        let name = path.file_name().unwrap_or_default();
+                        writeln!(f, "- `{}`", name.to_string_lossy())?;
+                    }
+                }
+            }
+        }
+
+        if !self.checksums.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "## Checksums")?;
+            writeln!(f)?;
+            writeln!(
+                f,
+                "SHA256 hashes of every file, also in [{CHECKSUMS_FILE}](./{CHECKSUMS_FILE}). To \
+                 check your downloads, run `sha256sum -c {CHECKSUMS_FILE} --ignore-missing` \
+                 where you downloaded them."
+            )?;
+            writeln!(f)?;
+            writeln!(f, "```")?;
+            for checksum in self.checksums {
+                writeln!(f, "{}  {}", checksum.sha256, checksum.path)?;
+            }
+            writeln!(f, "```")?;
+            if let Some(Signing { key, all_files }) = self.signing {
+                writeln!(f)?;
+                writeln!(
+                    f,
+                    "{CHECKSUMS_FILE} is signed by the uploader, so you can check who made these \
+                     files too:"
+                )?;
+                writeln!(f)?;
+                writeln!(f, "```")?;
+                writeln!(f, "{}", key.verify_command(CHECKSUMS_FILE))?;
+                writeln!(f, "```")?;
+                if *all_files {
+                    writeln!(f)?;
+                    writeln!(
+                        f,
+                        "Every GGUF and imatrix also has its own `.{}` signature next to it, \
+                         checked the same way.",
+                        key.extension()
+                    )?;
+                }
+            }
+        }
+
+        if let Some(readme) = self.source.readme.as_deref().map(without_frontmatter) {
+            if !readme.is_empty() {
+                writeln!(f)?;
+                writeln!(f, "## Original model card")?;
+                writeln!(f)?;
+                writeln!(
+                    f,
+                    "The model card of [{model_id}](https://huggingface.co/{model_id}), as \
+                     published:"
+                )?;
+                writeln!(f)?;
+                writeln!(f, "---")?;
+                writeln!(f)?;
+                writeln!(f, "{readme}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A bulleted list of calibration datasets: links for URLs, file names for local files.
+fn write_sources(f: &mut std::fmt::Formatter<'_>, sources: &[String]) -> std::fmt::Result {
+    for source in sources {
+        if source.starts_with("https://") || source.starts_with("http://") {
+            writeln!(f, "- <{source}>")?;
+        } else {
+            let name = Path::new(source).file_name().unwrap_or_default();
+            writeln!(f, "- `{}` (local)", name.to_string_lossy())?;
+        }
+    }
+    Ok(())
+}
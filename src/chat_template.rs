@@ -0,0 +1,130 @@
+//! The Jinja chat template llama.cpp formats conversations with, kept in a GGUF's
+//! `tokenizer.chat_template`. Every quant inherits the full-precision GGUF's, so a missing or
+//! wrong one there breaks chat with all of them.
+
+use crate::gguf::Header;
+use serde::{Deserialize, Serialize};
+use shellexpand::tilde;
+use std::{fmt::Display, str::FromStr};
+
+pub(crate) const CHAT_TEMPLATE_KEY: &str = "tokenizer.chat_template";
+
+/// Templates for common chat formats, by name.
+const PRESETS: &[(&str, &str)] = &[
+    (
+        "chatml",
+        "{% for message in messages %}{{ '<|im_start|>' + message['role'] + '\\n' + \
+         message['content'] + '<|im_end|>' + '\\n' }}{% endfor %}{% if add_generation_prompt %}\
+         {{ '<|im_start|>assistant\\n' }}{% endif %}",
+    ),
+    (
+        "llama3",
+        "{{ bos_token }}{% for message in messages %}{{ '<|start_header_id|>' + message['role'] + \
+         '<|end_header_id|>\\n\\n' + message['content'] | trim + '<|eot_id|>' }}{% endfor %}\
+         {% if add_generation_prompt %}{{ '<|start_header_id|>assistant<|end_header_id|>\\n\\n' }}\
+         {% endif %}",
+    ),
+    (
+        "gemma",
+        "{{ bos_token }}{% for message in messages %}{% set role = 'model' if message['role'] == \
+         'assistant' else message['role'] %}{{ '<start_of_turn>' + role + '\\n' + \
+         message['content'] | trim + '<end_of_turn>\\n' }}{% endfor %}\
+         {% if add_generation_prompt %}{{ '<start_of_turn>model\\n' }}{% endif %}",
+    ),
+    (
+        "mistral",
+        "{{ bos_token }}{% for message in messages %}{% if message['role'] == 'user' %}\
+         {{ '[INST] ' + message['content'] + ' [/INST]' }}{% elif message['role'] == 'assistant' %}\
+         {{ message['content'] + eos_token }}{% endif %}{% endfor %}",
+    ),
+    (
+        "phi3",
+        "{% for message in messages %}{{ '<|' + message['role'] + '|>\\n' + message['content'] + \
+         '<|end|>\\n' }}{% endfor %}{% if add_generation_prompt %}{{ '<|assistant|>\\n' }}\
+         {% else %}{{ eos_token }}{% endif %}",
+    ),
+];
+
+/// A chat template to write into the full-precision GGUF: one of the presets by name, or read
+/// from a Jinja file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ChatTemplate {
+    /// The preset's name or the file's path, as given.
+    source: String,
+    pub template: String,
+}
+
+impl FromStr for ChatTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, template)) = PRESETS.iter().find(|(name, _)| *name == s) {
+            return Ok(Self {
+                source: s.to_string(),
+                template: template.to_string(),
+            });
+        }
+        let template = std::fs::read_to_string(tilde(s).as_ref()).map_err(|e| {
+            let presets: Vec<_> = PRESETS.iter().map(|(name, _)| *name).collect();
+            format!(
+                "'{s}' isn't a preset ({}) or a readable file: {e}",
+                presets.join(", ")
+            )
+        })?;
+        if let Some(problem) = problem(&template) {
+            return Err(format!("the chat template in {s} {problem}"));
+        }
+        Ok(Self {
+            source: s.to_string(),
+            template,
+        })
+    }
+}
+
+impl TryFrom<String> for ChatTemplate {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ChatTemplate> for String {
+    fn from(template: ChatTemplate) -> Self {
+        template.source
+    }
+}
+
+impl Display for ChatTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// What's wrong with the chat template in `header`, if anything.
+pub(crate) fn check(header: &Header) -> Option<String> {
+    match header.get(CHAT_TEMPLATE_KEY).map(|value| value.as_str()) {
+        None => Some(format!("has no {CHAT_TEMPLATE_KEY}")),
+        Some(None) => Some(format!("has a {CHAT_TEMPLATE_KEY} that isn't a string")),
+        Some(Some(template)) => {
+            problem(template).map(|problem| format!("has a {CHAT_TEMPLATE_KEY} that {problem}"))
+        }
+    }
+}
+
+/// Why `template` can't be a working chat template, short of rendering it: it has to read the
+/// messages, and its Jinja statements have to be closed.
+fn problem(template: &str) -> Option<String> {
+    if template.trim().is_empty() {
+        return Some("is empty".to_string());
+    }
+    if !template.contains("messages") {
+        return Some("never reads the messages".to_string());
+    }
+    // NOTE: only statement tags, since a `}}` can turn up in a string literal of JSON
+    if template.matches("{%").count() != template.matches("%}").count() {
+        return Some("has unbalanced {% %} tags".to_string());
+    }
+    None
+}
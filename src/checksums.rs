@@ -0,0 +1,63 @@
+//! `SHA256SUMS`: the sha256 of every GGUF and imatrix uploaded, so downloads can be checked with
+//! `sha256sum -c`.
+
+use crate::hub::{hash_file, UploadFile};
+use sha2::Sha256;
+use std::{collections::HashMap, path::Path};
+
+pub(crate) const CHECKSUMS_FILE: &str = "SHA256SUMS";
+
+#[derive(Debug, Clone)]
+pub(crate) struct Checksum {
+    /// Where the file is in the repo, which is what `sha256sum -c` looks for.
+    pub path: String,
+    pub sha256: String,
+}
+
+/// Hash `files`, the GGUFs and imatrix to upload, and write them to `SHA256SUMS` in `dir`, in
+/// `sha256sum`'s format. Hashes already in `SHA256SUMS` are reused for files that haven't
+/// changed since it was written, since hashing a big model's quants takes minutes.
+pub(crate) async fn write(
+    dir: &Path,
+    files: &[UploadFile],
+) -> Result<Vec<Checksum>, Box<dyn std::error::Error + Send + Sync>> {
+    let path = dir.join(CHECKSUMS_FILE);
+    let written = tokio::fs::metadata(&path)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    let previous = match written {
+        Some(_) => read(&path).await?,
+        None => HashMap::new(),
+    };
+    let mut checksums = vec![];
+    for file in files {
+        let modified = tokio::fs::metadata(&file.local_path).await?.modified().ok();
+        let unchanged = matches!((written, modified), (Some(w), Some(m)) if m < w);
+        let sha256 = match previous.get(&file.path_in_repo) {
+            Some(sha256) if unchanged => sha256.clone(),
+            _ => hash_file::<Sha256>(&file.local_path, None).await?,
+        };
+        checksums.push(Checksum {
+            path: file.path_in_repo.clone(),
+            sha256,
+        });
+    }
+    checksums.sort_by(|a, b| a.path.cmp(&b.path));
+    let contents: String = checksums
+        .iter()
+        .map(|c| format!("{}  {}\n", c.sha256, c.path))
+        .collect();
+    tokio::fs::write(&path, contents).await?;
+    Ok(checksums)
+}
+
+/// The hashes in an existing `SHA256SUMS`, by path.
+async fn read(path: &Path) -> std::io::Result<HashMap<String, String>> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(sha256, path)| (path.to_string(), sha256.to_string()))
+        .collect())
+}
@@ -0,0 +1,65 @@
+use clap::ValueEnum;
+use std::{fmt::Display, path::PathBuf};
+
+/// Weight formats a HuggingFace repo ships, which are dead weight once converted to GGUF.
+pub(crate) const SOURCE_WEIGHT_EXTENSIONS: &[&str] = &[".safetensors", ".bin", ".pt", ".pth"];
+
+/// Files the pipeline can delete as soon as it's done with them, to fit on smaller disks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Cleanup {
+    /// The downloaded weights, once the full-precision GGUF converted from them checks out.
+    /// Config and tokenizer files are kept.
+    Sources,
+    /// The full-precision GGUF, once everything is uploaded.
+    Fp,
+    /// The generated or downloaded imatrix, once everything is uploaded.
+    Imatrix,
+    /// The quantized GGUFs, once everything is uploaded.
+    Quants,
+}
+
+impl Display for Cleanup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            Cleanup::Sources => "sources",
+            Cleanup::Fp => "fp",
+            Cleanup::Imatrix => "imatrix",
+            Cleanup::Quants => "quants",
+        };
+        write!(f, "{label}")
+    }
+}
+
+impl Cleanup {
+    /// The uploaded outputs, which can only go once the upload succeeds.
+    pub const UPLOADED: &'static [Cleanup] = &[Cleanup::Fp, Cleanup::Imatrix, Cleanup::Quants];
+}
+
+/// Delete whichever of `files` exist, returning how many were deleted and the bytes freed.
+/// Symlinks and files with other hard links free nothing, since their data stays where it is.
+pub(crate) async fn delete_files(files: &[PathBuf]) -> std::io::Result<(usize, u64)> {
+    let (mut deleted, mut freed) = (0, 0);
+    for file in files {
+        let Ok(metadata) = tokio::fs::symlink_metadata(file).await else {
+            continue;
+        };
+        tokio::fs::remove_file(file).await?;
+        deleted += 1;
+        if !metadata.is_symlink() && links(&metadata) <= 1 {
+            freed += metadata.len();
+        }
+    }
+    Ok((deleted, freed))
+}
+
+/// How many hard links the file has, counting the one it was found by.
+#[cfg(unix)]
+fn links(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink()
+}
+
+#[cfg(not(unix))]
+fn links(_metadata: &std::fs::Metadata) -> u64 {
+    1
+}
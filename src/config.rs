@@ -0,0 +1,145 @@
+use crate::{
+    retry::DEFAULT_RETRIES, storage::Bucket, timeout::TimedStage, ChatTemplate, LlamaBackend,
+    Mirror, NameTemplate, Precision, PythonInstaller, QuantLevel, QuantSpec, DEFAULT_QUANTS,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use shellexpand::tilde;
+use std::{collections::BTreeMap, path::PathBuf};
+
+pub const DEFAULT_CONFIG_PATH: &str = "~/.config/autogguf/config.toml";
+
+/// Persistent defaults, read from `~/.config/autogguf/config.toml` unless `--config` points
+/// elsewhere. Anything missing from the file falls back to the built-in defaults, and CLI flags
+/// override both.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Quant levels, or presets like `common` that expand to several.
+    #[serde(deserialize_with = "deserialize_quants")]
+    pub quants: Vec<QuantLevel>,
+    pub full_precision: Precision,
+    /// How quant files are named, e.g. `{model}-{quant}-imat.gguf`.
+    pub name_template: NameTemplate,
+    /// A chat template preset or Jinja file to write into the full-precision GGUF.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_template: Option<ChatTemplate>,
+    /// Metadata to set in the full-precision GGUF, e.g. `"general.name" = "Llama 3.1 8B"` under
+    /// `[gguf_meta]`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub gguf_meta: BTreeMap<String, String>,
+    pub llama_path: String,
+    /// Where CMake builds llama.cpp; `build` in `llama_path` if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llama_build_dir: Option<String>,
+    /// Where each model's quants, model card and checksums go; the current directory if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<String>,
+    /// Where each model is downloaded, converted and its imatrix generated; `output_dir` if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub work_dir: Option<String>,
+    /// The GPU backend to build llama.cpp with; CMake's defaults if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llama_backend: Option<LlamaBackend>,
+    /// What installs llama.cpp's Python deps; uv if it's on PATH, else pip, if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub python_installer: Option<PythonInstaller>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hf_user: Option<String>,
+    /// A Hub collection every uploaded repo is added to, e.g. `user/llama-ggufs-66f1...`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+    /// A bucket like `s3://bucket/prefix` to upload to instead of the Hub.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_backend: Option<Bucket>,
+    /// HuggingFace users or orgs, or buckets like `s3://bucket/prefix`, to copy every upload to.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<Mirror>,
+    /// A minisign secret key file or GPG key ID to sign uploads with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sign_key: Option<String>,
+    /// A Slack, Discord or other webhook to report each run's progress to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_url: Option<String>,
+    /// Times a download or upload that fails transiently is retried before giving up.
+    pub retries: u32,
+    /// The most minutes each stage may take, e.g. `imatrix = 240` under `[timeouts]`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub timeouts: BTreeMap<TimedStage, u64>,
+    /// Minutes a llama.cpp subprocess may print nothing for before it's killed as hung.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stall_timeout: Option<u64>,
+    /// Threads for imatrix generation and evaluation; every core available if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+    /// Layers `llama-imatrix` offloads to the GPU; 0 for CPU-only machines.
+    pub gpu_layers: u32,
+    /// The llama.cpp device to offload to, e.g. `CUDA1`; llama.cpp's pick if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu_device: Option<String>,
+    pub imatrix_chunks: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imatrix_ctx_size: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            quants: DEFAULT_QUANTS
+                .split(',')
+                .map(|q| q.parse().expect("default quants are valid"))
+                .collect(),
+            full_precision: Precision::F16,
+            name_template: NameTemplate::default(),
+            chat_template: None,
+            gguf_meta: BTreeMap::new(),
+            llama_path: "~/code/llama.cpp".to_string(),
+            llama_build_dir: None,
+            output_dir: None,
+            work_dir: None,
+            llama_backend: None,
+            python_installer: None,
+            hf_user: None,
+            collection: None,
+            upload_backend: None,
+            mirrors: vec![],
+            sign_key: None,
+            notify_url: None,
+            retries: DEFAULT_RETRIES,
+            timeouts: BTreeMap::new(),
+            stall_timeout: None,
+            threads: None,
+            gpu_layers: 999,
+            gpu_device: None,
+            imatrix_chunks: 2000,
+            imatrix_ctx_size: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file at `path`, or the default location when `None`. A missing file at
+    /// the default location is not an error; a missing file that was asked for explicitly is.
+    pub async fn load(path: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let explicit = path.is_some();
+        let path = PathBuf::from(tilde(path.unwrap_or(DEFAULT_CONFIG_PATH)).into_owned());
+        if !explicit && !tokio::fs::try_exists(&path).await? {
+            return Ok(Self::default());
+        }
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| format!("failed to read config {}: {e}", path.display()))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("invalid config {}: {e}", path.display()).into())
+    }
+}
+
+fn deserialize_quants<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<QuantLevel>, D::Error> {
+    let specs = Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| s.parse())
+        .collect::<Result<Vec<QuantSpec>, _>>()
+        .map_err(serde::de::Error::custom)?;
+    Ok(QuantSpec::expand(&specs))
+}
@@ -0,0 +1,413 @@
+use crate::{
+    event::{Event, Stage},
+    summary::RunSummary,
+    timeout::{self, Timeouts},
+};
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    path::PathBuf,
+    pin::Pin,
+    process::{ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    task::Poll,
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, ReadBuf},
+    process::Command,
+    select,
+    sync::{mpsc::UnboundedSender, Mutex, Notify},
+};
+
+/// Where per-stage logs are written, relative to the model directory.
+pub(crate) const LOG_DIR: &str = "logs";
+
+/// A stage's log file, shared by the tasks forwarding a subprocess's stdout and stderr.
+type StageLog = Option<Arc<Mutex<File>>>;
+
+/// The process IDs of every subprocess a stage is running, across all pipelines.
+static RUNNING: StdMutex<BTreeSet<u32>> = StdMutex::new(BTreeSet::new());
+
+/// Kill every subprocess any pipeline is running, right away, for quitting without waiting on
+/// stages to wind down.
+pub fn kill_subprocesses() {
+    for &pid in RUNNING.lock().unwrap().iter() {
+        kill(pid);
+    }
+}
+
+#[cfg(unix)]
+fn kill(pid: u32) {
+    // SAFETY: only signals the process; a pid that's already exited just gets ESRCH
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+#[cfg(windows)]
+fn kill(pid: u32) {
+    let _ = std::process::Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid.to_string()])
+        .output();
+}
+
+#[cfg(not(any(unix, windows)))]
+fn kill(_pid: u32) {}
+
+/// A subprocess's entry in [`RUNNING`], for as long as it's being waited on.
+struct Running(Option<u32>);
+
+impl Running {
+    fn register(pid: Option<u32>) -> Self {
+        if let Some(pid) = pid {
+            RUNNING.lock().unwrap().insert(pid);
+        }
+        Self(pid)
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if let Some(pid) = self.0 {
+            RUNNING.lock().unwrap().remove(&pid);
+        }
+    }
+}
+
+/// What llama.cpp and the allocators under it print when memory runs out, lowercased: CUDA,
+/// Metal and Vulkan buffer allocations failing, and plain RAM.
+const OUT_OF_MEMORY_MESSAGES: &[&str] = &[
+    "out of memory",
+    "outofmemory",
+    "outofdevicememory",
+    "insufficient memory",
+    "failed to allocate",
+    "cannot allocate memory",
+    "bad_alloc",
+];
+
+/// A subprocess that ran out of memory: killed by the OOM killer, or failing an allocation.
+/// Smaller settings may get it through where the same ones would fail again.
+#[derive(Debug)]
+pub(crate) struct OutOfMemory {
+    description: String,
+    status: ExitStatus,
+}
+
+impl Display for OutOfMemory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ran out of memory ({})",
+            self.description, self.status
+        )
+    }
+}
+
+impl std::error::Error for OutOfMemory {}
+
+impl OutOfMemory {
+    /// Whether `status` and the `output` that came before it look like running out of memory.
+    fn detect(status: &ExitStatus, output: &[String]) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if status.signal() == Some(9) {
+                return true;
+            }
+        }
+        // NOTE: 137 is SIGKILL as reported by a shell wrapping the real binary
+        status.code() == Some(137)
+            || output.iter().any(|line| {
+                let line = line.to_lowercase();
+                OUT_OF_MEMORY_MESSAGES.iter().any(|m| line.contains(m))
+            })
+    }
+}
+
+/// What every stage needs besides its own inputs: where to report progress, and how to find out
+/// it should stop.
+#[derive(Debug, Clone)]
+pub(crate) struct Context {
+    pub verbose: bool,
+    pub cancel: Arc<Notify>,
+    /// Set once a stage is cut short by `cancel`, so its failure isn't mistaken for any other.
+    pub interrupted: Arc<AtomicBool>,
+    pub events: Option<UnboundedSender<Event>>,
+    /// Where each stage's `<stage>.log` is appended to, if anywhere.
+    pub log_dir: Option<PathBuf>,
+    /// How each stage went, from the events emitted so far.
+    pub summary: Arc<StdMutex<RunSummary>>,
+    /// How many times a download or upload that fails transiently is retried.
+    pub retries: u32,
+    pub timeouts: Timeouts,
+}
+
+impl Context {
+    pub fn emit(&self, event: Event) {
+        self.summary.lock().unwrap().record(&event);
+        if let Some(events) = &self.events {
+            // NOTE: a listener hanging up shouldn't take the pipeline down with it
+            let _ = events.send(event);
+        }
+    }
+
+    /// A status message that's always shown.
+    pub fn info(&self, text: impl Into<String>) {
+        let text = text.into();
+        tracing::info!("{text}");
+        self.emit(Event::Message { text });
+    }
+
+    /// A status message that's only shown with `--verbose`, but always sent to listeners.
+    pub fn detail(&self, text: impl Into<String>) {
+        let text = text.into();
+        tracing::debug!("{text}");
+        self.emit(Event::Message { text });
+    }
+
+    pub fn warn(&self, text: impl Into<String>) {
+        let text = text.into();
+        tracing::warn!("{text}");
+        self.emit(Event::Message { text });
+    }
+
+    /// Record that work was cut short by `cancel`.
+    pub fn mark_interrupted(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the pipeline has been cancelled partway through a stage.
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst)
+    }
+
+    /// Whether progress goes to a listener rather than the terminal.
+    pub fn captures_output(&self) -> bool {
+        self.events.is_some()
+    }
+
+    /// Run `command` to completion for `stage`, killing it if the pipeline is cancelled or it
+    /// stalls. Its output is logged line by line and forwarded to listeners.
+    /// `description` names the process in errors, e.g. "Quantization process".
+    pub async fn run(
+        &self,
+        stage: &Stage,
+        command: Command,
+        description: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.run_capturing(stage, command, description).await?;
+        Ok(())
+    }
+
+    /// Like [`run`](Self::run), but also returns the lines it printed: all of stdout, then all
+    /// of stderr.
+    pub async fn run_capturing(
+        &self,
+        stage: &Stage,
+        mut command: Command,
+        description: &str,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        tracing::debug!(%stage, "running {:?}", command.as_std());
+        let log = self.open_log(stage).await;
+        write_line(&log, &format!("$ {:?}", command.as_std())).await;
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        // NOTE: so a stage that times out doesn't leave it running
+        command.kill_on_drop(true);
+        let mut child = command.spawn()?;
+        let _running = Running::register(child.id());
+        let activity = Arc::new(StdMutex::new(Instant::now()));
+        let mut forwarders = vec![];
+        if let Some(stdout) = child.stdout.take() {
+            let stdout = Watched::new(stdout, activity.clone());
+            forwarders.push(self.forward_lines(stage.clone(), stdout, log.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let stderr = Watched::new(stderr, activity.clone());
+            forwarders.push(self.forward_lines(stage.clone(), stderr, log.clone()));
+        }
+
+        select! {
+            status = child.wait() => {
+                let status = status?;
+                let mut output = vec![];
+                for forwarder in forwarders {
+                    output.extend(forwarder.await.unwrap_or_default());
+                }
+                write_line(&log, &format!("# {description} exited: {status}")).await;
+                if !status.success() {
+                    if OutOfMemory::detect(&status, &output) {
+                        return Err(Box::new(OutOfMemory {
+                            description: description.to_string(),
+                            status,
+                        }));
+                    }
+                    return Err(format!("{description} failed: {status}").into());
+                }
+                Ok(output)
+            }
+            _ = self.cancel.notified() => {
+                self.mark_interrupted();
+                child.kill().await?;
+                write_line(&log, &format!("# {description} killed due to interrupt")).await;
+                Err(format!("{description} killed due to interrupt").into())
+            }
+            limit = stalled(&activity, self.timeouts.stall) => {
+                child.kill().await?;
+                let error = format!(
+                    "{description} stalled: no output for {}",
+                    timeout::describe(limit)
+                );
+                write_line(&log, &format!("# {error}, killed")).await;
+                Err(error.into())
+            }
+        }
+    }
+
+    /// Append a line to `stage`'s log file, for stages that don't run a subprocess.
+    pub async fn log(&self, stage: &Stage, line: &str) {
+        write_line(&self.open_log(stage).await, line).await;
+    }
+
+    async fn open_log(&self, stage: &Stage) -> StageLog {
+        let dir = self.log_dir.as_ref()?;
+        let path = dir.join(format!("{}.log", stage.slug()));
+        let opened = async {
+            tokio::fs::create_dir_all(dir).await?;
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+        };
+        match opened.await {
+            Ok(file) => Some(Arc::new(Mutex::new(file))),
+            Err(e) => {
+                // NOTE: losing a log file isn't worth failing the stage over
+                tracing::warn!("couldn't open log file {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    fn forward_lines(
+        &self,
+        stage: Stage,
+        reader: impl AsyncRead + Unpin + Send + 'static,
+        log: StageLog,
+    ) -> tokio::task::JoinHandle<Vec<String>> {
+        let ctx = self.clone();
+        tokio::spawn(async move {
+            let mut output = vec![];
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                tracing::info!(target: "autogguf::subprocess", "{line}");
+                write_line(&log, &line).await;
+                output.push(line.clone());
+                ctx.emit(Event::Output {
+                    stage: stage.clone(),
+                    line,
+                });
+            }
+            output
+        })
+    }
+}
+
+/// Resolves, with `limit`, once that long passes without `activity`; never without a limit.
+async fn stalled(activity: &StdMutex<Instant>, limit: Option<Duration>) -> Duration {
+    let Some(limit) = limit else {
+        return std::future::pending().await;
+    };
+    loop {
+        let deadline = *activity.lock().unwrap() + limit;
+        if Instant::now() >= deadline {
+            return limit;
+        }
+        tokio::time::sleep_until(deadline.into()).await;
+    }
+}
+
+/// A subprocess's output, noting when it last printed anything. Any bytes count, not just whole
+/// lines: `llama-imatrix` reports progress on a single line that only ends when it's done.
+struct Watched<R> {
+    inner: R,
+    activity: Arc<StdMutex<Instant>>,
+}
+
+impl<R> Watched<R> {
+    fn new(inner: R, activity: Arc<StdMutex<Instant>>) -> Self {
+        Self { inner, activity }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Watched<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let polled = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            *self.activity.lock().unwrap() = Instant::now();
+        }
+        polled
+    }
+}
+
+async fn write_line(log: &StageLog, line: &str) {
+    if let Some(log) = log {
+        let mut file = log.lock().await;
+        let _ = file.write_all(format!("{line}\n").as_bytes()).await;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn exited(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    fn lines(output: &[&str]) -> Vec<String> {
+        output.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn allocation_failures_are_out_of_memory() {
+        for message in [
+            "ggml_backend_cuda_buffer_type_alloc_buffer: allocating 4096.00 MiB on device 0: cudaMalloc failed: out of memory",
+            "error: Insufficient Memory (00000008:kIOGPUCommandBufferCallbackErrorOutOfMemory)",
+            "ggml_vulkan: vk::Device::allocateMemory: ErrorOutOfDeviceMemory",
+            "llama_model_load: error loading model: failed to mmap: Cannot allocate memory",
+            "terminate called after throwing an instance of 'std::bad_alloc'",
+        ] {
+            let output = lines(&["llama_model_loader: loaded meta data", message]);
+            assert!(OutOfMemory::detect(&exited(1), &output), "{message}");
+        }
+    }
+
+    #[test]
+    fn being_killed_is_out_of_memory() {
+        assert!(OutOfMemory::detect(&ExitStatus::from_raw(9), &[]));
+        assert!(OutOfMemory::detect(&exited(137), &[]));
+    }
+
+    #[test]
+    fn other_failures_are_not_out_of_memory() {
+        let output = lines(&[
+            "llama_model_load: error loading model: check_tensor_dims: tensor 'blk.0.attn_q.weight' has wrong shape",
+            "main: failed to quantize model from 'model.f16.gguf'",
+        ]);
+        assert!(!OutOfMemory::detect(&exited(1), &output));
+        assert!(!OutOfMemory::detect(&ExitStatus::from_raw(15), &[]));
+    }
+}
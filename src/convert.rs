@@ -0,0 +1,706 @@
+use crate::{
+    context::{Context, OutOfMemory},
+    event::Stage,
+    gguf, llama, naming,
+    plan::human_bytes,
+    retry::with_retries,
+    Precision, QuantLevel,
+};
+use futures_util::StreamExt;
+use std::{
+    collections::BTreeSet,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWriteExt},
+    process::Command,
+};
+
+/// llama.cpp's HuggingFace-to-GGUF converter, relative to the repo root.
+pub(crate) const CONVERT_SCRIPT: &str = "convert_hf_to_gguf.py";
+/// Where the calibration dataset is written for `llama-imatrix`, in the model's work directory.
+pub(crate) const CALIBRATION_FILE: &str = "calibration_data.txt";
+pub(crate) const CALIBRATION_URL: &str =
+    "https://github.com/ggerganov/llama.cpp/files/14194570/groups_merged.txt";
+
+pub(crate) fn convert_command(
+    precision: &Precision,
+    llama_path: &Path,
+    model_dir: &Path,
+    output_path: &Path,
+    extra_args: &[String],
+) -> Command {
+    // NOTE: fall back to the system interpreter for checkouts set up without --update-llama
+    let venv_python = llama::venv_python(llama_path);
+    let mut command = match venv_python.exists() {
+        true => Command::new(venv_python),
+        false => Command::new(llama::python()),
+    };
+    command
+        .arg(llama_path.join(CONVERT_SCRIPT))
+        .arg(model_dir)
+        .arg("--outtype")
+        .arg(precision.to_string())
+        .arg("--outfile")
+        .arg(output_path)
+        .args(extra_args);
+    command
+}
+
+/// How `llama-imatrix` runs: these are `-t`, `-ngl`, `-dev`, `--chunks` and `-c`.
+#[derive(Debug, Clone)]
+pub(crate) struct ImatrixParams {
+    pub threads: u32,
+    /// Layers to offload to the GPU; 0 for CPU-only.
+    pub gpu_layers: u32,
+    /// The devices to offload to, e.g. `CUDA1`, or llama.cpp's pick when `None`.
+    pub device: Option<String>,
+    pub chunks: u32,
+    /// Context size per chunk, or llama-imatrix's default of 512 when `None`.
+    pub ctx_size: Option<u32>,
+}
+
+impl ImatrixParams {
+    /// `-ngl`, and `-dev` if a device was picked, for any llama.cpp tool that loads a model.
+    pub fn offload_args(&self) -> Vec<String> {
+        let mut args = vec!["-ngl".to_string(), self.gpu_layers.to_string()];
+        if let Some(device) = &self.device {
+            args.extend(["-dev".to_string(), device.clone()]);
+        }
+        args
+    }
+}
+
+/// Every core this process may use, which respects CPU affinity and cgroup quotas, or 4 if that
+/// can't be told.
+pub(crate) fn default_threads() -> u32 {
+    std::thread::available_parallelism().map_or(4, |n| n.get().try_into().unwrap_or(u32::MAX))
+}
+
+pub(crate) fn imatrix_command(
+    llama_bin: &Path,
+    fp: &Path,
+    calibration: &Path,
+    output_path: &Path,
+    params: &ImatrixParams,
+) -> Command {
+    let mut command = Command::new(llama::binary(llama_bin, "llama-imatrix"));
+    command
+        .arg("-m")
+        .arg(fp)
+        .arg("-f")
+        .arg(calibration)
+        .arg("-o")
+        .arg(output_path)
+        .arg("-t")
+        .arg(params.threads.to_string())
+        .args(params.offload_args())
+        .arg("--chunks")
+        .arg(params.chunks.to_string());
+    if let Some(ctx_size) = params.ctx_size {
+        command.arg("-c").arg(ctx_size.to_string());
+    }
+    command
+}
+
+/// Convert the model in `source_dir` to `output_path`. With `--split-max-size` or
+/// `--split-max-tensors` among `extra_args`, the converter writes shards instead, which are left
+/// for [`merge_shards`] to put back together.
+pub(crate) async fn convert_fp(
+    precision: Precision,
+    llama_path: PathBuf,
+    output_path: PathBuf,
+    source_dir: &Path,
+    model_name: &str,
+    extra_args: &[String],
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    ctx.detail(format!(
+        "🪄 converting {model_name} to {}...",
+        precision.to_string().to_uppercase()
+    ));
+    let convert_fp_task = convert_command(
+        &precision,
+        &llama_path,
+        source_dir,
+        &output_path,
+        extra_args,
+    );
+    ctx.run(&Stage::Convert, convert_fp_task, "Conversion process")
+        .await?;
+
+    if !tokio::fs::try_exists(&output_path).await? && find_shards(&output_path).await?.is_empty() {
+        return Err("💥 Conversion failed".into());
+    };
+
+    // teeeeeeeechnically this is new and missing from the og autogguf[.py].....
+    ctx.detail(format!(
+        "🪄 {model_name} conversion to {} complete!",
+        precision.to_string().to_uppercase()
+    ));
+
+    Ok(())
+}
+
+/// Write the calibration dataset for `llama-imatrix` to `path`: each of `sources` (local paths
+/// or URLs) concatenated in order, or the default dataset if there are none. Always written
+/// afresh, so what a failed run left behind is never taken for the dataset asked for.
+pub(crate) async fn prepare_calibration_data(
+    path: &Path,
+    sources: &[String],
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let default = [CALIBRATION_URL.to_string()];
+    let sources = if sources.is_empty() {
+        &default[..]
+    } else {
+        sources
+    };
+
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut f = File::create(path).await?;
+    let written: Result<(), Box<dyn std::error::Error>> = async {
+        for source in sources {
+            if is_url(source) {
+                ctx.detail(format!("🌐 downloading calibration dataset {source}..."));
+                download(source, &mut f, &Stage::Imatrix, ctx).await?;
+            } else {
+                let contents = tokio::fs::read(source)
+                    .await
+                    .map_err(|e| format!("couldn't read calibration dataset {source}: {e}"))?;
+                f.write_all(&contents).await?;
+            }
+            // NOTE: so the last line of one dataset doesn't run into the first of the next
+            f.write_all(b"\n").await?;
+        }
+        f.flush().await?;
+        Ok(())
+    }
+    .await;
+    if written.is_err() {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    written
+}
+
+pub(crate) fn is_url(source: &str) -> bool {
+    source.starts_with("https://") || source.starts_with("http://")
+}
+
+/// Stream `url` into `f` from its current position, retrying transient failures for `stage`.
+pub(crate) async fn download(
+    url: &str,
+    f: &mut File,
+    stage: &Stage,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let start = f.stream_position().await?;
+    let file = &*f;
+    with_retries(stage, &format!("download {url}"), ctx, || async move {
+        // NOTE: a fresh handle per attempt, truncated so a retry doesn't append to what a failed
+        // one got as far as writing
+        let mut f = file.try_clone().await?;
+        f.set_len(start).await?;
+        f.seek(SeekFrom::Start(start)).await?;
+        let response = reqwest::get(url).await?.error_for_status()?;
+        let mut byte_stream = response.bytes_stream();
+        while let Some(bytes) = byte_stream.next().await {
+            f.write_all(&bytes?).await?;
+        }
+        f.flush().await?;
+        Ok(())
+    })
+    .await
+    .map_err(|e| format!("failed to download {url}: {e}"))?;
+    f.seek(SeekFrom::End(0)).await?;
+    Ok(())
+}
+
+/// Merge `inputs` into one imatrix at `output_path`, weighting each by how many chunks it saw.
+pub(crate) fn combine_imatrix_command(
+    llama_bin: &Path,
+    inputs: &[PathBuf],
+    output_path: &Path,
+) -> Command {
+    let mut command = Command::new(llama::binary(llama_bin, "llama-imatrix"));
+    for input in inputs {
+        command.arg("--in-file").arg(input);
+    }
+    command.arg("-o").arg(output_path);
+    command
+}
+
+pub(crate) async fn combine_imatrices(
+    llama_bin: &Path,
+    inputs: &[PathBuf],
+    output_path: &Path,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    ctx.detail(format!("⚖️ combining {} imatrix files...", inputs.len()));
+    let combine = combine_imatrix_command(llama_bin, inputs, output_path);
+    ctx.run(&Stage::Imatrix, combine, "imatrix combination process")
+        .await?;
+    if !tokio::fs::try_exists(output_path).await? {
+        return Err("💥 combining imatrix files produced no output".into());
+    }
+    Ok(())
+}
+
+pub(crate) async fn generate_imatrix(
+    llama_bin: PathBuf,
+    fp: PathBuf,
+    output_path: PathBuf,
+    model_name: &str,
+    params: &ImatrixParams,
+    calibration: &Path,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    ctx.detail(format!("⚖️ generating imatrix for {model_name}..."));
+    let layers = gguf::validate(&fp)
+        .await
+        .ok()
+        .and_then(|header| header.block_count());
+    let mut params = params.clone();
+    loop {
+        let imatrix_task = imatrix_command(&llama_bin, &fp, calibration, &output_path, &params);
+        match ctx
+            .run(&Stage::Imatrix, imatrix_task, "imatrix generation process")
+            .await
+        {
+            Ok(()) => break,
+            Err(e) if e.is::<OutOfMemory>() => {
+                let Some(gpu_layers) = fewer_gpu_layers(params.gpu_layers, layers) else {
+                    return Err(format!(
+                        "💥 {e}, even without GPU offload; try a smaller --imatrix-ctx-size, a \
+                        machine with more RAM, or an existing imatrix with --imatrix or \
+                        --imatrix-repo"
+                    )
+                    .into());
+                };
+                ctx.warn(format!(
+                    "💾 {e}, retrying with {gpu_layers} GPU layers instead of {}",
+                    params.gpu_layers
+                ));
+                params.gpu_layers = gpu_layers;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    ctx.detail("🧹 cleaning up caliration dataset...");
+    tokio::fs::remove_file(calibration).await?;
+    Ok(())
+}
+
+/// Fewer layers to offload than `gpu_layers` after running out of memory: half as many of the
+/// model's `layers` (plus its output layer), or none once that's down to a handful, or if the
+/// model's size is unknown. `None` when already on the CPU.
+fn fewer_gpu_layers(gpu_layers: u32, layers: Option<u32>) -> Option<u32> {
+    if gpu_layers == 0 {
+        return None;
+    }
+    let half = layers.map_or(0, |layers| gpu_layers.min(layers + 1) / 2);
+    // NOTE: another whole attempt isn't worth it to keep a few layers on the GPU
+    Some(if half < 4 { 0 } else { half })
+}
+
+/// One `llama-quantize` invocation: which level to produce, from what, and where to write it.
+pub(crate) struct QuantizeJob {
+    pub level: QuantLevel,
+    pub fp: PathBuf,
+    pub imatrix: PathBuf,
+    pub output_path: PathBuf,
+    /// `--output-tensor-type`, if overridden.
+    pub output_tensor_type: Option<String>,
+    /// `--token-embedding-type`, if overridden.
+    pub token_embedding_type: Option<String>,
+    /// `--allow-requantize`: `fp` may itself be quantized, e.g. Q8_0.
+    pub allow_requantize: bool,
+    /// `--pure`: every tensor at `level`, without the k-quant mixes' higher-precision tensors.
+    pub pure: bool,
+    /// Anything else to pass to `llama-quantize`, verbatim, before the positional arguments.
+    pub extra_args: Vec<String>,
+}
+
+impl QuantizeJob {
+    /// Where `llama-quantize` writes, before the finished file is moved to `output_path`.
+    pub fn pending_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.pending", self.output_path.to_string_lossy()))
+    }
+}
+
+pub(crate) fn quantize_command(job: &QuantizeJob, llama_bin: &Path) -> Command {
+    let mut command = Command::new(llama::binary(llama_bin, "llama-quantize"));
+    if job.level.requires_imatrix() {
+        command.arg("--imatrix").arg(&job.imatrix);
+    }
+    if let Some(ggml_type) = &job.output_tensor_type {
+        command.arg("--output-tensor-type").arg(ggml_type);
+    }
+    if let Some(ggml_type) = &job.token_embedding_type {
+        command.arg("--token-embedding-type").arg(ggml_type);
+    }
+    if job.allow_requantize {
+        command.arg("--allow-requantize");
+    }
+    if job.pure {
+        command.arg("--pure");
+    }
+    command.args(&job.extra_args);
+    command
+        .arg(&job.fp)
+        .arg(job.pending_path())
+        .arg(job.level.to_string());
+    command
+}
+
+pub(crate) async fn quantize(
+    job: QuantizeJob,
+    llama_bin: PathBuf,
+    model_name: &str,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stage = Stage::Quantize(job.level.clone());
+    ctx.detail(format!(
+        "🪄 quantizing {model_name} to {}...",
+        job.level.to_string().to_uppercase()
+    ));
+    let quantize = quantize_command(&job, &llama_bin);
+    ctx.run(&stage, quantize, "Quantization process")
+        .await
+        .map_err(|e| match e.is::<OutOfMemory>() {
+            // NOTE: nothing llama-quantize takes makes it use less memory, unlike imatrix's -ngl
+            true => format!(
+                "{e}; free up memory or move to a machine with more RAM, then rerun to pick up \
+                from the quants already made"
+            )
+            .into(),
+            false => e,
+        })?;
+    gguf::validate(&job.pending_path())
+        .await
+        .map_err(|e| format!("💥 {e}"))?;
+
+    move_file(&job.pending_path(), &job.output_path)
+        .await
+        .map_err(|e| format!("Quantized file rename failed: {e}"))?;
+
+    Ok(())
+}
+
+/// Move `from` to `to`, copying and deleting the original if they're on different filesystems.
+pub(crate) async fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    match tokio::fs::rename(from, to).await {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            tokio::fs::copy(from, to).await?;
+            tokio::fs::remove_file(from).await
+        }
+        result => result,
+    }
+}
+
+/// Quants bigger than this are split to fit under the HuggingFace Hub's 50GB file limit.
+pub(crate) const SPLIT_THRESHOLD: u64 = 48_000_000_000;
+/// `--split-max-size` for `llama-gguf-split`, which counts in powers of 1000 like the threshold.
+const SPLIT_MAX_SIZE: &str = "48G";
+
+/// The prefix `llama-gguf-split` names shards after: `path` without its `.gguf` extension.
+fn shard_prefix(path: &Path) -> PathBuf {
+    path.with_extension("")
+}
+
+pub(crate) fn split_command(llama_bin: &Path, path: &Path) -> Command {
+    let mut command = Command::new(llama::binary(llama_bin, "llama-gguf-split"));
+    command
+        .arg("--split")
+        .arg("--split-max-size")
+        .arg(SPLIT_MAX_SIZE)
+        .arg(path)
+        .arg(shard_prefix(path));
+    command
+}
+
+pub(crate) fn merge_command(llama_bin: &Path, first_shard: &Path, output_path: &Path) -> Command {
+    let mut command = Command::new(llama::binary(llama_bin, "llama-gguf-split"));
+    command.arg("--merge").arg(first_shard).arg(output_path);
+    command
+}
+
+/// Merge the `shards` of `path` back into it, since quantizing needs a single GGUF, removing the
+/// shards once the merged GGUF is in place. Takes as much free space again as they do.
+pub(crate) async fn merge_shards(
+    llama_bin: &Path,
+    shards: &[PathBuf],
+    path: &Path,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(first) = shards.first() else {
+        return Err(format!("{} has no shards to merge", path.display()).into());
+    };
+    ctx.detail(format!(
+        "🧵 merging {} shards back into {}...",
+        shards.len(),
+        path.display()
+    ));
+    let pending = PathBuf::from(format!("{}.pending", path.to_string_lossy()));
+    let command = merge_command(llama_bin, first, &pending);
+    if let Err(e) = ctx
+        .run(&Stage::Convert, command, "GGUF merge process")
+        .await
+    {
+        let _ = tokio::fs::remove_file(&pending).await;
+        return Err(e);
+    }
+    move_file(&pending, path).await?;
+    for shard in shards {
+        tokio::fs::remove_file(shard).await?;
+    }
+    Ok(())
+}
+
+/// Split `path` into `<name>-00001-of-0000N.gguf` shards if it's over [`SPLIT_THRESHOLD`],
+/// removing the original once the split succeeds. Returns the shards, or `path` alone if it
+/// didn't need splitting; shards from a previous run are returned as-is.
+pub(crate) async fn split_if_oversized(
+    path: &Path,
+    llama_bin: &Path,
+    stage: &Stage,
+    ctx: &Context,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let size = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => {
+            let shards = find_shards(path).await?;
+            if shards.is_empty() {
+                return Err(format!("{} not found", path.display()).into());
+            }
+            return Ok(shards);
+        }
+    };
+    if size <= SPLIT_THRESHOLD {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    ctx.detail(format!(
+        "✂️ splitting {} ({}) into shards under {SPLIT_MAX_SIZE}...",
+        path.display(),
+        human_bytes(size)
+    ));
+    ctx.run(stage, split_command(llama_bin, path), "GGUF split process")
+        .await?;
+    let shards = find_shards(path).await?;
+    if shards.is_empty() {
+        return Err(format!("💥 splitting {} produced no shards", path.display()).into());
+    }
+    for shard in &shards {
+        gguf::validate(shard).await.map_err(|e| format!("💥 {e}"))?;
+    }
+    tokio::fs::remove_file(path).await?;
+    Ok(shards)
+}
+
+/// The complete set of `<name>-NNNNN-of-NNNNN.gguf` shards split from `path`, in order, or none
+/// if there aren't any, some are missing, or they disagree on how many there are, as leftovers
+/// of an earlier split into a different number would.
+pub(crate) async fn find_shards(path: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let prefix = shard_prefix(path);
+    let (Some(dir), Some(stem)) = (prefix.parent(), prefix.file_name()) else {
+        return Ok(vec![]);
+    };
+    let stem = stem.to_string_lossy();
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    if !tokio::fs::try_exists(dir).await? {
+        return Ok(vec![]);
+    }
+
+    let mut shards = vec![];
+    let mut totals = BTreeSet::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        match Shard::parse(&name) {
+            Some(shard) if shard.stem == stem => {
+                totals.insert(shard.of);
+                shards.push((shard.n, entry.path()));
+            }
+            _ => continue,
+        }
+    }
+    shards.sort();
+    let [total] = totals.into_iter().collect::<Vec<_>>()[..] else {
+        return Ok(vec![]);
+    };
+    if !shards.iter().map(|(n, _)| *n).eq(1..=total) {
+        return Ok(vec![]);
+    }
+    Ok(shards.into_iter().map(|(_, path)| path).collect())
+}
+
+/// The parts of a `<stem>-NNNNN-of-NNNNN.gguf` shard file name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Shard<'a> {
+    /// The unsplit file's name without `.gguf`, e.g. `model.Q8_0`.
+    pub stem: &'a str,
+    pub n: u32,
+    pub of: u32,
+}
+
+impl<'a> Shard<'a> {
+    pub fn parse(name: &'a str) -> Option<Self> {
+        let rest = name.strip_suffix(".gguf")?;
+        let (rest, of) = rest.rsplit_once("-of-")?;
+        let (stem, n) = rest.rsplit_once('-')?;
+        Some(Self {
+            stem,
+            n: n.parse().ok()?,
+            of: of.parse().ok()?,
+        })
+    }
+
+    /// The quant label from the stem, e.g. `Q8_0` for `model.Q8_0`.
+    pub fn label(&self) -> &'a str {
+        naming::label(self.stem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory in the temp dir, unique to this process and `name`.
+    async fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("autogguf-{}-{name}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        dir
+    }
+
+    async fn touch(dir: &Path, names: &[&str]) {
+        for name in names {
+            tokio::fs::write(dir.join(name), b"").await.unwrap();
+        }
+    }
+
+    #[test]
+    fn shard_parse_reads_the_stem_and_numbers() {
+        let shard = Shard::parse("model.Q8_0-00001-of-00003.gguf").unwrap();
+        assert_eq!(
+            shard,
+            Shard {
+                stem: "model.Q8_0",
+                n: 1,
+                of: 3
+            }
+        );
+        assert_eq!(shard.label(), "Q8_0");
+        let shard = Shard::parse("my-model-7b.f16-00012-of-00012.gguf").unwrap();
+        assert_eq!((shard.stem, shard.n, shard.of), ("my-model-7b.f16", 12, 12));
+    }
+
+    #[test]
+    fn shard_parse_rejects_unsplit_names() {
+        for name in [
+            "model.Q8_0.gguf",
+            "model.Q8_0-00001-of-00003.bin",
+            "model-of-experts.gguf",
+            "model.Q8_0-one-of-00003.gguf",
+        ] {
+            assert_eq!(Shard::parse(name), None, "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn find_shards_returns_a_complete_set_in_order() {
+        let dir = temp_dir("complete-shards").await;
+        touch(
+            &dir,
+            &[
+                "model.Q8_0-00002-of-00003.gguf",
+                "model.Q8_0-00003-of-00003.gguf",
+                "model.Q8_0-00001-of-00003.gguf",
+                "model.Q4_K_M-00001-of-00002.gguf",
+            ],
+        )
+        .await;
+        let shards = find_shards(&dir.join("model.Q8_0.gguf")).await.unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+        let names: Vec<_> = shards
+            .iter()
+            .map(|shard| shard.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "model.Q8_0-00001-of-00003.gguf",
+                "model.Q8_0-00002-of-00003.gguf",
+                "model.Q8_0-00003-of-00003.gguf",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn find_shards_returns_none_with_a_shard_missing() {
+        let dir = temp_dir("missing-shard").await;
+        touch(
+            &dir,
+            &[
+                "model.Q8_0-00001-of-00003.gguf",
+                "model.Q8_0-00003-of-00003.gguf",
+            ],
+        )
+        .await;
+        let shards = find_shards(&dir.join("model.Q8_0.gguf")).await.unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+        assert!(shards.is_empty());
+    }
+
+    #[tokio::test]
+    async fn find_shards_returns_none_when_shards_disagree_on_the_total() {
+        let dir = temp_dir("mixed-shards").await;
+        // NOTE: three shards, but from a split into two and a split into three
+        touch(
+            &dir,
+            &[
+                "model.Q8_0-00001-of-00002.gguf",
+                "model.Q8_0-00002-of-00002.gguf",
+                "model.Q8_0-00003-of-00003.gguf",
+            ],
+        )
+        .await;
+        let shards = find_shards(&dir.join("model.Q8_0.gguf")).await.unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+        assert!(shards.is_empty());
+    }
+
+    #[tokio::test]
+    async fn find_shards_returns_none_without_a_directory() {
+        let path = std::env::temp_dir().join("autogguf-nowhere/model.Q8_0.gguf");
+        assert!(find_shards(&path).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn fewer_gpu_layers_halves_down_to_the_cpu() {
+        let mut gpu_layers = 99;
+        let mut tries = vec![];
+        while let Some(fewer) = fewer_gpu_layers(gpu_layers, Some(32)) {
+            tries.push(fewer);
+            gpu_layers = fewer;
+        }
+        // NOTE: 99 means everything, which for 32 layers is 33 with the output layer
+        assert_eq!(tries, [16, 8, 4, 0]);
+    }
+
+    #[test]
+    fn fewer_gpu_layers_goes_to_the_cpu_without_a_layer_count() {
+        assert_eq!(fewer_gpu_layers(99, None), Some(0));
+        assert_eq!(fewer_gpu_layers(0, None), None);
+        assert_eq!(fewer_gpu_layers(0, Some(32)), None);
+    }
+}
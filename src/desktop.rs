@@ -0,0 +1,54 @@
+//! Desktop notifications for when a run ends, since conversions take hours and nobody sits
+//! watching them.
+
+use autogguf::Notification;
+use tokio::process::Command;
+
+/// Show `notification` on the desktop: in Notification Center on macOS, or through whatever
+/// notification daemon is running on Linux. Failing to only warns.
+pub async fn notify(notification: &Notification) {
+    let text = notification.to_string();
+    let mut command = if cfg!(target_os = "macos") {
+        // NOTE: passed as arguments rather than spliced into the script, so nothing needs escaping
+        let mut command = Command::new("osascript");
+        command.args([
+            "-e",
+            "on run argv",
+            "-e",
+            "display notification (item 2 of argv) with title (item 1 of argv)",
+            "-e",
+            "end run",
+            "autogguf",
+            &text,
+        ]);
+        command
+    } else if cfg!(target_os = "linux") {
+        let urgency = match notification {
+            Notification::RunFailed { .. } => "critical",
+            _ => "normal",
+        };
+        let mut command = Command::new("notify-send");
+        command.args([
+            "--app-name=autogguf",
+            "--urgency",
+            urgency,
+            "autogguf",
+            &text,
+        ]);
+        command
+    } else {
+        tracing::debug!("desktop notifications aren't supported on this platform");
+        return;
+    };
+    match command.output().await {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => tracing::warn!(
+            "couldn't show desktop notification: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => tracing::warn!(
+            "couldn't show desktop notification with {}: {e}",
+            command.as_std().get_program().to_string_lossy()
+        ),
+    }
+}
@@ -0,0 +1,269 @@
+//! Environment checks for `autogguf doctor`: everything a run shells out to or needs from the
+//! machine, checked up front with a suggested fix for each problem.
+
+use crate::{
+    context::Context,
+    convert::CONVERT_SCRIPT,
+    hub::{HubClient, HubError},
+    llama,
+    plan::human_bytes,
+};
+use hf_hub::Cache;
+use reqwest::StatusCode;
+use std::{fmt::Display, path::Path, process::Stdio};
+use tokio::process::Command;
+
+/// Below this much free space, warn: roughly a 7B model's download, f16 GGUF and default quants.
+const LOW_DISK_SPACE: u64 = 60 * 1024 * 1024 * 1024;
+
+/// Python modules `convert_hf_to_gguf.py` imports from its requirements.
+const CONVERTER_MODULES: &[&str] = &["numpy", "sentencepiece", "transformers", "torch"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    /// Works, but some runs will fail or be slow.
+    Warn,
+    Fail,
+}
+
+/// The outcome of one check, with how to fix it unless it passed.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let icon = match self.status {
+            CheckStatus::Pass => "✅",
+            CheckStatus::Warn => "⚠️",
+            CheckStatus::Fail => "❌",
+        };
+        write!(f, "{icon} {}: {}", self.name, self.detail)?;
+        if let Some(fix) = &self.fix {
+            write!(f, "\n   → {fix}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The first line `program args` prints, if it runs and succeeds.
+async fn version(program: impl AsRef<std::ffi::OsStr>, args: &[&str]) -> Option<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+    // NOTE: python 2 and some tools print their version on stderr
+    let text = match output.stdout.is_empty() {
+        true => output.stderr,
+        false => output.stdout,
+    };
+    output.status.success().then(|| {
+        String::from_utf8_lossy(&text)
+            .lines()
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_string()
+    })
+}
+
+pub(crate) async fn git() -> Check {
+    match version("git", &["--version"]).await {
+        Some(version) => Check::pass("git", version),
+        None => Check::fail(
+            "git",
+            "not found on PATH",
+            "install git; it's needed to clone and update llama.cpp",
+        ),
+    }
+}
+
+/// The interpreter conversion will use, and whether the converter's modules import in it.
+pub(crate) async fn python(llama_path: &Path) -> Vec<Check> {
+    let venv_python = llama::venv_python(llama_path);
+    let (python, source) = match venv_python.exists() {
+        true => (venv_python.into_os_string(), "llama.cpp venv"),
+        false => (llama::python().into(), "system"),
+    };
+    let Some(version) = version(&python, &["--version"]).await else {
+        return vec![Check::fail(
+            "python",
+            format!("{} not found", python.to_string_lossy()),
+            "install Python 3 and make sure python3 (or python) is on PATH",
+        )];
+    };
+    let python_check = Check::pass("python", format!("{version} ({source})"));
+
+    let imports = format!("import {}", CONVERTER_MODULES.join(", "));
+    let deps_check = match Command::new(&python)
+        .args(["-c", &imports])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+    {
+        Ok(status) if status.success() => {
+            Check::pass("converter deps", CONVERTER_MODULES.join(", "))
+        }
+        _ => Check::fail(
+            "converter deps",
+            format!("couldn't import {}", CONVERTER_MODULES.join(", ")),
+            format!(
+                "run with --update-llama to install llama.cpp's requirements into {}",
+                llama_path.join(llama::VENV_DIR).display()
+            ),
+        ),
+    };
+    vec![python_check, deps_check]
+}
+
+pub(crate) fn converter(llama_path: &Path) -> Check {
+    let script = llama_path.join(CONVERT_SCRIPT);
+    match script.exists() {
+        true => Check::pass("converter", script.display().to_string()),
+        false => Check::fail(
+            "converter",
+            format!("{} not found", script.display()),
+            "run with --update-llama to clone llama.cpp there, or point --llama-path at a \
+             llama.cpp checkout",
+        ),
+    }
+}
+
+pub(crate) fn binaries(llama_bin: &Path) -> Check {
+    let missing: Vec<String> = ["llama-quantize", "llama-imatrix", "llama-gguf-split"]
+        .iter()
+        .map(|name| llama::binary(llama_bin, name))
+        .filter(|path| !path.exists())
+        .map(|path| path.display().to_string())
+        .collect();
+    match missing.is_empty() {
+        true => Check::pass("llama.cpp binaries", llama_bin.display().to_string()),
+        false => Check::fail(
+            "llama.cpp binaries",
+            format!("missing {}", missing.join(", ")),
+            "run with --update-llama to build them, or --update-llama --llama-prebuilt to \
+             download release binaries",
+        ),
+    }
+}
+
+/// Whether `hf_token` is valid and there's a user to upload as. Downloads can also use the token
+/// `huggingface-cli login` saved, but uploads only use `hf_token`.
+pub(crate) async fn huggingface(hf_user: &str, hf_token: &str, ctx: &Context) -> Check {
+    const NAME: &str = "huggingface auth";
+    if hf_token.is_empty() {
+        let detail = match Cache::from_env().token() {
+            Some(_) => {
+                "no --hf-token or HF_TOKEN; downloads will use the saved login, but \
+                        uploads will fail"
+            }
+            None => "no --hf-token or HF_TOKEN; gated models and uploads will fail",
+        };
+        return Check::warn(
+            NAME,
+            detail,
+            "create a write token at https://huggingface.co/settings/tokens and set HF_TOKEN",
+        );
+    }
+    match HubClient::new(hf_token, ctx.clone()).whoami().await {
+        Ok(name) if hf_user.is_empty() => Check::warn(
+            NAME,
+            format!("logged in as {name}, but there's no --hf-user or HF_USER to upload as"),
+            format!("set HF_USER={name}"),
+        ),
+        Ok(name) => Check::pass(NAME, format!("logged in as {name}, uploading as {hf_user}")),
+        Err(e)
+            if e.downcast_ref::<HubError>()
+                .is_some_and(|e| e.status == StatusCode::UNAUTHORIZED) =>
+        {
+            Check::fail(
+                NAME,
+                "the token was rejected",
+                "create a new token at https://huggingface.co/settings/tokens and set HF_TOKEN",
+            )
+        }
+        Err(e) => Check::warn(
+            NAME,
+            format!("couldn't check the token: {e}"),
+            "check your connection to the HuggingFace Hub",
+        ),
+    }
+}
+
+/// A GPU for imatrix generation to offload to. Not required, but imatrix quants take hours
+/// instead of minutes without one.
+pub(crate) async fn gpu() -> Check {
+    if cfg!(target_os = "macos") {
+        return Check::pass("gpu", "Metal");
+    }
+    if let Some(gpu) = version("nvidia-smi", &["-L"]).await {
+        return Check::pass("gpu", gpu);
+    }
+    // NOTE: rocm-smi's output starts with a banner, so just report that it works
+    if version("rocm-smi", &["--showproductname"]).await.is_some() {
+        return Check::pass("gpu", "AMD GPU (rocm-smi)");
+    }
+    Check::warn(
+        "gpu",
+        "no NVIDIA or AMD GPU found; imatrix generation will run on the CPU and be slow",
+        "pass --gpu-layers 0 on CPU-only machines, or skip the imatrix quants",
+    )
+}
+
+pub(crate) fn disk_space(dir: &Path) -> Check {
+    match fs4::available_space(dir) {
+        Ok(available) if available < LOW_DISK_SPACE => Check::warn(
+            "disk space",
+            format!("{} free in {}", human_bytes(available), dir.display()),
+            "free up space, or pass --cleanup sources,fp to delete files as soon as they're \
+             no longer needed",
+        ),
+        Ok(available) => Check::pass(
+            "disk space",
+            format!("{} free in {}", human_bytes(available), dir.display()),
+        ),
+        Err(e) => Check::warn(
+            "disk space",
+            format!("couldn't check free space in {}: {e}", dir.display()),
+            "make sure the directory exists and is readable",
+        ),
+    }
+}
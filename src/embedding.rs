@@ -0,0 +1,126 @@
+//! Embedding and reranker models: encoders like BERT, and rerankers scoring a query against a
+//! document, which llama.cpp runs with `llama-embedding` or `llama-server --embedding` rather than
+//! generating text. Their GGUFs need the right pooling to produce anything useful, and they
+//! suffer from low-bit quantization far more than chat models do.
+
+use crate::{context::Context, event::Stage, llama, QuantLevel};
+use serde_json::Value as Json;
+use std::{fmt::Display, path::Path};
+use tokio::process::Command;
+
+/// Where sentence-transformers keeps the pooling an embedding model was trained with.
+pub(crate) const POOLING_CONFIG: &str = "1_Pooling/config.json";
+
+/// The levels worth making of an embedding model. Anything below 4 bits loses too much of what
+/// separates one embedding from another, and imatrix quants need a causal model to calibrate.
+pub(crate) const QUANTS: &[QuantLevel] = &[
+    QuantLevel::Q4KM,
+    QuantLevel::Q5KM,
+    QuantLevel::Q6K,
+    QuantLevel::Q8_0,
+    QuantLevel::BF16,
+    QuantLevel::F16,
+    QuantLevel::F32,
+];
+
+/// The text embedded to check the output's dimensionality.
+const CHECK_PROMPT: &str = "The quick brown fox jumps over the lazy dog.";
+
+/// How the model combines its token embeddings into one, numbered as llama.cpp's
+/// `{arch}.pooling_type` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Pooling {
+    Mean = 1,
+    Cls = 2,
+    Last = 3,
+    /// A reranker's: a relevance score rather than an embedding.
+    Rank = 4,
+}
+
+impl Display for Pooling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Mean => "mean",
+            Self::Cls => "cls",
+            Self::Last => "last",
+            Self::Rank => "rank",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The pooling of the model with `config.json` `config` and, if it's a sentence-transformers
+/// model, [`POOLING_CONFIG`] `pooling`, or `None` if it isn't an embedding or reranker model.
+/// Classifiers are taken to be rerankers; encoders without a pooling config pool by mean, as
+/// sentence-transformers does by default.
+pub(crate) fn detect(config: &Json, pooling: Option<&Json>) -> Option<Pooling> {
+    let architectures: Vec<&str> = config
+        .get("architectures")
+        .and_then(Json::as_array)
+        .map(|a| a.iter().filter_map(Json::as_str).collect())
+        .unwrap_or_default();
+    if architectures
+        .iter()
+        .any(|a| a.ends_with("ForSequenceClassification"))
+    {
+        return Some(Pooling::Rank);
+    }
+    let is_mode = |mode: &str| {
+        pooling
+            .and_then(|p| p.get(format!("pooling_mode_{mode}")))
+            .and_then(Json::as_bool)
+            .unwrap_or(false)
+    };
+    if is_mode("cls_token") {
+        Some(Pooling::Cls)
+    } else if is_mode("lasttoken") {
+        Some(Pooling::Last)
+    } else if pooling.is_some() || is_encoder(&architectures) {
+        Some(Pooling::Mean)
+    } else {
+        None
+    }
+}
+
+/// Whether `architectures` are a bare encoder, like `BertModel` or `XLMRobertaModel`, rather than
+/// one with a head, like `LlamaForCausalLM`.
+fn is_encoder(architectures: &[&str]) -> bool {
+    !architectures.is_empty()
+        && architectures
+            .iter()
+            .all(|a| a.ends_with("Model") && !a.contains("For"))
+}
+
+/// Embed a sentence with `model` using `llama-embedding`, returning how many dimensions the
+/// embedding has.
+pub(crate) async fn output_dimensions(
+    model: &Path,
+    llama_bin: &Path,
+    ctx: &Context,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut command = Command::new(llama::binary(llama_bin, "llama-embedding"));
+    command
+        .arg("-m")
+        .arg(model)
+        .args(["-p", CHECK_PROMPT])
+        .args(["--embd-output-format", "json"]);
+    let output = ctx
+        .run_capturing(&Stage::Convert, command, "llama-embedding")
+        .await?;
+    // NOTE: the JSON is pretty-printed over several lines of stdout, which comes first
+    let start = output
+        .iter()
+        .position(|line| line.starts_with('{'))
+        .ok_or("llama-embedding printed no embedding")?;
+    let json = output[start..].join("\n");
+    let parsed: Json = serde_json::Deserializer::from_str(&json)
+        .into_iter()
+        .next()
+        .ok_or("llama-embedding printed no embedding")??;
+    let dimensions = parsed
+        .pointer("/data/0/embedding")
+        .and_then(Json::as_array)
+        .map(Vec::len)
+        .ok_or("llama-embedding's output has no embedding")?;
+    Ok(dimensions)
+}
@@ -0,0 +1,42 @@
+use crate::Stage;
+use std::error::Error as StdError;
+
+/// Why a [`Pipeline`](crate::Pipeline) run failed, by the stage it failed in, so a wrapper can
+/// tell a dropped connection it should retry from a model llama.cpp can't convert.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Checking the llama.cpp install, the model's architecture, disk space and the like,
+    /// before any stage ran.
+    #[error("{0}")]
+    Setup(Box<dyn StdError>),
+    #[error("{0}")]
+    Download(Box<dyn StdError>),
+    #[error("{0}")]
+    Convert(Box<dyn StdError>),
+    #[error("{0}")]
+    Imatrix(Box<dyn StdError>),
+    /// One or more quants failed; the rest were still made and uploaded.
+    #[error("{0}")]
+    Quantize(Box<dyn StdError>),
+    #[error("{0}")]
+    Upload(Box<dyn StdError>),
+    /// The run was cancelled, e.g. by Ctrl-C.
+    #[error("{0}")]
+    Interrupted(Box<dyn StdError>),
+}
+
+impl Error {
+    /// `error`, attributed to the stage it happened in, or to setup if none had started.
+    pub(crate) fn in_stage(stage: Option<&Stage>, error: Box<dyn StdError>) -> Self {
+        match stage {
+            None | Some(Stage::UpdateLlama) => Error::Setup(error),
+            Some(Stage::Download) => Error::Download(error),
+            Some(Stage::Convert) => Error::Convert(error),
+            Some(Stage::Imatrix) => Error::Imatrix(error),
+            // NOTE: evaluation failures are only warned about, so anything after quantizing is
+            // the quants' doing
+            Some(Stage::Quantize(_) | Stage::Eval) => Error::Quantize(error),
+            Some(Stage::Upload) => Error::Upload(error),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! `autogguf estimate`: how big each quant of a model would be, from nothing but its
+//! `config.json`, before committing to a download.
+
+use crate::{
+    context::Context, gguf::human_count, hub::HubClient, plan::human_bytes, retry::DEFAULT_RETRIES,
+    QuantLevel,
+};
+use serde_json::Value as Json;
+use std::{fmt::Display, sync::Arc};
+use tokio::sync::Notify;
+
+/// A model's parameter count, and the estimated size of every quant level.
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    pub model_id: String,
+    pub parameters: u64,
+    /// Smallest first, with each level's bits per weight and estimated size in bytes.
+    pub sizes: Vec<(QuantLevel, f64, u64)>,
+}
+
+impl Display for Estimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "📐 {}: ~{} parameters, from config.json",
+            self.model_id,
+            human_count(self.parameters)
+        )?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "| Quant | Bits per weight | Estimated size | Needs imatrix |"
+        )?;
+        writeln!(
+            f,
+            "| ----- | --------------: | -------------: | ------------- |"
+        )?;
+        for (level, bits_per_weight, bytes) in &self.sizes {
+            let imatrix = match level.requires_imatrix() {
+                true => "yes",
+                false => "",
+            };
+            writeln!(
+                f,
+                "| {} | {bits_per_weight:.2} | {} | {imatrix} |",
+                level.to_string().to_uppercase(),
+                human_bytes(*bytes)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetch `model_id`'s `config.json` at `revision` and estimate every quant level's size.
+pub async fn estimate(
+    model_id: &str,
+    revision: Option<&str>,
+    hf_token: &str,
+) -> Result<Estimate, Box<dyn std::error::Error>> {
+    let ctx = Context {
+        verbose: false,
+        cancel: Arc::new(Notify::new()),
+        interrupted: Default::default(),
+        events: None,
+        log_dir: None,
+        summary: Default::default(),
+        retries: DEFAULT_RETRIES,
+        timeouts: Default::default(),
+    };
+    let client = HubClient::new(hf_token, ctx);
+    let config = client
+        .config(model_id, revision.unwrap_or("main"))
+        .await
+        .map_err(|e| format!("couldn't fetch {model_id}'s config.json: {e}"))?;
+    let parameters = parameter_count(&config)
+        .map_err(|e| format!("couldn't count {model_id}'s parameters: {e}"))?;
+    let mut sizes: Vec<_> = QuantLevel::ALL
+        .iter()
+        .filter(|level| **level != QuantLevel::Copy)
+        .map(|level| {
+            let bits_per_weight = level.bits_per_weight();
+            let bytes = (parameters as f64 * bits_per_weight / 8.0) as u64;
+            (level.clone(), bits_per_weight, bytes)
+        })
+        .collect();
+    sizes.sort_by(|a, b| a.1.total_cmp(&b.1));
+    Ok(Estimate {
+        model_id: model_id.to_string(),
+        parameters,
+        sizes,
+    })
+}
+
+/// Count a decoder-only transformer's parameters from its hyperparameters: embeddings,
+/// attention and (gated, possibly mixture-of-experts) MLP weights per layer, and the output head
+/// unless it's tied to the embeddings. Biases are left out, being a rounding error.
+fn parameter_count(config: &Json) -> Result<u64, String> {
+    // NOTE: multimodal configs nest the language model's under `text_config`
+    let text = config.get("text_config").unwrap_or(config);
+    let get = |key: &str| text.get(key).or_else(|| config.get(key));
+    let int = |keys: &[&str]| keys.iter().find_map(|key| get(key).and_then(Json::as_u64));
+    let required = |keys: &[&str]| int(keys).ok_or(format!("no {} in config.json", keys[0]));
+    let hidden = required(&["hidden_size", "n_embd", "d_model"])?;
+    let layers = required(&["num_hidden_layers", "n_layer", "num_layers"])?;
+    let vocab = required(&["vocab_size"])?;
+    let heads = required(&["num_attention_heads", "n_head"])?;
+    let kv_heads = int(&["num_key_value_heads"]).unwrap_or(heads);
+    let head_dim = int(&["head_dim"]).unwrap_or(hidden / heads.max(1));
+    let intermediate = int(&["intermediate_size", "n_inner", "ffn_dim"]).unwrap_or(4 * hidden);
+    // NOTE: transformers ties them unless the config says otherwise
+    let tied = get("tie_word_embeddings")
+        .and_then(Json::as_bool)
+        .unwrap_or(true);
+
+    let attention = hidden * head_dim * (2 * heads + 2 * kv_heads);
+    let experts = int(&["num_local_experts", "num_experts", "n_routed_experts"]).unwrap_or(0);
+    let mlp = match experts {
+        0 => 3 * hidden * intermediate,
+        experts => {
+            let expert = int(&["moe_intermediate_size"]).unwrap_or(intermediate);
+            let shared = int(&["shared_expert_intermediate_size"])
+                .unwrap_or_else(|| int(&["n_shared_experts"]).unwrap_or(0) * expert);
+            3 * hidden * (experts * expert + shared) + hidden * experts
+        }
+    };
+    let norms = 2 * hidden;
+    let embeddings = vocab * hidden;
+    let output = if tied { 0 } else { vocab * hidden };
+    Ok(embeddings + layers * (attention + mlp + norms) + hidden + output)
+}
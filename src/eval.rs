@@ -0,0 +1,491 @@
+//! Measuring what quantization costs: each quant's perplexity next to the full-precision GGUF's,
+//! and how far its token probabilities drift from the full-precision GGUF's (KL-divergence), as
+//! well as how fast each runs on this machine.
+//! Results are kept in the output directory, so the model card can show them and an interrupted
+//! run doesn't measure the same file twice.
+
+use crate::{
+    context::Context,
+    convert::{self, ImatrixParams},
+    event::Stage,
+    llama,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+use tokio::{fs::File, process::Command};
+
+pub(crate) const EVAL_FILE: &str = "eval.json";
+/// wikitext-2's raw test split, which llama.cpp's perplexity numbers are usually quoted on.
+pub(crate) const WIKITEXT_URL: &str =
+    "https://huggingface.co/datasets/ggml-org/ci/resolve/main/wikitext-2-raw-v1.zip";
+const WIKITEXT_TEST_FILE: &str = "wikitext-2-raw/wiki.test.raw";
+const EVAL_DATA_FILE: &str = "eval_data.txt";
+/// The full-precision GGUF's logits over the dataset, which each quant's are compared to. Several
+/// GB for a big vocab, so it only lives as long as the eval stage.
+const KLD_BASE_FILE: &str = "kld_base.bin";
+/// `llama-bench`'s standard tests: processing a 512-token prompt, and generating 128 tokens.
+const BENCH_PROMPT: u32 = 512;
+const BENCH_GEN: u32 = 128;
+
+/// Everything measured about a model's GGUFs so far.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EvalResults {
+    /// What everything was measured on: a path or URL, or `None` for wikitext-2.
+    #[serde(default)]
+    pub dataset: Option<String>,
+    #[serde(default)]
+    pub perplexity: Vec<Perplexity>,
+    #[serde(default)]
+    pub kl_divergence: Vec<KlDivergence>,
+    /// The CPU or GPU the speed benchmarks ran on, as `llama-bench` reports it.
+    #[serde(default)]
+    pub bench_hardware: Option<String>,
+    #[serde(default)]
+    pub bench: Vec<Bench>,
+}
+
+/// One GGUF's perplexity over the dataset. Lower is better; the gap to the full-precision
+/// baseline is what the quant costs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Perplexity {
+    /// The quant level or precision, e.g. `Q4_K_M` or `F16`.
+    pub label: String,
+    /// Whether this is the full-precision GGUF the quants are compared to.
+    pub baseline: bool,
+    pub ppl: f64,
+    /// `llama-perplexity`'s ± estimate of the error.
+    pub uncertainty: f64,
+}
+
+/// How far a quant's token probabilities are from the full-precision GGUF's over the dataset.
+/// Unlike perplexity, this catches a quant being wrong differently, not just worse on average.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KlDivergence {
+    pub label: String,
+    pub mean: f64,
+    /// `llama-perplexity`'s ± estimate of the error in `mean`.
+    pub uncertainty: f64,
+    pub median: f64,
+    /// The 99th percentile: how bad the worst tokens get.
+    pub p99: f64,
+    /// How often the quant's most likely token is the full-precision GGUF's, in percent.
+    pub same_top_p: f64,
+}
+
+/// How fast a GGUF runs on this machine, in tokens per second.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bench {
+    pub label: String,
+    pub prompt_tps: f64,
+    pub prompt_stddev: f64,
+    pub gen_tps: f64,
+    pub gen_stddev: f64,
+}
+
+impl EvalResults {
+    /// The results saved in `model_dir`, or none if there aren't any yet.
+    pub(crate) async fn load(model_dir: &Path) -> Self {
+        let Ok(contents) = tokio::fs::read_to_string(model_dir.join(EVAL_FILE)).await else {
+            return Self::default();
+        };
+        serde_json::from_str(&contents).unwrap_or_default()
+    }
+
+    pub(crate) async fn save(&self, model_dir: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        tokio::fs::write(model_dir.join(EVAL_FILE), json).await
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.perplexity.is_empty() && self.kl_divergence.is_empty() && self.bench.is_empty()
+    }
+
+    /// Start over if the results were measured on a different dataset.
+    pub(crate) fn use_dataset(&mut self, dataset: Option<&str>) {
+        if self.dataset.as_deref() != dataset {
+            self.dataset = dataset.map(ToString::to_string);
+            self.perplexity.clear();
+            self.kl_divergence.clear();
+        }
+    }
+
+    pub(crate) fn has_perplexity(&self, label: &str) -> bool {
+        self.perplexity.iter().any(|p| p.label == label)
+    }
+
+    pub(crate) fn has_kl_divergence(&self, label: &str) -> bool {
+        self.kl_divergence.iter().any(|k| k.label == label)
+    }
+
+    pub(crate) fn has_bench(&self, label: &str) -> bool {
+        self.bench.iter().any(|b| b.label == label)
+    }
+
+    /// Record a benchmark, starting over if the earlier ones ran on different hardware.
+    pub(crate) fn record_bench(&mut self, bench: Bench, hardware: String) {
+        if self.bench_hardware.as_ref() != Some(&hardware) {
+            self.bench_hardware = Some(hardware);
+            self.bench.clear();
+        }
+        self.bench.push(bench);
+    }
+
+    /// A name for the dataset: wikitext-2 by default, a file name for a local file.
+    pub fn dataset_name(&self) -> String {
+        match &self.dataset {
+            None => "wikitext-2 (test)".to_string(),
+            Some(source) if convert::is_url(source) => source.clone(),
+            Some(path) => Path::new(path)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+}
+
+/// Markdown tables, for both the terminal and the model card.
+impl Display for EvalResults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sections = vec![];
+        if !self.perplexity.is_empty() {
+            sections.push(self.perplexity_table());
+        }
+        if !self.kl_divergence.is_empty() {
+            sections.push(self.kl_divergence_table());
+        }
+        if !self.bench.is_empty() {
+            sections.push(self.bench_table());
+        }
+        write!(f, "{}", sections.join("\n"))
+    }
+}
+
+impl EvalResults {
+    fn perplexity_table(&self) -> String {
+        let baseline = self.perplexity.iter().find(|p| p.baseline);
+        let baseline_label = baseline.map_or("full precision", |b| b.label.as_str());
+        let mut table = format!(
+            "Perplexity on {} with `llama-perplexity` (lower is better):\n\n\
+             | GGUF | Perplexity | vs. {baseline_label} |\n\
+             | ---- | ---------: | ------: |\n",
+            self.dataset_name()
+        );
+        for p in &self.perplexity {
+            let delta = match baseline {
+                Some(baseline) if !p.baseline => {
+                    format!("{:+.2}%", (p.ppl / baseline.ppl - 1.0) * 100.0)
+                }
+                _ => "-".to_string(),
+            };
+            table.push_str(&format!(
+                "| {} | {:.4} ± {:.4} | {delta} |\n",
+                p.label, p.ppl, p.uncertainty
+            ));
+        }
+        table
+    }
+
+    fn kl_divergence_table(&self) -> String {
+        let mut table = format!(
+            "KL-divergence from the full-precision GGUF's token probabilities on {} with \
+             `llama-perplexity` (lower is better), and how often both pick the same most likely \
+             token:\n\n\
+             | GGUF | Mean KLD | Median KLD | 99% KLD | Same top token |\n\
+             | ---- | -------: | ---------: | ------: | -------------: |\n",
+            self.dataset_name()
+        );
+        for k in &self.kl_divergence {
+            table.push_str(&format!(
+                "| {} | {:.6} ± {:.6} | {:.6} | {:.6} | {:.2}% |\n",
+                k.label, k.mean, k.uncertainty, k.median, k.p99, k.same_top_p
+            ));
+        }
+        table
+    }
+
+    fn bench_table(&self) -> String {
+        let hardware = self
+            .bench_hardware
+            .as_deref()
+            .unwrap_or("the quantizing machine");
+        let mut table = format!(
+            "Speed on {hardware} with `llama-bench`, in tokens per second (higher is better):\n\n\
+             | GGUF | Prompt processing (pp{BENCH_PROMPT}) | Text generation (tg{BENCH_GEN}) |\n\
+             | ---- | ------------------: | -------------------: |\n"
+        );
+        for b in &self.bench {
+            table.push_str(&format!(
+                "| {} | {:.2} ± {:.2} | {:.2} ± {:.2} |\n",
+                b.label, b.prompt_tps, b.prompt_stddev, b.gen_tps, b.gen_stddev
+            ));
+        }
+        table
+    }
+}
+
+/// Get the text to measure perplexity on: `source` as-is if it's a local file, else downloaded
+/// into `dir`. Returns its path, and whether it's a download to clean up afterwards.
+pub(crate) async fn prepare_dataset(
+    source: Option<&str>,
+    dir: &Path,
+    ctx: &Context,
+) -> Result<(PathBuf, bool), Box<dyn std::error::Error>> {
+    let path = dir.join(EVAL_DATA_FILE);
+    match source {
+        Some(source) if !convert::is_url(source) => Ok((PathBuf::from(source), false)),
+        Some(url) => {
+            ctx.detail(format!("🌐 downloading eval dataset {url}..."));
+            let downloaded =
+                convert::download(url, &mut File::create(&path).await?, &Stage::Eval, ctx).await;
+            if let Err(e) = downloaded {
+                let _ = tokio::fs::remove_file(&path).await;
+                return Err(e);
+            }
+            Ok((path, true))
+        }
+        None => {
+            ctx.detail(format!("🌐 downloading wikitext-2 from {WIKITEXT_URL}..."));
+            let archive = dir.join("wikitext-2-raw-v1.zip");
+            let downloaded = convert::download(
+                WIKITEXT_URL,
+                &mut File::create(&archive).await?,
+                &Stage::Eval,
+                ctx,
+            )
+            .await;
+            if let Err(e) = downloaded {
+                let _ = tokio::fs::remove_file(&archive).await;
+                return Err(e);
+            }
+            let extracted = dir.join("wikitext-2-raw-v1");
+            tokio::fs::create_dir_all(&extracted).await?;
+            ctx.run(
+                &Stage::Eval,
+                llama::unzip_command(&archive, &extracted),
+                "wikitext-2 extraction process",
+            )
+            .await?;
+            tokio::fs::rename(extracted.join(WIKITEXT_TEST_FILE), &path).await?;
+            tokio::fs::remove_dir_all(&extracted).await?;
+            tokio::fs::remove_file(&archive).await?;
+            Ok((path, true))
+        }
+    }
+}
+
+pub(crate) fn perplexity_command(
+    llama_bin: &Path,
+    model: &Path,
+    dataset: &Path,
+    params: &ImatrixParams,
+) -> Command {
+    let mut command = Command::new(llama::binary(llama_bin, "llama-perplexity"));
+    command
+        .arg("-m")
+        .arg(model)
+        .arg("-f")
+        .arg(dataset)
+        .arg("-t")
+        .arg(params.threads.to_string())
+        .args(params.offload_args());
+    command
+}
+
+/// Measure `model`'s perplexity on `dataset` with `llama-perplexity`.
+pub(crate) async fn perplexity(
+    label: &str,
+    baseline: bool,
+    model: &Path,
+    dataset: &Path,
+    llama_bin: &Path,
+    params: &ImatrixParams,
+    ctx: &Context,
+) -> Result<Perplexity, Box<dyn std::error::Error>> {
+    ctx.detail(format!("📏 measuring {label} perplexity..."));
+    let command = perplexity_command(llama_bin, model, dataset, params);
+    let output = ctx
+        .run_capturing(&Stage::Eval, command, "Perplexity process")
+        .await?;
+    let (ppl, uncertainty) = output
+        .iter()
+        .find_map(|line| parse_final_estimate(line))
+        .ok_or_else(|| format!("💥 llama-perplexity printed no final estimate for {label}"))?;
+    ctx.detail(format!("📏 {label}: PPL = {ppl:.4} ± {uncertainty:.4}"));
+    Ok(Perplexity {
+        label: label.to_string(),
+        baseline,
+        ppl,
+        uncertainty,
+    })
+}
+
+/// Save `fp`'s logits over `dataset` to [`KLD_BASE_FILE`] in `dir`, for [`kl_divergence`] to
+/// compare quants against.
+pub(crate) async fn kld_base(
+    fp: &Path,
+    dataset: &Path,
+    dir: &Path,
+    llama_bin: &Path,
+    params: &ImatrixParams,
+    ctx: &Context,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    ctx.detail("📏 saving full-precision logits for KL-divergence...");
+    let logits = dir.join(KLD_BASE_FILE);
+    let mut command = perplexity_command(llama_bin, fp, dataset, params);
+    command.arg("--kl-divergence-base").arg(&logits);
+    ctx.run(&Stage::Eval, command, "KL-divergence base process")
+        .await?;
+    Ok(logits)
+}
+
+pub(crate) fn kl_divergence_command(
+    llama_bin: &Path,
+    model: &Path,
+    logits: &Path,
+    params: &ImatrixParams,
+) -> Command {
+    let mut command = Command::new(llama::binary(llama_bin, "llama-perplexity"));
+    command
+        .arg("-m")
+        .arg(model)
+        .arg("--kl-divergence-base")
+        .arg(logits)
+        .arg("--kl-divergence")
+        .arg("-t")
+        .arg(params.threads.to_string())
+        .args(params.offload_args());
+    command
+}
+
+/// Compare `model`'s token probabilities to the full-precision ones saved in `logits`.
+pub(crate) async fn kl_divergence(
+    label: &str,
+    model: &Path,
+    logits: &Path,
+    llama_bin: &Path,
+    params: &ImatrixParams,
+    ctx: &Context,
+) -> Result<KlDivergence, Box<dyn std::error::Error>> {
+    ctx.detail(format!("📏 measuring {label} KL-divergence..."));
+    let command = kl_divergence_command(llama_bin, model, logits, params);
+    let output = ctx
+        .run_capturing(&Stage::Eval, command, "KL-divergence process")
+        .await?;
+    let (mean, uncertainty) = parse_statistic(&output, "Mean KLD").ok_or_else(|| {
+        format!("💥 llama-perplexity printed no KL-divergence statistics for {label}")
+    })?;
+    let value = |name| parse_statistic(&output, name).map_or(f64::NAN, |(value, _)| value);
+    let kld = KlDivergence {
+        label: label.to_string(),
+        mean,
+        uncertainty: uncertainty.unwrap_or(0.0),
+        median: value("Median KLD"),
+        p99: value("99.0% KLD"),
+        same_top_p: value("Same top p"),
+    };
+    ctx.detail(format!("📏 {label}: mean KLD = {:.6}", kld.mean));
+    Ok(kld)
+}
+
+pub(crate) fn bench_command(llama_bin: &Path, model: &Path, params: &ImatrixParams) -> Command {
+    let mut command = Command::new(llama::binary(llama_bin, "llama-bench"));
+    command
+        .arg("-m")
+        .arg(model)
+        .arg("-p")
+        .arg(BENCH_PROMPT.to_string())
+        .arg("-n")
+        .arg(BENCH_GEN.to_string())
+        .arg("-t")
+        .arg(params.threads.to_string())
+        .args(params.offload_args())
+        .args(["-o", "jsonl"]);
+    command
+}
+
+/// One of `llama-bench`'s JSON lines: a test's result and what it ran on.
+#[derive(Debug, Deserialize)]
+struct BenchResult {
+    n_prompt: u32,
+    n_gen: u32,
+    avg_ts: f64,
+    stddev_ts: f64,
+    #[serde(default)]
+    cpu_info: String,
+    #[serde(default)]
+    gpu_info: String,
+    #[serde(default)]
+    backends: String,
+}
+
+/// Benchmark `model` with `llama-bench`, returning its speeds and the hardware they're for.
+pub(crate) async fn bench(
+    label: &str,
+    model: &Path,
+    llama_bin: &Path,
+    params: &ImatrixParams,
+    ctx: &Context,
+) -> Result<(Bench, String), Box<dyn std::error::Error>> {
+    ctx.detail(format!("⏱️ benchmarking {label}..."));
+    let command = bench_command(llama_bin, model, params);
+    let output = ctx
+        .run_capturing(&Stage::Eval, command, "Benchmark process")
+        .await?;
+    let results: Vec<BenchResult> = output
+        .iter()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let test = |prompt, gen| {
+        results
+            .iter()
+            .find(|r| r.n_prompt == prompt && r.n_gen == gen)
+            .ok_or_else(|| {
+                format!("💥 llama-bench printed no pp{prompt}/tg{gen} result for {label}")
+            })
+    };
+    let (prompt, gen) = (test(BENCH_PROMPT, 0)?, test(0, BENCH_GEN)?);
+    let hardware = match prompt.gpu_info.is_empty() {
+        true => prompt.cpu_info.clone(),
+        false => format!("{} ({})", prompt.gpu_info, prompt.backends),
+    };
+    ctx.detail(format!(
+        "⏱️ {label}: {:.2} t/s prompt processing, {:.2} t/s generation",
+        prompt.avg_ts, gen.avg_ts
+    ));
+    let bench = Bench {
+        label: label.to_string(),
+        prompt_tps: prompt.avg_ts,
+        prompt_stddev: prompt.stddev_ts,
+        gen_tps: gen.avg_ts,
+        gen_stddev: gen.stddev_ts,
+    };
+    Ok((bench, hardware))
+}
+
+/// One of `llama-perplexity`'s KL-divergence statistics, printed as `Name: value` or
+/// `Name: value ± error`, with the name padded out, e.g. `Same top p: 94.123 ± 0.123 %`.
+fn parse_statistic(output: &[String], name: &str) -> Option<(f64, Option<f64>)> {
+    output.iter().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key.split_whitespace().collect::<Vec<_>>().join(" ") != name {
+            return None;
+        }
+        let value = value.trim().trim_end_matches('%');
+        let (value, error) = match value.split_once('±') {
+            Some((value, error)) => (value, error.trim().parse().ok()),
+            None => (value, None),
+        };
+        Some((value.trim().parse().ok()?, error))
+    })
+}
+
+/// `llama-perplexity`'s summary line: `Final estimate: PPL = 5.9843 +/- 0.03321`.
+fn parse_final_estimate(line: &str) -> Option<(f64, f64)> {
+    let (_, estimate) = line.split_once("Final estimate: PPL = ")?;
+    let (ppl, uncertainty) = estimate.split_once("+/-")?;
+    Some((ppl.trim().parse().ok()?, uncertainty.trim().parse().ok()?))
+}
@@ -0,0 +1,118 @@
+use crate::QuantLevel;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Display, path::PathBuf};
+use tracing::{field::Empty, Span};
+
+/// The target of the spans covering each run and its stages, for exporting to a tracing backend.
+/// Kept apart from the crate's other targets so console output isn't prefixed with span context.
+pub const SPAN_TARGET: &str = "autogguf::span";
+
+/// A stage of the pipeline, as reported in [`Event`]s.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    UpdateLlama,
+    Download,
+    Convert,
+    Imatrix,
+    Quantize(QuantLevel),
+    /// Measuring the quants' quality and speed.
+    Eval,
+    Upload,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::UpdateLlama => write!(f, "update llama.cpp"),
+            Stage::Download => write!(f, "download"),
+            Stage::Convert => write!(f, "convert"),
+            Stage::Imatrix => write!(f, "imatrix"),
+            Stage::Quantize(q) => write!(f, "quantize {}", q.to_string().to_uppercase()),
+            Stage::Eval => write!(f, "eval"),
+            Stage::Upload => write!(f, "upload"),
+        }
+    }
+}
+
+impl Stage {
+    /// A span covering this stage of converting `model`, with room for the size of its output
+    /// and whether it failed to be recorded once it's over.
+    pub(crate) fn span(&self, model: &str) -> Span {
+        let quant = match self {
+            Stage::Quantize(level) => Some(level.to_string()),
+            _ => None,
+        };
+        tracing::info_span!(
+            target: SPAN_TARGET,
+            "stage",
+            otel.name = %self,
+            stage = %self.slug(),
+            model,
+            quant,
+            bytes = Empty,
+            otel.status_code = Empty,
+            otel.status_message = Empty,
+        )
+    }
+
+    /// A filesystem-friendly name, e.g. `quantize-q4_k_m`.
+    pub fn slug(&self) -> String {
+        match self {
+            Stage::UpdateLlama => "update-llama".to_string(),
+            Stage::Quantize(q) => format!("quantize-{q}"),
+            stage => stage.to_string(),
+        }
+    }
+}
+
+/// Progress reported by a running pipeline to whoever is listening on its event channel.
+///
+/// Serializes as an object tagged by `"event"`, e.g.
+/// `{"event":"stage_finished","stage":{"quantize":"Q4_K_M"},"output":"...","bytes":4683073952}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    StageStarted {
+        stage: Stage,
+    },
+    StageFinished {
+        stage: Stage,
+        output: Option<PathBuf>,
+        /// Size of `output`, when it's a file.
+        bytes: Option<u64>,
+    },
+    /// The stage was skipped by a flag, or because a previous run already finished it.
+    StageSkipped {
+        stage: Stage,
+        reason: String,
+    },
+    StageFailed {
+        stage: Stage,
+        error: String,
+    },
+    /// Files were committed to a HuggingFace Hub repo.
+    Uploaded {
+        repo_id: String,
+        files: Vec<String>,
+    },
+    /// Files were added to IPFS, with their CIDs by path in the repo.
+    Pinned {
+        cids: BTreeMap<String, String>,
+    },
+    /// A line of stdout or stderr from a stage's subprocess.
+    Output {
+        stage: Stage,
+        line: String,
+    },
+    /// A human-readable status message.
+    Message {
+        text: String,
+    },
+}
+
+/// Mark `span` as having ended in `error`.
+pub(crate) fn record_error(span: &Span, error: &dyn Display) {
+    span.record("otel.status_code", "ERROR");
+    span.record("otel.status_message", error.to_string());
+}
@@ -0,0 +1,863 @@
+//! A minimal GGUF reader and v3 writer: metadata, tensor infos, then aligned tensor data, as laid
+//! out in llama.cpp's `gguf.h`. Just enough for the native converter, to validate outputs and to
+//! fix up a converted GGUF's metadata.
+
+use serde_json::Value as Json;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const ALIGNMENT: u64 = 32;
+const VERSION: u32 = 3;
+
+/// Tensor types the native converter writes, with their `ggml_type` ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GgmlType {
+    F32 = 0,
+    F16 = 1,
+    BF16 = 30,
+}
+
+impl GgmlType {
+    pub fn size(&self) -> usize {
+        match self {
+            GgmlType::F32 => 4,
+            GgmlType::F16 | GgmlType::BF16 => 2,
+        }
+    }
+}
+
+/// A metadata value. Arrays only come in the element types the converter needs.
+#[derive(Debug, Clone)]
+pub(crate) enum Value {
+    U32(u32),
+    F32(f32),
+    Bool(bool),
+    String(String),
+    I32Array(Vec<i32>),
+    F32Array(Vec<f32>),
+    StringArray(Vec<String>),
+}
+
+// NOTE: `gguf_type` ids
+const TYPE_U8: u32 = 0;
+const TYPE_I8: u32 = 1;
+const TYPE_U16: u32 = 2;
+const TYPE_I16: u32 = 3;
+const TYPE_U32: u32 = 4;
+const TYPE_I32: u32 = 5;
+const TYPE_F32: u32 = 6;
+const TYPE_BOOL: u32 = 7;
+const TYPE_STRING: u32 = 8;
+const TYPE_ARRAY: u32 = 9;
+const TYPE_U64: u32 = 10;
+const TYPE_I64: u32 = 11;
+const TYPE_F64: u32 = 12;
+
+/// A tensor's name, shape (outermost dimension first, as in PyTorch) and type.
+#[derive(Debug, Clone)]
+pub(crate) struct TensorInfo {
+    pub name: String,
+    pub shape: Vec<u64>,
+    pub dtype: GgmlType,
+}
+
+impl TensorInfo {
+    pub fn bytes(&self) -> u64 {
+        self.shape.iter().product::<u64>() * self.dtype.size() as u64
+    }
+}
+
+/// Writes a GGUF: [`Writer::header`] once, then [`Writer::tensor`] for each tensor, in the
+/// same order as the infos passed to the header.
+pub(crate) struct Writer<W: Write> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, written: 0 }
+    }
+
+    pub fn header(
+        &mut self,
+        metadata: &[(String, Value)],
+        tensors: &[TensorInfo],
+    ) -> io::Result<()> {
+        self.write(b"GGUF")?;
+        self.u32(VERSION)?;
+        self.u64(tensors.len() as u64)?;
+        self.u64(metadata.len() as u64)?;
+        for (key, value) in metadata {
+            self.string(key)?;
+            self.value(value)?;
+        }
+        let mut offset = 0;
+        for tensor in tensors {
+            self.string(&tensor.name)?;
+            self.u32(tensor.shape.len() as u32)?;
+            // NOTE: ggml lists dimensions innermost first
+            for dim in tensor.shape.iter().rev() {
+                self.u64(*dim)?;
+            }
+            self.u32(tensor.dtype as u32)?;
+            self.u64(offset)?;
+            offset = align(offset + tensor.bytes());
+        }
+        self.pad()
+    }
+
+    /// Write a tensor's data, which must be [`TensorInfo::bytes`] long.
+    pub fn tensor(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write(data)?;
+        self.pad()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn value(&mut self, value: &Value) -> io::Result<()> {
+        match value {
+            Value::U32(v) => {
+                self.u32(TYPE_U32)?;
+                self.u32(*v)
+            }
+            Value::F32(v) => {
+                self.u32(TYPE_F32)?;
+                self.write(&v.to_le_bytes())
+            }
+            Value::Bool(v) => {
+                self.u32(TYPE_BOOL)?;
+                self.write(&[*v as u8])
+            }
+            Value::String(v) => {
+                self.u32(TYPE_STRING)?;
+                self.string(v)
+            }
+            Value::I32Array(values) => {
+                self.array_header(TYPE_I32, values.len())?;
+                values.iter().try_for_each(|v| self.write(&v.to_le_bytes()))
+            }
+            Value::F32Array(values) => {
+                self.array_header(TYPE_F32, values.len())?;
+                values.iter().try_for_each(|v| self.write(&v.to_le_bytes()))
+            }
+            Value::StringArray(values) => {
+                self.array_header(TYPE_STRING, values.len())?;
+                values.iter().try_for_each(|v| self.string(v))
+            }
+        }
+    }
+
+    fn array_header(&mut self, element_type: u32, len: usize) -> io::Result<()> {
+        self.u32(TYPE_ARRAY)?;
+        self.u32(element_type)?;
+        self.u64(len as u64)
+    }
+
+    fn string(&mut self, s: &str) -> io::Result<()> {
+        self.u64(s.len() as u64)?;
+        self.write(s.as_bytes())
+    }
+
+    fn u32(&mut self, v: u32) -> io::Result<()> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn u64(&mut self, v: u64) -> io::Result<()> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn pad(&mut self) -> io::Result<()> {
+        let padding = align(self.written) - self.written;
+        self.write(&vec![0; padding as usize])
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.inner.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+fn align(offset: u64) -> u64 {
+    offset.div_ceil(ALIGNMENT) * ALIGNMENT
+}
+
+/// A GGUF's header: its metadata, and where each tensor's data is.
+#[derive(Debug)]
+pub(crate) struct Header {
+    /// In file order.
+    pub metadata: Vec<(String, Json)>,
+    /// Where each of `metadata` is in the file, key and all.
+    metadata_spans: Vec<Range<u64>>,
+    pub tensors: Vec<TensorEntry>,
+    /// Where the tensor infos are in the file.
+    tensor_infos: Range<u64>,
+    /// Where tensor data starts in the file; tensor offsets are relative to this.
+    pub data_start: u64,
+    alignment: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct TensorEntry {
+    pub name: String,
+    /// Innermost dimension first, as ggml stores them.
+    pub dims: Vec<u64>,
+    pub ggml_type: u32,
+    pub offset: u64,
+}
+
+impl Header {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        self.metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    /// How many layers the model has, from its architecture's `block_count`.
+    pub fn block_count(&self) -> Option<u32> {
+        let arch = self.get("general.architecture")?.as_str()?;
+        let count = self.get(&format!("{arch}.block_count"))?.as_u64()?;
+        count.try_into().ok()
+    }
+}
+
+impl TensorEntry {
+    pub fn elements(&self) -> u64 {
+        self.dims.iter().product()
+    }
+
+    /// The size of the tensor's data, if its type is one this knows the block size of.
+    pub fn bytes(&self) -> Option<u64> {
+        let (_, block_elements, block_bytes) = type_info(self.ggml_type)?;
+        Some(self.elements() / block_elements * block_bytes)
+    }
+
+    /// The type's name as llama.cpp prints it, e.g. `Q4_K`.
+    pub fn type_name(&self) -> String {
+        match type_info(self.ggml_type) {
+            Some((name, _, _)) => name.to_string(),
+            None => format!("type {}", self.ggml_type),
+        }
+    }
+}
+
+/// Name, elements per block and bytes per block of each `ggml_type`, from `ggml.c`'s type traits.
+fn type_info(ggml_type: u32) -> Option<(&'static str, u64, u64)> {
+    Some(match ggml_type {
+        0 => ("F32", 1, 4),
+        1 => ("F16", 1, 2),
+        2 => ("Q4_0", 32, 18),
+        3 => ("Q4_1", 32, 20),
+        6 => ("Q5_0", 32, 22),
+        7 => ("Q5_1", 32, 24),
+        8 => ("Q8_0", 32, 34),
+        9 => ("Q8_1", 32, 36),
+        10 => ("Q2_K", 256, 84),
+        11 => ("Q3_K", 256, 110),
+        12 => ("Q4_K", 256, 144),
+        13 => ("Q5_K", 256, 176),
+        14 => ("Q6_K", 256, 210),
+        15 => ("Q8_K", 256, 292),
+        16 => ("IQ2_XXS", 256, 66),
+        17 => ("IQ2_XS", 256, 74),
+        18 => ("IQ3_XXS", 256, 98),
+        19 => ("IQ1_S", 256, 50),
+        20 => ("IQ4_NL", 32, 18),
+        21 => ("IQ3_S", 256, 110),
+        22 => ("IQ2_S", 256, 82),
+        23 => ("IQ4_XS", 256, 136),
+        24 => ("I8", 1, 1),
+        25 => ("I16", 1, 2),
+        26 => ("I32", 1, 4),
+        27 => ("I64", 1, 8),
+        28 => ("F64", 1, 8),
+        29 => ("IQ1_M", 256, 56),
+        30 => ("BF16", 1, 2),
+        34 => ("TQ1_0", 256, 54),
+        35 => ("TQ2_0", 256, 66),
+        _ => return None,
+    })
+}
+
+/// Read the header of the GGUF at `path`.
+pub(crate) fn read_header(path: &Path) -> io::Result<Header> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = Reader {
+        inner: BufReader::new(file),
+        position: 0,
+        len,
+    };
+    if reader.bytes(4)? != b"GGUF" {
+        return Err(invalid("not a GGUF file"));
+    }
+    let version = reader.u32()?;
+    if !(2..=3).contains(&version) {
+        return Err(invalid(format!("unsupported GGUF version {version}")));
+    }
+    let tensor_count = reader.u64()?;
+    let metadata_count = reader.u64()?;
+    let mut metadata = vec![];
+    let mut metadata_spans = vec![];
+    for _ in 0..metadata_count {
+        let start = reader.position;
+        let key = reader.string()?;
+        let value_type = reader.u32()?;
+        metadata.push((key, reader.value(value_type)?));
+        metadata_spans.push(start..reader.position);
+    }
+    let tensor_infos_start = reader.position;
+    let mut tensors = vec![];
+    for _ in 0..tensor_count {
+        let name = reader.string()?;
+        let n_dims = reader.u32()?;
+        let dims = (0..n_dims)
+            .map(|_| reader.u64())
+            .collect::<io::Result<_>>()?;
+        tensors.push(TensorEntry {
+            name,
+            dims,
+            ggml_type: reader.u32()?,
+            offset: reader.u64()?,
+        });
+    }
+    let mut header = Header {
+        metadata,
+        metadata_spans,
+        tensors,
+        tensor_infos: tensor_infos_start..reader.position,
+        data_start: 0,
+        alignment: ALIGNMENT,
+    };
+    header.alignment = header
+        .get("general.alignment")
+        .and_then(Json::as_u64)
+        .filter(|&alignment| alignment > 0)
+        .unwrap_or(ALIGNMENT);
+    header.data_start = reader.position.div_ceil(header.alignment) * header.alignment;
+    Ok(header)
+}
+
+/// A `KEY=VALUE` metadata override, e.g. `general.url=https://example.com`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataOverride {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for MetadataOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected KEY=VALUE, got '{s}'"))?;
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(format!("'{key}' isn't a metadata key"));
+        }
+        Ok(Self {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// Rewrite the GGUF at `path` with each key in `entries` set to its value, in the type the key
+/// already has, or if it's new, as a string unless llama.cpp reads it as a number. Everything
+/// else is copied byte for byte, tensor data included, so this takes as long as copying the file.
+pub(crate) fn set_metadata(path: &Path, entries: &[(String, String)]) -> io::Result<()> {
+    let header = read_header(path)?;
+    let mut file = File::open(path)?;
+    let mut raw = vec![0; header.tensor_infos.end as usize];
+    file.read_exact(&mut raw)?;
+    let span = |range: &Range<u64>| &raw[range.start as usize..range.end as usize];
+    // NOTE: a key given more than once takes its last value, since a GGUF can't repeat keys
+    let mut unique: Vec<&(String, String)> = vec![];
+    for entry in entries {
+        unique.retain(|(key, _)| *key != entry.0);
+        unique.push(entry);
+    }
+    let value_of = |key: &str| unique.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+
+    let mut metadata = vec![];
+    for ((key, _), range) in header.metadata.iter().zip(&header.metadata_spans) {
+        let entry = span(range);
+        let Some(value) = value_of(key) else {
+            metadata.push(entry.to_vec());
+            continue;
+        };
+        // NOTE: the key's length and bytes come before its type
+        let type_at = 8 + key.len();
+        let value_type = u32::from_le_bytes(entry[type_at..type_at + 4].try_into().unwrap());
+        let mut replaced = entry[..type_at].to_vec();
+        replaced.extend(encode(value_type, value).map_err(|e| invalid(format!("{key}: {e}")))?);
+        metadata.push(replaced);
+    }
+    for (key, value) in &unique {
+        if header.get(key).is_none() {
+            let mut added = (key.len() as u64).to_le_bytes().to_vec();
+            added.extend(key.as_bytes());
+            added.extend(encode(new_key_type(key), value).map_err(invalid)?);
+            metadata.push(added);
+        }
+    }
+
+    let pending = PathBuf::from(format!("{}.pending", path.to_string_lossy()));
+    let written: io::Result<()> = (|| {
+        let mut writer = Writer::new(BufWriter::new(File::create(&pending)?));
+        // NOTE: the magic and version
+        writer.write(&raw[..8])?;
+        writer.u64(header.tensors.len() as u64)?;
+        writer.u64(metadata.len() as u64)?;
+        for entry in &metadata {
+            writer.write(entry)?;
+        }
+        writer.write(span(&header.tensor_infos))?;
+        let padding = writer.written.div_ceil(header.alignment) * header.alignment - writer.written;
+        writer.write(&vec![0; padding as usize])?;
+        file.seek(SeekFrom::Start(header.data_start))?;
+        let mut inner = writer.into_inner();
+        io::copy(&mut file, &mut inner)?;
+        inner.into_inner().map_err(|e| e.into_error())?.sync_all()
+    })();
+    match written {
+        Ok(()) => std::fs::rename(&pending, path),
+        Err(e) => {
+            let _ = std::fs::remove_file(&pending);
+            Err(e)
+        }
+    }
+}
+
+/// The type [`set_metadata`] writes the new key `key` as.
+fn new_key_type(key: &str) -> u32 {
+    match key.rsplit('.').next() {
+        Some("pooling_type") => TYPE_U32,
+        _ => TYPE_STRING,
+    }
+}
+
+/// `value` as metadata of `value_type`, type id first, as it's laid out in the file.
+fn encode(value_type: u32, value: &str) -> Result<Vec<u8>, String> {
+    fn parse<T: FromStr>(value: &str, type_name: &str) -> Result<T, String> {
+        value
+            .parse()
+            .map_err(|_| format!("'{value}' isn't a valid {type_name}"))
+    }
+    let mut bytes = value_type.to_le_bytes().to_vec();
+    match value_type {
+        TYPE_U8 => bytes.extend(parse::<u8>(value, "u8")?.to_le_bytes()),
+        TYPE_I8 => bytes.extend(parse::<i8>(value, "i8")?.to_le_bytes()),
+        TYPE_U16 => bytes.extend(parse::<u16>(value, "u16")?.to_le_bytes()),
+        TYPE_I16 => bytes.extend(parse::<i16>(value, "i16")?.to_le_bytes()),
+        TYPE_U32 => bytes.extend(parse::<u32>(value, "u32")?.to_le_bytes()),
+        TYPE_I32 => bytes.extend(parse::<i32>(value, "i32")?.to_le_bytes()),
+        TYPE_F32 => bytes.extend(parse::<f32>(value, "f32")?.to_le_bytes()),
+        TYPE_BOOL => bytes.push(parse::<bool>(value, "bool")?.into()),
+        TYPE_STRING => {
+            bytes.extend((value.len() as u64).to_le_bytes());
+            bytes.extend(value.as_bytes());
+        }
+        TYPE_U64 => bytes.extend(parse::<u64>(value, "u64")?.to_le_bytes()),
+        TYPE_I64 => bytes.extend(parse::<i64>(value, "i64")?.to_le_bytes()),
+        TYPE_F64 => bytes.extend(parse::<f64>(value, "f64")?.to_le_bytes()),
+        _ => return Err("arrays can't be overridden".to_string()),
+    }
+    Ok(bytes)
+}
+
+/// Check that the GGUF at `path` is whole: a supported version, every tensor's data inside the
+/// file, and the model's architecture in its metadata (unless it's a later split shard, which
+/// only carries the split keys). Returns its header.
+pub(crate) async fn validate(path: &Path) -> Result<Header, String> {
+    let owned = path.to_path_buf();
+    tokio::task::spawn_blocking(move || validate_blocking(&owned))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{} isn't a valid GGUF: {e}", path.display()))
+}
+
+fn validate_blocking(path: &Path) -> Result<Header, String> {
+    let header = read_header(path).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => "truncated header".to_string(),
+        _ => e.to_string(),
+    })?;
+    let is_split = header.get("split.count").is_some();
+    let is_first = header.get("split.no").and_then(Json::as_u64).unwrap_or(0) == 0;
+    if is_first && header.get("general.architecture").is_none() {
+        return Err("no general.architecture in its metadata".to_string());
+    }
+    if !is_split && header.tensors.is_empty() {
+        return Err("no tensors".to_string());
+    }
+    let len = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+    for tensor in &header.tensors {
+        let Some(bytes) = tensor.bytes() else {
+            continue;
+        };
+        let end = header.data_start + tensor.offset + bytes;
+        if end > len {
+            return Err(format!(
+                "truncated: {} ends at byte {end}, but the file is {len} bytes",
+                tensor.name
+            ));
+        }
+    }
+    Ok(header)
+}
+
+/// What `autogguf inspect` prints about a GGUF: its metadata, tensors and parameter count.
+#[derive(Debug)]
+pub struct Inspection {
+    path: PathBuf,
+    header: Header,
+}
+
+/// Read the metadata and tensor list of the GGUF at `path`.
+pub async fn inspect(path: impl AsRef<Path>) -> Result<Inspection, Box<dyn std::error::Error>> {
+    let path = path.as_ref().to_path_buf();
+    let read = path.clone();
+    let header = tokio::task::spawn_blocking(move || read_header(&read))
+        .await?
+        .map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+    Ok(Inspection { path, header })
+}
+
+impl Inspection {
+    pub fn parameters(&self) -> u64 {
+        self.header.tensors.iter().map(TensorEntry::elements).sum()
+    }
+}
+
+/// Arrays longer than this, like the vocab, are cut short.
+const ARRAY_PREVIEW: usize = 8;
+
+fn format_value(value: &Json) -> String {
+    match value {
+        Json::String(s) => s.clone(),
+        Json::Array(items) => {
+            let preview: Vec<String> = items
+                .iter()
+                .take(ARRAY_PREVIEW)
+                .map(Json::to_string)
+                .collect();
+            match items.len() > ARRAY_PREVIEW {
+                true => format!("[{}, ...] ({} items)", preview.join(", "), items.len()),
+                false => format!("[{}]", preview.join(", ")),
+            }
+        }
+        value => value.to_string(),
+    }
+}
+
+/// `7241732096` as `7.24B`.
+pub(crate) fn human_count(count: u64) -> String {
+    const UNITS: &[(u64, &str)] = &[
+        (1_000_000_000_000, "T"),
+        (1_000_000_000, "B"),
+        (1_000_000, "M"),
+        (1_000, "K"),
+    ];
+    UNITS
+        .iter()
+        .find(|(size, _)| count >= *size)
+        .map(|(size, unit)| format!("{:.2}{unit}", count as f64 / *size as f64))
+        .unwrap_or_else(|| count.to_string())
+}
+
+impl Display for Inspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Header {
+            metadata, tensors, ..
+        } = &self.header;
+        writeln!(
+            f,
+            "{}: {} metadata keys, {} tensors",
+            self.path.display(),
+            metadata.len(),
+            tensors.len()
+        )?;
+        writeln!(f)?;
+        for (key, value) in metadata {
+            writeln!(f, "{key} = {}", format_value(value))?;
+        }
+        writeln!(f)?;
+        let width = tensors.iter().map(|t| t.name.len()).max().unwrap_or(0);
+        let mut types: BTreeMap<String, usize> = BTreeMap::new();
+        for tensor in tensors {
+            let shape: Vec<String> = tensor.dims.iter().map(u64::to_string).collect();
+            writeln!(
+                f,
+                "{:width$}  {:8}  [{}]",
+                tensor.name,
+                tensor.type_name(),
+                shape.join(", ")
+            )?;
+            *types.entry(tensor.type_name()).or_default() += 1;
+        }
+        writeln!(f)?;
+        let types: Vec<String> = types
+            .iter()
+            .map(|(name, count)| format!("{name} × {count}"))
+            .collect();
+        writeln!(f, "tensor types: {}", types.join(", "))?;
+        writeln!(
+            f,
+            "parameters: {} ({})",
+            human_count(self.parameters()),
+            self.parameters()
+        )
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+struct Reader<R: Read> {
+    inner: R,
+    position: u64,
+    len: u64,
+}
+
+impl<R: Read> Reader<R> {
+    /// Read `n` bytes, failing before allocating if the file is too short to hold them, as a
+    /// corrupt length would make it.
+    fn bytes(&mut self, n: u64) -> io::Result<Vec<u8>> {
+        if n > self.len - self.position {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut bytes = vec![0; n as usize];
+        self.inner.read_exact(&mut bytes)?;
+        self.position += n;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.bytes(N as u64)?);
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u64()?;
+        String::from_utf8(self.bytes(len)?).map_err(|e| invalid(e.to_string()))
+    }
+
+    fn value(&mut self, value_type: u32) -> io::Result<Json> {
+        Ok(match value_type {
+            TYPE_U8 => Json::from(u8::from_le_bytes(self.array()?)),
+            TYPE_I8 => Json::from(i8::from_le_bytes(self.array()?)),
+            TYPE_U16 => Json::from(u16::from_le_bytes(self.array()?)),
+            TYPE_I16 => Json::from(i16::from_le_bytes(self.array()?)),
+            TYPE_U32 => Json::from(self.u32()?),
+            TYPE_I32 => Json::from(i32::from_le_bytes(self.array()?)),
+            TYPE_F32 => {
+                // NOTE: via its shortest decimal form, so 1e-5 doesn't widen to 9.99999974e-6
+                let value = f32::from_le_bytes(self.array()?);
+                Json::from(value.to_string().parse::<f64>().unwrap_or(value.into()))
+            }
+            TYPE_BOOL => Json::from(self.array::<1>()?[0] != 0),
+            TYPE_STRING => Json::from(self.string()?),
+            TYPE_ARRAY => {
+                let element_type = self.u32()?;
+                let count = self.u64()?;
+                let mut values = vec![];
+                for _ in 0..count {
+                    values.push(self.value(element_type)?);
+                }
+                Json::Array(values)
+            }
+            TYPE_U64 => Json::from(self.u64()?),
+            TYPE_I64 => Json::from(i64::from_le_bytes(self.array()?)),
+            TYPE_F64 => Json::from(f64::from_le_bytes(self.array()?)),
+            value_type => return Err(invalid(format!("unknown metadata type {value_type}"))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A path in the temp dir, unique to this process and `name`.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("autogguf-{}-{name}.gguf", std::process::id()))
+    }
+
+    fn decode(bytes: &[u8]) -> Json {
+        let mut reader = Reader {
+            inner: Cursor::new(bytes),
+            position: 0,
+            len: bytes.len() as u64,
+        };
+        let value_type = reader.u32().unwrap();
+        reader.value(value_type).unwrap()
+    }
+
+    #[test]
+    fn set_metadata_round_trips() {
+        let path = temp_path("set-metadata");
+        let tensor = TensorInfo {
+            name: "token_embd.weight".to_string(),
+            shape: vec![2, 3],
+            dtype: GgmlType::F32,
+        };
+        let data: Vec<u8> = (0..6u8).flat_map(|i| f32::from(i).to_le_bytes()).collect();
+        let metadata = [
+            ("general.architecture", Value::String("llama".to_string())),
+            ("llama.context_length", Value::U32(2048)),
+            ("llama.rope.freq_base", Value::F32(10000.0)),
+            ("general.experimental", Value::Bool(false)),
+            (
+                "tokenizer.ggml.tokens",
+                Value::StringArray(vec!["a".into(), "b".into()]),
+            ),
+        ]
+        .map(|(key, value)| (key.to_string(), value));
+        let mut writer = Writer::new(File::create(&path).unwrap());
+        writer
+            .header(&metadata, std::slice::from_ref(&tensor))
+            .unwrap();
+        writer.tensor(&data).unwrap();
+        drop(writer);
+
+        let entries = [
+            ("general.architecture", "qwen2"),
+            ("llama.context_length", "4096"),
+            ("llama.rope.freq_base", "500000.5"),
+            ("general.experimental", "true"),
+            ("general.name", "First"),
+            ("llama.pooling_type", "2"),
+            ("general.name", "Second"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+        set_metadata(&path, &entries).unwrap();
+
+        let header = read_header(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            header.get("general.architecture"),
+            Some(&Json::from("qwen2"))
+        );
+        assert_eq!(header.get("llama.context_length"), Some(&Json::from(4096)));
+        assert_eq!(
+            header.get("llama.rope.freq_base"),
+            Some(&Json::from(500000.5))
+        );
+        assert_eq!(header.get("general.experimental"), Some(&Json::from(true)));
+        assert_eq!(header.get("general.name"), Some(&Json::from("Second")));
+        assert_eq!(header.get("llama.pooling_type"), Some(&Json::from(2)));
+        assert_eq!(
+            header.get("tokenizer.ggml.tokens"),
+            Some(&Json::from(vec!["a", "b"]))
+        );
+        assert_eq!(header.metadata.len(), 7);
+
+        assert_eq!(header.tensors.len(), 1);
+        let entry = &header.tensors[0];
+        assert_eq!(entry.name, tensor.name);
+        assert_eq!(entry.dims, vec![3, 2]);
+        let start = (header.data_start + entry.offset) as usize;
+        assert_eq!(&bytes[start..start + data.len()], data);
+    }
+
+    #[test]
+    fn set_metadata_keeps_each_keys_type() {
+        // NOTE: `Value` only has the types the converter writes, so these are laid out by hand
+        let cases = [
+            (TYPE_U8, "1", "200", Json::from(200)),
+            (TYPE_I8, "1", "-100", Json::from(-100)),
+            (TYPE_U16, "1", "60000", Json::from(60000)),
+            (TYPE_I16, "1", "-30000", Json::from(-30000)),
+            (TYPE_I32, "1", "-70000", Json::from(-70000)),
+            (TYPE_U64, "1", "5000000000", Json::from(5000000000u64)),
+            (TYPE_I64, "1", "-5000000000", Json::from(-5000000000i64)),
+            (TYPE_F64, "1", "2.5", Json::from(2.5)),
+        ];
+        let path = temp_path("key-types");
+        let mut writer = Writer::new(File::create(&path).unwrap());
+        writer.write(b"GGUF").unwrap();
+        writer.u32(VERSION).unwrap();
+        writer.u64(0).unwrap();
+        writer.u64(cases.len() as u64).unwrap();
+        for (value_type, original, _, _) in &cases {
+            writer.string(&format!("test.type_{value_type}")).unwrap();
+            writer
+                .write(&encode(*value_type, original).unwrap())
+                .unwrap();
+        }
+        writer.pad().unwrap();
+        drop(writer);
+
+        let entries = cases
+            .iter()
+            .map(|(value_type, _, value, _)| (format!("test.type_{value_type}"), value.to_string()))
+            .collect::<Vec<_>>();
+        set_metadata(&path, &entries).unwrap();
+        let header = read_header(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        for (value_type, _, _, expected) in cases {
+            let key = format!("test.type_{value_type}");
+            assert_eq!(header.get(&key), Some(&expected), "{key}");
+        }
+    }
+
+    #[test]
+    fn encode_round_trips_every_scalar_type() {
+        let cases = [
+            (TYPE_U8, "255", Json::from(255)),
+            (TYPE_I8, "-128", Json::from(-128)),
+            (TYPE_U16, "65535", Json::from(65535)),
+            (TYPE_I16, "-32768", Json::from(-32768)),
+            (TYPE_U32, "4294967295", Json::from(4294967295u32)),
+            (TYPE_I32, "-2147483648", Json::from(-2147483648)),
+            (TYPE_F32, "1e-5", Json::from(1e-5)),
+            (TYPE_BOOL, "false", Json::from(false)),
+            (TYPE_STRING, "héllo", Json::from("héllo")),
+            (TYPE_U64, "18446744073709551615", Json::from(u64::MAX)),
+            (TYPE_I64, "-9223372036854775808", Json::from(i64::MIN)),
+            (TYPE_F64, "0.1", Json::from(0.1)),
+        ];
+        for (value_type, value, expected) in cases {
+            let bytes = encode(value_type, value).unwrap();
+            assert_eq!(decode(&bytes), expected, "type {value_type}");
+        }
+    }
+
+    #[test]
+    fn encode_rejects_values_out_of_range_and_arrays() {
+        assert!(encode(TYPE_U8, "256").is_err());
+        assert!(encode(TYPE_U32, "-1").is_err());
+        assert!(encode(TYPE_BOOL, "yes").is_err());
+        assert!(encode(TYPE_ARRAY, "[1, 2]").is_err());
+    }
+
+    #[test]
+    fn new_keys_are_strings_unless_llama_cpp_reads_a_number() {
+        assert_eq!(new_key_type("bert.pooling_type"), TYPE_U32);
+        assert_eq!(new_key_type("general.name"), TYPE_STRING);
+        assert_eq!(new_key_type("general.url"), TYPE_STRING);
+    }
+}
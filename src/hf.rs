@@ -0,0 +1,835 @@
+use crate::{
+    card::{CardInfo, MODEL_CARD_FILE},
+    checksums::{self, CHECKSUMS_FILE},
+    context::{Context, LOG_DIR},
+    convert::{move_file, Shard},
+    event::{self, Event, Stage},
+    hub::{files_with_extensions, is_transient, HubClient, ModelInfo, UploadFile},
+    ipfs,
+    mirror::Mirror,
+    retry::with_retries,
+    sign::Signing,
+    storage::Bucket,
+    torrent::{self, MAGNETS_FILE},
+};
+use glob::Pattern;
+use hf_hub::{
+    api::tokio::{Api, ApiBuilder, ApiError, ApiRepo},
+    Cache, Repo, RepoType,
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+use tokio::{select, sync::mpsc};
+use tracing::Instrument;
+
+/// Checkpoint formats the converter doesn't need when the repo also has safetensors.
+const REDUNDANT_WITH_SAFETENSORS: &[&str] = &[
+    "*.bin",
+    "*.pt",
+    "*.pth",
+    "*.ckpt",
+    "*.h5",
+    "*.msgpack",
+    "*.ot",
+    "*.onnx",
+    "*.gguf",
+    "onnx/*",
+    "original/*",
+];
+/// Leftovers from training that no conversion needs.
+const TRAINING_ARTIFACTS: &[&str] = &[
+    "optimizer.pt",
+    "scheduler.pt",
+    "rng_state*.pth",
+    "training_args.bin",
+    "trainer_state.json",
+    "checkpoint-*/*",
+    "runs/*",
+];
+
+/// Which files of the source repo to download. By default, everything but training artifacts
+/// and, when there are safetensors, other checkpoint formats.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FileFilter {
+    /// Only download files matching one of these globs, overriding the default exclusions.
+    pub include: Vec<String>,
+    /// Never download files matching one of these globs.
+    pub exclude: Vec<String>,
+    /// Turn the default exclusions off.
+    pub all: bool,
+}
+
+impl FileFilter {
+    /// The subset of `files` to download.
+    fn select(&self, files: Vec<String>) -> Result<Vec<String>, glob::PatternError> {
+        fn patterns<S: AsRef<str>>(globs: &[S]) -> Result<Vec<Pattern>, glob::PatternError> {
+            globs.iter().map(|g| Pattern::new(g.as_ref())).collect()
+        }
+        let include = patterns(&self.include)?;
+        let exclude = patterns(&self.exclude)?;
+        let mut defaults = vec![];
+        if !self.all {
+            defaults = patterns(TRAINING_ARTIFACTS)?;
+            if files.iter().any(|f| f.ends_with(".safetensors")) {
+                defaults.extend(patterns(REDUNDANT_WITH_SAFETENSORS)?);
+                // NOTE: Mistral-style repos ship the same weights twice, sharded for
+                // transformers and whole for their own inference code
+                if files
+                    .iter()
+                    .any(|f| f.ends_with(".safetensors") && !f.starts_with("consolidated"))
+                {
+                    defaults.push(Pattern::new("consolidated*.safetensors")?);
+                }
+            }
+        }
+        let matches = |patterns: &[Pattern], file: &str| patterns.iter().any(|p| p.matches(file));
+        Ok(files
+            .into_iter()
+            .filter(|f| !matches(&exclude, f))
+            .filter(|f| match include.is_empty() {
+                true => !matches(&defaults, f),
+                false => matches(&include, f),
+            })
+            .collect())
+    }
+}
+
+/// How to download the source model.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DownloadOptions {
+    pub files: FileFilter,
+    /// Fetch chunks of each file in parallel, one connection per core, instead of one at a time.
+    pub fast: bool,
+    /// Download into the shared HF cache and link the files into the model directory, reusing
+    /// anything already cached instead of storing it twice.
+    pub shared_cache: bool,
+}
+
+/// Download `model_id` at `revision` (a branch, tag or commit; `main` if `None`) into
+/// `local_dir`, returning the commit it resolved to. Files already there are kept unless
+/// `overwrite`, e.g. because they're from another revision. With `options.shared_cache`, files
+/// are linked from the shared HF cache rather than moved out of a private one.
+pub(crate) async fn download_model(
+    model_id: &str,
+    revision: Option<&str>,
+    local_dir: &Path,
+    options: &DownloadOptions,
+    overwrite: bool,
+    hf_token: &str,
+    ctx: &Context,
+) -> Result<String, Box<dyn std::error::Error>> {
+    tokio::fs::create_dir_all(local_dir).await?;
+    let cache = match options.shared_cache {
+        true => Cache::from_env(),
+        false => private_cache(local_dir),
+    };
+    let api = hub_api(&cache, hf_token, options.fast, ctx)?;
+    let repo_ref = match revision {
+        Some(revision) => {
+            ctx.detail(format!("🤗 downloading {model_id} at {revision}..."));
+            Repo::with_revision(model_id.to_string(), RepoType::Model, revision.to_string())
+        }
+        None => {
+            ctx.detail(format!("🤗 downloading {model_id}..."));
+            Repo::model(model_id.to_string())
+        }
+    };
+    let repo = api.repo(repo_ref.clone());
+    let info = select! {
+        info = repo.info() => info.map_err(|e| access_error(&e, model_id, hf_token))?,
+        _ = ctx.cancel.notified() => {
+            ctx.mark_interrupted();
+            return Err("Download cancelled due to interrupt".into());
+        }
+    };
+
+    let available: Vec<String> = info.siblings.into_iter().map(|s| s.rfilename).collect();
+    let total = available.len();
+    let selected = options.files.select(available)?;
+    if selected.len() < total {
+        ctx.detail(format!(
+            "🤗 skipping {} of {total} files in the repo.",
+            total - selected.len()
+        ));
+    }
+    for filename in selected {
+        let target = local_dir.join(&filename);
+        if !overwrite && tokio::fs::try_exists(&target).await? {
+            continue;
+        }
+        if !options.shared_cache {
+            download_file(&repo, model_id, hf_token, &filename, &target, ctx).await?;
+            continue;
+        }
+        let cached = cache
+            .path()
+            .join(repo_ref.folder_name())
+            .join("snapshots")
+            .join(&info.sha)
+            .join(&filename);
+        let pointer = match tokio::fs::try_exists(&cached).await? {
+            true => cached,
+            false => fetch(&repo, model_id, hf_token, &filename, ctx).await?,
+        };
+        link_cached(&pointer, &target).await?;
+    }
+
+    ctx.detail(format!("🤗 downloaded {model_id} at {}!", info.sha));
+    Ok(info.sha)
+}
+
+/// A cache inside `local_dir`, like `huggingface-cli download --local-dir` keeps, which files are
+/// moved out of once downloaded.
+fn private_cache(local_dir: &Path) -> Cache {
+    Cache::new(local_dir.join(".cache/huggingface"))
+}
+
+/// A Hub client that caches in `cache`. Authenticates with `hf_token`, or the token
+/// `huggingface-cli login` saved if that's empty, so gated and private repos can be downloaded.
+fn hub_api(
+    cache: &Cache,
+    hf_token: &str,
+    fast: bool,
+    ctx: &Context,
+) -> Result<Api, Box<dyn std::error::Error>> {
+    let mut builder = ApiBuilder::from_env();
+    if fast {
+        builder = builder.high();
+    }
+    Ok(builder
+        .with_cache_dir(cache.path().clone())
+        .with_token(download_token(hf_token))
+        .with_progress(ctx.verbose && !ctx.captures_output())
+        .build()?)
+}
+
+/// `hf_token`, or the token `huggingface-cli login` saved if that's empty.
+fn download_token(hf_token: &str) -> Option<String> {
+    match hf_token {
+        "" => Cache::from_env().token(),
+        token => Some(token.to_string()),
+    }
+}
+
+fn status(e: &ApiError) -> Option<u16> {
+    match e {
+        ApiError::RequestError(e) => e.status().map(|s| s.as_u16()),
+        ApiError::TooManyRetries(e) => status(e),
+        _ => None,
+    }
+}
+
+/// What went wrong fetching from `repo_id`, spelling out what to do about gated and private
+/// repos, which the Hub reports as 401 without a token and 403 with one that lacks access.
+fn access_error(e: &ApiError, repo_id: &str, hf_token: &str) -> String {
+    let authenticated = download_token(hf_token).is_some();
+    match status(e) {
+        Some(401) if authenticated => format!(
+            "HuggingFace rejected your token fetching {repo_id}; check it's valid, or that \
+             {repo_id} exists"
+        ),
+        Some(401) => format!(
+            "{repo_id} doesn't exist, or is gated or private; pass --hf-token or set HF_TOKEN \
+             to a token with access to it"
+        ),
+        Some(403) => format!(
+            "your HuggingFace token doesn't have access to {repo_id}; if it's gated, accept its \
+             terms at https://huggingface.co/{repo_id}, and make sure a fine-grained token is \
+             allowed to read gated repos"
+        ),
+        Some(404) => format!("{repo_id} not found on HuggingFace Hub"),
+        _ => format!("failed to fetch {repo_id} from HuggingFace Hub: {e}"),
+    }
+}
+
+/// Download `filename` from `repo` to `target`, retrying failures. The file lands in the cache
+/// first, then its blob is moved into place so it isn't stored twice.
+async fn download_file(
+    repo: &ApiRepo,
+    repo_id: &str,
+    hf_token: &str,
+    filename: &str,
+    target: &Path,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pointer = fetch(repo, repo_id, hf_token, filename, ctx).await?;
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let blob = tokio::fs::canonicalize(&pointer).await?;
+    move_file(&blob, target).await?;
+    tokio::fs::remove_file(&pointer).await?;
+    Ok(())
+}
+
+/// Download `filename` into `repo`'s cache, retrying transient failures, and return its path
+/// in the snapshot.
+async fn fetch(
+    repo: &ApiRepo,
+    repo_id: &str,
+    hf_token: &str,
+    filename: &str,
+    ctx: &Context,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let action = format!("download {filename}");
+    let download = with_retries(&Stage::Download, &action, ctx, || async {
+        repo.download(filename).await.map_err(Box::from)
+    });
+    select! {
+        result = download => result.map_err(|e| match e.downcast_ref::<ApiError>() {
+            Some(e) if matches!(status(e), Some(401 | 403)) => {
+                access_error(e, repo_id, hf_token).into()
+            }
+            _ if ctx.is_interrupted() => e.to_string().into(),
+            _ => format!("failed to download {filename}: {e}").into(),
+        }),
+        _ = ctx.cancel.notified() => {
+            ctx.mark_interrupted();
+            Err("Download cancelled due to interrupt".into())
+        }
+    }
+}
+
+/// Put the cached file `pointer` refers to at `target` without copying it: a hard link when the
+/// cache is on the same filesystem, a symlink otherwise.
+async fn link_cached(pointer: &Path, target: &Path) -> std::io::Result<()> {
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    if tokio::fs::symlink_metadata(target).await.is_ok() {
+        tokio::fs::remove_file(target).await?;
+    }
+    let blob = tokio::fs::canonicalize(pointer).await?;
+    if tokio::fs::hard_link(&blob, target).await.is_ok() {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    return tokio::fs::symlink(&blob, target).await;
+    #[cfg(windows)]
+    return tokio::fs::symlink_file(&blob, target).await;
+}
+
+/// Download the `.imatrix` published in `repo_id` to `output_path`, caching in `model_dir`. If
+/// the repo has several, the one named after `model_name` wins.
+pub(crate) async fn download_imatrix(
+    repo_id: &str,
+    model_name: &str,
+    model_dir: &Path,
+    output_path: &Path,
+    hf_token: &str,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    ctx.detail(format!("🤗 fetching imatrix from {repo_id}..."));
+    let repo = hub_api(&private_cache(model_dir), hf_token, false, ctx)?.model(repo_id.to_string());
+    let info = select! {
+        info = repo.info() => info.map_err(|e| access_error(&e, repo_id, hf_token))?,
+        _ = ctx.cancel.notified() => {
+            ctx.mark_interrupted();
+            return Err("Download cancelled due to interrupt".into());
+        }
+    };
+    let mut imatrices: Vec<String> = info
+        .siblings
+        .into_iter()
+        .map(|s| s.rfilename)
+        .filter(|f| f.ends_with(".imatrix"))
+        .collect();
+    imatrices.sort_by_key(|f| !f.to_lowercase().contains(&model_name.to_lowercase()));
+    let Some(filename) = imatrices.first() else {
+        return Err(format!("no .imatrix file in {repo_id}").into());
+    };
+    download_file(&repo, repo_id, hf_token, filename, output_path, ctx).await?;
+    ctx.detail(format!("🤗 fetched {filename} from {repo_id}!"));
+    Ok(())
+}
+
+/// What to upload to the HuggingFace Hub, and as whom.
+#[derive(Debug, Clone)]
+pub(crate) struct UploadTarget {
+    pub hf_user: String,
+    pub hf_token: String,
+    pub model_name: String,
+    /// Where the full-precision GGUF, imatrix and logs are.
+    pub work_dir: PathBuf,
+    /// Where the quants are, and the checksums and model card are written.
+    pub output_dir: PathBuf,
+    /// Also upload the per-stage logs under `logs/`.
+    pub include_logs: bool,
+    /// Create the repo as private, if it doesn't exist yet.
+    pub private: bool,
+    /// Open a pull request on the repo, which must exist, rather than committing to `main`.
+    pub as_pr: bool,
+    /// Upload here instead of `{hf_user}/{model_name}-GGUF`, e.g. to an organization.
+    pub repo_id: Option<String>,
+    /// The Hub collection to add the repo to once it's uploaded.
+    pub collection: Option<String>,
+    /// Upload to this bucket instead of the Hub.
+    pub backend: Option<Bucket>,
+    /// Where everything uploaded is copied to afterwards.
+    pub mirrors: Vec<Mirror>,
+    /// Paths the repo already has that are left as they are, when updating an existing repo.
+    pub published: HashSet<String>,
+    pub signing: Option<Signing>,
+    /// Also upload a torrent of each quant, web-seeded from the repo.
+    pub torrent: bool,
+    /// Pin every GGUF to IPFS, with the node `ipfs` talks to.
+    pub ipfs: bool,
+    /// The remote pinning service to pin to as well.
+    pub ipfs_pin_service: Option<String>,
+    pub card: CardInfo,
+}
+
+impl UploadTarget {
+    /// The repo to upload to: `repo_id` if given, else `{hf_user}/{model_name}-GGUF`.
+    pub fn repo_id(&self) -> String {
+        self.repo_id
+            .clone()
+            .unwrap_or_else(|| format!("{}/{}-GGUF", self.hf_user, self.model_name))
+    }
+
+    /// Where uploads go, for messages: the Hub repo's URL without the scheme, or where in the
+    /// bucket.
+    pub fn destination(&self) -> String {
+        let repo_id = self.repo_id();
+        match &self.backend {
+            Some(bucket) => bucket.destination(repo_id.rsplit('/').next().unwrap_or(&repo_id)),
+            None => format!("huggingface.co/{repo_id}"),
+        }
+    }
+
+    pub(crate) fn check_credentials(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.backend.is_none() && self.repo_id.is_none() && self.hf_user.is_empty() {
+            return Err(
+                "no HuggingFace user to upload as; pass --hf-user, set HF_USER or pass --repo-id"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Every GGUF and imatrix in the output and work directories.
+    pub async fn outputs(
+        &self,
+    ) -> Result<Vec<UploadFile>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut files =
+            files_with_extensions(&self.output_dir, &[".gguf", ".imatrix", ".llamafile"]).await?;
+        if self.work_dir != self.output_dir && tokio::fs::try_exists(&self.work_dir).await? {
+            files.extend(files_with_extensions(&self.work_dir, &[".gguf", ".imatrix"]).await?);
+        }
+        Ok(files.into_iter().map(|f| repo_file(f.local_path)).collect())
+    }
+
+    /// The checksums and their signatures, model card and, if asked for, the torrents and logs:
+    /// these change as the run goes on, so they're committed last. GGUFs to pin to IPFS are
+    /// pinned first, so the card can list their CIDs.
+    async fn metadata_files(
+        &self,
+        client: &HubClient,
+        ctx: &Context,
+    ) -> Result<Vec<UploadFile>, Box<dyn std::error::Error + Send + Sync>> {
+        // NOTE: the source model's card and its license, languages and tags carry over, but it
+        // being unreachable shouldn't block the upload
+        let source = if self.card.model_id.is_empty() {
+            ModelInfo::default()
+        } else {
+            let model_id = &self.card.model_id;
+            let revision = self.card.revision.as_deref().unwrap_or("main");
+            let mut source = client.model_info(model_id).await.unwrap_or_default();
+            source.readme = client.readme(model_id, revision).await.ok();
+            source
+        };
+        let outputs = self.outputs().await?;
+        let checksums = checksums::write(&self.output_dir, &outputs).await?;
+        let checksums_file = UploadFile::new(self.output_dir.join(CHECKSUMS_FILE));
+        let mut files = vec![];
+        if let Some(signing) = &self.signing {
+            files.push(signing.key.sign(&checksums_file).await?);
+            if signing.all_files {
+                for file in &outputs {
+                    files.push(signing.key.sign(file).await?);
+                }
+            }
+        }
+        let mut card = self.card.clone();
+        if self.torrent {
+            card.torrents = torrent::write(
+                &self.output_dir,
+                &outputs,
+                &client.resolve_url(&self.repo_id()),
+            )
+            .await?;
+            for torrent in &card.torrents {
+                files.push(UploadFile::new(self.output_dir.join(&torrent.path)));
+            }
+            files.push(UploadFile::new(self.output_dir.join(MAGNETS_FILE)));
+        }
+        if self.ipfs {
+            card.pins = ipfs::pin(&outputs, self.ipfs_pin_service.as_deref(), ctx).await?;
+            ctx.emit(Event::Pinned {
+                cids: card
+                    .pins
+                    .iter()
+                    .map(|p| (p.path.clone(), p.cid.clone()))
+                    .collect(),
+            });
+        }
+        let card = card
+            .write(
+                &self.output_dir,
+                &outputs,
+                &self.repo_id(),
+                &source,
+                &checksums,
+                self.signing.as_ref(),
+            )
+            .await?;
+        files.push(checksums_file);
+        files.push(UploadFile {
+            local_path: card,
+            path_in_repo: MODEL_CARD_FILE.to_string(),
+        });
+        let log_dir = self.work_dir.join(LOG_DIR);
+        if self.include_logs && tokio::fs::try_exists(&log_dir).await? {
+            for mut log in files_with_extensions(&log_dir, &[".log"]).await? {
+                log.path_in_repo = format!("{LOG_DIR}/{}", log.path_in_repo);
+                files.push(log);
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Where `path` goes in the repo: shards of a split quant go in a folder named for the quant,
+/// e.g. `Q8_0/model.Q8_0-00001-of-00002.gguf`, and everything else at the root.
+pub(crate) fn repo_file(path: PathBuf) -> UploadFile {
+    let mut file = UploadFile::new(path);
+    if let Some(shard) = Shard::parse(&file.path_in_repo) {
+        file.path_in_repo = format!("{}/{}", shard.label(), file.path_in_repo);
+    }
+    file
+}
+
+/// Upload every GGUF and imatrix in the output and work directories, then the model card.
+pub(crate) async fn upload_ggufs_to_hf(
+    target: &UploadTarget,
+    ctx: &Context,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    for file in target.outputs().await? {
+        sender.send(file)?;
+    }
+    drop(sender);
+    upload_worker(receiver, target.clone(), ctx.clone()).await
+}
+
+/// Upload files as they arrive on `receiver`, each exactly once, committing whatever has queued
+/// up together. Once the sender hangs up, commit the model card and return the repo ID.
+pub(crate) async fn upload_worker(
+    mut receiver: mpsc::UnboundedReceiver<UploadFile>,
+    target: UploadTarget,
+    ctx: Context,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let stage = Stage::Upload;
+    ctx.emit(Event::StageStarted {
+        stage: stage.clone(),
+    });
+    let span = stage.span(&target.model_name);
+    let result = select! {
+        result = upload_queue(&mut receiver, &target, &ctx).instrument(span.clone()) => result,
+        _ = ctx.cancel.notified() => {
+            ctx.mark_interrupted();
+            ctx.log(&stage, "# upload cancelled due to interrupt").await;
+            Err("Upload cancelled due to interrupt".into())
+        }
+    };
+    match &result {
+        Ok(_) => ctx.emit(Event::StageFinished {
+            stage,
+            output: None,
+            bytes: None,
+        }),
+        Err(e) => {
+            event::record_error(&span, e);
+            ctx.log(&stage, &format!("# upload failed: {e}")).await;
+            ctx.emit(Event::StageFailed {
+                stage,
+                error: e.to_string(),
+            });
+        }
+    }
+    result
+}
+
+async fn upload_queue(
+    receiver: &mut mpsc::UnboundedReceiver<UploadFile>,
+    target: &UploadTarget,
+    ctx: &Context,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    target.check_credentials()?;
+    // NOTE: a bucket has no repo, so uploads are reported as going to where they land in it
+    let repo_id = match &target.backend {
+        Some(_) => target.destination(),
+        None => target.repo_id(),
+    };
+    let mut client = HubClient::new(target.hf_token.clone(), ctx.clone());
+    match (&target.backend, target.as_pr) {
+        // NOTE: a bucket's prefix needs no creating
+        (Some(_), _) => {}
+        (None, true) => {
+            let title = format!("Add GGUFs of {}", target.model_name);
+            let description =
+                "GGUF quants made with [autogguf](https://github.com/brittlewis12/autogguf-rs).";
+            let num = with_retries(
+                &Stage::Upload,
+                &format!("open a pull request on {repo_id}"),
+                ctx,
+                || client.create_pull_request(&repo_id, &title, description),
+            )
+            .await?;
+            ctx.info(format!(
+                "🤗 opened https://huggingface.co/{repo_id}/discussions/{num} to upload to."
+            ));
+            client = client.on_revision(format!("refs/pr/{num}"));
+        }
+        (None, false) => {
+            with_retries(&Stage::Upload, &format!("create {repo_id}"), ctx, || {
+                client.create_repo(&repo_id, target.private)
+            })
+            .await?;
+        }
+    }
+
+    let mut queued = HashSet::new();
+    let mut failed = vec![];
+    while let Some(file) = receiver.recv().await {
+        let mut batch = vec![file];
+        while let Ok(file) = receiver.try_recv() {
+            batch.push(file);
+        }
+        batch.retain(|f| {
+            !target.published.contains(&f.path_in_repo) && queued.insert(f.path_in_repo.clone())
+        });
+        match send(&client, target, &repo_id, &batch, ctx).await {
+            Ok(()) => {}
+            // NOTE: out of retries, but the hub may recover in time for the next quant
+            Err(e) if is_transient(e.as_ref()) => {
+                ctx.warn(format!("🤗 giving up on this batch: {e}"));
+                failed.extend(batch.into_iter().map(|f| f.path_in_repo));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let (kept, metadata): (Vec<_>, Vec<_>) = target
+        .metadata_files(&client, ctx)
+        .await?
+        .into_iter()
+        .partition(|f| target.published.contains(&f.path_in_repo));
+    send(&client, target, &repo_id, &metadata, ctx).await?;
+    if !kept.is_empty() {
+        let kept: Vec<&str> = kept.iter().map(|f| f.path_in_repo.as_str()).collect();
+        ctx.warn(format!(
+            "📝 left {} in {repo_id} as they were, so they don't cover the files just added",
+            kept.join(", ")
+        ));
+    }
+    if !failed.is_empty() {
+        return Err(format!(
+            "failed to upload {} to {repo_id}; rerun with --only-upload to retry",
+            failed.join(", ")
+        )
+        .into());
+    }
+    if let Some(slug) = &target.collection {
+        // NOTE: the upload itself succeeded, so this only warns
+        match with_retries(
+            &Stage::Upload,
+            &format!("add {repo_id} to {slug}"),
+            ctx,
+            || client.add_to_collection(slug, &repo_id),
+        )
+        .await
+        {
+            Ok(()) => ctx.detail(format!("🤗 added {repo_id} to the collection {slug}!")),
+            Err(e) => ctx.warn(format!(
+                "🤗 couldn't add {repo_id} to the collection {slug}; add it on the Hub instead: {e}"
+            )),
+        }
+    }
+    if !target.mirrors.is_empty() {
+        let mut files: Vec<UploadFile> = target
+            .outputs()
+            .await?
+            .into_iter()
+            .filter(|f| !target.published.contains(&f.path_in_repo))
+            .collect();
+        files.extend(metadata);
+        let repo_name = repo_id.rsplit('/').next().unwrap_or(&repo_id);
+        let mut failures = vec![];
+        for mirror in &target.mirrors {
+            let pushed = mirror
+                .push(repo_name, &files, &target.hf_token, target.private, ctx)
+                .await;
+            if let Err(e) = pushed {
+                failures.push(format!("{}: {e}", mirror.destination(repo_name)));
+            }
+        }
+        if !failures.is_empty() {
+            return Err(format!(
+                "couldn't mirror to {}; rerun with --only-upload to retry",
+                failures.join("; ")
+            )
+            .into());
+        }
+    }
+    Ok(repo_id)
+}
+
+/// Upload `files` as `target` says: committed to the Hub repo `repo_id`, or copied into the
+/// bucket.
+async fn send(
+    client: &HubClient,
+    target: &UploadTarget,
+    repo_id: &str,
+    files: &[UploadFile],
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(bucket) = &target.backend else {
+        return commit(client, repo_id, files, &target.model_name, ctx).await;
+    };
+    if files.is_empty() {
+        return Ok(());
+    }
+    let paths: Vec<String> = files.iter().map(|f| f.path_in_repo.clone()).collect();
+    ctx.detail(format!("🪣 copying {} to {repo_id}...", paths.join(", ")));
+    ctx.log(
+        &Stage::Upload,
+        &format!("# copy {} to {repo_id}", paths.join(", ")),
+    )
+    .await;
+    bucket
+        .copy(repo_id.rsplit('/').next().unwrap_or(repo_id), files, ctx)
+        .await?;
+    ctx.emit(Event::Uploaded {
+        repo_id: repo_id.to_string(),
+        files: paths,
+    });
+    Ok(())
+}
+
+async fn commit(
+    client: &HubClient,
+    repo_id: &str,
+    files: &[UploadFile],
+    model_name: &str,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    let names = files
+        .iter()
+        .map(|f| f.path_in_repo.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    ctx.detail(format!("🤗 uploading {names} to HuggingFace Hub..."));
+    let message = format!("Upload {names}");
+    ctx.log(&Stage::Upload, &format!("# {message} to {repo_id}"))
+        .await;
+    // NOTE: files a failed attempt got as far as uploading are skipped on the next, since the hub
+    // already has them
+    let committed = with_retries(&Stage::Upload, &format!("upload {names}"), ctx, || {
+        client.upload_files(repo_id, files, &message)
+    })
+    .await?;
+    ctx.log(
+        &Stage::Upload,
+        &format!("# committed {}", committed.join(", ")),
+    )
+    .await;
+    ctx.detail(format!(
+        "🤗 uploaded {} file(s) from {model_name} to HuggingFace Hub!",
+        committed.len()
+    ));
+    ctx.emit(Event::Uploaded {
+        repo_id: repo_id.to_string(),
+        files: committed,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    const REPO: &[&str] = &[
+        "config.json",
+        "model-00001-of-00002.safetensors",
+        "model-00002-of-00002.safetensors",
+        "consolidated.safetensors",
+        "pytorch_model.bin",
+        "original/params.json",
+        "optimizer.pt",
+        "tokenizer.json",
+    ];
+
+    #[test]
+    fn the_defaults_skip_training_artifacts_and_redundant_checkpoints() {
+        let selected = FileFilter::default().select(files(REPO)).unwrap();
+        assert_eq!(
+            selected,
+            files(&[
+                "config.json",
+                "model-00001-of-00002.safetensors",
+                "model-00002-of-00002.safetensors",
+                "tokenizer.json",
+            ])
+        );
+    }
+
+    #[test]
+    fn the_defaults_keep_other_checkpoints_without_safetensors() {
+        let repo = files(&["config.json", "pytorch_model.bin", "optimizer.pt"]);
+        let selected = FileFilter::default().select(repo).unwrap();
+        assert_eq!(selected, files(&["config.json", "pytorch_model.bin"]));
+    }
+
+    #[test]
+    fn include_overrides_the_default_exclusions() {
+        let filter = FileFilter {
+            include: vec!["*.json".into(), "original/*".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.select(files(REPO)).unwrap(),
+            files(&["config.json", "original/params.json", "tokenizer.json"])
+        );
+    }
+
+    #[test]
+    fn exclude_applies_even_to_included_files() {
+        let filter = FileFilter {
+            include: vec!["*.json".into()],
+            exclude: vec!["tokenizer.json".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.select(files(REPO)).unwrap(),
+            files(&["config.json", "original/params.json"])
+        );
+    }
+
+    #[test]
+    fn all_files_turns_the_defaults_off() {
+        let filter = FileFilter {
+            all: true,
+            ..Default::default()
+        };
+        assert_eq!(filter.select(files(REPO)).unwrap(), files(REPO));
+    }
+}
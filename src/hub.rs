@@ -0,0 +1,913 @@
+//! A minimal client for the HuggingFace Hub HTTP and git-lfs APIs: just enough to create a repo
+//! and commit files to it without the Python CLI.
+
+use crate::context::Context;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hf_hub::api::tokio::ApiError;
+use reqwest::{header, Body, Client, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, io::Read, path::Path};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+const DEFAULT_ENDPOINT: &str = "https://huggingface.co";
+const SAMPLE_SIZE: usize = 512;
+
+/// A non-success response from the Hub.
+#[derive(Debug)]
+pub(crate) struct HubError {
+    pub status: StatusCode,
+    message: String,
+}
+
+impl std::fmt::Display for HubError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for HubError {}
+
+#[cfg(test)]
+impl HubError {
+    pub(crate) fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+/// A local file to commit, and where it goes in the repo.
+#[derive(Debug, Clone)]
+pub(crate) struct UploadFile {
+    pub local_path: std::path::PathBuf,
+    pub path_in_repo: String,
+}
+
+impl UploadFile {
+    /// Upload `local_path` to the root of the repo, under its own file name.
+    pub fn new(local_path: impl Into<std::path::PathBuf>) -> Self {
+        let local_path = local_path.into();
+        let path_in_repo = local_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        Self {
+            local_path,
+            path_in_repo,
+        }
+    }
+}
+
+/// The parts of `/api/models/{repo_id}` we use.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ModelInfo {
+    pub safetensors: Option<Safetensors>,
+    pub card_data: Option<CardData>,
+    pub pipeline_tag: Option<String>,
+    pub config: Option<ModelConfig>,
+    /// The Hub's tags, including ones it derives like `license:mit`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Every file in the repo.
+    #[serde(default)]
+    pub siblings: Vec<RepoFile>,
+    /// The model card itself, fetched separately with [`HubClient::readme`].
+    #[serde(skip)]
+    pub readme: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RepoFile {
+    /// The file's path in the repo.
+    pub rfilename: String,
+    /// Only listed by [`HubClient::files`].
+    pub size: Option<u64>,
+}
+
+/// The parts of a model's `config.json` we use, which the Hub also includes in model info.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ModelConfig {
+    /// The transformers model classes, like `LlamaForCausalLM`, which the converter keys on.
+    #[serde(default)]
+    pub architectures: Vec<String>,
+}
+
+/// A model as `/api/models` lists it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ListedModel {
+    pub id: String,
+    /// The latest commit.
+    pub sha: Option<String>,
+    pub last_modified: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Safetensors {
+    pub total: u64,
+}
+
+/// A model card's YAML frontmatter.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct CardData {
+    pub license: Option<Value>,
+    /// For `license: other`, the license's name and where to read it.
+    pub license_name: Option<String>,
+    pub license_link: Option<String>,
+    /// A single language code or a list of them.
+    pub language: Option<Value>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl ModelInfo {
+    /// The license from the model card, which may be a single identifier or a list, or else
+    /// from the Hub's `license:` tag.
+    pub fn license(&self) -> Option<String> {
+        let from_card = match self.card_data.as_ref().and_then(|c| c.license.as_ref()) {
+            Some(Value::String(license)) => Some(license.clone()),
+            Some(Value::Array(licenses)) => {
+                licenses.first().and_then(Value::as_str).map(str::to_string)
+            }
+            _ => None,
+        };
+        from_card.or_else(|| {
+            self.tags
+                .iter()
+                .find_map(|tag| tag.strip_prefix("license:"))
+                .map(str::to_string)
+        })
+    }
+
+    /// The languages from the model card.
+    pub fn languages(&self) -> Vec<String> {
+        match self.card_data.as_ref().and_then(|c| c.language.as_ref()) {
+            Some(Value::String(language)) => vec![language.clone()],
+            Some(Value::Array(languages)) => languages
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// The model card's own tags, less the ones about the source's format, which don't
+    /// describe its GGUFs.
+    pub fn card_tags(&self) -> Vec<&str> {
+        const FORMAT_TAGS: &[&str] = &["gguf", "imatrix", "pytorch", "safetensors", "transformers"];
+        self.card_data
+            .iter()
+            .flat_map(|c| &c.tags)
+            .map(String::as_str)
+            .filter(|tag| !FORMAT_TAGS.contains(tag))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct HubClient {
+    client: Client,
+    endpoint: String,
+    token: String,
+    /// The branch uploads are committed to: `main`, or a pull request's `refs/pr/{n}`.
+    revision: String,
+    ctx: Context,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PreuploadFile {
+    path: String,
+    upload_mode: String,
+    #[serde(default)]
+    should_ignore: bool,
+    oid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LfsBatchObject {
+    #[serde(default)]
+    actions: Option<LfsActions>,
+    error: Option<LfsError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LfsActions {
+    upload: Option<LfsAction>,
+    verify: Option<LfsAction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LfsAction {
+    href: String,
+    #[serde(default)]
+    header: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LfsError {
+    code: u16,
+    message: String,
+}
+
+impl HubClient {
+    pub fn new(token: impl Into<String>, ctx: Context) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: std::env::var("HF_ENDPOINT").unwrap_or_else(|_| DEFAULT_ENDPOINT.to_string()),
+            token: token.into(),
+            revision: "main".to_string(),
+            ctx,
+        }
+    }
+
+    /// Commit uploads to `revision` instead of `main`, e.g. a pull request's `refs/pr/{n}`.
+    pub fn on_revision(mut self, revision: impl Into<String>) -> Self {
+        self.revision = revision.into();
+        self
+    }
+
+    /// The URL files in `repo_id` are downloaded from, by their path in the repo appended.
+    pub fn resolve_url(&self, repo_id: &str) -> String {
+        format!(
+            "{}/{repo_id}/resolve/{}/",
+            self.endpoint,
+            self.revision_path()
+        )
+    }
+
+    /// `revision` as it goes in a URL path, where its slashes have to be escaped.
+    fn revision_path(&self) -> String {
+        self.revision.replace('/', "%2F")
+    }
+
+    /// Metadata for a model repo.
+    pub async fn model_info(&self, repo_id: &str) -> Result<ModelInfo, Error> {
+        let mut request = self
+            .client
+            .get(format!("{}/api/models/{repo_id}", self.endpoint));
+        if !self.token.is_empty() {
+            request = request.bearer_auth(&self.token);
+        }
+        Ok(check(request.send().await?, "model info")
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Every file in a model repo, with its size.
+    pub async fn files(&self, repo_id: &str) -> Result<Vec<RepoFile>, Error> {
+        let mut request = self
+            .client
+            .get(format!("{}/api/models/{repo_id}", self.endpoint))
+            .query(&[("blobs", "true")]);
+        if !self.token.is_empty() {
+            request = request.bearer_auth(&self.token);
+        }
+        let info: ModelInfo = check(request.send().await?, "list files")
+            .await?
+            .json()
+            .await?;
+        Ok(info.siblings)
+    }
+
+    /// The username `token` belongs to.
+    pub async fn whoami(&self) -> Result<String, Error> {
+        #[derive(Deserialize)]
+        struct WhoAmI {
+            name: String,
+        }
+        let request = self
+            .client
+            .get(format!("{}/api/whoami-v2", self.endpoint))
+            .bearer_auth(&self.token);
+        let whoami: WhoAmI = check(request.send().await?, "whoami").await?.json().await?;
+        Ok(whoami.name)
+    }
+
+    /// A model's `config.json` at `revision`, on its own.
+    pub async fn config(&self, repo_id: &str, revision: &str) -> Result<Value, Error> {
+        self.json_file(repo_id, revision, "config.json").await
+    }
+
+    /// The JSON file at `path` in a repo at `revision`.
+    pub async fn json_file(
+        &self,
+        repo_id: &str,
+        revision: &str,
+        path: &str,
+    ) -> Result<Value, Error> {
+        let mut request = self.client.get(format!(
+            "{}/{repo_id}/resolve/{revision}/{path}",
+            self.endpoint
+        ));
+        if !self.token.is_empty() {
+            request = request.bearer_auth(&self.token);
+        }
+        Ok(check(request.send().await?, path).await?.json().await?)
+    }
+
+    /// A model's `README.md` at `revision`: its model card, frontmatter and all.
+    pub async fn readme(&self, repo_id: &str, revision: &str) -> Result<String, Error> {
+        let mut request = self.client.get(format!(
+            "{}/{repo_id}/resolve/{revision}/README.md",
+            self.endpoint
+        ));
+        if !self.token.is_empty() {
+            request = request.bearer_auth(&self.token);
+        }
+        Ok(check(request.send().await?, "README.md")
+            .await?
+            .text()
+            .await?)
+    }
+
+    /// The public models `author` (a user or org) has published, with their latest commits,
+    /// following the listing from page to page.
+    pub async fn list_models(&self, author: &str) -> Result<Vec<ListedModel>, Error> {
+        let mut models = vec![];
+        let mut request = self
+            .client
+            .get(format!("{}/api/models", self.endpoint))
+            .query(&[
+                ("author", author),
+                ("expand[]", "sha"),
+                ("expand[]", "lastModified"),
+                ("expand[]", "tags"),
+            ]);
+        loop {
+            if !self.token.is_empty() {
+                request = request.bearer_auth(&self.token);
+            }
+            let response = check(request.send().await?, "list models").await?;
+            let next = next_page(response.headers());
+            models.extend(response.json::<Vec<ListedModel>>().await?);
+            match next {
+                Some(url) => request = self.client.get(url),
+                None => return Ok(models),
+            }
+        }
+    }
+
+    /// Total parameter count of a model, as reported for its safetensors weights.
+    pub async fn parameter_count(&self, repo_id: &str) -> Result<Option<u64>, Error> {
+        Ok(self.model_info(repo_id).await?.safetensors.map(|s| s.total))
+    }
+
+    /// Create a model repo, succeeding if it already exists. An existing repo keeps its
+    /// visibility regardless of `private`.
+    pub async fn create_repo(&self, repo_id: &str, private: bool) -> Result<(), Error> {
+        let (organization, name) = repo_id
+            .split_once('/')
+            .ok_or_else(|| format!("invalid repo ID '{repo_id}', expected namespace/name"))?;
+        let response = self
+            .client
+            .post(format!("{}/api/repos/create", self.endpoint))
+            .bearer_auth(&self.token)
+            .json(&json!({
+                "type": "model",
+                "name": name,
+                "organization": organization,
+                "private": private,
+            }))
+            .send()
+            .await?;
+        if response.status() == StatusCode::CONFLICT {
+            return Ok(());
+        }
+        check(response, "create repo").await?;
+        Ok(())
+    }
+
+    /// Add the model `repo_id` to the collection `slug`, e.g. `user/llama-ggufs-66f1...`,
+    /// succeeding if it's already there.
+    pub async fn add_to_collection(&self, slug: &str, repo_id: &str) -> Result<(), Error> {
+        let response = self
+            .client
+            .post(format!("{}/api/collections/{slug}/items", self.endpoint))
+            .bearer_auth(&self.token)
+            .json(&json!({ "item": { "type": "model", "id": repo_id } }))
+            .send()
+            .await?;
+        if response.status() == StatusCode::CONFLICT {
+            return Ok(());
+        }
+        check(response, "add to collection").await?;
+        Ok(())
+    }
+
+    /// Open a pull request on `repo_id` to commit uploads to, returning its number.
+    pub async fn create_pull_request(
+        &self,
+        repo_id: &str,
+        title: &str,
+        description: &str,
+    ) -> Result<u64, Error> {
+        #[derive(Deserialize)]
+        struct Discussion {
+            num: u64,
+        }
+
+        let response = self
+            .client
+            .post(format!(
+                "{}/api/models/{repo_id}/discussions",
+                self.endpoint
+            ))
+            .bearer_auth(&self.token)
+            .json(&json!({
+                "title": title,
+                "description": description,
+                "pullRequest": true,
+            }))
+            .send()
+            .await?;
+        let discussion: Discussion = check(response, "open pull request").await?.json().await?;
+        Ok(discussion.num)
+    }
+
+    /// Upload `files` to `repo_id` in a single commit, skipping any the repo already has.
+    /// Returns the paths actually committed.
+    pub async fn upload_files(
+        &self,
+        repo_id: &str,
+        files: &[UploadFile],
+        commit_message: &str,
+    ) -> Result<Vec<String>, Error> {
+        if files.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut entries = Vec::with_capacity(files.len());
+        for file in files {
+            let size = tokio::fs::metadata(&file.local_path).await?.len();
+            let mut sample = vec![0; SAMPLE_SIZE.min(size as usize)];
+            File::open(&file.local_path)
+                .await?
+                .read_exact(&mut sample)
+                .await?;
+            entries.push((file, size, sample));
+        }
+
+        let modes = self
+            .preupload(
+                repo_id,
+                entries
+                    .iter()
+                    .map(|(file, size, sample)| {
+                        json!({
+                            "path": file.path_in_repo,
+                            "size": size,
+                            "sample": BASE64.encode(sample),
+                        })
+                    })
+                    .collect(),
+            )
+            .await?;
+
+        let mut operations = vec![];
+        for (file, size, _) in entries {
+            let mode = modes.get(&file.path_in_repo);
+            if mode.is_some_and(|m| m.should_ignore) {
+                continue;
+            }
+            if mode.is_some_and(|m| m.upload_mode == "lfs") {
+                let oid = hash_file::<Sha256>(&file.local_path, None).await?;
+                if mode.and_then(|m| m.oid.as_deref()) == Some(oid.as_str()) {
+                    continue;
+                }
+                self.upload_lfs(repo_id, file, &oid, size).await?;
+                operations.push(json!({
+                    "key": "lfsFile",
+                    "value": {
+                        "path": file.path_in_repo,
+                        "algo": "sha256",
+                        "oid": oid,
+                        "size": size,
+                    },
+                }));
+            } else {
+                let oid = hash_file::<Sha1>(&file.local_path, Some(size)).await?;
+                if mode.and_then(|m| m.oid.as_deref()) == Some(oid.as_str()) {
+                    continue;
+                }
+                let content = tokio::fs::read(&file.local_path).await?;
+                operations.push(json!({
+                    "key": "file",
+                    "value": {
+                        "path": file.path_in_repo,
+                        "content": BASE64.encode(content),
+                        "encoding": "base64",
+                    },
+                }));
+            }
+        }
+
+        if operations.is_empty() {
+            self.ctx
+                .detail(format!("🤗 {repo_id} is already up to date."));
+            return Ok(vec![]);
+        }
+        let committed = operations
+            .iter()
+            .filter_map(|op| op["value"]["path"].as_str().map(str::to_string))
+            .collect();
+        self.commit(repo_id, commit_message, operations).await?;
+        Ok(committed)
+    }
+
+    async fn preupload(
+        &self,
+        repo_id: &str,
+        files: Vec<Value>,
+    ) -> Result<HashMap<String, PreuploadFile>, Error> {
+        #[derive(Deserialize)]
+        struct PreuploadResponse {
+            files: Vec<PreuploadFile>,
+        }
+
+        let response = self
+            .client
+            .post(format!(
+                "{}/api/models/{repo_id}/preupload/{}",
+                self.endpoint,
+                self.revision_path()
+            ))
+            .bearer_auth(&self.token)
+            .json(&json!({ "files": files }))
+            .send()
+            .await?;
+        let response: PreuploadResponse = check(response, "preupload").await?.json().await?;
+        Ok(response
+            .files
+            .into_iter()
+            .map(|f| (f.path.clone(), f))
+            .collect())
+    }
+
+    async fn upload_lfs(
+        &self,
+        repo_id: &str,
+        file: &UploadFile,
+        oid: &str,
+        size: u64,
+    ) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct BatchResponse {
+            objects: Vec<LfsBatchObject>,
+        }
+
+        let response = self
+            .client
+            .post(format!(
+                "{}/{repo_id}.git/info/lfs/objects/batch",
+                self.endpoint
+            ))
+            .bearer_auth(&self.token)
+            .header(header::ACCEPT, "application/vnd.git-lfs+json")
+            .header(header::CONTENT_TYPE, "application/vnd.git-lfs+json")
+            .json(&json!({
+                "operation": "upload",
+                "transfers": ["basic", "multipart"],
+                "objects": [{ "oid": oid, "size": size }],
+                "hash_algo": "sha256",
+                "ref": { "name": self.revision },
+            }))
+            .send()
+            .await?;
+        let batch: BatchResponse = check(response, "lfs batch").await?.json().await?;
+        let object = batch
+            .objects
+            .into_iter()
+            .next()
+            .ok_or("lfs batch response contained no objects")?;
+        if let Some(error) = object.error {
+            return Err(format!(
+                "lfs batch error for {} ({}): {}",
+                file.path_in_repo, error.code, error.message
+            )
+            .into());
+        }
+        // NOTE: no actions means the hub already has this object
+        let Some(actions) = object.actions else {
+            return Ok(());
+        };
+
+        if let Some(upload) = actions.upload {
+            self.ctx.detail(format!(
+                "🤗 uploading {} ({size} bytes)...",
+                file.path_in_repo
+            ));
+            if let Some(chunk_size) = upload.header.get("chunk_size") {
+                self.upload_multipart(file, oid, &upload, chunk_size.parse()?)
+                    .await?;
+            } else {
+                let response = self
+                    .client
+                    .put(&upload.href)
+                    .header(header::CONTENT_LENGTH, size)
+                    .body(file_body(&file.local_path, 0, size).await?)
+                    .send()
+                    .await?;
+                check(response, "lfs upload").await?;
+            }
+        }
+
+        if let Some(verify) = actions.verify {
+            let response = self
+                .client
+                .post(&verify.href)
+                .bearer_auth(&self.token)
+                .json(&json!({ "oid": oid, "size": size }))
+                .send()
+                .await?;
+            check(response, "lfs verify").await?;
+        }
+
+        Ok(())
+    }
+
+    async fn upload_multipart(
+        &self,
+        file: &UploadFile,
+        oid: &str,
+        upload: &LfsAction,
+        chunk_size: u64,
+    ) -> Result<(), Error> {
+        let size = tokio::fs::metadata(&file.local_path).await?.len();
+        let mut part_urls = upload
+            .header
+            .iter()
+            .filter_map(|(key, url)| key.parse::<u32>().ok().map(|n| (n, url)))
+            .collect::<Vec<_>>();
+        part_urls.sort_by_key(|(n, _)| *n);
+
+        let n_parts = part_urls.len();
+        let mut parts = Vec::with_capacity(n_parts);
+        for (i, (part_number, url)) in part_urls.into_iter().enumerate() {
+            let offset = i as u64 * chunk_size;
+            let len = chunk_size.min(size - offset);
+            self.ctx.detail(format!(
+                "🤗 uploading {} part {part_number}/{n_parts}...",
+                file.path_in_repo
+            ));
+            let response = self
+                .client
+                .put(url)
+                .header(header::CONTENT_LENGTH, len)
+                .body(file_body(&file.local_path, offset, len).await?)
+                .send()
+                .await?;
+            let response = check(response, "lfs part upload").await?;
+            let etag = response
+                .headers()
+                .get(header::ETAG)
+                .and_then(|v| v.to_str().ok())
+                .ok_or("lfs part upload response is missing an ETag")?
+                .to_string();
+            parts.push(json!({ "partNumber": part_number, "etag": etag }));
+        }
+
+        let response = self
+            .client
+            .post(&upload.href)
+            .header(header::ACCEPT, "application/vnd.git-lfs+json")
+            .header(header::CONTENT_TYPE, "application/vnd.git-lfs+json")
+            .json(&json!({ "oid": oid, "parts": parts }))
+            .send()
+            .await?;
+        check(response, "lfs multipart completion").await?;
+        Ok(())
+    }
+
+    async fn commit(
+        &self,
+        repo_id: &str,
+        summary: &str,
+        operations: Vec<Value>,
+    ) -> Result<(), Error> {
+        let mut body = json!({
+            "key": "header",
+            "value": { "summary": summary, "description": "" },
+        })
+        .to_string();
+        for op in operations {
+            body.push('\n');
+            body.push_str(&op.to_string());
+        }
+        let response = self
+            .client
+            .post(format!(
+                "{}/api/models/{repo_id}/commit/{}",
+                self.endpoint,
+                self.revision_path()
+            ))
+            .bearer_auth(&self.token)
+            .header(header::CONTENT_TYPE, "application/x-ndjson")
+            .body(body)
+            .send()
+            .await?;
+        check(response, "commit").await?;
+        Ok(())
+    }
+}
+
+/// Turn a non-success response into an error carrying the hub's message.
+/// The next page of a paginated listing, from the `Link` header the Hub sends with each page
+/// but the last, e.g. `<https://huggingface.co/api/models?author=x&cursor=...>; rel="next"`.
+fn next_page(headers: &header::HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|link| {
+            let (url, params) = link.split_once(';')?;
+            params
+                .split(';')
+                .any(|param| param.trim().replace(' ', "") == r#"rel="next""#)
+                .then(|| url.trim().strip_prefix('<')?.strip_suffix('>'))
+                .flatten()
+                .map(str::to_string)
+        })
+}
+
+async fn check(response: Response, action: &str) -> Result<Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let url = response.url().clone();
+    let body = response.text().await.unwrap_or_default();
+    Err(Box::new(HubError {
+        status,
+        message: format!("HuggingFace Hub {action} failed ({status}) for {url}: {body}"),
+    }))
+}
+
+/// Whether `e` is worth retrying: a dropped connection, a timeout, rate limiting or a server
+/// error, as opposed to e.g. bad credentials or a missing file.
+pub(crate) fn is_transient(e: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(e) = e.downcast_ref::<HubError>() {
+        is_transient_status(e.status)
+    } else if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+        match e.status() {
+            Some(status) => is_transient_status(status),
+            None => e.is_connect() || e.is_timeout() || e.is_request() || e.is_body(),
+        }
+    } else if let Some(e) = e.downcast_ref::<ApiError>() {
+        match e {
+            ApiError::RequestError(e) => is_transient(e),
+            ApiError::TooManyRetries(e) => is_transient(e.as_ref()),
+            _ => false,
+        }
+    } else {
+        false
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error()
+}
+
+/// Stream `len` bytes of `path` starting at `offset`.
+async fn file_body(path: &Path, offset: u64, len: u64) -> Result<Body, Error> {
+    let mut f = File::open(path).await?;
+    f.seek(std::io::SeekFrom::Start(offset)).await?;
+    Ok(Body::wrap_stream(ReaderStream::new(f.take(len))))
+}
+
+/// Hex digest of a file. With `git_blob_size`, hashes it as a git blob object, which is how the
+/// hub identifies regular (non-lfs) files.
+pub(crate) async fn hash_file<D: Digest>(
+    path: &Path,
+    git_blob_size: Option<u64>,
+) -> Result<String, Error> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut hasher = D::new();
+        if let Some(size) = git_blob_size {
+            hasher.update(format!("blob {size}\0"));
+        }
+        let mut f = std::fs::File::open(path)?;
+        let mut buf = vec![0; 8 * 1024 * 1024];
+        loop {
+            let n = f.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        let digest = hasher.finalize();
+        Ok(digest.iter().map(|b| format!("{b:02x}")).collect())
+    })
+    .await?
+}
+
+/// Every file in `dir` (not recursing) whose name ends with one of `extensions`.
+pub(crate) async fn files_with_extensions(
+    dir: &Path,
+    extensions: &[&str],
+) -> Result<Vec<UploadFile>, Error> {
+    let mut files = vec![];
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type().await?.is_file() && extensions.iter().any(|ext| name.ends_with(ext)) {
+            files.push(UploadFile {
+                local_path: entry.path(),
+                path_in_repo: name,
+            });
+        }
+    }
+    files.sort_by(|a, b| a.path_in_repo.cmp(&b.path_in_repo));
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    fn links(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(header::LINK, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn rate_limits_and_server_errors_are_transient() {
+        for status in [
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::REQUEST_TIMEOUT,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ] {
+            assert!(is_transient(&HubError::new(status, "")), "{status}");
+        }
+    }
+
+    #[test]
+    fn client_errors_are_permanent() {
+        for status in [
+            StatusCode::BAD_REQUEST,
+            StatusCode::UNAUTHORIZED,
+            StatusCode::FORBIDDEN,
+            StatusCode::NOT_FOUND,
+        ] {
+            assert!(!is_transient(&HubError::new(status, "")), "{status}");
+        }
+        let io = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        assert!(!is_transient(&io));
+    }
+
+    #[tokio::test]
+    async fn a_refused_connection_is_transient() {
+        // NOTE: nothing listens on port 1, so this fails to connect rather than getting a response
+        let e = reqwest::get("http://127.0.0.1:1/").await.unwrap_err();
+        assert!(e.is_connect());
+        assert!(is_transient(&e));
+        assert!(is_transient(&ApiError::RequestError(e)));
+    }
+
+    #[test]
+    fn next_page_follows_the_next_link() {
+        let headers =
+            links(&[r#"<https://huggingface.co/api/models?author=org&cursor=abc>; rel="next""#]);
+        assert_eq!(
+            next_page(&headers).as_deref(),
+            Some("https://huggingface.co/api/models?author=org&cursor=abc")
+        );
+        let headers = links(&[r#"<https://a/prev>; rel="prev", <https://a/next>; rel = "next""#]);
+        assert_eq!(next_page(&headers).as_deref(), Some("https://a/next"));
+    }
+
+    #[test]
+    fn next_page_runs_out_on_the_last_page() {
+        assert_eq!(next_page(&HeaderMap::new()), None);
+        assert_eq!(
+            next_page(&links(&[r#"<https://a/prev>; rel="prev""#])),
+            None
+        );
+    }
+}
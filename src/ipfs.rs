@@ -0,0 +1,63 @@
+//! Pinning the GGUFs to IPFS with kubo's `ipfs` CLI, so they can also be fetched by content
+//! address from any gateway or peer. Whichever node the CLI is set up to talk to pins them, and
+//! optionally a remote pinning service configured on it too.
+
+use crate::{context::Context, event::Stage, hub::UploadFile};
+use tokio::process::Command;
+
+/// A file added to IPFS.
+#[derive(Debug, Clone)]
+pub(crate) struct Pinned {
+    /// Where the file is in the repo.
+    pub path: String,
+    pub cid: String,
+}
+
+/// Add each GGUF among `files` to IPFS, pinned on the local node and, with `service`, on that
+/// remote pinning service from `ipfs pin remote service ls` too, returning their CIDs.
+pub(crate) async fn pin(
+    files: &[UploadFile],
+    service: Option<&str>,
+    ctx: &Context,
+) -> Result<Vec<Pinned>, Box<dyn std::error::Error + Send + Sync>> {
+    let ggufs: Vec<_> = files
+        .iter()
+        .filter(|f| f.path_in_repo.ends_with(".gguf"))
+        .collect();
+    ctx.detail(format!("📌 adding {} files to IPFS...", ggufs.len()));
+    let mut pinned = vec![];
+    for file in ggufs {
+        let mut add = Command::new("ipfs");
+        add.args(["add", "--cid-version=1", "--pin=true", "--quieter"])
+            .arg(&file.local_path);
+        let description = format!("ipfs add of {}", file.path_in_repo);
+        let output = ctx
+            .run_capturing(&Stage::Upload, add, &description)
+            .await
+            .map_err(|e| e.to_string())?;
+        let cid = output
+            .first()
+            .map(|line| line.trim().to_string())
+            .filter(|cid| !cid.is_empty())
+            .ok_or_else(|| format!("{description} printed no CID"))?;
+        if let Some(service) = service {
+            // NOTE: in the background, since the service fetches multi-gigabyte files slowly
+            let mut remote = Command::new("ipfs");
+            remote
+                .args(["pin", "remote", "add", "--background"])
+                .arg(format!("--service={service}"))
+                .arg(format!("--name={}", file.path_in_repo))
+                .arg(&cid);
+            let description = format!("ipfs pin remote add of {}", file.path_in_repo);
+            ctx.run(&Stage::Upload, remote, &description)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        ctx.detail(format!("📌 pinned {} as {cid}", file.path_in_repo));
+        pinned.push(Pinned {
+            path: file.path_in_repo.clone(),
+            cid,
+        });
+    }
+    Ok(pinned)
+}
@@ -0,0 +1,97 @@
+//! Convert HuggingFace models to GGUF automatically.
+//!
+//! The [`Pipeline`] drives llama.cpp and the HuggingFace Hub through each stage of a
+//! conversion: download, full-precision conversion, imatrix generation, quantization, and
+//! upload. Progress is logged with [`tracing`], and can also be received as [`Event`]s via
+//! [`PipelineBuilder::events`].
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use autogguf::{Pipeline, QuantLevel};
+//!
+//! let report = Pipeline::builder("mistralai/Mistral-7B-Instruct-v0.3")
+//!     .quants([QuantLevel::Q4KM, QuantLevel::Q8_0])
+//!     .skip_upload(true)
+//!     .build()
+//!     .run()
+//!     .await?;
+//! for quant in report.quants {
+//!     println!("{}", quant.path.display());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod audit;
+mod card;
+mod chat_template;
+mod checksums;
+mod cleanup;
+pub mod config;
+mod context;
+mod convert;
+mod doctor;
+mod embedding;
+mod error;
+mod estimate;
+mod eval;
+mod event;
+mod gguf;
+mod hf;
+mod hub;
+mod ipfs;
+mod llama;
+mod llamafile;
+mod lmstudio;
+mod lora;
+mod memory;
+mod mirror;
+mod mmproj;
+mod naming;
+mod native;
+mod pipeline;
+mod plan;
+mod quant;
+mod retry;
+mod run_lock;
+mod sign;
+mod state;
+mod storage;
+mod summary;
+mod timeout;
+mod torrent;
+mod verify;
+mod watch;
+mod webhook;
+
+pub use audit::QuantAudit;
+pub use chat_template::ChatTemplate;
+pub use cleanup::Cleanup;
+pub use config::Config;
+pub use context::kill_subprocesses;
+pub use doctor::{Check, CheckStatus};
+pub use error::Error;
+pub use estimate::{estimate, Estimate};
+pub use eval::{Bench, EvalResults, KlDivergence, Perplexity};
+pub use event::{Event, Stage, SPAN_TARGET};
+pub use gguf::{inspect, Inspection, MetadataOverride};
+pub use llama::{LlamaBackend, PythonInstaller};
+pub use llamafile::DEFAULT_RUNTIME as DEFAULT_LLAMAFILE_RUNTIME;
+pub use lora::LoraAdapter;
+pub use mirror::Mirror;
+pub use naming::{NameTemplate, DEFAULT_NAME_TEMPLATE};
+pub use pipeline::{
+    Converted, Downloaded, ImatrixGenerated, Pipeline, PipelineBuilder, PipelineReport, Quantized,
+};
+pub use plan::{Decision, Plan, PlannedStage};
+pub use quant::{
+    Precision, QuantLevel, QuantPreset, QuantSpec, TensorTypeOverride, DEFAULT_QUANTS,
+};
+pub use run_lock::RUN_LOCK_FILE;
+pub use state::PipelineState;
+pub use storage::Bucket;
+pub use summary::{RunSummary, StageStatus, StageSummary, SUMMARY_FILE};
+pub use timeout::{StageTimeout, TimedStage};
+pub use verify::Verification;
+pub use watch::{Changed, Watcher, WATCH_FILE};
+pub use webhook::{Notification, Webhook};
@@ -0,0 +1,610 @@
+use crate::{context::Context, convert::CONVERT_SCRIPT, event::Stage};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+/// The ggml backend to build llama.cpp with. Mostly matters for imatrix generation, which is
+/// orders of magnitude faster offloaded to a GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LlamaBackend {
+    Cuda,
+    Metal,
+    Vulkan,
+    /// AMD GPUs, via HIP.
+    Rocm,
+    /// No GPU offload, not even Metal on macOS.
+    Cpu,
+}
+
+impl Display for LlamaBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            LlamaBackend::Cuda => "cuda",
+            LlamaBackend::Metal => "metal",
+            LlamaBackend::Vulkan => "vulkan",
+            LlamaBackend::Rocm => "rocm",
+            LlamaBackend::Cpu => "cpu",
+        };
+        write!(f, "{label}")
+    }
+}
+
+impl LlamaBackend {
+    /// CMake flags turning this backend on and every other off, so switching backends in an
+    /// existing build dir doesn't leave the old one enabled in the CMake cache.
+    pub(crate) fn cmake_flags(&self) -> Vec<String> {
+        [
+            ("GGML_CUDA", LlamaBackend::Cuda),
+            ("GGML_METAL", LlamaBackend::Metal),
+            ("GGML_VULKAN", LlamaBackend::Vulkan),
+            ("GGML_HIP", LlamaBackend::Rocm),
+        ]
+        .iter()
+        .map(|(flag, backend)| {
+            let value = if backend == self { "ON" } else { "OFF" };
+            format!("-D{flag}={value}")
+        })
+        .collect()
+    }
+}
+
+/// The llama.cpp binary `name` in `llama_bin`, with `.exe` on Windows.
+pub(crate) fn binary(llama_bin: &Path, name: &str) -> PathBuf {
+    llama_bin.join(format!("{name}{}", std::env::consts::EXE_SUFFIX))
+}
+
+/// The system Python interpreter: `python3` where there is one, else
+/// `python`, which is all the python.org installer puts on PATH on Windows.
+pub(crate) fn python() -> &'static str {
+    static PYTHON: std::sync::OnceLock<&'static str> = std::sync::OnceLock::new();
+    PYTHON.get_or_init(|| {
+        let has_python3 = std::process::Command::new("python3")
+            .arg("--version")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if has_python3 {
+            "python3"
+        } else {
+            "python"
+        }
+    })
+}
+
+/// What installs llama.cpp's Python deps into its venv.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PythonInstaller {
+    /// Much faster than pip, especially reinstalling after each llama.cpp update.
+    Uv,
+    Pip,
+}
+
+impl Display for PythonInstaller {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            PythonInstaller::Uv => "uv",
+            PythonInstaller::Pip => "pip",
+        };
+        write!(f, "{label}")
+    }
+}
+
+impl PythonInstaller {
+    /// `uv` if it's on PATH, else `pip`.
+    fn detect() -> Self {
+        let has_uv = std::process::Command::new("uv")
+            .arg("--version")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        match has_uv {
+            true => PythonInstaller::Uv,
+            false => PythonInstaller::Pip,
+        }
+    }
+}
+
+/// The virtualenv in the llama.cpp dir its Python deps are installed into, so they don't
+/// conflict with (or clobber) packages in the system interpreter.
+pub(crate) const VENV_DIR: &str = ".venv";
+
+/// The interpreter of the [`VENV_DIR`] venv in `llama_path`.
+pub(crate) fn venv_python(llama_path: &Path) -> PathBuf {
+    let venv = llama_path.join(VENV_DIR);
+    match cfg!(windows) {
+        true => venv.join("Scripts").join("python.exe"),
+        false => venv.join("bin").join("python"),
+    }
+}
+
+/// Records which llama.cpp a model's outputs were made with, so they can be reproduced.
+pub(crate) const LOCK_FILE: &str = "llama.cpp.lock";
+
+/// The contents of [`LOCK_FILE`]: the commit llama.cpp was at, and the tag or commit it was
+/// pinned to, if any.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct LlamaLock {
+    #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+    pub commit: String,
+}
+
+impl LlamaLock {
+    /// The lock in `model_dir`, if there is a valid one.
+    pub async fn load(model_dir: &Path) -> Option<Self> {
+        let contents = tokio::fs::read_to_string(model_dir.join(LOCK_FILE))
+            .await
+            .ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub async fn save(&self, model_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        tokio::fs::write(
+            model_dir.join(LOCK_FILE),
+            serde_json::to_string_pretty(self)?,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// The full commit hash checked out at `llama_path`, if it's a git repo.
+pub(crate) async fn head_commit(llama_path: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("rev-parse")
+        .arg("HEAD")
+        .current_dir(llama_path)
+        .output()
+        .await
+        .ok()?;
+    let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !commit.is_empty()).then_some(commit)
+}
+
+/// Clone or pull llama.cpp at `llama_path`, or check out `git_ref` (a tag or commit) if given,
+/// build it with CMake into `build_dir` for `backend` (CMake's defaults if `None`), and install
+/// the Python deps `convert_hf_to_gguf.py` needs with `installer` (uv if available if `None`).
+pub(crate) async fn update_llama_cpp(
+    llama_path: PathBuf,
+    git_ref: Option<&str>,
+    build_dir: PathBuf,
+    backend: Option<LlamaBackend>,
+    installer: Option<PythonInstaller>,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stage = Stage::UpdateLlama;
+    checkout(&llama_path, git_ref, ctx).await?;
+
+    ctx.detail("🐪 compiling llama.cpp...");
+    let mut configure = Command::new("cmake");
+    configure
+        .arg("-B")
+        .arg(&build_dir)
+        .arg("-DCMAKE_BUILD_TYPE=Release")
+        .args(backend.map(|b| b.cmake_flags()).unwrap_or_default())
+        .current_dir(&llama_path);
+    ctx.run(&stage, configure, "Llama.cpp build configure process")
+        .await?;
+
+    let jobs = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut build = Command::new("cmake");
+    build
+        .arg("--build")
+        .arg(&build_dir)
+        .args(["--config", "Release", "-j"])
+        .arg(jobs.to_string())
+        .current_dir(&llama_path);
+    ctx.run(&stage, build, "Llama.cpp build process").await?;
+
+    install_python_deps(&llama_path, installer, ctx).await
+}
+
+/// Install the official llama.cpp release binaries for this platform and `backend` into `bin`
+/// in `build_dir`, instead of compiling them, and check out the matching source at
+/// `llama_path` for `convert_hf_to_gguf.py`. Installs release `tag` if given, else the latest.
+pub(crate) async fn install_prebuilt(
+    llama_path: PathBuf,
+    tag: Option<&str>,
+    build_dir: PathBuf,
+    backend: Option<LlamaBackend>,
+    installer: Option<PythonInstaller>,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stage = Stage::UpdateLlama;
+    let api = std::env::var("GITHUB_API_URL").unwrap_or_else(|_| GITHUB_API.to_string());
+    let url = match tag {
+        Some(tag) => format!("{api}/repos/{LLAMA_REPO}/releases/tags/{tag}"),
+        None => format!("{api}/repos/{LLAMA_REPO}/releases/latest"),
+    };
+    let client = reqwest::Client::builder().user_agent("autogguf").build()?;
+    let release: Release = client
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()
+        .map_err(|e| {
+            format!(
+                "couldn't find llama.cpp release {}: {e}",
+                tag.unwrap_or("latest")
+            )
+        })?
+        .json()
+        .await?;
+    let platform = Platform::current(backend)?;
+    let Some(asset) = release.assets.iter().find(|a| platform.matches(&a.name)) else {
+        let names: Vec<&str> = release.assets.iter().map(|a| a.name.as_str()).collect();
+        return Err(format!(
+            "llama.cpp {} has no prebuilt binaries for {platform}; available: {}",
+            release.tag_name,
+            names.join(", ")
+        )
+        .into());
+    };
+
+    checkout(&llama_path, Some(&release.tag_name), ctx).await?;
+
+    ctx.detail(format!("🐪 downloading {}...", asset.name));
+    tokio::fs::create_dir_all(&build_dir).await?;
+    let archive = build_dir.join(&asset.name);
+    let mut response = client
+        .get(&asset.browser_download_url)
+        .send()
+        .await?
+        .error_for_status()?;
+    let mut file = tokio::fs::File::create(&archive).await?;
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+
+    let extracted = build_dir.join("release");
+    if tokio::fs::try_exists(&extracted).await? {
+        tokio::fs::remove_dir_all(&extracted).await?;
+    }
+    tokio::fs::create_dir_all(&extracted).await?;
+    let extract = match asset.name.ends_with(".zip") {
+        true => unzip_command(&archive, &extracted),
+        false => {
+            let mut tar = Command::new("tar");
+            tar.arg("-xzf").arg(&archive).arg("-C").arg(&extracted);
+            tar
+        }
+    };
+    ctx.run(&stage, extract, "Llama.cpp release extraction process")
+        .await?;
+    // NOTE: archive layouts vary between releases (build/bin/, a versioned dir, or flat), so
+    // look for the binaries rather than assume where they are
+    let quantize = binary(Path::new(""), "llama-quantize");
+    let Some(found) = find_dir_containing(&extracted, &quantize).await? else {
+        return Err(format!("no llama-quantize in {}", asset.name).into());
+    };
+    let bin = build_dir.join("bin");
+    if tokio::fs::try_exists(&bin).await? {
+        tokio::fs::remove_dir_all(&bin).await?;
+    }
+    tokio::fs::rename(&found, &bin).await?;
+    tokio::fs::remove_dir_all(&extracted).await?;
+    tokio::fs::remove_file(&archive).await?;
+    ctx.detail(format!(
+        "🐪 installed llama.cpp {} binaries in {}",
+        release.tag_name,
+        bin.display()
+    ));
+
+    install_python_deps(&llama_path, installer, ctx).await
+}
+
+/// Extract the zip `archive` into `dir`.
+pub(crate) fn unzip_command(archive: &Path, dir: &Path) -> Command {
+    // NOTE: Windows has no unzip, but its bsdtar reads zips too
+    if cfg!(windows) {
+        let mut tar = Command::new("tar");
+        tar.arg("-xf").arg(archive).arg("-C").arg(dir);
+        return tar;
+    }
+    let mut unzip = Command::new("unzip");
+    unzip.arg("-q").arg(archive).arg("-d").arg(dir);
+    unzip
+}
+
+const GITHUB_API: &str = "https://api.github.com";
+const LLAMA_REPO: &str = "ggerganov/llama.cpp";
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReleaseAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Which release archive fits this machine, as named in e.g.
+/// `llama-b4500-bin-ubuntu-vulkan-x64.zip`: the OS, a backend flavor, and the architecture.
+struct Platform {
+    os: &'static str,
+    flavor: &'static str,
+    arch: &'static str,
+}
+
+impl Platform {
+    fn current(backend: Option<LlamaBackend>) -> Result<Self, String> {
+        let os = match std::env::consts::OS {
+            "linux" => "ubuntu",
+            "macos" => "macos",
+            "windows" => "win",
+            os => return Err(format!("llama.cpp doesn't publish binaries for {os}")),
+        };
+        let arch = match std::env::consts::ARCH {
+            "x86_64" => "x64",
+            "aarch64" => "arm64",
+            arch => return Err(format!("llama.cpp doesn't publish binaries for {arch}")),
+        };
+        let flavor = match (backend, os) {
+            (None | Some(LlamaBackend::Cpu), "win") => "cpu",
+            (None | Some(LlamaBackend::Cpu), _) => "",
+            (Some(LlamaBackend::Metal), "macos") => "",
+            (Some(LlamaBackend::Metal), _) => {
+                return Err("Metal binaries are only published for macOS".to_string())
+            }
+            (Some(LlamaBackend::Cuda), _) => "cuda",
+            (Some(LlamaBackend::Vulkan), _) => "vulkan",
+            (Some(LlamaBackend::Rocm), _) => "hip",
+        };
+        Ok(Self { os, flavor, arch })
+    }
+
+    fn matches(&self, asset: &str) -> bool {
+        let Some((_, rest)) = asset.split_once(&format!("-bin-{}-", self.os)) else {
+            return false;
+        };
+        let Some(middle) = [".zip", ".tar.gz"]
+            .iter()
+            .find_map(|ext| rest.strip_suffix(&format!("{}{ext}", self.arch)))
+        else {
+            return false;
+        };
+        match self.flavor {
+            "" => middle.is_empty(),
+            // NOTE: flavors can carry a version, like `cuda-cu12.4-`
+            flavor => middle.starts_with(flavor),
+        }
+    }
+}
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.flavor {
+            "" => write!(f, "{}-{}", self.os, self.arch),
+            flavor => write!(f, "{}-{flavor}-{}", self.os, self.arch),
+        }
+    }
+}
+
+/// The first directory under `root` (breadth-first, including `root`) with a file named `name`.
+async fn find_dir_containing(root: &Path, name: &Path) -> std::io::Result<Option<PathBuf>> {
+    let mut queue = std::collections::VecDeque::from([root.to_path_buf()]);
+    while let Some(dir) = queue.pop_front() {
+        if tokio::fs::try_exists(dir.join(name)).await? {
+            return Ok(Some(dir));
+        }
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                queue.push_back(entry.path());
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Clone llama.cpp to `llama_path` if it isn't there yet, then check out `git_ref`, or pull the
+/// latest without one.
+async fn checkout(
+    llama_path: &Path,
+    git_ref: Option<&str>,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stage = Stage::UpdateLlama;
+    if !llama_path.exists() {
+        ctx.detail(format!(
+            "🐪 llama.cpp not found at {}, installing...",
+            llama_path.display()
+        ));
+        let mut clone = Command::new("git");
+        clone
+            .arg("clone")
+            .arg(format!("https://github.com/{LLAMA_REPO}"))
+            .arg(llama_path);
+        ctx.run(&stage, clone, "Llama.cpp installation process")
+            .await?;
+    }
+
+    match git_ref {
+        Some(git_ref) => {
+            let mut fetch = Command::new("git");
+            fetch
+                .args(["fetch", "--tags", "origin"])
+                .current_dir(llama_path);
+            ctx.run(&stage, fetch, "Llama.cpp update process").await?;
+            let mut checkout = Command::new("git");
+            checkout
+                .args(["checkout", "--detach", git_ref])
+                .current_dir(llama_path);
+            ctx.run(&stage, checkout, "Llama.cpp checkout process")
+                .await?;
+        }
+        None => {
+            let mut pull = Command::new("git");
+            pull.arg("pull").current_dir(llama_path);
+            ctx.run(&stage, pull, "Llama.cpp update process").await?;
+        }
+    }
+    Ok(())
+}
+
+/// Where the hash of the requirements last installed into the venv is kept, relative to it.
+const REQUIREMENTS_HASH_FILE: &str = "requirements.sha256";
+
+/// A hash of `requirements.txt` in `llama_path` and the `requirements/` files it pulls in.
+async fn requirements_hash(llama_path: &Path) -> std::io::Result<String> {
+    let mut files = vec![llama_path.join("requirements.txt")];
+    let nested = llama_path.join("requirements");
+    if tokio::fs::try_exists(&nested).await? {
+        let mut entries = tokio::fs::read_dir(&nested).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            files.push(entry.path());
+        }
+    }
+    files[1..].sort();
+
+    let mut hasher = Sha256::new();
+    for file in files {
+        hasher.update(
+            file.strip_prefix(llama_path)
+                .unwrap_or(&file)
+                .to_string_lossy()
+                .as_bytes(),
+        );
+        hasher.update(tokio::fs::read(&file).await?);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Install llama.cpp's `requirements.txt` into its [`VENV_DIR`] venv with `installer`, creating
+/// the venv if needed. Skipped when the requirements haven't changed since the last install.
+async fn install_python_deps(
+    llama_path: &Path,
+    installer: Option<PythonInstaller>,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stage = Stage::UpdateLlama;
+    let python = venv_python(llama_path);
+    let hash = requirements_hash(llama_path).await?;
+    let hash_file = llama_path.join(VENV_DIR).join(REQUIREMENTS_HASH_FILE);
+    let python_exists = tokio::fs::try_exists(&python).await?;
+    if python_exists && tokio::fs::read_to_string(&hash_file).await.ok() == Some(hash.clone()) {
+        ctx.detail("🐪 llama.cpp python deps unchanged, skipping install.");
+        return Ok(());
+    }
+
+    let installer = installer.unwrap_or_else(PythonInstaller::detect);
+    if !python_exists {
+        let venv_dir = llama_path.join(VENV_DIR);
+        ctx.detail(format!(
+            "🐪 creating a python venv in {}...",
+            venv_dir.display()
+        ));
+        let venv = match installer {
+            // NOTE: seeded with pip, so switching back to --python-installer pip still works
+            PythonInstaller::Uv => {
+                let mut venv = Command::new("uv");
+                venv.args(["venv", "--seed"]).arg(venv_dir);
+                venv
+            }
+            PythonInstaller::Pip => {
+                let mut venv = Command::new(self::python());
+                venv.args(["-m", "venv"]).arg(venv_dir);
+                venv
+            }
+        };
+        ctx.run(&stage, venv, "Python venv creation process")
+            .await?;
+    }
+
+    ctx.detail(format!(
+        "🐪 installing llama.cpp python deps with {installer}..."
+    ));
+    // NOTE: no current_dir, since the venv's python is relative when llama_path is
+    let mut deps = match installer {
+        PythonInstaller::Uv => {
+            let mut deps = Command::new("uv");
+            deps.args(["pip", "install", "--python"]).arg(&python);
+            deps
+        }
+        PythonInstaller::Pip => {
+            let mut deps = Command::new(&python);
+            deps.args(["-m", "pip", "install"]);
+            deps
+        }
+    };
+    deps.arg("-r")
+        .arg(llama_path.join("requirements.txt"))
+        .arg(if ctx.verbose { "-v" } else { "-q" });
+    ctx.run(&stage, deps, "Llama.cpp build process").await?;
+    tokio::fs::write(&hash_file, hash).await?;
+
+    Ok(())
+}
+
+/// The model architectures `convert_hf_to_gguf.py` at `llama_path` can convert, from the
+/// `@ModelBase.register("LlamaForCausalLM", ...)` decorators on its model classes. `None` if
+/// the script couldn't be read or no registrations were found.
+pub(crate) async fn supported_architectures(llama_path: &Path) -> Option<Vec<String>> {
+    let script = tokio::fs::read_to_string(llama_path.join(CONVERT_SCRIPT))
+        .await
+        .ok()?;
+    let mut architectures = vec![];
+    for (i, _) in script.match_indices(".register(") {
+        let line_start = script[..i].rfind('\n').map_or(0, |n| n + 1);
+        if !script[line_start..i].trim_start().starts_with('@') {
+            continue;
+        }
+        let args = &script[i + ".register(".len()..];
+        let Some(end) = args.find(')') else {
+            continue;
+        };
+        architectures.extend(
+            args[..end]
+                .split('"')
+                .skip(1)
+                .step_by(2)
+                .map(str::to_string),
+        );
+    }
+    (!architectures.is_empty()).then_some(architectures)
+}
+
+/// The quant types the `llama-quantize` in `llama_bin` accepts, lowercased, parsed from the
+/// "Allowed quantization types" table in its `--help`. `None` if it couldn't be run or the
+/// table wasn't found, e.g. because the format changed.
+pub(crate) async fn supported_quant_types(llama_bin: &Path) -> Option<Vec<String>> {
+    let output = Command::new(binary(llama_bin, "llama-quantize"))
+        .arg("--help")
+        .output()
+        .await
+        .ok()?;
+    // NOTE: it exits non-zero after printing usage, so the status is no signal either way
+    let help = String::from_utf8_lossy(&output.stdout) + String::from_utf8_lossy(&output.stderr);
+    let (_, table) = help.split_once("Allowed quantization types:")?;
+    let types: Vec<String> = table.lines().filter_map(parse_quant_type_line).collect();
+    (!types.is_empty()).then_some(types)
+}
+
+/// The type name from an allowed-types line, `   2  or  Q4_0    :  4.34G, +0.4685 ppl` or
+/// `          COPY    : only copy tensors, no quantizing`.
+fn parse_quant_type_line(line: &str) -> Option<String> {
+    let (entry, _) = line.split_once(':')?;
+    let name = match entry.split_whitespace().collect::<Vec<_>>()[..] {
+        [id, "or", name] if id.parse::<u32>().is_ok() => name,
+        [name] if !name.starts_with('-') => name,
+        _ => return None,
+    };
+    Some(name.to_lowercase())
+}
@@ -0,0 +1,92 @@
+//! Packaging a quant as a llamafile: the llamafile runtime with the GGUF zipped inside, a single
+//! file that runs the model on Linux, macOS and Windows with nothing to install.
+
+use crate::{context::Context, convert::move_file, event::Stage};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// The llamafile runtime, looked up on the PATH unless given as a path.
+pub const DEFAULT_RUNTIME: &str = "llamafile";
+
+/// What each llamafile starts with, so it loads the GGUF inside it. The `...` lets whoever runs it
+/// pass more arguments.
+const ARGS: &str = "-m\n{gguf}\n...\n";
+
+/// Where `program` is: at its path if it's one, or else on the PATH. Absolute, so it can be run
+/// from another directory.
+fn find(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return program
+            .is_file()
+            .then(|| std::path::absolute(program).ok())
+            .flatten();
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+/// Where the llamafile for `gguf` goes: next to it, named like it, e.g.
+/// `llama-3.1-8b.Q4_K_M.llamafile`.
+pub(crate) fn path(gguf: &Path) -> PathBuf {
+    gguf.with_extension("llamafile")
+}
+
+/// Package `gguf` into a llamafile at [`path`]: a copy of `runtime` with the GGUF and the
+/// arguments to load it added to its zip, aligned by the `zipalign` from llamafile's releases,
+/// found next to `runtime` or on the PATH.
+pub(crate) async fn package(
+    runtime: &Path,
+    gguf: &Path,
+    stage: &Stage,
+    ctx: &Context,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let not_found = |program: &Path| {
+        format!(
+            "couldn't find {}; download it from https://github.com/Mozilla-Ocho/llamafile/releases",
+            program.display()
+        )
+    };
+    let runtime = find(runtime).ok_or_else(|| not_found(runtime))?;
+    let zipalign = runtime
+        .parent()
+        .map(|dir| dir.join("zipalign"))
+        .filter(|path| path.is_file())
+        .or_else(|| find(Path::new("zipalign")))
+        .ok_or_else(|| not_found(Path::new("zipalign")))?;
+    let (Some(dir), Some(name)) = (gguf.parent(), gguf.file_name()) else {
+        return Err(format!("{} isn't a file", gguf.display()).into());
+    };
+    let output = path(gguf);
+    let pending = PathBuf::from(format!("{}.pending", output.to_string_lossy()));
+    ctx.detail(format!(
+        "📦 packaging {} with {} into {}...",
+        name.to_string_lossy(),
+        runtime.display(),
+        output.display()
+    ));
+    tokio::fs::copy(&runtime, &pending).await?;
+    let args = dir.join(".args");
+    tokio::fs::write(&args, ARGS.replace("{gguf}", &name.to_string_lossy())).await?;
+    // NOTE: the zip's entries are named as given, so both are passed relative to their directory
+    let mut command = Command::new(&zipalign);
+    command
+        .current_dir(dir)
+        .arg("-j0")
+        .arg(std::path::absolute(&pending)?)
+        .arg(name)
+        .arg(".args");
+    let zipped = ctx.run(stage, command, "zipalign").await;
+    let _ = tokio::fs::remove_file(&args).await;
+    if let Err(e) = zipped {
+        let _ = tokio::fs::remove_file(&pending).await;
+        return Err(e);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(&pending, std::fs::Permissions::from_mode(0o755)).await?;
+    }
+    move_file(&pending, &output).await?;
+    Ok(output)
+}
@@ -0,0 +1,92 @@
+//! The manifest LM Studio reads to list a sideloaded model, so the output directory can be
+//! dropped into its models directory as `{owner}/{name}` and show up with the right context
+//! length and chat template.
+
+use crate::{chat_template::CHAT_TEMPLATE_KEY, gguf::Header};
+use serde::Serialize;
+use serde_json::Value as Json;
+use std::path::{Path, PathBuf};
+
+pub(crate) const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Manifest {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    owner: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    architecture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chat_template: Option<String>,
+    files: Vec<ManifestFile>,
+}
+
+/// One GGUF of a quant; a split quant has one per shard, the first of which LM Studio loads.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestFile {
+    name: String,
+    quant: String,
+    size_bytes: u64,
+}
+
+impl Manifest {
+    /// A manifest for the repo `repo_id`, described by the metadata in `header`, which every
+    /// quant shares with the full-precision GGUF.
+    pub fn new(repo_id: &str, header: Option<&Header>) -> Self {
+        // NOTE: without an HF user to upload as, there's no owner to go by
+        let (owner, name) = match repo_id.split_once('/') {
+            Some((owner, name)) if !owner.is_empty() => (owner, name),
+            Some((_, name)) => ("local", name),
+            None => ("local", repo_id),
+        };
+        let architecture = header
+            .and_then(|h| h.get("general.architecture"))
+            .and_then(Json::as_str)
+            .map(str::to_string);
+        let context_length = header.zip(architecture.as_ref()).and_then(|(h, arch)| {
+            h.get(&format!("{arch}.context_length"))
+                .and_then(Json::as_u64)
+        });
+        Self {
+            kind: "model",
+            owner: owner.to_string(),
+            name: name.to_string(),
+            architecture,
+            context_length,
+            chat_template: header
+                .and_then(|h| h.get(CHAT_TEMPLATE_KEY))
+                .and_then(Json::as_str)
+                .map(str::to_string),
+            files: vec![],
+        }
+    }
+
+    /// List the `files` of the quant labelled `quant`, e.g. `Q4_K_M`.
+    pub async fn add(&mut self, quant: &str, files: &[PathBuf]) {
+        for file in files {
+            let Some(name) = file.file_name() else {
+                continue;
+            };
+            self.files.push(ManifestFile {
+                name: name.to_string_lossy().into_owned(),
+                quant: quant.to_string(),
+                size_bytes: tokio::fs::metadata(file)
+                    .await
+                    .map(|m| m.len())
+                    .unwrap_or_default(),
+            });
+        }
+    }
+
+    pub async fn save(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        let path = dir.join(MANIFEST_FILE);
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(&path, json).await?;
+        Ok(path)
+    }
+}
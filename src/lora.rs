@@ -0,0 +1,177 @@
+//! LoRA adapters, converted to GGUF with llama.cpp's `convert_lora_to_gguf.py` against the base
+//! model, so they can be loaded on top of its GGUFs with `--lora`, or merged into its
+//! full-precision GGUF with `llama-export-lora` to publish a fine-tune as standalone quants.
+
+use crate::{context::Context, convert::move_file, event::Stage, llama, Precision};
+use shellexpand::tilde;
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tokio::process::Command;
+
+/// llama.cpp's LoRA-to-GGUF converter, relative to the repo root.
+pub(crate) const CONVERT_LORA_SCRIPT: &str = "convert_lora_to_gguf.py";
+
+/// A PEFT LoRA adapter: a local directory, or a HuggingFace repo to download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoraAdapter {
+    Local(PathBuf),
+    Hub(String),
+}
+
+impl FromStr for LoraAdapter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let path = PathBuf::from(tilde(s).into_owned());
+        if path.is_dir() {
+            return Ok(Self::Local(path));
+        }
+        match s.split_once('/') {
+            Some((owner, name)) if !owner.is_empty() && !name.is_empty() && !name.contains('/') => {
+                Ok(Self::Hub(s.to_string()))
+            }
+            _ => Err(format!(
+                "'{s}' isn't a directory or a HuggingFace repo like owner/adapter"
+            )),
+        }
+    }
+}
+
+impl Display for LoraAdapter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Local(path) => write!(f, "{}", path.display()),
+            Self::Hub(repo_id) => write!(f, "{repo_id}"),
+        }
+    }
+}
+
+impl LoraAdapter {
+    /// The adapter's name: its repo's, or its directory's.
+    pub fn name(&self) -> String {
+        match self {
+            Self::Local(path) => std::fs::canonicalize(path)
+                .unwrap_or_else(|_| path.clone())
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            Self::Hub(repo_id) => repo_id.rsplit('/').next().unwrap_or(repo_id).to_string(),
+        }
+    }
+
+    /// Where the adapter's files are: its own directory, or where a Hub adapter is downloaded
+    /// to under `work_dir`.
+    pub(crate) fn dir(&self, work_dir: &Path) -> PathBuf {
+        match self {
+            Self::Local(path) => path.clone(),
+            Self::Hub(_) => work_dir.join("lora").join(self.name()),
+        }
+    }
+
+    /// Its GGUF in `output_dir` at `precision`, e.g. `my-adapter-lora.f16.gguf`.
+    pub(crate) fn gguf_path(&self, output_dir: &Path, precision: &Precision) -> PathBuf {
+        output_dir.join(format!(
+            "{}-lora.{precision}.gguf",
+            self.name().to_lowercase()
+        ))
+    }
+}
+
+/// The base model the adapter was trained on, which the converter reads the architecture from.
+#[derive(Debug, Clone)]
+pub(crate) enum LoraBase {
+    /// A directory with the base model's `config.json`.
+    Local(PathBuf),
+    /// A HuggingFace repo the converter fetches the config from.
+    Hub(String),
+}
+
+pub(crate) fn convert_lora_command(
+    precision: &Precision,
+    llama_path: &Path,
+    adapter_dir: &Path,
+    base: &LoraBase,
+    output_path: &Path,
+) -> Command {
+    let venv_python = llama::venv_python(llama_path);
+    let mut command = match venv_python.exists() {
+        true => Command::new(venv_python),
+        false => Command::new(llama::python()),
+    };
+    command
+        .arg(llama_path.join(CONVERT_LORA_SCRIPT))
+        .arg(adapter_dir)
+        .arg("--outtype")
+        .arg(precision.to_string())
+        .arg("--outfile")
+        .arg(output_path);
+    match base {
+        LoraBase::Local(dir) => command.arg("--base").arg(dir),
+        LoraBase::Hub(model_id) => command.arg("--base-model-id").arg(model_id),
+    };
+    command
+}
+
+/// Convert the adapter in `adapter_dir` to a GGUF at `output_path`, written to the side and
+/// moved into place once it's finished.
+pub(crate) async fn convert_lora(
+    precision: &Precision,
+    llama_path: &Path,
+    adapter_dir: &Path,
+    base: &LoraBase,
+    output_path: &Path,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pending = PathBuf::from(format!("{}.pending", output_path.to_string_lossy()));
+    let command = convert_lora_command(precision, llama_path, adapter_dir, base, &pending);
+    let description = format!("{CONVERT_LORA_SCRIPT} of {}", adapter_dir.display());
+    if let Err(e) = ctx.run(&Stage::Convert, command, &description).await {
+        let _ = tokio::fs::remove_file(&pending).await;
+        return Err(e);
+    }
+    move_file(&pending, output_path).await?;
+    Ok(())
+}
+
+pub(crate) fn export_lora_command(
+    llama_bin: &Path,
+    base: &Path,
+    adapter: &Path,
+    output_path: &Path,
+    threads: u32,
+) -> Command {
+    let mut command = Command::new(llama::binary(llama_bin, "llama-export-lora"));
+    command
+        .arg("-m")
+        .arg(base)
+        .arg("--lora")
+        .arg(adapter)
+        .arg("-o")
+        .arg(output_path)
+        .arg("-t")
+        .arg(threads.to_string());
+    command
+}
+
+/// Merge the GGUF `adapter` into the full-precision GGUF `base`, writing the merged model to
+/// `output_path` once it's finished.
+pub(crate) async fn merge(
+    llama_bin: &Path,
+    base: &Path,
+    adapter: &Path,
+    output_path: &Path,
+    threads: u32,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pending = PathBuf::from(format!("{}.pending", output_path.to_string_lossy()));
+    let command = export_lora_command(llama_bin, base, adapter, &pending, threads);
+    if let Err(e) = ctx.run(&Stage::Convert, command, "llama-export-lora").await {
+        let _ = tokio::fs::remove_file(&pending).await;
+        return Err(e);
+    }
+    move_file(&pending, output_path).await?;
+    Ok(())
+}
@@ -1,9 +1,16 @@
-use clap::{Parser, ValueEnum};
-use futures_util::StreamExt;
+use autogguf::{
+    Bucket, ChatTemplate, CheckStatus, Cleanup, Config, LlamaBackend, LoraAdapter,
+    MetadataOverride, Mirror, NameTemplate, Notification, Pipeline, PipelineBuilder, Precision,
+    PythonInstaller, QuantLevel, QuantSpec, StageTimeout, TensorTypeOverride, Watcher, Webhook,
+    DEFAULT_LLAMAFILE_RUNTIME,
+};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use shellexpand::tilde;
 use std::{
-    fmt::Display,
-    path::PathBuf,
+    fs::File,
+    net::SocketAddr,
+    path::Path,
+    process::ExitCode,
     str::FromStr,
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -11,47 +18,230 @@ use std::{
     },
     time::Duration,
 };
-use tokio::{
-    fs::File,
-    io::AsyncWriteExt,
-    process::Command,
-    select, signal,
-    sync::{mpsc, Notify},
-    task::JoinHandle,
-    time::sleep,
+use tokio::{signal, sync::Notify};
+use tracing_subscriber::{
+    filter::EnvFilter,
+    fmt::{self, writer::MakeWriterExt},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer,
 };
 
+mod desktop;
+mod serve;
+mod telemetry;
+mod tui;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable progress messages.
+    Human,
+    /// One JSON object per pipeline event, one per line.
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check for everything a run needs (git, Python, llama.cpp, HuggingFace auth, a GPU, disk space) and how to fix what's missing.
+    Doctor,
+    /// Print a GGUF's metadata, tensor names, shapes and types, and parameter count.
+    Inspect {
+        /// The .gguf file to inspect.
+        file: String,
+    },
+    /// Estimate every quant level's file size for a HuggingFace model from its config.json alone, without downloading or converting anything.
+    Estimate {
+        /// The HuggingFace model ID to estimate.
+        model_id: String,
+        #[clap(long)]
+        /// Read the config at this branch, tag or commit instead of main.
+        revision: Option<String>,
+    },
+    /// Run as a worker that converts models queued through an HTTP API, one at a time, with the flags given here. POST /jobs with {"model_id": "..."} to queue one, GET /jobs or /jobs/{id} for progress, and DELETE /jobs/{id} to cancel.
+    Serve {
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        /// The address to listen on.
+        listen: SocketAddr,
+    },
+    /// Poll a HuggingFace user or org for new and updated models, and convert each with the flags given here. What's already there when first watched is skipped unless --backfill; commits converted so far are kept in .autogguf-watch.json.
+    Watch {
+        /// The HuggingFace user or org to watch.
+        namespace: String,
+        #[clap(long = "match", value_name = "GLOB", value_parser = parse_glob)]
+        /// Only convert models whose names match this glob, e.g. '*-Instruct*'. Repeatable.
+        patterns: Vec<String>,
+        #[clap(long, value_name = "NAMESPACE")]
+        /// Upload to NAMESPACE/{model name}-GGUF, e.g. an org, instead of under --hf-user.
+        to: Option<String>,
+        #[clap(long, value_name = "MINUTES", default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+        /// How often to poll.
+        interval: u64,
+        #[clap(long)]
+        /// Also convert the models already in the namespace when it's first watched.
+        backfill: bool,
+        #[clap(long)]
+        /// Poll once, convert whatever changed, and exit, e.g. to run from cron.
+        once: bool,
+    },
+    /// Re-check the quants a run produced: intact headers, the same tensor count as the full-precision GGUF, and optionally a short generation.
+    Verify {
+        #[clap(long)]
+        /// Also generate a little text from each quant with llama-cli, through its chat template if it has one, to catch quants that load but are broken.
+        smoke_test: bool,
+    },
+    /// Reconcile the requested quants with the local files and the published repo, reporting each that's missing, corrupted, unpublished or a different size on the Hub.
+    Audit,
+}
+
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(
+    version,
+    about,
+    subcommand_negates_reqs = true,
+    subcommand_precedence_over_arg = true,
+    after_long_help = "Exit codes: 0 success, 1 other failures, 2 bad arguments, 3 setup (llama.cpp, \
+                       disk space, unsupported model), 4 download, 5 conversion, 6 imatrix, \
+                       7 quantization, 8 upload, 130 interrupted."
+)]
 struct Args {
-    /// The HuggingFace model ID to convert. Required.
-    model_id: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Comma-separated list of quant levels to convert. Defaults to all non-imatrix quants.
-    #[clap(
-        short,
-        long,
-        value_delimiter = ',',
-        num_args = 1..,
-        default_value = "q2_k,q3_k_s,q3_k_m,q3_k_l,q4_0,q4_1,q4_k_s,q4_k_m,q5_0,q5_1,q5_k_s,q5_k_m,q6_k,q8_0"
-    )]
-    quants: Vec<QuantLevel>,
+    /// The HuggingFace model IDs to convert, one after another. Required unless reading them from --models-file, or converting a --local-model, where it's recorded as the base model.
+    #[clap(value_name = "MODEL_ID", value_parser = parse_repo_id, required_unless_present_any = ["print_config", "local_model", "models_file"])]
+    model_ids: Vec<String>,
 
-    #[clap(short, long)]
-    /// Increase output verbosity.
-    verbose: bool,
+    #[clap(long, value_name = "FILE", conflicts_with = "local_model")]
+    /// Also convert the HuggingFace model IDs listed in this file, one per line, after any given as arguments. Blank lines and lines starting with # are skipped.
+    models_file: Option<String>,
+
+    #[clap(long, conflicts_with = "local_model")]
+    /// Download the model at this branch, tag or commit instead of main.
+    revision: Option<String>,
+
+    #[clap(long, value_name = "GLOB", value_parser = parse_glob)]
+    /// Only download source files matching this glob (e.g. '*.safetensors'), instead of what conversion usually needs. Repeatable.
+    include: Vec<String>,
+
+    #[clap(long, value_name = "GLOB", value_parser = parse_glob)]
+    /// Never download source files matching this glob. Repeatable.
+    exclude: Vec<String>,
+
+    #[clap(long, conflicts_with = "include")]
+    /// Download every file in the source repo. By default, training artifacts and .bin/.pt/original/ weights are skipped when there are safetensors.
+    all_files: bool,
+
+    #[clap(long, env = "HF_HUB_ENABLE_HF_TRANSFER", value_parser = clap::builder::FalseyValueParser::new())]
+    /// Download each file in parallel chunks across all cores to saturate fast links. Can bog down a desktop.
+    fast_download: bool,
+
+    #[clap(long, conflicts_with = "local_model")]
+    /// Download into the shared HF cache (~/.cache/huggingface, or $HF_HOME) and link the files into the model directory, reusing models already cached there instead of storing the weights twice.
+    hf_cache: bool,
+
+    #[clap(long, value_enum, value_delimiter = ',')]
+    /// Delete files as soon as they're no longer needed: 'sources' removes the downloaded weights once the full-precision GGUF is converted; 'fp', 'imatrix' and 'quants' remove those outputs once the upload succeeds.
+    cleanup: Vec<Cleanup>,
+
+    #[clap(long, value_enum, value_delimiter = ',', conflicts_with = "cleanup")]
+    /// Keep only these of fp, imatrix and quants once the upload succeeds, deleting the rest.
+    keep: Option<Vec<Cleanup>>,
+
+    #[clap(long, value_name = "DIR", conflicts_with_all = ["fp", "skip_download"])]
+    /// Convert the HuggingFace-format model in this local directory instead of downloading one. Outputs go in <dir name> under --output-dir.
+    local_model: Option<String>,
+
+    #[clap(long, value_name = "DIR")]
+    /// Write the quants, model card and checksums to <model name> under this directory. Defaults to the current directory.
+    output_dir: Option<String>,
+
+    #[clap(long, value_name = "DIR")]
+    /// Download the model, and write the full-precision GGUF, imatrix, logs and resume state, to <model name> under this directory, e.g. on a big scratch disk. Defaults to --output-dir.
+    work_dir: Option<String>,
+
+    /// Comma-separated list of quant levels to convert, or presets: all, imatrix, common, bartowski. Defaults to all non-imatrix quants.
+    #[clap(short, long, value_delimiter = ',', num_args = 1..)]
+    quants: Option<Vec<QuantSpec>>,
+
+    /// Comma-separated list of quant levels or presets to drop from --quants or the default list, e.g. q4_0,q4_1,q5_0,q5_1 for the legacy formats.
+    #[clap(long, value_delimiter = ',', num_args = 1..)]
+    exclude_quants: Vec<QuantSpec>,
+
+    #[clap(long)]
+    /// Pass --allow-requantize to llama-quantize, to quantize from an already-quantized --fp such as a Q8_0 GGUF.
+    allow_requantize: bool,
+
+    #[clap(long)]
+    /// Pass --pure to llama-quantize: quantize every tensor to the requested level, without k-quant mixing.
+    pure: bool,
+
+    #[clap(long, value_name = "ARGS", allow_hyphen_values = true)]
+    /// Extra arguments passed verbatim to every llama-quantize run, split like a shell would, e.g. --quantize-args="--keep-split --override-kv general.name=str:Foo".
+    quantize_args: Option<ShellArgs>,
+
+    #[clap(long, value_name = "ARGS", allow_hyphen_values = true)]
+    /// Extra arguments passed verbatim to convert_hf_to_gguf.py, split like a shell would, e.g. --convert-args="--model-name Foo --use-temp-file".
+    convert_args: Option<ShellArgs>,
+
+    #[clap(long, conflicts_with = "convert_args")]
+    /// Experimental: convert Llama, Mistral and Qwen2 models to the full-precision GGUF in-process instead of with convert_hf_to_gguf.py, so Python and its deps aren't needed.
+    native_convert: bool,
+
+    #[clap(long, value_name = "[LEVEL=]TYPE")]
+    /// Quantize the output tensor to this ggml type, e.g. q8_0 for every level or q4_k_m=q8_0 for one. Repeatable.
+    output_tensor_type: Vec<TensorTypeOverride>,
+
+    #[clap(long, value_name = "[LEVEL=]TYPE")]
+    /// Quantize the token embeddings to this ggml type, e.g. q8_0 for every level or q4_k_m=q8_0 for one. Repeatable.
+    token_embedding_type: Vec<TensorTypeOverride>,
+
+    #[clap(short, long, action = ArgAction::Count)]
+    /// Increase output verbosity: -v for detail and timestamps, -vv for tracing, -vvv for tracing from dependencies too.
+    verbose: u8,
+
+    #[clap(long)]
+    /// Also write the full log of the run to this file, at -vv detail regardless of --verbose.
+    log_file: Option<String>,
+
+    #[clap(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT", value_name = "URL")]
+    /// Export a trace of each run, with a span per stage, to this OpenTelemetry collector over OTLP/HTTP, e.g. http://localhost:4318.
+    otlp_endpoint: Option<String>,
+
+    #[clap(long)]
+    /// The full-precision GGUF format to convert to and quantize from. Defaults to f16.
+    full_precision: Option<Precision>,
+
+    #[clap(long, value_name = "TEMPLATE")]
+    /// Name quant files by this template, e.g. '{model}-{quant}-imat.gguf', to match an existing repo's names. Variables: {model}, {model_lower}, {quant} (required), {precision} and {date} (the day the run started, so resuming on a later day quantizes again). Defaults to '{model_lower}.{quant}.gguf'.
+    name_template: Option<NameTemplate>,
+
+    #[clap(long, value_name = "FILE|PRESET")]
+    /// Write this chat template into the full-precision GGUF before quantizing, for a model converted without one or with a broken one: a Jinja file, or one of the presets chatml, llama3, gemma, mistral or phi3.
+    chat_template: Option<ChatTemplate>,
 
-    #[clap(long, default_value = "f16")]
-    /// The full-precision GGUF format to convert to and quantize from.
-    full_precision: Precision,
+    #[clap(long, value_name = "KEY=VALUE")]
+    /// Set this metadata in the full-precision GGUF before quantizing, so every quant carries it, e.g. general.name="Llama 3.1 8B" or general.url=https://example.com. A key the GGUF already has keeps its type, so the value must parse as it; a new key is a string. Repeatable.
+    gguf_meta: Vec<MetadataOverride>,
 
     #[clap(long)]
-    /// Path to fp16, bf16 or fp32 GGUF file for quantization. Implies skipping download and initial conversion to full precision GGUF.
+    /// Path to fp16, bf16 or fp32 GGUF file for quantization. Implies skipping download and initial conversion to full precision GGUF. The file is never modified, so it must already have whatever --chat-template and --gguf-meta would set.
     fp: Option<String>,
 
     #[clap(long)]
-    /// Path to custom imatrix file for imatrix quantization. Skips downloading calibration dataset and generating imatrix.
-    imatrix: Option<String>,
+    /// Path to custom imatrix file for imatrix quantization. Skips downloading calibration dataset and generating imatrix. Repeat to combine several into one.
+    imatrix: Vec<String>,
+
+    #[clap(long, value_parser = parse_repo_id, conflicts_with = "imatrix")]
+    /// Download the .imatrix published in this HuggingFace repo (e.g. someuser/Model-GGUF) instead of generating one.
+    imatrix_repo: Option<String>,
+
+    #[clap(long, value_name = "PATH_OR_URL", conflicts_with_all = ["imatrix", "imatrix_repo"])]
+    /// Calibration text for imatrix generation instead of the default groups_merged.txt. Repeat to concatenate several datasets.
+    calibration_data: Vec<String>,
+
+    #[clap(long, requires = "calibration_data")]
+    /// Generate a separate imatrix from each --calibration-data dataset and combine them, instead of one over all of them concatenated.
+    imatrix_per_dataset: bool,
 
     #[clap(long)]
     /// Skip downloading the model to convert from HuggingFace Hub.
@@ -61,640 +251,1164 @@ struct Args {
     /// Skip uploading converted files to HuggingFace Hub.
     skip_upload: bool,
 
+    #[clap(long)]
+    /// Don't convert a vision-language model's projector to the mmproj GGUFs llama.cpp loads alongside its quants, which is otherwise done whenever its config.json has a vision tower.
+    skip_mmproj: bool,
+
     #[clap(long, conflicts_with = "skip_upload")]
-    /// Upload .gguf files in the target model directory to HuggingFace Hub.
+    /// Upload the .gguf files already in the output and work directories to HuggingFace Hub.
     only_upload: bool,
 
+    #[clap(long, conflicts_with = "skip_upload")]
+    /// Also upload the per-stage subprocess logs from the model directory's logs/ folder.
+    upload_logs: bool,
+
+    #[clap(long, conflicts_with = "skip_upload")]
+    /// Create the target HuggingFace repo as private. Existing repos keep their visibility, so flip it to public on the Hub when ready.
+    private: bool,
+
+    #[clap(long, conflicts_with_all = ["skip_upload", "private"])]
+    /// Upload to a new pull request on the target repo instead of pushing to its main branch, e.g. to contribute quants to a repo owned by someone else or an org that reviews changes. The repo must already exist.
+    as_pr: bool,
+
+    #[clap(long, value_parser = parse_repo_id, conflicts_with = "skip_upload")]
+    /// Upload to this HuggingFace repo (e.g. my-org/custom-name) instead of $HF_USER/{model_name}-GGUF.
+    repo_id: Option<String>,
+
+    #[clap(long, conflicts_with = "skip_upload")]
+    /// Add to an already published GGUF repo: make only the requested quants it doesn't have yet, and upload them without touching any file it already has, README.md and SHA256SUMS included.
+    update_existing: bool,
+
+    #[clap(long, value_name = "SLUG", value_parser = parse_repo_id, conflicts_with = "skip_upload")]
+    /// After uploading, add the repo to this HuggingFace collection, e.g. my-user/llama-ggufs-66f1a2b3c4d5e6f7a8b9c0d1, as shown in the collection's URL.
+    collection: Option<String>,
+
+    #[clap(long, value_name = "URL", conflicts_with_all = ["skip_upload", "as_pr", "collection", "update_existing"])]
+    /// Upload to an object storage bucket instead of the HuggingFace Hub, under a folder named like the repo would be: s3://bucket/prefix, gs://bucket/prefix or az://container/prefix, copied to with the aws, gcloud or az CLI and its usual credentials.
+    upload_backend: Option<Bucket>,
+
+    #[clap(long, value_name = "DEST", conflicts_with = "skip_upload")]
+    /// After uploading, copy everything uploaded to DEST too: a HuggingFace user or org, which gets a repo named like the target one, using the same token, or an s3://bucket/prefix, copied to with the AWS CLI. Repeatable.
+    mirror: Vec<Mirror>,
+
+    #[clap(long)]
+    /// Ignore the .autogguf-state.json manifest and redo every stage instead of resuming.
+    no_resume: bool,
+
+    #[clap(long)]
+    /// Requantize levels whose output .gguf already exists instead of skipping them.
+    force: bool,
+
+    #[clap(long)]
+    /// Break the model directory's lock if another run seems to hold it. Only for a run that's hung, or a lock left on a network filesystem by one that crashed; two live runs on one model overwrite each other's outputs.
+    force_unlock: bool,
+
+    #[clap(long)]
+    /// Warn instead of failing when the estimated disk space needed exceeds what's available.
+    ignore_disk_space: bool,
+
     #[clap(short, long)]
     /// Update the llama.cpp repo before converting. Installs llama.cpp if llama-path doesn’t exist.
     update_llama: bool,
 
-    #[clap(short, long, default_value = "~/code/llama.cpp")]
-    /// The path to the llama.cpp repo.
-    llama_path: String,
+    #[clap(short, long)]
+    /// The path to the llama.cpp repo. Defaults to ~/code/llama.cpp.
+    llama_path: Option<String>,
+
+    #[clap(long, value_name = "REF", requires = "update_llama")]
+    /// Check out this llama.cpp tag or commit (e.g. b4500) when updating, instead of pulling the latest. The commit used is recorded in llama.cpp.lock in the output directory and on the model card.
+    llama_ref: Option<String>,
+
+    #[clap(long, requires = "update_llama")]
+    /// Download llama.cpp's prebuilt release binaries for this platform and --llama-backend when updating, instead of compiling. --llama-ref must then be a release tag.
+    llama_prebuilt: bool,
+
+    #[clap(long, value_name = "DIR")]
+    /// Where CMake builds llama.cpp, and where its binaries are found under bin/. Defaults to build/ in the llama.cpp repo.
+    llama_build_dir: Option<String>,
+
+    #[clap(long, value_enum, value_name = "BACKEND")]
+    /// The GPU backend to build llama.cpp with for --update-llama. Defaults to CMake's (Metal on macOS, CPU elsewhere).
+    llama_backend: Option<LlamaBackend>,
+
+    #[clap(long, value_enum, value_name = "INSTALLER")]
+    /// What installs llama.cpp's Python deps into its venv for --update-llama. Defaults to uv if it's on PATH, else pip.
+    python_installer: Option<PythonInstaller>,
+
+    #[clap(long)]
+    /// After quantizing, measure the perplexity of each quant and the full-precision GGUF with llama-perplexity, and add the results to the summary and model card.
+    eval_ppl: bool,
+
+    #[clap(long)]
+    /// After quantizing, compare each quant's token probabilities to the full-precision GGUF's (KL-divergence) with llama-perplexity, and add the results to the summary and model card. Temporarily needs several GB for the full-precision logits.
+    eval_kld: bool,
+
+    #[clap(long, value_name = "PATH_OR_URL")]
+    /// Text to evaluate on for --eval-ppl and --eval-kld. Defaults to wikitext-2's test split.
+    eval_dataset: Option<String>,
+
+    #[clap(long)]
+    /// After quantizing, benchmark each quant and the full-precision GGUF on this machine with llama-bench (pp512 and tg128), and add their tokens/sec to the summary and model card.
+    bench: bool,
+
+    #[clap(long, value_name = "KEY")]
+    /// Sign the uploaded SHA256SUMS with this minisign secret key file (passwordless) or GPG key ID, uploading the detached signature next to it.
+    sign_key: Option<String>,
+
+    #[clap(long)]
+    /// Also sign every uploaded GGUF and imatrix, not just the SHA256SUMS that covers them. Needs a --sign-key.
+    sign_files: bool,
+
+    #[clap(long, conflicts_with_all = ["skip_upload", "upload_backend"])]
+    /// Also upload a .torrent of each quant, web-seeded from the repo so it downloads even with no other peers, and list their magnet links in the model card and MAGNETS. For models too big to download comfortably over HTTP.
+    torrent: bool,
+
+    #[clap(long, conflicts_with = "skip_upload")]
+    /// Also pin every GGUF to IPFS with kubo's ipfs CLI, on whichever node it's set up to use, and list their CIDs in the model card and summary.json.
+    ipfs: bool,
+
+    #[clap(long, value_name = "NAME", requires = "ipfs")]
+    /// Also pin to this remote pinning service, as added with `ipfs pin remote service add`, e.g. pinata.
+    ipfs_pin_service: Option<String>,
+
+    #[clap(long, value_name = "URL")]
+    /// POST to this webhook when the run starts, each quant finishes, files are uploaded, and the run succeeds or fails. Slack and Discord webhooks get chat messages; anything else gets JSON.
+    notify_url: Option<String>,
+
+    #[clap(long)]
+    /// Show a desktop notification when the run finishes or fails, with notify-send on Linux or osascript on macOS.
+    desktop_notify: bool,
+
+    #[clap(long)]
+    /// Generate a little text from each quant as it's made with llama-cli, through its chat template if it has one, and fail the quant if that errors or looks like garbage. Catches tokenizer and metadata problems that only show up at inference time.
+    smoke_test: bool,
+
+    #[clap(long)]
+    /// Write the manifest.json LM Studio reads to sideload a model into the output directory, listing the quants with the model's context length and chat template, so the directory can be copied into LM Studio's models directory as is.
+    lmstudio_manifest: bool,
+
+    #[clap(long, value_name = "PATH_OR_REPO")]
+    /// Also convert this PEFT LoRA adapter of the model, a directory or a HuggingFace repo, to a GGUF with llama.cpp's convert_lora_to_gguf.py, and upload it with the quants to load on top of them with --lora. Repeatable.
+    lora: Vec<LoraAdapter>,
+
+    #[clap(long, value_name = "PATH_OR_REPO", conflicts_with_all = ["fp", "models_file"])]
+    /// Merge this PEFT LoRA adapter of the model, a directory or a HuggingFace repo, into it with llama.cpp's llama-export-lora before quantizing, to publish a fine-tune only distributed as an adapter as standalone quants. Outputs and the repo are named for the adapter.
+    merge_lora: Option<LoraAdapter>,
+
+    #[clap(long, value_name = "QUANT")]
+    /// Package this quant with the llamafile runtime into a single executable, e.g. q4_k_m, that runs the model on Linux, macOS and Windows with nothing to install. It's uploaded with the quants. Must be one of the quants being made.
+    llamafile: Option<QuantLevel>,
+
+    #[clap(long, value_name = "PATH", default_value = DEFAULT_LLAMAFILE_RUNTIME, requires = "llamafile")]
+    /// The llamafile runtime to package --llamafile with, with llamafile's zipalign next to it or on the PATH. Both come with llamafile's releases.
+    llamafile_runtime: String,
+
+    #[clap(long, value_name = "N")]
+    /// Retry a download, calibration or eval dataset fetch, or upload that fails transiently (a dropped connection, a timeout, rate limiting or a HuggingFace server error) up to N times, backing off exponentially, before failing its stage. Defaults to 5.
+    retries: Option<u32>,
+
+    #[clap(long, value_name = "STAGE=MINUTES")]
+    /// Fail a stage that runs longer than this, killing whatever it's running, e.g. imatrix=240. Each quant gets its own quantize limit. Stages: update-llama, download, convert, imatrix, quantize, eval. Repeatable.
+    timeout: Vec<StageTimeout>,
+
+    #[clap(long, value_name = "MINUTES")]
+    /// Kill a llama.cpp process that prints nothing for this many minutes, e.g. a hung imatrix run, and fail its stage. Off by default.
+    stall_timeout: Option<u64>,
+
+    #[clap(short, long)]
+    /// Number of threads for imatrix generation and evaluation. Defaults to every CPU core available to autogguf.
+    threads: Option<u32>,
+
+    #[clap(long, visible_alias = "ngl")]
+    /// Number of layers to offload to the GPU for imatrix generation, evaluation and smoke tests. Defaults to 999 (all); use 0 on CPU-only machines, or fewer when the full-precision model doesn't fit in VRAM.
+    gpu_layers: Option<u32>,
+
+    #[clap(long, value_name = "DEVICE")]
+    /// Offload to this llama.cpp device, e.g. CUDA1 or Vulkan0, or several comma-separated, for imatrix generation, evaluation and smoke tests. `llama-imatrix --list-devices` shows what there is. Defaults to llama.cpp's pick.
+    gpu_device: Option<String>,
+
+    #[clap(long)]
+    /// Number of calibration chunks to generate the imatrix over. Defaults to 2000.
+    imatrix_chunks: Option<u32>,
+
+    #[clap(long)]
+    /// Context size for each imatrix chunk. Defaults to llama-imatrix's own default (512).
+    imatrix_ctx_size: Option<u32>,
 
     #[clap(long, env = "HF_TOKEN", hide_env_values = true)]
-    /// Your HuggingFace API token for uploading converted models.
+    /// Your HuggingFace API token, for uploading converted models and downloading gated or private ones. Downloads fall back to the token saved by `huggingface-cli login`.
     hf_token: Option<String>,
 
     #[clap(long, env = "HF_USER")]
     /// Your HuggingFace username for uploading converted models.
     hf_user: Option<String>,
-}
 
-#[derive(Debug, Clone, ValueEnum)]
-enum Precision {
-    F16,
-    BF16,
-    F32,
-}
+    #[clap(long)]
+    /// Path to a TOML config file of defaults. Defaults to ~/.config/autogguf/config.toml.
+    config: Option<String>,
 
-impl Display for Precision {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let label = match self {
-            Precision::F16 => "f16",
-            Precision::BF16 => "bf16",
-            Precision::F32 => "f32",
-        };
-        write!(f, "{label}")
-    }
+    #[clap(long)]
+    /// Print the stages, paths, commands and estimated output sizes that would run, then exit without running anything.
+    dry_run: bool,
+
+    #[clap(long, value_enum, default_value_t = OutputFormat::Human)]
+    /// How to report progress on stdout. `json` emits one JSON object per pipeline event, for CI systems and wrappers.
+    output: OutputFormat,
+
+    #[clap(long, conflicts_with_all = ["dry_run", "output"])]
+    /// Show a live dashboard of stage status, subprocess output and timings instead of plain output.
+    tui: bool,
+
+    #[clap(long)]
+    /// Print the effective configuration (config file merged with CLI flags) and exit.
+    print_config: bool,
 }
 
-macro_rules! quant_level_enum {
-    ($($variant:ident => $str:expr),* $(,)?) => {
-        #[derive(Debug, Clone)]
-        enum QuantLevel {
-            $($variant),*
+impl Args {
+    /// Override config values with any flags given on the command line.
+    fn apply_to(&self, config: &mut Config) -> Result<(), String> {
+        if let Some(quants) = &self.quants {
+            for level in QuantSpec::duplicates(quants) {
+                tracing::warn!("{level} is listed more than once in --quants");
+            }
+            config.quants = QuantSpec::expand(quants);
         }
+        if !self.exclude_quants.is_empty() {
+            let excluded = QuantSpec::expand(&self.exclude_quants);
+            // NOTE: levels asked for by name, or through a preset that's excluded as a whole too;
+            // a preset only trimmed by --exclude-quants is the point of it
+            let requested = self.quants.as_deref().unwrap_or_default();
+            let named: Vec<QuantSpec> = requested
+                .iter()
+                .filter(|spec| match spec {
+                    QuantSpec::Level(_) => true,
+                    QuantSpec::Preset(_) => self.exclude_quants.contains(spec),
+                })
+                .cloned()
+                .collect();
+            let conflicting: Vec<String> = QuantSpec::expand(&named)
+                .into_iter()
+                .filter(|level| excluded.contains(level))
+                .map(|level| level.to_string())
+                .collect();
+            if !conflicting.is_empty() {
+                return Err(format!(
+                    "--exclude-quants removes {}, which --quants asks for",
+                    conflicting.join(", ")
+                ));
+            }
+            for level in QuantSpec::duplicates(&self.exclude_quants) {
+                tracing::warn!("{level} is listed more than once in --exclude-quants");
+            }
+            for level in excluded.iter().filter(|q| !config.quants.contains(q)) {
+                tracing::warn!("--exclude-quants {level} isn't in the quant list anyway");
+            }
+            config.quants.retain(|q| !excluded.contains(q));
+            if config.quants.is_empty() {
+                return Err("--exclude-quants leaves no quant levels to convert".to_string());
+            }
+        }
+        if let Some(precision) = &self.full_precision {
+            config.full_precision = precision.clone();
+        }
+        if let Some(template) = &self.name_template {
+            config.name_template = template.clone();
+        }
+        if self.chat_template.is_some() {
+            config.chat_template.clone_from(&self.chat_template);
+        }
+        for meta in &self.gguf_meta {
+            config
+                .gguf_meta
+                .insert(meta.key.clone(), meta.value.clone());
+        }
+        if let Some(llama_path) = &self.llama_path {
+            config.llama_path.clone_from(llama_path);
+        }
+        if self.llama_backend.is_some() {
+            config.llama_backend = self.llama_backend;
+        }
+        if self.python_installer.is_some() {
+            config.python_installer = self.python_installer;
+        }
+        if self.llama_build_dir.is_some() {
+            config.llama_build_dir.clone_from(&self.llama_build_dir);
+        }
+        if self.output_dir.is_some() {
+            config.output_dir.clone_from(&self.output_dir);
+        }
+        if self.work_dir.is_some() {
+            config.work_dir.clone_from(&self.work_dir);
+        }
+        if let Some(retries) = self.retries {
+            config.retries = retries;
+        }
+        for timeout in &self.timeout {
+            config.timeouts.insert(timeout.stage, timeout.minutes);
+        }
+        if self.stall_timeout.is_some() {
+            config.stall_timeout = self.stall_timeout;
+        }
+        if self.threads.is_some() {
+            config.threads = self.threads;
+        }
+        if let Some(gpu_layers) = self.gpu_layers {
+            config.gpu_layers = gpu_layers;
+        }
+        if self.gpu_device.is_some() {
+            config.gpu_device.clone_from(&self.gpu_device);
+        }
+        if let Some(chunks) = self.imatrix_chunks {
+            config.imatrix_chunks = chunks;
+        }
+        if self.imatrix_ctx_size.is_some() {
+            config.imatrix_ctx_size = self.imatrix_ctx_size;
+        }
+        if self.hf_user.is_some() {
+            config.hf_user.clone_from(&self.hf_user);
+        }
+        if self.collection.is_some() {
+            config.collection.clone_from(&self.collection);
+        }
+        if self.upload_backend.is_some() {
+            config.upload_backend.clone_from(&self.upload_backend);
+        }
+        for mirror in &self.mirror {
+            if !config.mirrors.contains(mirror) {
+                config.mirrors.push(mirror.clone());
+            }
+        }
+        if self.sign_key.is_some() {
+            config.sign_key.clone_from(&self.sign_key);
+        }
+        if self.notify_url.is_some() {
+            config.notify_url.clone_from(&self.notify_url);
+        }
+        if self.eval_dataset.is_some() && !self.eval_ppl && !self.eval_kld {
+            return Err("--eval-dataset needs --eval-ppl or --eval-kld".to_string());
+        }
+        if let Some(level) = self
+            .llamafile
+            .as_ref()
+            .filter(|l| !config.quants.contains(l))
+        {
+            return Err(format!(
+                "--llamafile {level} isn't one of the quants being made"
+            ));
+        }
+        if self.sign_files && config.sign_key.is_none() {
+            return Err("--sign-files needs a --sign-key, or sign_key in the config".to_string());
+        }
+        Ok(())
+    }
 
-        impl FromStr for QuantLevel {
-            type Err = String;
-
-            fn from_str(s: &str) -> Result<Self, Self::Err> {
-                match s.to_lowercase().as_str() {
-                    $($str => Ok(QuantLevel::$variant),)*
-                    _ => Err(format!("'{s}' is not a valid quant level")),
+    /// The model IDs given as arguments, then those in `--models-file`.
+    async fn model_ids(&self) -> Result<Vec<String>, String> {
+        let mut model_ids = self.model_ids.clone();
+        if let Some(path) = &self.models_file {
+            let contents = tokio::fs::read_to_string(tilde(path).as_ref())
+                .await
+                .map_err(|e| format!("couldn't read --models-file {path}: {e}"))?;
+            for (number, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
                 }
+                let model_id = parse_repo_id(line)
+                    .map_err(|e| format!("--models-file {path} line {}: {e}", number + 1))?;
+                model_ids.push(model_id);
             }
         }
+        if model_ids.len() > 1 {
+            self.check_single_model_flags()?;
+        }
+        Ok(model_ids)
+    }
 
-        impl Display for QuantLevel {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                let label = match self {
-                    $(QuantLevel::$variant => $str,)*
-                };
-                write!(f, "{label}")
-            }
+    /// Fail if given flags that only make sense for one model, when converting several.
+    fn check_single_model_flags(&self) -> Result<(), String> {
+        let single: &[(&str, bool)] = &[
+            ("--local-model", self.local_model.is_some()),
+            ("--fp", self.fp.is_some()),
+            ("--imatrix", !self.imatrix.is_empty()),
+            ("--repo-id", self.repo_id.is_some()),
+            ("--revision", self.revision.is_some()),
+            ("--tui", self.tui),
+        ];
+        match single.iter().find(|(_, given)| *given) {
+            Some((flag, _)) => Err(format!("{flag} only works when converting a single model")),
+            None => Ok(()),
         }
-    };
+    }
+
+    /// What to delete along the way: `--cleanup` as given, or whatever `--keep` leaves out.
+    fn cleanup(&self) -> Result<Vec<Cleanup>, String> {
+        let cleanup: Vec<Cleanup> = match &self.keep {
+            Some(keep) => Cleanup::UPLOADED
+                .iter()
+                .filter(|c| !keep.contains(c))
+                .copied()
+                .collect(),
+            None => self.cleanup.clone(),
+        };
+        if self.local_model.is_some() && cleanup.contains(&Cleanup::Sources) {
+            return Err(
+                "--cleanup sources only deletes downloaded weights, not a --local-model"
+                    .to_string(),
+            );
+        }
+        if self.hf_cache && cleanup.contains(&Cleanup::Sources) {
+            return Err(
+                "--cleanup sources can't free the weights --hf-cache links to from the shared \
+                 cache; clear them from there with `hf cache delete` instead"
+                    .to_string(),
+            );
+        }
+        if self.skip_upload && cleanup.iter().any(|c| Cleanup::UPLOADED.contains(c)) {
+            return Err(
+                "--cleanup fp/imatrix/quants and --keep only apply once an upload \
+                        succeeds, so they can't be used with --skip-upload"
+                    .to_string(),
+            );
+        }
+        Ok(cleanup)
+    }
 }
 
-quant_level_enum! {
-    Q2K => "q2_k",
-    Q3KS => "q3_k_s",
-    Q3KM => "q3_k_m",
-    Q3KL => "q3_k_l",
-    Q4_0 => "q4_0",
-    Q4_1 => "q4_1",
-    Q4KS => "q4_k_s",
-    Q4KM => "q4_k_m",
-    Q5_0 => "q5_0",
-    Q5_1 => "q5_1",
-    Q5KS => "q5_k_s",
-    Q5KM => "q5_k_m",
-    Q6K => "q6_k",
-    Q8_0 => "q8_0",
-    BF16 => "bf16",
-    IQ1S => "iq1_s",
-    IQ1M => "iq1_m",
-    IQ2XXS => "iq2_xxs",
-    IQ2XS => "iq2_xs",
-    IQ2S => "iq2_s",
-    IQ2M => "iq2_m",
-    Q2KS => "q2_k_s",
-    IQ3XXS => "iq3_xxs",
-    IQ3XS => "iq3_xs",
-    IQ3S => "iq3_s",
-    IQ3M => "iq3_m",
-    IQ4XS => "iq4_xs",
-    IQ4NL => "iq4_nl",
+/// Exit codes for each way a run can fail, so wrappers can tell a flaky network from a bad model.
+/// Anything else exits 1, and bad arguments 2, as clap does.
+const EXIT_SETUP: u8 = 3;
+const EXIT_DOWNLOAD: u8 = 4;
+const EXIT_CONVERT: u8 = 5;
+const EXIT_IMATRIX: u8 = 6;
+const EXIT_QUANTIZE: u8 = 7;
+const EXIT_UPLOAD: u8 = 8;
+/// 128 + SIGINT, as shells report a process killed by Ctrl-C.
+const EXIT_INTERRUPTED: u8 = 130;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match cli().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::from(exit_code(e.as_ref()))
+        }
+    }
 }
 
-impl QuantLevel {
-    fn requires_imatrix(&self) -> bool {
-        matches!(
-            self,
-            QuantLevel::IQ1S
-                | QuantLevel::IQ1M
-                | QuantLevel::IQ2XXS
-                | QuantLevel::IQ2XS
-                | QuantLevel::IQ2S
-                | QuantLevel::IQ2M
-                | QuantLevel::Q2KS
-                | QuantLevel::IQ3XXS
-                | QuantLevel::IQ3XS
-                | QuantLevel::IQ3S
-                | QuantLevel::IQ3M
-                | QuantLevel::IQ4XS
-                | QuantLevel::IQ4NL
-        )
+fn exit_code(error: &(dyn std::error::Error + 'static)) -> u8 {
+    match error.downcast_ref::<autogguf::Error>() {
+        Some(autogguf::Error::Setup(_)) => EXIT_SETUP,
+        Some(autogguf::Error::Download(_)) => EXIT_DOWNLOAD,
+        Some(autogguf::Error::Convert(_)) => EXIT_CONVERT,
+        Some(autogguf::Error::Imatrix(_)) => EXIT_IMATRIX,
+        Some(autogguf::Error::Quantize(_)) => EXIT_QUANTIZE,
+        Some(autogguf::Error::Upload(_)) => EXIT_UPLOAD,
+        Some(autogguf::Error::Interrupted(_)) => EXIT_INTERRUPTED,
+        None => 1,
     }
 }
 
-async fn update_llama_cpp(
-    llama_path: PathBuf,
-    verbose: bool,
-    cancel_rx: Arc<Notify>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if !llama_path.exists() {
-        if verbose {
-            println!(
-                "🐪 llama.cpp not found at {}, installing...",
-                llama_path.display()
+async fn cli() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let console = !args.tui && args.output == OutputFormat::Human;
+    let telemetry = args
+        .otlp_endpoint
+        .as_deref()
+        .map(telemetry::Telemetry::new)
+        .transpose()?;
+    init_logging(
+        args.verbose,
+        console,
+        args.log_file.as_deref(),
+        telemetry.as_ref(),
+    )?;
+
+    if let Some(Command::Inspect { file }) = &args.command {
+        print!("{}", autogguf::inspect(tilde(file).as_ref()).await?);
+        return Ok(());
+    }
+    if let Some(Command::Estimate { model_id, revision }) = &args.command {
+        let hf_token = args.hf_token.clone().unwrap_or_default();
+        print!(
+            "{}",
+            autogguf::estimate(model_id, revision.as_deref(), &hf_token).await?
+        );
+        return Ok(());
+    }
+
+    let mut config = Config::load(args.config.as_deref()).await?;
+    args.apply_to(&mut config)?;
+    if args.print_config {
+        print!("{}", toml::to_string_pretty(&config)?);
+        return Ok(());
+    }
+
+    tracing::debug!("Got args: {args:?}");
+    tracing::debug!("Using config: {config:?}");
+
+    let model_ids = args.model_ids().await?;
+    let webhook = config.notify_url.as_deref().map(Webhook::new).transpose()?;
+    let notify = Arc::new(Notify::new());
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let notifier = notify.clone();
+    let interrupter = interrupted.clone();
+    tokio::spawn(async move {
+        shutdown_signal()
+            .await
+            .expect("failed to register shutdown signal handlers");
+        interrupter.store(true, Ordering::SeqCst);
+        notifier.notify_waiters(); // Signal cancellation
+        tracing::warn!(
+            "⏹️ stopping once the current step winds down; press Ctrl-C again to quit now"
+        );
+        if shutdown_signal().await.is_ok() {
+            autogguf::kill_subprocesses();
+            eprintln!("💥 force quit; partial outputs are left behind");
+            std::process::exit(EXIT_INTERRUPTED.into());
+        }
+    });
+
+    if let Some(Command::Serve { listen }) = args.command {
+        if !model_ids.is_empty() || args.local_model.is_some() {
+            return Err("serve takes its models from the API, not the command line".into());
+        }
+        if args.tui || args.dry_run {
+            return Err(
+                "serve reports progress through the API, so can't use --tui or --dry-run".into(),
             );
         }
-        let mut clone = Command::new("git")
-            .arg("clone")
-            .arg("https://github.com/ggerganov/llama.cpp")
-            .arg(llama_path.clone())
-            .spawn()?;
-        select! {
-            status = clone.wait() => {
-                status?;
+        let build = async |model_id: &str, cancel: &Arc<Notify>| {
+            prepare(&args, pipeline_builder(&args, &config, model_id, cancel)?).await
+        };
+        return serve::serve(listen, build, webhook, notify, interrupted).await;
+    }
+    if let Some(Command::Watch {
+        namespace,
+        patterns,
+        to,
+        interval,
+        backfill,
+        once,
+    }) = &args.command
+    {
+        if !model_ids.is_empty() || args.local_model.is_some() {
+            return Err("watch takes its models from the namespace, not the command line".into());
+        }
+        args.check_single_model_flags()?;
+        let mut watcher = Watcher::new(
+            namespace,
+            patterns,
+            *backfill,
+            &args.hf_token.clone().unwrap_or_default(),
+            Path::new("."),
+        )
+        .await?;
+        loop {
+            let changed = match watcher.poll().await {
+                Ok(changed) => changed,
+                Err(e) if *once => return Err(e),
+                Err(e) => {
+                    tracing::warn!("👀 {e}, retrying next poll");
+                    vec![]
+                }
+            };
+            for model in changed {
+                let short = &model.commit[..model.commit.len().min(7)];
+                match model.updated {
+                    true => tracing::info!("👀 {} updated to {short}", model.model_id),
+                    false => tracing::info!("👀 new model {} at {short}", model.model_id),
+                }
+                // NOTE: pinned to the commit seen, so a push since the poll isn't what's recorded
+                let mut pipeline = pipeline_builder(&args, &config, &model.model_id, &notify)?
+                    .revision(model.commit.clone());
+                if model.updated {
+                    // NOTE: outputs of the earlier commit are stale, so redo everything
+                    pipeline = pipeline.resume(false).force(true);
+                }
+                if let Some(to) = to {
+                    let name = model.model_id.rsplit('/').next().unwrap_or_default();
+                    pipeline = pipeline.repo_id(format!("{to}/{name}-GGUF"));
+                }
+                match run(
+                    &args,
+                    pipeline,
+                    &model.model_id,
+                    webhook.as_ref(),
+                    notify.clone(),
+                )
+                .await
+                {
+                    Ok(()) if !args.dry_run => watcher.converted(&model).await?,
+                    Ok(()) => {}
+                    Err(e) => {
+                        tracing::error!("💥 {} failed, retrying next poll: {e}", model.model_id)
+                    }
+                }
+                if interrupted.load(Ordering::SeqCst) {
+                    return Err("interrupted".into());
+                }
             }
-            _ = cancel_rx.notified() => {
-                clone.kill().await?;
-                return Err("Llama.cpp installation process cancelled".into());
+            if *once {
+                return Ok(());
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(interval * 60)) => {}
+                _ = notify.notified() => return Ok(()),
             }
         }
     }
-
-    if verbose {
-        println!("🐪 compiling llama.cpp...");
-    }
-    let mut pull = Command::new("git")
-        .arg("pull")
-        .current_dir(&llama_path)
-        .spawn()?;
-    select! {
-        status = pull.wait() => {
-            status?;
+    if let Some(Command::Verify { smoke_test }) = args.command {
+        if model_ids.is_empty() && args.local_model.is_none() {
+            return Err("verify needs the MODEL_ID or --local-model whose quants to check".into());
+        }
+        let model_id = model_ids.first().map(String::as_str).unwrap_or_default();
+        let pipeline = pipeline_builder(&args, &config, model_id, &notify)?.build();
+        let verifications = pipeline.verify(smoke_test).await?;
+        for verification in &verifications {
+            println!("{verification}");
         }
-        _ = cancel_rx.notified() => {
-            pull.kill().await?;
-            return Err("Llama.cpp update process cancelled".into());
+        let failed = verifications.iter().filter(|v| !v.passed).count();
+        if failed > 0 {
+            return Err(format!(
+                "{failed} of {} quants failed verification",
+                verifications.len()
+            )
+            .into());
         }
+        return Ok(());
     }
-
-    let mut clean = Command::new("make")
-        .arg("clean")
-        .current_dir(&llama_path)
-        .spawn()?;
-    select! {
-        status = clean.wait() => {
-            status?;
+    if let Some(Command::Audit) = args.command {
+        if model_ids.is_empty() && args.local_model.is_none() {
+            return Err("audit needs the MODEL_ID or --local-model whose quants to check".into());
+        }
+        let model_id = model_ids.first().map(String::as_str).unwrap_or_default();
+        let pipeline = pipeline_builder(&args, &config, model_id, &notify)?.build();
+        let audits = pipeline.audit().await?;
+        for audit in &audits {
+            println!("{audit}");
         }
-        _ = cancel_rx.notified() => {
-            clean.kill().await?;
-            return Err("Llama.cpp build clean process cancelled".into());
+        let failed = audits.iter().filter(|a| !a.passed()).count();
+        if failed > 0 {
+            return Err(format!("{failed} of {} quants need attention", audits.len()).into());
         }
+        return Ok(());
     }
-
-    let mut make = Command::new("make").current_dir(&llama_path).spawn()?;
-    select! {
-        status = make.wait() => {
-            status?;
+    if let Some(Command::Doctor) = args.command {
+        let model_id = model_ids.first().map(String::as_str).unwrap_or_default();
+        let pipeline = pipeline_builder(&args, &config, model_id, &notify)?.build();
+        let checks = pipeline.doctor().await;
+        for check in &checks {
+            println!("{check}");
         }
-        _ = cancel_rx.notified() => {
-            make.kill().await?;
-            return Err("Llama.cpp build process cancelled".into());
+        let failed = checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Fail)
+            .count();
+        if failed > 0 {
+            return Err(format!("{failed} of {} checks failed", checks.len()).into());
         }
+        return Ok(());
     }
 
-    if verbose {
-        println!("🐪 installing llama.cpp python deps...");
+    if model_ids.len() <= 1 {
+        let model_id = model_ids.first().map(String::as_str).unwrap_or_default();
+        let pipeline = pipeline_builder(&args, &config, model_id, &notify)?;
+        return run(&args, pipeline, model_id, webhook.as_ref(), notify).await;
     }
-    let mut deps = Command::new("pip3")
-        .arg("install")
-        .arg("-r")
-        .arg("requirements.txt")
-        .arg(if verbose { "-v" } else { "-q" })
-        .current_dir(&llama_path)
-        .spawn()?;
 
-    select! {
-        status = deps.wait() => {
-            status?;
+    // NOTE: a failed model doesn't stop the batch, but an interrupt does
+    let mut outcomes = vec![];
+    for (i, model_id) in model_ids.iter().enumerate() {
+        tracing::info!("📦 [{}/{}] {model_id}", i + 1, model_ids.len());
+        let result = match pipeline_builder(&args, &config, model_id, &notify) {
+            Ok(pipeline) => run(&args, pipeline, model_id, webhook.as_ref(), notify.clone()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            tracing::error!("💥 {model_id} failed: {e}");
         }
-        _ = cancel_rx.notified() => {
-            deps.kill().await?;
-            return Err("Llama.cpp build process cancelled".into());
+        outcomes.push((model_id, result.err().map(|e| e.to_string())));
+        if interrupted.load(Ordering::SeqCst) {
+            break;
         }
     }
-
-    Ok(())
-}
-
-async fn download_model(
-    model_id: &str,
-    model_name: &str,
-    verbose: bool,
-    cancel_rx: Arc<Notify>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    Command::new("mkdir")
-        .arg("-p")
-        .arg(model_name)
-        .spawn()?
-        .wait()
-        .await?;
-    if verbose {
-        println!("🤗 downloading {model_name}...");
-    }
-    let mut args = vec![
-        "download".to_string(),
-        model_id.to_string(),
-        "--local-dir".to_string(),
-        format!("./{model_name}"),
-    ];
-    if !verbose {
-        args.push("--quiet".to_string());
-    }
-    let mut download_task = Command::new("huggingface-cli").args(args).spawn()?;
-    select! {
-        status = download_task.wait() => {
-            status?;
-            if verbose {
-                println!("🤗 downloaded {model_name}!");
+    if args.output == OutputFormat::Human {
+        println!("\n📦 batch summary:");
+        for (model_id, error) in &outcomes {
+            match error {
+                None => println!("✅ {model_id}"),
+                Some(e) => println!("❌ {model_id}: {e}"),
             }
-            Ok(())
         }
-        _ = cancel_rx.notified() => {
-            download_task.kill().await?;
-            Err("Download process killed due to interrupt".into())
+        for model_id in &model_ids[outcomes.len()..] {
+            println!("⏭️ {model_id}: not started, interrupted");
         }
     }
+    let failed = outcomes.iter().filter(|(_, error)| error.is_some()).count();
+    if failed > 0 {
+        return Err(format!("{failed} of {} models failed", model_ids.len()).into());
+    }
+    if outcomes.len() < model_ids.len() {
+        return Err("interrupted before every model was converted".into());
+    }
+    Ok(())
 }
 
-async fn convert_fp(
-    precision: Precision,
-    llama_path: PathBuf,
-    output_path: PathBuf,
-    model_name: &str,
-    verbose: bool,
-    cancel_rx: Arc<Notify>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if verbose {
-        println!(
-            "🪄 converting {model_name} to {}...",
-            precision.to_string().to_uppercase()
+/// A pipeline for `model_id` with everything the config and flags set.
+fn pipeline_builder(
+    args: &Args,
+    config: &Config,
+    model_id: &str,
+    notify: &Arc<Notify>,
+) -> Result<PipelineBuilder, Box<dyn std::error::Error>> {
+    let mut pipeline = Pipeline::builder(model_id)
+        .quants(config.quants.clone())
+        .precision(config.full_precision.clone())
+        .name_template(config.name_template.clone())
+        .skip_download(args.skip_download)
+        .skip_upload(args.skip_upload)
+        .skip_mmproj(args.skip_mmproj)
+        .only_upload(args.only_upload)
+        .upload_logs(args.upload_logs)
+        .private(args.private)
+        .as_pr(args.as_pr)
+        .imatrix_per_dataset(args.imatrix_per_dataset)
+        .download_all_files(args.all_files)
+        .fast_download(args.fast_download)
+        .shared_cache(args.hf_cache)
+        .cleanup(args.cleanup()?)
+        .llama_prebuilt(args.llama_prebuilt)
+        .allow_requantize(args.allow_requantize)
+        .native_convert(args.native_convert)
+        .sign_files(args.sign_files)
+        .torrent(args.torrent)
+        .ipfs(args.ipfs)
+        .smoke_test(args.smoke_test)
+        .lmstudio_manifest(args.lmstudio_manifest)
+        .llamafile_runtime(&args.llamafile_runtime)
+        .eval_perplexity(args.eval_ppl)
+        .eval_kl_divergence(args.eval_kld)
+        .bench(args.bench)
+        .pure(args.pure)
+        .update_llama(args.update_llama)
+        .resume(!args.no_resume)
+        .force(args.force)
+        .force_unlock(args.force_unlock)
+        .ignore_disk_space(args.ignore_disk_space)
+        .llama_path(&config.llama_path)
+        .retries(config.retries)
+        .gpu_layers(config.gpu_layers)
+        .imatrix_chunks(config.imatrix_chunks)
+        .hf_credentials(
+            config.hf_user.clone().unwrap_or_default(),
+            args.hf_token.clone().unwrap_or_default(),
+        )
+        .verbose(args.verbose > 0)
+        .cancel(notify.clone());
+    if let Some(fp) = &args.fp {
+        pipeline = pipeline.fp(tilde(fp).into_owned());
+    }
+    if !args.calibration_data.is_empty() {
+        pipeline = pipeline.calibration_data(
+            args.calibration_data
+                .iter()
+                .map(|source| tilde(source).into_owned()),
         );
     }
-    let mut convert_fp_task = Command::new("python3")
-        .arg(llama_path.join("convert_hf_to_gguf.py"))
-        .arg(model_name)
-        .arg("--outtype")
-        .arg(precision.to_string())
-        .arg("--outfile")
-        .arg(&output_path)
-        .spawn()?;
-    select! {
-        status = convert_fp_task.wait() => {
-            status?;
-        }
-        _ = cancel_rx.notified() => {
-            convert_fp_task.kill().await?;
-            return Err("Conversion process killed due to interrupt".into());
-        }
+    if let Some(threads) = config.threads {
+        pipeline = pipeline.threads(threads);
     }
-
-    if !tokio::fs::try_exists(output_path).await? {
-        return Err("💥 Conversion failed".into());
-    };
-
-    if verbose {
-        // teeeeeeeechnically this is new and missing from the og autogguf[.py].....
-        println!(
-            "🪄 {model_name} conversion to {} complete!",
-            precision.to_string().to_uppercase()
-        );
+    if let Some(device) = &config.gpu_device {
+        pipeline = pipeline.gpu_device(device);
     }
-
-    Ok(())
+    if let Some(ctx_size) = config.imatrix_ctx_size {
+        pipeline = pipeline.imatrix_ctx_size(ctx_size);
+    }
+    for (&stage, &minutes) in &config.timeouts {
+        pipeline = pipeline.timeout(stage, Duration::from_secs(minutes * 60));
+    }
+    if let Some(minutes) = config.stall_timeout {
+        pipeline = pipeline.stall_timeout(Duration::from_secs(minutes * 60));
+    }
+    if let Some(repo_id) = &args.repo_id {
+        pipeline = pipeline.repo_id(repo_id);
+    }
+    if let Some(imatrix_repo) = &args.imatrix_repo {
+        pipeline = pipeline.imatrix_repo(imatrix_repo);
+    }
+    if let Some(convert_args) = &args.convert_args {
+        pipeline = pipeline.convert_args(&convert_args.0);
+    }
+    if let Some(quantize_args) = &args.quantize_args {
+        pipeline = pipeline.quantize_args(&quantize_args.0);
+    }
+    for tensor_type in &args.output_tensor_type {
+        pipeline = pipeline.output_tensor_type(tensor_type.clone());
+    }
+    for tensor_type in &args.token_embedding_type {
+        pipeline = pipeline.token_embedding_type(tensor_type.clone());
+    }
+    if !args.include.is_empty() {
+        pipeline = pipeline.download_include(&args.include);
+    }
+    if !args.exclude.is_empty() {
+        pipeline = pipeline.download_exclude(&args.exclude);
+    }
+    if let Some(git_ref) = &args.llama_ref {
+        pipeline = pipeline.llama_ref(git_ref);
+    }
+    if let Some(backend) = config.llama_backend {
+        pipeline = pipeline.llama_backend(backend);
+    }
+    if let Some(dataset) = &args.eval_dataset {
+        pipeline = pipeline.eval_dataset(tilde(dataset).into_owned());
+    }
+    if let Some(service) = &args.ipfs_pin_service {
+        pipeline = pipeline.ipfs_pin_service(service);
+    }
+    for adapter in &args.lora {
+        pipeline = pipeline.lora(adapter.clone());
+    }
+    if let Some(level) = &args.llamafile {
+        pipeline = pipeline.llamafile(level.clone());
+    }
+    if let Some(slug) = &config.collection {
+        pipeline = pipeline.collection(slug);
+    }
+    if let Some(bucket) = &config.upload_backend {
+        pipeline = pipeline.upload_backend(bucket.clone());
+    }
+    for mirror in &config.mirrors {
+        pipeline = pipeline.mirror(mirror.clone());
+    }
+    if let Some(key) = &config.sign_key {
+        pipeline = pipeline.sign_key(key);
+    }
+    if let Some(installer) = config.python_installer {
+        pipeline = pipeline.python_installer(installer);
+    }
+    if let Some(dir) = &config.llama_build_dir {
+        pipeline = pipeline.llama_build_dir(dir);
+    }
+    if let Some(dir) = &config.output_dir {
+        pipeline = pipeline.output_dir(dir);
+    }
+    if let Some(dir) = &config.work_dir {
+        pipeline = pipeline.work_dir(dir);
+    }
+    if let Some(template) = &config.chat_template {
+        pipeline = pipeline.chat_template(template.clone());
+    }
+    for (key, value) in &config.gguf_meta {
+        pipeline = pipeline.gguf_meta(key, value);
+    }
+    if let Some(revision) = &args.revision {
+        pipeline = pipeline.revision(revision);
+    }
+    if let Some(local_model) = &args.local_model {
+        pipeline = pipeline.local_model(tilde(local_model).into_owned());
+    }
+    // NOTE: after --local-model, since the adapter's name takes precedence over its directory's
+    if let Some(adapter) = &args.merge_lora {
+        pipeline = pipeline.merge_lora(adapter.clone());
+    }
+    for imatrix in &args.imatrix {
+        pipeline = pipeline.imatrix(tilde(imatrix).into_owned());
+    }
+    Ok(pipeline)
 }
 
-async fn generate_imatrix(
-    llama_path: PathBuf,
-    fp: PathBuf,
-    output_path: PathBuf,
-    model_name: &str,
-    verbose: bool,
-    cancel_rx: Arc<Notify>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if !tokio::fs::try_exists("calibration_data.txt").await? {
-        if verbose {
-            println!("🌐 downloading calibration dataset...");
-        }
-        let mut byte_stream =
-            reqwest::get("https://github.com/ggerganov/llama.cpp/files/14194570/groups_merged.txt")
-                .await?
-                .bytes_stream();
-        let mut f = File::create("calibration_data.txt").await?;
-        while let Some(bytes) = byte_stream.next().await {
-            f.write_all(&bytes?).await?;
-        }
-        f.flush().await?;
-    };
-    if verbose {
-        println!("⚖️ generating imatrix for {model_name}...");
-    }
-    let mut imatrix_task = Command::new(llama_path.join("llama-imatrix"))
-        .arg("-m")
-        .arg(fp)
-        .arg("-f")
-        .arg("calibration_data.txt")
-        .arg("-o")
-        .arg(output_path)
-        .arg("-t")
-        .arg("7")
-        .arg("-ngl")
-        .arg("999")
-        .arg("--chunks")
-        .arg("2000")
-        .spawn()?;
-    select! {
-        status = imatrix_task.wait() => {
-            status?;
-        }
-        _ = cancel_rx.notified() => {
-            imatrix_task.kill().await?;
-            return Err("imatrix generation process killed due to interrupt".into());
-        }
-    }
-    if verbose {
-        println!("🧹 cleaning up caliration dataset...");
-    }
-    tokio::fs::remove_file("calibration_data.txt").await?;
-    Ok(())
+/// Finish setting up `pipeline` with what can only be found out by looking at the model and the
+/// published repo, before it's built to run, whether from the command line or `serve`.
+async fn prepare(
+    args: &Args,
+    pipeline: PipelineBuilder,
+) -> Result<PipelineBuilder, Box<dyn std::error::Error>> {
+    let mut pipeline = pipeline.detect_embedding().await?;
+    // NOTE: last, since it narrows the quants everything else has settled on
+    if args.update_existing {
+        pipeline = pipeline.update_existing().await?;
+    }
+    Ok(pipeline)
 }
 
-async fn quantize(
-    q: QuantLevel,
-    llama_path: PathBuf,
-    fp: PathBuf,
-    imatrix: PathBuf,
-    model_name: &str,
-    verbose: bool,
-    cancel_rx: Arc<Notify>,
+/// Run `pipeline`, or with `--dry-run` just print its plan, reporting progress as `--output`
+/// and `--tui` ask, and to `webhook` and the desktop if asked to.
+async fn run(
+    args: &Args,
+    mut pipeline: PipelineBuilder,
+    model_id: &str,
+    webhook: Option<&Webhook>,
+    notify: Arc<Notify>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if verbose {
-        println!(
-            "🪄 quantizing {model_name} to {}...",
-            q.to_string().to_uppercase()
-        );
+    let (events_tx, events_rx) = tokio::sync::mpsc::unbounded_channel();
+    let listening = args.tui || args.output == OutputFormat::Json;
+    if listening || webhook.is_some() {
+        pipeline = pipeline.events(events_tx);
     }
-    let quant_path = format!(
-        "{model_name}/{}.{}.gguf",
-        model_name.to_lowercase(),
-        q.to_string().to_uppercase()
-    );
-    let default_args = vec![
-        fp.to_string_lossy().to_string(),
-        format!("{quant_path}.pending"),
-        q.to_string(),
-    ];
-    let mut args = vec![];
-    if q.requires_imatrix() {
-        args.push("--imatrix".to_string());
-        args.push(imatrix.to_string_lossy().to_string());
+    pipeline = prepare(args, pipeline).await?;
+    let pipeline = pipeline.build();
+    if args.dry_run {
+        println!("{}", pipeline.plan().await?);
+        return Ok(());
     }
-    args.extend_from_slice(default_args.as_slice());
-    let mut quantize = Command::new(llama_path.join("llama-quantize"))
-        .args(args)
-        .spawn()?;
-
-    select! {
-        status = quantize.wait() => {
-            status?;
-        }
-        _ = cancel_rx.notified() => {
-            quantize.kill().await?;
-            return Err("Quantization process killed due to interrupt".into());
+    let model_id = match model_id.is_empty() {
+        true => args.local_model.clone().unwrap_or_default(),
+        false => model_id.to_string(),
+    };
+    let (events_rx, forwarder) = match webhook {
+        // NOTE: the webhook sees every event first, passing them on to whoever else is listening
+        Some(webhook) => {
+            let (next_tx, next_rx) = tokio::sync::mpsc::unbounded_channel();
+            let forwarder = tokio::spawn(webhook.clone().forward(
+                model_id.clone(),
+                events_rx,
+                listening.then_some(next_tx),
+            ));
+            webhook
+                .send(&Notification::RunStarted {
+                    model_id: model_id.clone(),
+                })
+                .await;
+            (next_rx, Some(forwarder))
         }
+        None => (events_rx, None),
+    };
+    let result = report(args, pipeline, model_id.clone(), events_rx, notify).await;
+    if let Some(forwarder) = forwarder {
+        forwarder.await?;
     }
-
-    let mut moov = Command::new("mv")
-        .arg(format!("{quant_path}.pending"))
-        .arg(quant_path)
-        .spawn()?;
-
-    select! {
-        status = moov.wait() => {
-            status?;
-        }
-        _ = cancel_rx.notified() => {
-            moov.kill().await?;
-            return Err("Quantized file rename process killed due to interrupt".into());
-        }
+    let finished = match &result {
+        Ok(()) => Notification::RunFinished { model_id },
+        Err(e) => Notification::RunFailed {
+            model_id,
+            error: e.to_string(),
+        },
+    };
+    if let Some(webhook) = webhook {
+        webhook.send(&finished).await;
     }
-
-    Ok(())
+    if args.desktop_notify {
+        desktop::notify(&finished).await;
+    }
+    result
 }
 
-async fn upload_ggufs_to_hf(
-    hf_user: String,
-    hf_token: String,
-    verbose: bool,
-    model_name: &str,
-    cancel_rx: Arc<Notify>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    if verbose {
-        println!("🤗 uploading {model_name} to HuggingFace Hub...");
-    }
-
-    let repo_name = format!("{model_name}-GGUF");
-    let repo_id = format!("{hf_user}/{repo_name}");
-    let mut upload = Command::new("huggingface-cli")
-        .env("HF_USER", hf_user)
-        .env("HF_TOKEN", hf_token)
-        .arg("upload")
-        .arg(repo_id)
-        .arg(model_name) // local path
-        .arg(".") // remote path
-        .arg("--include")
-        .arg("*.gguf")
-        .arg("*.imatrix")
-        .spawn()?;
-
-    select! {
-        status = upload.wait() => {
-            status?;
-            if verbose {
-                println!("🤗 uploaded {model_name} to HuggingFace Hub!");
-            }
+/// Run `pipeline`, reporting its `events` as `--output` and `--tui` ask.
+async fn report(
+    args: &Args,
+    pipeline: Pipeline,
+    model_id: String,
+    events_rx: tokio::sync::mpsc::UnboundedReceiver<autogguf::Event>,
+    notify: Arc<Notify>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if args.tui {
+        let stages = pipeline.stages();
+        let dashboard =
+            tokio::task::spawn_blocking(move || tui::run(model_id, stages, events_rx, notify));
+        let result = pipeline.run().await;
+        // NOTE: hanging up the event channel is what tells the dashboard to exit
+        drop(pipeline);
+        dashboard.await??;
+        result?;
+    } else if args.output == OutputFormat::Json {
+        let printer = tokio::spawn(print_json_events(events_rx));
+        let result = pipeline.run().await;
+        let summary = pipeline.summary();
+        drop(pipeline);
+        printer.await??;
+        let finished = match &result {
+            Ok(_) => serde_json::json!({
+                "event": "run_finished",
+                "model_id": model_id,
+                "summary": summary,
+            }),
+            Err(e) => serde_json::json!({
+                "event": "run_failed",
+                "model_id": model_id,
+                "error": e.to_string(),
+                "summary": summary,
+            }),
+        };
+        println!("{finished}");
+        result?;
+        return Ok(());
+    } else {
+        let result = pipeline.run().await;
+        if let Some(eval) = result
+            .as_ref()
+            .ok()
+            .and_then(|report| report.eval.as_ref())
+            .filter(|eval| !eval.is_empty())
+        {
+            println!("\n📏 {eval}");
         }
-        _ = cancel_rx.notified() => {
-            upload.kill().await?;
-            return Err("Upload process killed due to interrupt".into());
+        let summary = pipeline.summary();
+        // NOTE: nothing ran when updating a repo that already has every quant
+        if !summary.stages.is_empty() {
+            println!("\n📋 {}:\n\n{summary}", pipeline.model_name());
         }
-    }
-
-    Ok(())
-}
-
-async fn upload_worker(
-    mut receiver: mpsc::Receiver<()>,
-    busy: Arc<AtomicBool>,
-    hf_user: String,
-    hf_token: String,
-    verbose: bool,
-    model_name: String,
-    cancel_rx: Arc<Notify>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    while receiver.recv().await.is_some() {
-        if !busy.swap(true, Ordering::Acquire) {
-            upload_ggufs_to_hf(
-                hf_user.clone(),
-                hf_token.clone(),
-                verbose,
-                &model_name,
-                cancel_rx.clone(),
-            )
-            .await?;
-
-            busy.store(false, Ordering::Release);
+        if let Err(autogguf::Error::Interrupted(_)) = &result {
+            match args.no_resume {
+                true => {
+                    println!("▶️ run it again without --no-resume to pick up where it left off")
+                }
+                false => println!("▶️ run the same command again to pick up where it left off"),
+            }
         }
+        result?;
     }
 
+    tracing::info!("🎉 done!");
+
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-
-    if args.verbose {
-        println!("Got args: {args:?}");
+fn parse_repo_id(repo_id: &str) -> Result<String, String> {
+    // NOTE: the name becomes a directory under --output-dir, so it mustn't climb out of it
+    let is_part =
+        |part: &str| !part.is_empty() && part != "." && part != ".." && !part.contains(['/', '\\']);
+    match repo_id.split_once('/') {
+        Some((namespace, name)) if is_part(namespace) && is_part(name) => Ok(repo_id.to_string()),
+        _ => Err(format!("expected namespace/name, got '{repo_id}'")),
     }
+}
 
-    let notify = Arc::new(Notify::new());
-    let notifier = notify.clone();
-    tokio::spawn(async move {
-        signal::ctrl_c()
-            .await
-            .expect("failed to register ctrl-c handler");
-        notifier.notify_waiters(); // Signal cancellation
-    });
+fn parse_glob(glob: &str) -> Result<String, String> {
+    glob::Pattern::new(glob)
+        .map(|_| glob.to_string())
+        .map_err(|e| format!("invalid glob '{glob}': {e}"))
+}
 
-    let llama_path = PathBuf::from(tilde(&args.llama_path).into_owned());
-    if args.update_llama {
-        update_llama_cpp(llama_path.clone(), args.verbose, notify.clone()).await?;
-    }
+/// A single `--*-args` value, split into arguments the way a POSIX shell would.
+#[derive(Debug, Clone)]
+struct ShellArgs(Vec<String>);
 
-    let model_name = args
-        .model_id
-        .split('/')
-        .map(std::string::ToString::to_string)
-        .collect::<Vec<_>>()
-        .get(1)
-        .cloned()
-        .unwrap_or_default();
+impl FromStr for ShellArgs {
+    type Err = String;
 
-    let override_fp = args.fp.is_some();
-    if args.skip_download || override_fp || args.only_upload {
-        println!("🤗 skipping download from HuggingFace Hub.");
-    } else {
-        download_model(&args.model_id, &model_name, args.verbose, notify.clone()).await?;
+    fn from_str(args: &str) -> Result<Self, Self::Err> {
+        shell_words::split(args)
+            .map(ShellArgs)
+            .map_err(|e| format!("couldn't split '{args}' into arguments: {e}"))
     }
+}
 
-    let precision = args.full_precision;
-    let fp = if let Some(fp) = args.fp {
-        PathBuf::from(tilde(&fp).into_owned())
-    } else {
-        PathBuf::from(format!(
-            "{model_name}/{}.{precision}.gguf",
-            model_name.to_lowercase()
-        ))
-    };
-    if override_fp || args.only_upload {
-        println!(
-            "skipping {} conversion.",
-            precision.to_string().to_uppercase()
-        );
-    } else {
-        convert_fp(
-            precision,
-            llama_path.clone(),
-            fp.clone(),
-            &model_name,
-            args.verbose,
-            notify.clone(),
-        )
-        .await?;
-    }
+/// Log to the console (unless progress is reported some other way) and to `log_file`, if given,
+/// and export spans through `telemetry`, if given.
+/// `RUST_LOG` overrides the console filter that `verbosity` picks.
+fn init_logging(
+    verbosity: u8,
+    console: bool,
+    log_file: Option<&str>,
+    telemetry: Option<&telemetry::Telemetry>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let console_layer = console.then(|| {
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+            EnvFilter::new(match verbosity {
+                0 => "autogguf=info,autogguf::span=off",
+                1 => "autogguf=debug,autogguf::span=off",
+                2 => "autogguf=trace,autogguf::span=off",
+                _ => "trace",
+            })
+        });
+        let writer = std::io::stderr
+            .with_max_level(tracing::Level::WARN)
+            .or_else(std::io::stdout);
+        let layer = fmt::layer().with_writer(writer);
+        if verbosity == 0 {
+            // NOTE: plain messages by default, to read like the emoji progress output always has
+            layer
+                .without_time()
+                .with_level(false)
+                .with_target(false)
+                .with_filter(filter)
+                .boxed()
+        } else {
+            layer.with_filter(filter).boxed()
+        }
+    });
 
-    let override_imat = args.imatrix.is_some();
-    let imatrix_path = if let Some(imat) = args.imatrix.clone() {
-        PathBuf::from(tilde(&imat).into_owned())
-    } else {
-        PathBuf::from(format!(
-            "{model_name}/{}.imatrix",
-            model_name.to_lowercase()
-        ))
+    let file_layer = match log_file {
+        Some(path) => {
+            let file = File::create(tilde(path).as_ref())
+                .map_err(|e| format!("couldn't create log file {path}: {e}"))?;
+            Some(
+                fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(Arc::new(file))
+                    .with_filter(EnvFilter::new("autogguf=trace,autogguf::span=off,info")),
+            )
+        }
+        None => None,
     };
-    if !args.only_upload && !override_imat && args.quants.iter().any(QuantLevel::requires_imatrix) {
-        generate_imatrix(
-            llama_path.clone(),
-            fp.clone(),
-            imatrix_path.clone(),
-            &model_name,
-            args.verbose,
-            notify.clone(),
-        )
-        .await?;
-    }
 
-    let hf_user = args.hf_user.clone().unwrap_or_default();
-    let hf_token = args.hf_token.clone().unwrap_or_default();
-
-    let (upload_tx, upload_rx) = mpsc::channel(10);
-    let busy = Arc::new(AtomicBool::new(false));
-    let busy_clone = busy.clone();
-    let mut upload_handle: Option<JoinHandle<_>> = None;
-    if !args.skip_upload {
-        upload_handle = Some(tokio::task::spawn(upload_worker(
-            upload_rx,
-            busy_clone,
-            hf_user.clone(),
-            hf_token.clone(),
-            args.verbose,
-            model_name.clone(),
-            notify.clone(),
-        )));
-    }
-
-    let n_quants = args.quants.len();
-
-    if !args.only_upload {
-        for q in args.quants {
-            quantize(
-                q,
-                llama_path.clone(),
-                fp.clone(),
-                imatrix_path.clone(),
-                &model_name,
-                args.verbose,
-                notify.clone(),
-            )
-            .await?;
+    tracing_subscriber::registry()
+        .with(console_layer)
+        .with(file_layer)
+        .with(telemetry.map(|telemetry| telemetry.layer()))
+        .init();
+    Ok(())
+}
 
-            if !args.skip_upload && !busy.load(Ordering::Acquire) {
-                upload_tx.send(()).await?;
-            }
-        }
+/// Print each event as a line of JSON until the pipeline hangs up.
+async fn print_json_events(
+    mut events: tokio::sync::mpsc::UnboundedReceiver<autogguf::Event>,
+) -> Result<(), serde_json::Error> {
+    while let Some(event) = events.recv().await {
+        println!("{}", serde_json::to_string(&event)?);
     }
+    Ok(())
+}
 
-    if !args.skip_upload {
-        while busy.load(Ordering::Acquire) {
-            sleep(Duration::from_millis(100)).await;
-        }
-        if n_quants > 1 {
-            // NOTE: given eager uploading, ensure all quants uploaded if multiple were quantized
-            upload_tx.send(()).await?;
+/// Resolves on Ctrl-C, or when the process is asked to stop some other way: SIGTERM or SIGHUP on
+/// Unix, as systemd, CI cancellation or closing the terminal send, and Ctrl-Break or closing the
+/// console window on Windows.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut hangup = signal(SignalKind::hangup())?;
+        tokio::select! {
+            result = signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+            _ = hangup.recv() => {
+                detach_stdio();
+                Ok(())
+            }
         }
     }
-    drop(upload_tx);
-    if let Some(handle) = upload_handle {
-        match handle.await? {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("Error in upload worker: {e:?}");
-            }
+    #[cfg(windows)]
+    {
+        let mut ctrl_break = signal::windows::ctrl_break()?;
+        let mut ctrl_close = signal::windows::ctrl_close()?;
+        tokio::select! {
+            result = signal::ctrl_c() => result,
+            _ = ctrl_break.recv() => Ok(()),
+            _ = ctrl_close.recv() => Ok(()),
         }
     }
+    #[cfg(not(any(unix, windows)))]
+    signal::ctrl_c().await
+}
 
-    println!("🎉 done!");
-
-    Ok(())
+/// Point stdout and stderr at `/dev/null` once the terminal they wrote to has hung up, so
+/// reporting how the run ended doesn't panic on a write error. The log file still gets it all.
+#[cfg(unix)]
+fn detach_stdio() {
+    let Ok(null) = File::options().write(true).open("/dev/null") else {
+        return;
+    };
+    use std::os::fd::AsRawFd;
+    // SAFETY: only swaps which file descriptors 1 and 2 refer to; `null` outlives the calls
+    unsafe {
+        libc::dup2(null.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(null.as_raw_fd(), libc::STDERR_FILENO);
+    }
 }
 
 #[test]
@@ -702,3 +1416,26 @@ fn verify_clap_cli() {
     use clap::CommandFactory;
     Args::command().debug_assert();
 }
+
+#[test]
+fn flags_override_the_config_and_it_fills_the_rest() {
+    let mut config: Config = toml::from_str(
+        r#"
+        quants = ["q8_0", "q6_k"]
+        full_precision = "bf16"
+        llama_path = "/opt/llama.cpp"
+        threads = 16
+        "#,
+    )
+    .unwrap();
+    let args =
+        Args::try_parse_from(["autogguf", "org/Model", "--quants", "q4_k_m,q5_k_m"]).unwrap();
+    args.apply_to(&mut config).unwrap();
+
+    let quants: Vec<String> = config.quants.iter().map(|q| q.to_string()).collect();
+    assert_eq!(quants, ["q4_k_m", "q5_k_m"]);
+
+    assert_eq!(config.full_precision.to_string(), "bf16");
+    assert_eq!(config.llama_path, "/opt/llama.cpp");
+    assert_eq!(config.threads, Some(16));
+}
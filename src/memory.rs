@@ -0,0 +1,84 @@
+//! Rough memory requirements for running each GGUF, for the model card: its weights, plus the KV
+//! cache llama.cpp allocates for the context.
+
+use crate::gguf::Header;
+use serde_json::Value as Json;
+
+/// Context lengths the model card estimates memory at, capped at what the model was trained for.
+pub(crate) const CONTEXT_LENGTHS: [u64; 3] = [4096, 16384, 32768];
+/// Common GPU memory sizes, in GiB, for the "which quant fits" table.
+pub(crate) const GPU_MEMORY_GIB: [u64; 6] = [8, 12, 16, 24, 48, 80];
+/// The share of GPU memory left over for llama.cpp's compute buffers, the display and the driver.
+pub(crate) const HEADROOM: f64 = 0.1;
+
+/// How the KV cache grows with context, from a GGUF's hyperparameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct KvCache {
+    /// Bytes of f16 keys and values per token of context, across all layers.
+    bytes_per_token: u64,
+    /// The longest context the model was trained for, if its GGUF says.
+    context_length: Option<u64>,
+}
+
+impl KvCache {
+    /// Read the attention hyperparameters under the header's `general.architecture`. None for
+    /// architectures without a KV cache that grows with context, like Mamba's.
+    pub fn from_header(header: &Header) -> Option<Self> {
+        let arch = header.get("general.architecture")?.as_str()?;
+        let key = |name: &str| header.get(&format!("{arch}.{name}"));
+        let layers = key("block_count")?.as_u64()? as usize;
+        // NOTE: some architectures vary head counts by layer, storing an array instead
+        let per_layer = |value: &Json| -> Option<Vec<u64>> {
+            match value {
+                Json::Array(items) => items.iter().map(Json::as_u64).collect(),
+                value => Some(vec![value.as_u64()?; layers]),
+            }
+        };
+        let heads = per_layer(key("attention.head_count")?)?;
+        let kv_heads = match key("attention.head_count_kv") {
+            Some(value) => per_layer(value)?,
+            None => heads.clone(),
+        };
+        let head_dim = key("embedding_length")?.as_u64()? / heads.iter().max()?.max(&1);
+        let length = |name: &str| key(name).and_then(Json::as_u64).unwrap_or(head_dim);
+        let per_head = length("attention.key_length") + length("attention.value_length");
+        let bytes_per_token = kv_heads.iter().sum::<u64>() * per_head * 2;
+        if bytes_per_token == 0 {
+            return None;
+        }
+        Some(Self {
+            bytes_per_token,
+            context_length: key("context_length").and_then(Json::as_u64),
+        })
+    }
+
+    /// The KV cache for `context` tokens.
+    pub fn bytes(&self, context: u64) -> u64 {
+        self.bytes_per_token * context
+    }
+
+    /// The [`CONTEXT_LENGTHS`] the model supports, or just its own if that's shorter than all.
+    pub fn contexts(&self) -> Vec<u64> {
+        let Some(max) = self.context_length else {
+            return CONTEXT_LENGTHS.to_vec();
+        };
+        let contexts: Vec<u64> = CONTEXT_LENGTHS.into_iter().filter(|c| *c <= max).collect();
+        match contexts.is_empty() {
+            true => vec![max],
+            false => contexts,
+        }
+    }
+}
+
+/// A context length as people usually write it, e.g. `4K` for 4096.
+pub(crate) fn context_label(context: u64) -> String {
+    match context % 1024 {
+        0 => format!("{}K", context / 1024),
+        _ => context.to_string(),
+    }
+}
+
+/// What's usable of a GPU with `gib` GiB, after [`HEADROOM`].
+pub(crate) fn usable_bytes(gib: u64) -> u64 {
+    ((gib << 30) as f64 * (1.0 - HEADROOM)) as u64
+}
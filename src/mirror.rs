@@ -0,0 +1,107 @@
+//! Extra destinations every upload is copied to once the main repo has it, like an org's
+//! internal copy on the Hub or an object storage bucket.
+
+use crate::{
+    context::Context,
+    event::Stage,
+    hub::{HubClient, UploadFile},
+    retry::with_retries,
+    storage::Bucket,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
+
+/// Where uploads are mirrored: a HuggingFace user or org, which gets a repo named like the main
+/// one, or a [`Bucket`] the repo's files are copied under.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Mirror {
+    Hub(String),
+    Bucket(Bucket),
+}
+
+impl FromStr for Mirror {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains("://") {
+            return s.parse().map(Self::Bucket);
+        }
+        if s.is_empty() || s.contains(['/', ':']) {
+            return Err(format!(
+                "'{s}' isn't a HuggingFace user or org, or a bucket URL like s3://bucket/prefix"
+            ));
+        }
+        Ok(Self::Hub(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for Mirror {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Mirror> for String {
+    fn from(mirror: Mirror) -> Self {
+        mirror.to_string()
+    }
+}
+
+impl Display for Mirror {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hub(namespace) => write!(f, "{namespace}"),
+            Self::Bucket(bucket) => write!(f, "{bucket}"),
+        }
+    }
+}
+
+impl Mirror {
+    /// Where the repo named `repo_name` goes, e.g. `my-org/Llama-3.1-8B-GGUF`.
+    pub fn destination(&self, repo_name: &str) -> String {
+        format!("{self}/{repo_name}")
+    }
+
+    /// Copy `files` into the repo named `repo_name` at this mirror, skipping whatever a Hub
+    /// mirror already has. A Hub repo that doesn't exist yet is created as `private` says.
+    pub(crate) async fn push(
+        &self,
+        repo_name: &str,
+        files: &[UploadFile],
+        hf_token: &str,
+        private: bool,
+        ctx: &Context,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let destination = self.destination(repo_name);
+        ctx.detail(format!(
+            "🪞 mirroring {} files to {destination}...",
+            files.len()
+        ));
+        match self {
+            Self::Hub(_) => {
+                let client = HubClient::new(hf_token, ctx.clone());
+                with_retries(
+                    &Stage::Upload,
+                    &format!("create {destination}"),
+                    ctx,
+                    || client.create_repo(&destination, private),
+                )
+                .await?;
+                let message = format!("Mirror {repo_name}");
+                with_retries(
+                    &Stage::Upload,
+                    &format!("mirror to {destination}"),
+                    ctx,
+                    || client.upload_files(&destination, files, &message),
+                )
+                .await?;
+            }
+            Self::Bucket(bucket) => bucket.copy(repo_name, files, ctx).await?,
+        }
+        ctx.detail(format!("🪞 mirrored to {destination}!"));
+        Ok(())
+    }
+}
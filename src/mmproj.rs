@@ -0,0 +1,93 @@
+//! Vision-language models' projectors. A model like LLaVA or Qwen2-VL pairs its language model
+//! with a vision encoder and projector, which llama.cpp's converter writes to a GGUF of its own,
+//! an mmproj, for llama.cpp to load alongside whichever quant of the language model with
+//! `--mmproj`.
+
+use crate::{
+    context::Context,
+    convert::{move_file, CONVERT_SCRIPT},
+    event::Stage,
+    llama, Precision, QuantLevel,
+};
+use serde_json::Value as Json;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Keys only a vision-language model's `config.json` has: the vision tower's config, as
+/// transformers' LLaVA, Qwen2-VL, Gemma 3, Mistral 3 and the like have, or the original LLaVA's.
+const VISION_KEYS: &[&str] = &["vision_config", "mm_vision_tower"];
+
+/// Whether the model in `source_dir` has a vision tower to convert.
+pub(crate) async fn is_multimodal(source_dir: &Path) -> bool {
+    let Ok(json) = tokio::fs::read_to_string(source_dir.join("config.json")).await else {
+        return false;
+    };
+    serde_json::from_str::<Json>(&json)
+        .is_ok_and(|config| VISION_KEYS.iter().any(|key| config.get(key).is_some()))
+}
+
+/// Whether the file at `path` is a projector, by its name, which llama.cpp keys on too.
+pub(crate) fn is_mmproj(path: &str) -> bool {
+    path.rsplit('/')
+        .next()
+        .is_some_and(|name| name.starts_with("mmproj-"))
+}
+
+/// The types the projector is converted to, as the converter's `--outtype` and the label in its
+/// file name: `precision`, like the language model, and `Q8_0`, which the converter quantizes to
+/// itself since `llama-quantize` doesn't handle projectors.
+pub(crate) fn types(precision: &Precision) -> [(String, String); 2] {
+    let q8_0 = QuantLevel::Q8_0.to_string();
+    [
+        (precision.to_string(), precision.to_string()),
+        (q8_0.clone(), q8_0.to_uppercase()),
+    ]
+}
+
+/// Where the projector labelled `label` goes in `output_dir`, e.g. `mmproj-qwen2-vl-7b.f16.gguf`.
+pub(crate) fn path(output_dir: &Path, model_name: &str, label: &str) -> PathBuf {
+    output_dir.join(format!("mmproj-{}.{label}.gguf", model_name.to_lowercase()))
+}
+
+pub(crate) fn convert_command(
+    llama_path: &Path,
+    source_dir: &Path,
+    outtype: &str,
+    output_path: &Path,
+) -> Command {
+    let venv_python = llama::venv_python(llama_path);
+    let mut command = match venv_python.exists() {
+        true => Command::new(venv_python),
+        false => Command::new(llama::python()),
+    };
+    command
+        .arg(llama_path.join(CONVERT_SCRIPT))
+        .arg(source_dir)
+        .arg("--mmproj")
+        .arg("--outtype")
+        .arg(outtype)
+        .arg("--outfile")
+        .arg(output_path);
+    command
+}
+
+/// Convert the projector of the model in `source_dir` to `outtype` at `output_path`, written to
+/// the side and moved into place once it's finished.
+pub(crate) async fn convert(
+    llama_path: &Path,
+    source_dir: &Path,
+    outtype: &str,
+    output_path: &Path,
+    ctx: &Context,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // NOTE: the converter adds an `mmproj-` prefix to outfiles without one, which this keeps
+    let pending = PathBuf::from(format!("{}.pending", output_path.to_string_lossy()));
+    let command = convert_command(llama_path, source_dir, outtype, &pending);
+    let description = format!("{CONVERT_SCRIPT} --mmproj of {}", source_dir.display());
+    if let Err(e) = ctx.run(&Stage::Convert, command, &description).await {
+        let _ = tokio::fs::remove_file(&pending).await;
+        return Err(e);
+    }
+    move_file(&pending, output_path).await?;
+    Ok(())
+}
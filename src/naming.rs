@@ -0,0 +1,220 @@
+//! Quant file names, from a template like `{model}-{quant}-imat.gguf` so they can match an
+//! existing repo's conventions.
+
+use crate::{Precision, QuantLevel};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The variables a [`NameTemplate`] can use.
+const VARIABLES: &[&str] = &["model", "model_lower", "quant", "precision", "date"];
+
+/// How quants are named unless configured otherwise, e.g. `llama-3.1-8b.Q4_K_M.gguf`.
+pub const DEFAULT_NAME_TEMPLATE: &str = "{model_lower}.{quant}.gguf";
+
+/// A quant file name with `{variable}`s filled in per quant: `{model}` and `{model_lower}` for
+/// the model name as is and lowercased, `{quant}` for the quant level in capitals, `{precision}`
+/// for the full-precision GGUF's, e.g. `F16`, and `{date}` for the day the run started.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NameTemplate(String);
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self(DEFAULT_NAME_TEMPLATE.to_string())
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.ends_with(".gguf") {
+            return Err(format!("'{s}' doesn't end in .gguf"));
+        }
+        if s.contains(['/', '\\']) {
+            return Err(format!(
+                "'{s}' is a path; set the directory with --output-dir instead"
+            ));
+        }
+        let mut rest = s;
+        let mut has_quant = false;
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                return Err(format!("unclosed {{ in '{s}'"));
+            };
+            let variable = &rest[start + 1..start + end];
+            if !VARIABLES.contains(&variable) {
+                return Err(format!(
+                    "unknown variable {{{variable}}} in '{s}', expected one of {}",
+                    VARIABLES
+                        .iter()
+                        .map(|v| format!("{{{v}}}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+            has_quant |= variable == "quant";
+            rest = &rest[start + end + 1..];
+        }
+        if !has_quant {
+            return Err(format!(
+                "'{s}' has no {{quant}}, so every quant would get the same name"
+            ));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for NameTemplate {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<NameTemplate> for String {
+    fn from(template: NameTemplate) -> Self {
+        template.0
+    }
+}
+
+impl Display for NameTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl NameTemplate {
+    /// The file name for `level` of `model_name`, converted from a `precision` GGUF on `date`.
+    pub fn render(
+        &self,
+        model_name: &str,
+        level: &QuantLevel,
+        precision: &Precision,
+        date: &str,
+    ) -> String {
+        self.0
+            .replace("{model_lower}", &model_name.to_lowercase())
+            .replace("{model}", model_name)
+            .replace("{quant}", &level.to_string().to_uppercase())
+            .replace("{precision}", &precision.to_string().to_uppercase())
+            .replace("{date}", date)
+    }
+}
+
+/// Today's date in UTC, as `YYYY-MM-DD`.
+pub(crate) fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86_400;
+    civil_date(days)
+}
+
+/// The date `days` after 1970-01-01, as `YYYY-MM-DD`.
+fn civil_date(days: u64) -> String {
+    // NOTE: Howard Hinnant's days-to-civil, shifted so years start in March
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// The quant or precision of the GGUF at `path` in a repo, in capitals, e.g. `Q8_0` for
+/// `Q8_0/model.Q8_0-00001-of-00002.gguf`. `None` for anything but a GGUF.
+pub(crate) fn repo_label(path: &str) -> Option<String> {
+    let stem = path.rsplit('/').next()?.strip_suffix(".gguf")?;
+    Some(label(stem).to_uppercase())
+}
+
+/// The quant or precision in a GGUF's name without `.gguf`, e.g. `Q4_K_M` in
+/// `Llama-3.1-8B-Q4_K_M-imat`, found whatever template named it. Falls back to whatever follows
+/// the last `.`.
+pub(crate) fn label(stem: &str) -> &str {
+    stem.rsplit(['.', '-'])
+        .find(|part| part.parse::<QuantLevel>().is_ok() || Precision::from_str(part, true).is_ok())
+        .unwrap_or_else(|| stem.rsplit('.').next().unwrap_or(stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_need_a_quant_and_known_variables() {
+        assert!("{model}-{quant}-imat.gguf".parse::<NameTemplate>().is_ok());
+        assert!(DEFAULT_NAME_TEMPLATE.parse::<NameTemplate>().is_ok());
+        for bad in [
+            "{model}-{quant}",
+            "{model}.gguf",
+            "quants/{quant}.gguf",
+            "{model}-{quant.gguf",
+            "{model}-{size}-{quant}.gguf",
+        ] {
+            assert!(bad.parse::<NameTemplate>().is_err(), "{bad} parsed");
+        }
+    }
+
+    #[test]
+    fn render_fills_in_every_variable() {
+        let template: NameTemplate = "{model}_{model_lower}_{quant}_{precision}_{date}.gguf"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            template.render(
+                "Llama-3.1-8B",
+                &QuantLevel::Q4KM,
+                &Precision::BF16,
+                "2024-07-23"
+            ),
+            "Llama-3.1-8B_llama-3.1-8b_Q4_K_M_BF16_2024-07-23.gguf"
+        );
+        assert_eq!(
+            NameTemplate::default().render("Llama-3.1-8B", &QuantLevel::Q8_0, &Precision::F16, ""),
+            "llama-3.1-8b.Q8_0.gguf"
+        );
+    }
+
+    #[test]
+    fn label_finds_the_quant_whatever_the_template() {
+        assert_eq!(label("llama-3.1-8b.Q4_K_M"), "Q4_K_M");
+        assert_eq!(label("Llama-3.1-8B-Q4_K_M-imat"), "Q4_K_M");
+        assert_eq!(label("Llama-3.1-8B-f16"), "f16");
+        assert_eq!(label("model.custom"), "custom");
+    }
+
+    #[test]
+    fn repo_label_reads_templated_shard_names() {
+        assert_eq!(
+            repo_label("Q8_0/model.Q8_0-00001-of-00002.gguf").as_deref(),
+            Some("Q8_0")
+        );
+        assert_eq!(
+            repo_label("Llama-3.1-8B-Q4_K_M-imat-00002-of-00002.gguf").as_deref(),
+            Some("Q4_K_M")
+        );
+        assert_eq!(repo_label("README.md"), None);
+    }
+
+    #[test]
+    fn civil_date_counts_from_the_epoch() {
+        assert_eq!(civil_date(0), "1970-01-01");
+        assert_eq!(civil_date(59), "1970-03-01");
+        assert_eq!(civil_date(11_016), "2000-02-29");
+        assert_eq!(civil_date(19_927), "2024-07-23");
+    }
+}
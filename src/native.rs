@@ -0,0 +1,1116 @@
+//! An experimental in-process HuggingFace-to-GGUF converter for the most common architectures,
+//! so converting them needs neither Python nor `convert_hf_to_gguf.py`. It aims to write the
+//! GGUF the Python converter would; anything it doesn't recognize is an error rather than a
+//! guess, so the Python converter can take over.
+
+use crate::{
+    gguf::{GgmlType, TensorInfo, Value, Writer},
+    Precision,
+};
+use half::{bf16, f16};
+use serde::Deserialize;
+use serde_json::Value as Json;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::File,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// The HuggingFace architectures the native converter handles, and the llama.cpp architecture
+/// each converts to.
+pub(crate) const ARCHITECTURES: &[(&str, &str)] = &[
+    ("LlamaForCausalLM", "llama"),
+    ("MistralForCausalLM", "llama"),
+    ("Qwen2ForCausalLM", "qwen2"),
+];
+
+/// `GGML_QUANT_VERSION`, recorded in every GGUF.
+const QUANT_VERSION: u32 = 2;
+
+#[derive(Debug, Deserialize)]
+struct HParams {
+    #[serde(default)]
+    architectures: Vec<String>,
+    hidden_size: u32,
+    num_hidden_layers: u32,
+    intermediate_size: u32,
+    num_attention_heads: u32,
+    num_key_value_heads: Option<u32>,
+    max_position_embeddings: u32,
+    rms_norm_eps: f32,
+    rope_theta: Option<f32>,
+    vocab_size: Option<u32>,
+    head_dim: Option<u32>,
+    rope_scaling: Option<RopeScaling>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RopeScaling {
+    rope_type: Option<String>,
+    #[serde(rename = "type")]
+    legacy_type: Option<String>,
+    factor: Option<f32>,
+    low_freq_factor: Option<f32>,
+    high_freq_factor: Option<f32>,
+    original_max_position_embeddings: Option<u32>,
+}
+
+impl HParams {
+    fn head_dim(&self) -> u32 {
+        self.head_dim
+            .unwrap_or(self.hidden_size / self.num_attention_heads)
+    }
+}
+
+/// Where a tensor's data comes from: a safetensors file, or computed by the converter.
+enum TensorData {
+    File {
+        path: PathBuf,
+        offset: u64,
+        dtype: GgmlType,
+        /// The head count to permute Q/K rows for, for llama.
+        permute_heads: Option<u32>,
+    },
+    Computed(Vec<f32>),
+}
+
+/// Convert the HuggingFace model in `source_dir` to a GGUF at `output_path`, like
+/// `convert_hf_to_gguf.py --outtype {precision}` would. Blocking, so run it off the runtime.
+pub(crate) fn convert(
+    source_dir: &Path,
+    output_path: &Path,
+    precision: &Precision,
+    model_name: &str,
+) -> Result<(), Error> {
+    let config_path = source_dir.join("config.json");
+    let config = std::fs::read_to_string(&config_path)
+        .map_err(|e| format!("couldn't read {}: {e}", config_path.display()))?;
+    let hparams: HParams = serde_json::from_str(&config)?;
+    let Some(arch) = hparams.architectures.iter().find_map(|a| {
+        ARCHITECTURES
+            .iter()
+            .find(|(hf, _)| hf == a)
+            .map(|(_, arch)| *arch)
+    }) else {
+        return Err(format!(
+            "the native converter doesn't support {}; it only handles {}",
+            hparams.architectures.join(", "),
+            supported()
+        )
+        .into());
+    };
+
+    let mut metadata = model_metadata(arch, &hparams, precision, model_name)?;
+    metadata.extend(vocab::metadata(source_dir, arch, hparams.vocab_size)?);
+    let tensors = tensors(source_dir, arch, &hparams, precision)?;
+    let infos: Vec<TensorInfo> = tensors.iter().map(|(info, _)| info.clone()).collect();
+
+    let mut gguf = Writer::new(BufWriter::new(File::create(output_path)?));
+    gguf.header(&metadata, &infos)?;
+    for (info, data) in tensors {
+        let bytes = match data {
+            TensorData::File {
+                path,
+                offset,
+                dtype,
+                permute_heads,
+            } => {
+                let len = info.shape.iter().product::<u64>() * dtype.size() as u64;
+                let mut file = File::open(&path)?;
+                file.seek(SeekFrom::Start(offset))?;
+                let mut bytes = vec![0; len as usize];
+                file.read_exact(&mut bytes)?;
+                if let Some(n_head) = permute_heads {
+                    bytes = permute(&bytes, info.shape[0] as usize, n_head as usize)?;
+                }
+                cast(bytes, dtype, info.dtype)
+            }
+            TensorData::Computed(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        };
+        gguf.tensor(&bytes)?;
+    }
+    gguf.into_inner().flush()?;
+    Ok(())
+}
+
+/// The supported HuggingFace architectures, for messages.
+pub(crate) fn supported() -> String {
+    ARCHITECTURES
+        .iter()
+        .map(|(hf, _)| *hf)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn model_metadata(
+    arch: &str,
+    hparams: &HParams,
+    precision: &Precision,
+    model_name: &str,
+) -> Result<Vec<(String, Value)>, Error> {
+    let key = |k: &str| format!("{arch}.{k}");
+    let file_type = match precision {
+        Precision::F32 => 0,
+        Precision::F16 => 1,
+        Precision::BF16 => 32,
+    };
+    let mut metadata = vec![
+        (
+            "general.architecture".to_string(),
+            Value::String(arch.to_string()),
+        ),
+        (
+            "general.type".to_string(),
+            Value::String("model".to_string()),
+        ),
+        (
+            "general.name".to_string(),
+            Value::String(model_name.to_string()),
+        ),
+        (key("block_count"), Value::U32(hparams.num_hidden_layers)),
+        (
+            key("context_length"),
+            Value::U32(hparams.max_position_embeddings),
+        ),
+        (key("embedding_length"), Value::U32(hparams.hidden_size)),
+        (
+            key("feed_forward_length"),
+            Value::U32(hparams.intermediate_size),
+        ),
+        (
+            key("attention.head_count"),
+            Value::U32(hparams.num_attention_heads),
+        ),
+    ];
+    if let Some(n_head_kv) = hparams.num_key_value_heads {
+        metadata.push((key("attention.head_count_kv"), Value::U32(n_head_kv)));
+    }
+    if let Some(theta) = hparams.rope_theta {
+        metadata.push((key("rope.freq_base"), Value::F32(theta)));
+    }
+    metadata.push((
+        key("attention.layer_norm_rms_epsilon"),
+        Value::F32(hparams.rms_norm_eps),
+    ));
+    if let Some(head_dim) = hparams.head_dim {
+        metadata.push((key("attention.key_length"), Value::U32(head_dim)));
+        metadata.push((key("attention.value_length"), Value::U32(head_dim)));
+    }
+    metadata.push(("general.file_type".to_string(), Value::U32(file_type)));
+    metadata.push((
+        "general.quantization_version".to_string(),
+        Value::U32(QUANT_VERSION),
+    ));
+    if arch == "llama" {
+        if let Some(vocab_size) = hparams.vocab_size {
+            metadata.push((key("vocab_size"), Value::U32(vocab_size)));
+        }
+        metadata.push((key("rope.dimension_count"), Value::U32(hparams.head_dim())));
+    }
+
+    if let Some(scaling) = &hparams.rope_scaling {
+        let kind = scaling
+            .rope_type
+            .as_deref()
+            .or(scaling.legacy_type.as_deref())
+            .unwrap_or("default");
+        match (kind, arch) {
+            ("default", _) => {}
+            // NOTE: llama3 scaling is baked into the rope_freqs tensor instead
+            ("llama3", "llama") => {}
+            ("linear", _) => {
+                metadata.push((
+                    key("rope.scaling.type"),
+                    Value::String("linear".to_string()),
+                ));
+                metadata.push((
+                    key("rope.scaling.factor"),
+                    Value::F32(scaling.factor.unwrap_or(1.0)),
+                ));
+            }
+            ("yarn", "qwen2") => {
+                metadata.push((key("rope.scaling.type"), Value::String("yarn".to_string())));
+                metadata.push((
+                    key("rope.scaling.factor"),
+                    Value::F32(scaling.factor.unwrap_or(1.0)),
+                ));
+                if let Some(original) = scaling.original_max_position_embeddings {
+                    metadata.push((
+                        key("rope.scaling.original_context_length"),
+                        Value::U32(original),
+                    ));
+                }
+            }
+            (kind, arch) => {
+                return Err(format!(
+                    "the native converter doesn't handle {kind} rope scaling for {arch}"
+                )
+                .into())
+            }
+        }
+    }
+    Ok(metadata)
+}
+
+/// Every tensor to write, named and typed for llama.cpp, with where to read it from.
+fn tensors(
+    source_dir: &Path,
+    arch: &str,
+    hparams: &HParams,
+    precision: &Precision,
+) -> Result<Vec<(TensorInfo, TensorData)>, Error> {
+    let mut tensors = vec![];
+    if let Some(freqs) = llama3_rope_freqs(arch, hparams) {
+        tensors.push((
+            TensorInfo {
+                name: "rope_freqs.weight".to_string(),
+                shape: vec![freqs.len() as u64],
+                dtype: GgmlType::F32,
+            },
+            TensorData::Computed(freqs),
+        ));
+    }
+
+    for path in weight_files(source_dir)? {
+        for (hf_name, entry) in safetensors_header(&path)? {
+            if hf_name.ends_with(".rotary_emb.inv_freq") {
+                continue;
+            }
+            let Some(name) = tensor_name(&hf_name) else {
+                return Err(format!(
+                    "the native converter doesn't know where {hf_name} goes in a GGUF"
+                )
+                .into());
+            };
+            let dtype = match entry.dtype.as_str() {
+                "F32" => GgmlType::F32,
+                "F16" => GgmlType::F16,
+                "BF16" => GgmlType::BF16,
+                dtype => {
+                    return Err(format!(
+                        "the native converter can't read {dtype} tensors like {hf_name}"
+                    )
+                    .into())
+                }
+            };
+            // NOTE: like convert_hf_to_gguf.py, norms and biases stay F32 whatever the precision
+            let out_type = match (entry.shape.len(), precision) {
+                (1, _) | (_, Precision::F32) => GgmlType::F32,
+                (_, Precision::F16) => GgmlType::F16,
+                (_, Precision::BF16) => GgmlType::BF16,
+            };
+            let permute_heads = match arch {
+                "llama" if name.contains(".attn_q.") => Some(hparams.num_attention_heads),
+                "llama" if name.contains(".attn_k.") => Some(
+                    hparams
+                        .num_key_value_heads
+                        .unwrap_or(hparams.num_attention_heads),
+                ),
+                _ => None,
+            };
+            tensors.push((
+                TensorInfo {
+                    name,
+                    shape: entry.shape,
+                    dtype: out_type,
+                },
+                TensorData::File {
+                    path: path.clone(),
+                    offset: entry.data_offsets[0],
+                    dtype,
+                    permute_heads,
+                },
+            ));
+        }
+    }
+    Ok(tensors)
+}
+
+/// The llama.cpp name for a HuggingFace Llama/Mistral/Qwen2 tensor, if it's one of theirs.
+fn tensor_name(hf_name: &str) -> Option<String> {
+    let (module, param) = hf_name.rsplit_once('.')?;
+    let name = match module {
+        "model.embed_tokens" => "token_embd".to_string(),
+        "model.norm" => "output_norm".to_string(),
+        "lm_head" => "output".to_string(),
+        _ => {
+            let (layer, module) = module.strip_prefix("model.layers.")?.split_once('.')?;
+            let layer: u32 = layer.parse().ok()?;
+            let name = match module {
+                "self_attn.q_proj" => "attn_q",
+                "self_attn.k_proj" => "attn_k",
+                "self_attn.v_proj" => "attn_v",
+                "self_attn.o_proj" => "attn_output",
+                "mlp.gate_proj" => "ffn_gate",
+                "mlp.up_proj" => "ffn_up",
+                "mlp.down_proj" => "ffn_down",
+                "input_layernorm" => "attn_norm",
+                "post_attention_layernorm" => "ffn_norm",
+                _ => return None,
+            };
+            format!("blk.{layer}.{name}")
+        }
+    };
+    Some(format!("{name}.{param}"))
+}
+
+/// The safetensors files the weights are in: the ones the index lists for sharded checkpoints,
+/// which also skips e.g. Mistral's `consolidated.safetensors` duplicate.
+fn weight_files(source_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    #[derive(Deserialize)]
+    struct Index {
+        weight_map: HashMap<String, String>,
+    }
+    let index = source_dir.join("model.safetensors.index.json");
+    if index.exists() {
+        let index: Index = serde_json::from_str(&std::fs::read_to_string(index)?)?;
+        let files: BTreeSet<String> = index.weight_map.into_values().collect();
+        return Ok(files.iter().map(|file| source_dir.join(file)).collect());
+    }
+    let single = source_dir.join("model.safetensors");
+    if single.exists() {
+        return Ok(vec![single]);
+    }
+    Err(format!(
+        "no model.safetensors in {}; the native converter doesn't read .bin or .pt checkpoints",
+        source_dir.display()
+    )
+    .into())
+}
+
+#[derive(Debug, Deserialize)]
+struct SafetensorsEntry {
+    dtype: String,
+    shape: Vec<u64>,
+    data_offsets: [u64; 2],
+}
+
+/// The tensors in a safetensors file, in file order, with absolute data offsets.
+fn safetensors_header(path: &Path) -> Result<Vec<(String, SafetensorsEntry)>, Error> {
+    let mut file = File::open(path)?;
+    let mut len = [0; 8];
+    file.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    let mut header = vec![0; len as usize];
+    file.read_exact(&mut header)?;
+    let mut entries: HashMap<String, Json> = serde_json::from_slice(&header)?;
+    entries.remove("__metadata__");
+    let mut tensors = entries
+        .into_iter()
+        .map(|(name, entry)| {
+            let mut entry: SafetensorsEntry = serde_json::from_value(entry)?;
+            entry.data_offsets = entry.data_offsets.map(|offset| offset + 8 + len);
+            Ok((name, entry))
+        })
+        .collect::<Result<Vec<_>, serde_json::Error>>()?;
+    tensors.sort_by_key(|(_, entry)| entry.data_offsets[0]);
+    Ok(tensors)
+}
+
+/// Reorder a Q or K projection's rows from HuggingFace's rotary layout to llama.cpp's, like
+/// `LlamaModel.permute` in `convert_hf_to_gguf.py`: each head's two halves get interleaved.
+fn permute(data: &[u8], rows: usize, n_head: usize) -> Result<Vec<u8>, Error> {
+    if !rows.is_multiple_of(n_head * 2) {
+        return Err(format!("can't split {rows} rows into {n_head} rotary heads").into());
+    }
+    let row_len = data.len() / rows;
+    let half = rows / n_head / 2;
+    let mut permuted = Vec::with_capacity(data.len());
+    for head in 0..n_head {
+        for i in 0..half {
+            for j in 0..2 {
+                let row = head * 2 * half + j * half + i;
+                permuted.extend_from_slice(&data[row * row_len..(row + 1) * row_len]);
+            }
+        }
+    }
+    Ok(permuted)
+}
+
+/// Convert little-endian tensor data between float types.
+fn cast(data: Vec<u8>, from: GgmlType, to: GgmlType) -> Vec<u8> {
+    if from == to {
+        return data;
+    }
+    let values = data.chunks_exact(from.size()).map(|b| match from {
+        GgmlType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        GgmlType::F16 => f16::from_le_bytes([b[0], b[1]]).to_f32(),
+        GgmlType::BF16 => bf16::from_le_bytes([b[0], b[1]]).to_f32(),
+    });
+    match to {
+        GgmlType::F32 => values.flat_map(f32::to_le_bytes).collect(),
+        GgmlType::F16 => values
+            .flat_map(|v| f16::from_f32(v).to_le_bytes())
+            .collect(),
+        GgmlType::BF16 => values
+            .flat_map(|v| bf16::from_f32(v).to_le_bytes())
+            .collect(),
+    }
+}
+
+/// Llama 3.1-style rope scaling factors, which llama.cpp reads from a `rope_freqs` tensor.
+fn llama3_rope_freqs(arch: &str, hparams: &HParams) -> Option<Vec<f32>> {
+    let scaling = hparams.rope_scaling.as_ref()?;
+    if arch != "llama" || scaling.rope_type.as_deref() != Some("llama3") {
+        return None;
+    }
+    let base = hparams.rope_theta.unwrap_or(10000.0) as f64;
+    let dim = hparams.head_dim() as f64;
+    let factor = scaling.factor.unwrap_or(8.0) as f64;
+    let low_freq_factor = scaling.low_freq_factor.unwrap_or(1.0) as f64;
+    let high_freq_factor = scaling.high_freq_factor.unwrap_or(4.0) as f64;
+    let old_context_len = scaling.original_max_position_embeddings.unwrap_or(8192) as f64;
+    let low_freq_wavelen = old_context_len / low_freq_factor;
+    let high_freq_wavelen = old_context_len / high_freq_factor;
+    let freqs = (0..hparams.head_dim())
+        .step_by(2)
+        .map(|i| {
+            let freq = 1.0 / base.powf(i as f64 / dim);
+            let wavelen = 2.0 * std::f64::consts::PI / freq;
+            let factor = if wavelen < high_freq_wavelen {
+                1.0
+            } else if wavelen > low_freq_wavelen {
+                factor
+            } else {
+                let smooth = (old_context_len / wavelen - low_freq_factor)
+                    / (high_freq_factor - low_freq_factor);
+                1.0 / ((1.0 - smooth) / factor + smooth)
+            };
+            factor as f32
+        })
+        .collect();
+    Some(freqs)
+}
+
+/// Tokenizer metadata, like `set_vocab` and gguf-py's `SpecialVocab`.
+mod vocab {
+    use super::{Error, Json, Value};
+    use serde::Deserialize;
+    use std::{collections::HashMap, path::Path};
+
+    // NOTE: llama.cpp's `llama_token_type`, which shares its values with SentencePiece's
+    const NORMAL: i32 = 1;
+    const UNKNOWN: i32 = 2;
+    const CONTROL: i32 = 3;
+    const USER_DEFINED: i32 = 4;
+    const UNUSED: i32 = 5;
+    const BYTE: i32 = 6;
+
+    /// Byte-level BPE pre-tokenizer regexes llama.cpp has built in, by their
+    /// `tokenizer.ggml.pre` name. `convert_hf_to_gguf.py` tells them apart by hashing the HF
+    /// tokenizer's output on a test string instead, which needs the `tokenizers` library.
+    const PRE_TOKENIZERS: &[(&str, &str)] = &[
+        (
+            "llama-bpe",
+            r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+",
+        ),
+        (
+            "qwen2",
+            r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+",
+        ),
+    ];
+
+    #[derive(Debug, Deserialize)]
+    struct TokenizerJson {
+        model: BpeModel,
+        #[serde(default)]
+        added_tokens: Vec<AddedToken>,
+        pre_tokenizer: Option<Json>,
+        post_processor: Option<Json>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct BpeModel {
+        #[serde(rename = "type")]
+        kind: String,
+        #[serde(default)]
+        byte_fallback: bool,
+        #[serde(default)]
+        vocab: HashMap<String, u32>,
+        #[serde(default)]
+        merges: Vec<Json>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct AddedToken {
+        id: u32,
+        content: String,
+        #[serde(default)]
+        special: bool,
+    }
+
+    struct Vocab {
+        model: &'static str,
+        pre: String,
+        tokens: Vec<String>,
+        scores: Option<Vec<f32>>,
+        types: Vec<i32>,
+        merges: Option<Vec<String>>,
+    }
+
+    pub(super) fn metadata(
+        source_dir: &Path,
+        arch: &str,
+        vocab_size: Option<u32>,
+    ) -> Result<Vec<(String, Value)>, Error> {
+        let tokenizer_config: Json =
+            read_json(&source_dir.join("tokenizer_config.json"))?.unwrap_or(Json::Null);
+        let tokenizer: Option<TokenizerJson> = read_json(&source_dir.join("tokenizer.json"))?
+            .map(serde_json::from_value)
+            .transpose()?;
+        let spm = source_dir.join("tokenizer.model");
+        let vocab = match (arch, &tokenizer) {
+            ("llama", _) if spm.exists() => {
+                sentencepiece(source_dir, &spm, vocab_size, &tokenizer_config)?
+            }
+            (_, Some(tokenizer)) => bpe(tokenizer, vocab_size)?,
+            (_, None) => {
+                return Err(format!(
+                    "no tokenizer.json or tokenizer.model in {}",
+                    source_dir.display()
+                )
+                .into())
+            }
+        };
+
+        let mut metadata = vec![
+            (
+                "tokenizer.ggml.model".to_string(),
+                Value::String(vocab.model.to_string()),
+            ),
+            ("tokenizer.ggml.pre".to_string(), Value::String(vocab.pre)),
+            (
+                "tokenizer.ggml.tokens".to_string(),
+                Value::StringArray(vocab.tokens.clone()),
+            ),
+        ];
+        if let Some(scores) = vocab.scores {
+            metadata.push(("tokenizer.ggml.scores".to_string(), Value::F32Array(scores)));
+        }
+        metadata.push((
+            "tokenizer.ggml.token_type".to_string(),
+            Value::I32Array(vocab.types),
+        ));
+        if let Some(merges) = vocab.merges {
+            metadata.push((
+                "tokenizer.ggml.merges".to_string(),
+                Value::StringArray(merges),
+            ));
+        }
+        metadata.extend(special(
+            source_dir,
+            &vocab.tokens,
+            &tokenizer_config,
+            tokenizer.as_ref(),
+        )?);
+        Ok(metadata)
+    }
+
+    fn read_json(path: &Path) -> Result<Option<Json>, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Tokens that llama.cpp should treat as control tokens even when not marked special.
+    fn looks_special(token: &str) -> bool {
+        (token.starts_with("<|") && token.ends_with("|>"))
+            || (token.starts_with("<｜") && token.ends_with("｜>"))
+            || ["<pad>", "<mask>", "<2mass>", "[@BOS@]"].contains(&token)
+    }
+
+    /// A SentencePiece vocab from `tokenizer.model`, like `_create_vocab_sentencepiece`.
+    fn sentencepiece(
+        source_dir: &Path,
+        spm: &Path,
+        vocab_size: Option<u32>,
+        tokenizer_config: &Json,
+    ) -> Result<Vocab, Error> {
+        let pieces = sentencepiece_pieces(&std::fs::read(spm)?)?;
+        let vocab_size = vocab_size.map_or(pieces.len(), |n| n as usize);
+        let mut tokens: Vec<String> = (0..vocab_size).map(|i| format!("[PAD{i}]")).collect();
+        let mut scores = vec![-10000.0; vocab_size];
+        let mut types = vec![UNUSED; vocab_size];
+        for (id, (piece, score, kind)) in pieces.into_iter().enumerate().take(vocab_size) {
+            tokens[id] = piece;
+            scores[id] = score;
+            types[id] = match kind {
+                2 => UNKNOWN,
+                3 => CONTROL,
+                5 => UNUSED,
+                6 => BYTE,
+                _ => NORMAL,
+            };
+        }
+
+        if let Some(Json::Object(added)) = read_json(&source_dir.join("added_tokens.json"))? {
+            for (token, id) in added {
+                let Some(id) = id.as_u64().map(|id| id as usize) else {
+                    continue;
+                };
+                if id < vocab_size {
+                    tokens[id] = token;
+                    scores[id] = -1000.0;
+                    types[id] = USER_DEFINED;
+                }
+            }
+        }
+        if let Some(Json::Object(decoder)) = tokenizer_config.get("added_tokens_decoder") {
+            for (id, data) in decoder {
+                let (Ok(id), Some(content)) = (
+                    id.parse::<usize>(),
+                    data.get("content").and_then(Json::as_str),
+                ) else {
+                    continue;
+                };
+                if id >= vocab_size {
+                    continue;
+                }
+                let special = data.get("special").and_then(Json::as_bool) == Some(true);
+                if special || looks_special(content) {
+                    tokens[id] = content.to_string();
+                    types[id] = CONTROL;
+                } else {
+                    // NOTE: llama.cpp expects user-defined tokens pre-normalized
+                    tokens[id] = content.replace('▁', " ");
+                    types[id] = USER_DEFINED;
+                }
+                scores[id] = -1000.0;
+            }
+        }
+
+        Ok(Vocab {
+            model: "llama",
+            pre: "default".to_string(),
+            tokens,
+            scores: Some(scores),
+            types,
+            merges: None,
+        })
+    }
+
+    /// A byte-level BPE vocab from `tokenizer.json`, like `_set_vocab_gpt2`.
+    fn bpe(tokenizer: &TokenizerJson, vocab_size: Option<u32>) -> Result<Vocab, Error> {
+        if tokenizer.model.kind != "BPE" || tokenizer.model.byte_fallback {
+            return Err(format!(
+                "the native converter only handles byte-level BPE tokenizers, not {}{}",
+                tokenizer.model.kind,
+                if tokenizer.model.byte_fallback {
+                    " with byte fallback"
+                } else {
+                    ""
+                }
+            )
+            .into());
+        }
+        let regex = tokenizer
+            .pre_tokenizer
+            .as_ref()
+            .and_then(split_regex)
+            .unwrap_or_default();
+        let Some((pre, _)) = PRE_TOKENIZERS.iter().find(|(_, r)| *r == regex) else {
+            return Err(format!(
+                "the native converter doesn't recognize this tokenizer's pre-tokenizer ({regex:?})"
+            )
+            .into());
+        };
+
+        let mut by_id: HashMap<u32, &str> = tokenizer
+            .model
+            .vocab
+            .iter()
+            .map(|(token, id)| (*id, token.as_str()))
+            .collect();
+        let added: HashMap<u32, &AddedToken> =
+            tokenizer.added_tokens.iter().map(|t| (t.id, t)).collect();
+        for token in &tokenizer.added_tokens {
+            by_id.insert(token.id, &token.content);
+        }
+        let vocab_size = vocab_size.map_or(by_id.len(), |n| n as usize);
+        let mut tokens = Vec::with_capacity(vocab_size);
+        let mut types = Vec::with_capacity(vocab_size);
+        for id in 0..vocab_size as u32 {
+            match (by_id.get(&id), added.get(&id)) {
+                (None, _) => {
+                    tokens.push(format!("[PAD{id}]"));
+                    types.push(UNUSED);
+                }
+                (Some(token), Some(added)) if added.special || looks_special(token) => {
+                    tokens.push(token.to_string());
+                    types.push(CONTROL);
+                }
+                (Some(token), Some(_)) => {
+                    tokens.push(token.replace('▁', " "));
+                    types.push(USER_DEFINED);
+                }
+                (Some(token), None) => {
+                    tokens.push(token.to_string());
+                    types.push(NORMAL);
+                }
+            }
+        }
+
+        let merges = tokenizer
+            .model
+            .merges
+            .iter()
+            .filter_map(|merge| match merge {
+                Json::String(merge) => Some(merge.clone()),
+                Json::Array(pair) => Some(
+                    pair.iter()
+                        .filter_map(Json::as_str)
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+                _ => None,
+            })
+            .collect();
+
+        Ok(Vocab {
+            model: "gpt2",
+            pre: pre.to_string(),
+            tokens,
+            scores: None,
+            types,
+            merges: Some(merges),
+        })
+    }
+
+    /// The regex of the first `Split` pre-tokenizer, searching into `Sequence`s.
+    fn split_regex(pre_tokenizer: &Json) -> Option<String> {
+        match pre_tokenizer.get("type")?.as_str()? {
+            "Split" => Some(
+                pre_tokenizer
+                    .get("pattern")?
+                    .get("Regex")?
+                    .as_str()?
+                    .to_string(),
+            ),
+            "Sequence" => pre_tokenizer
+                .get("pretokenizers")?
+                .as_array()?
+                .iter()
+                .find_map(split_regex),
+            _ => None,
+        }
+    }
+
+    /// Special token ids, whether to add BOS/EOS, and the chat template, like `SpecialVocab`:
+    /// from `tokenizer_config.json`, falling back to `config.json`.
+    fn special(
+        source_dir: &Path,
+        tokens: &[String],
+        tokenizer_config: &Json,
+        tokenizer: Option<&TokenizerJson>,
+    ) -> Result<Vec<(String, Value)>, Error> {
+        let config = read_json(&source_dir.join("config.json"))?.unwrap_or(Json::Null);
+        let ids: HashMap<&str, usize> = tokens
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(id, token)| (token.as_str(), id))
+            .collect();
+        let mut metadata = vec![];
+        for (kind, key) in [
+            ("bos", "bos"),
+            ("eos", "eos"),
+            ("unk", "unknown"),
+            ("sep", "seperator"),
+            ("pad", "padding"),
+        ] {
+            let content = match tokenizer_config.get(format!("{kind}_token")) {
+                Some(Json::String(content)) => Some(content.as_str()),
+                Some(entry) => entry.get("content").and_then(Json::as_str),
+                None => None,
+            };
+            let id = content
+                .and_then(|content| ids.get(content).copied())
+                .or_else(|| {
+                    config
+                        .get(format!("{kind}_token_id"))
+                        .and_then(Json::as_u64)
+                        .map(|id| id as usize)
+                });
+            if let Some(id) = id.filter(|id| *id < tokens.len()) {
+                metadata.push((
+                    format!("tokenizer.ggml.{key}_token_id"),
+                    Value::U32(id as u32),
+                ));
+            }
+        }
+
+        let (mut add_bos, mut add_eos) = tokenizer
+            .and_then(|t| t.post_processor.as_ref())
+            .map_or((None, None), template_adds);
+        for (kind, add) in [("bos", &mut add_bos), ("eos", &mut add_eos)] {
+            if let Some(value) = tokenizer_config
+                .get(format!("add_{kind}_token"))
+                .and_then(Json::as_bool)
+            {
+                *add = Some(value);
+            }
+        }
+        for (kind, add) in [("bos", add_bos), ("eos", add_eos)] {
+            if let Some(add) = add {
+                metadata.push((format!("tokenizer.ggml.add_{kind}_token"), Value::Bool(add)));
+            }
+        }
+
+        let template = match tokenizer_config.get("chat_template") {
+            Some(Json::String(template)) => Some(template.clone()),
+            Some(Json::Array(templates)) => templates
+                .iter()
+                .find(|t| t.get("name").and_then(Json::as_str) == Some("default"))
+                .and_then(|t| t.get("template")?.as_str().map(str::to_string)),
+            _ => std::fs::read_to_string(source_dir.join("chat_template.jinja")).ok(),
+        };
+        if let Some(template) = template {
+            metadata.push((
+                "tokenizer.chat_template".to_string(),
+                Value::String(template),
+            ));
+        }
+        Ok(metadata)
+    }
+
+    /// Whether a `TemplateProcessing` post-processor adds a special token before and after a
+    /// single sequence.
+    fn template_adds(post_processor: &Json) -> (Option<bool>, Option<bool>) {
+        let template = match post_processor.get("type").and_then(Json::as_str) {
+            Some("TemplateProcessing") => Some(post_processor),
+            Some("Sequence") => post_processor
+                .get("processors")
+                .and_then(Json::as_array)
+                .and_then(|processors| {
+                    processors.iter().find(|p| {
+                        p.get("type").and_then(Json::as_str) == Some("TemplateProcessing")
+                    })
+                }),
+            _ => None,
+        };
+        let Some(single) = template
+            .and_then(|t| t.get("single"))
+            .and_then(Json::as_array)
+        else {
+            return (None, None);
+        };
+        let is_special =
+            |item: Option<&Json>| item.is_some_and(|i| i.get("SpecialToken").is_some());
+        (
+            Some(is_special(single.first())),
+            Some(single.len() > 1 && is_special(single.last())),
+        )
+    }
+
+    /// The pieces in a SentencePiece `tokenizer.model`: text, score and type, read straight
+    /// from the protobuf.
+    fn sentencepiece_pieces(model: &[u8]) -> Result<Vec<(String, f32, u64)>, Error> {
+        let mut pieces = vec![];
+        for (field, value) in proto_fields(model)? {
+            let (1, Proto::Bytes(piece)) = (field, value) else {
+                continue;
+            };
+            let (mut text, mut score, mut kind) = (String::new(), 0.0, 1);
+            for field in proto_fields(piece)? {
+                match field {
+                    (1, Proto::Bytes(bytes)) => text = String::from_utf8(bytes.to_vec())?,
+                    (2, Proto::Fixed32(bits)) => score = f32::from_bits(bits),
+                    (3, Proto::Varint(value)) => kind = value,
+                    _ => {}
+                }
+            }
+            pieces.push((text, score, kind));
+        }
+        Ok(pieces)
+    }
+
+    enum Proto<'a> {
+        Varint(u64),
+        Fixed32(u32),
+        Fixed64,
+        Bytes(&'a [u8]),
+    }
+
+    fn proto_fields(mut buf: &[u8]) -> Result<Vec<(u64, Proto<'_>)>, Error> {
+        let mut fields = vec![];
+        while !buf.is_empty() {
+            let key = varint(&mut buf)?;
+            let value = match key & 7 {
+                0 => Proto::Varint(varint(&mut buf)?),
+                1 => {
+                    take(&mut buf, 8)?;
+                    Proto::Fixed64
+                }
+                2 => {
+                    let len = varint(&mut buf)? as usize;
+                    Proto::Bytes(take(&mut buf, len)?)
+                }
+                5 => {
+                    let bytes = take(&mut buf, 4)?;
+                    Proto::Fixed32(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                }
+                wire => return Err(format!("unsupported protobuf wire type {wire}").into()),
+            };
+            fields.push((key >> 3, value));
+        }
+        Ok(fields)
+    }
+
+    fn varint(buf: &mut &[u8]) -> Result<u64, Error> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let Some((&byte, rest)) = buf.split_first() else {
+                return Err("truncated tokenizer.model".into());
+            };
+            *buf = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("malformed varint in tokenizer.model".into())
+    }
+
+    fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+        if buf.len() < len {
+            return Err("truncated tokenizer.model".into());
+        }
+        let (head, rest) = buf.split_at(len);
+        *buf = rest;
+        Ok(head)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `LlamaModel.permute`'s numpy, spelled out: reshape the rows to `(n_head, 2, half)`, then
+    /// swap the last two axes.
+    fn reference_permute(rows: &[Vec<u8>], n_head: usize) -> Vec<Vec<u8>> {
+        let half = rows.len() / n_head / 2;
+        let reshaped: Vec<Vec<Vec<&Vec<u8>>>> = (0..n_head)
+            .map(|h| {
+                (0..2)
+                    .map(|j| (0..half).map(|i| &rows[(h * 2 + j) * half + i]).collect())
+                    .collect()
+            })
+            .collect();
+        let mut permuted = vec![];
+        for head in &reshaped {
+            for i in 0..half {
+                for pair in head {
+                    permuted.push(pair[i].clone());
+                }
+            }
+        }
+        permuted
+    }
+
+    #[test]
+    fn permute_matches_reshape_and_swapaxes() {
+        let (n_rows, cols, n_head) = (12, 3, 2);
+        let rows: Vec<Vec<u8>> = (0..n_rows)
+            .map(|r| (0..cols).map(|c| (r * cols + c) as u8).collect())
+            .collect();
+        let permuted = permute(&rows.concat(), n_rows, n_head).unwrap();
+        assert_eq!(permuted, reference_permute(&rows, n_head).concat());
+
+        // NOTE: each head's halves interleave: rows 0..3 and 3..6 become 0, 3, 1, 4, 2, 5
+        let first_column: Vec<u8> = permuted.iter().step_by(cols).map(|b| b / 3).collect();
+        assert_eq!(first_column, [0, 3, 1, 4, 2, 5, 6, 9, 7, 10, 8, 11]);
+    }
+
+    #[test]
+    fn permute_rejects_rows_that_dont_split_into_heads() {
+        assert!(permute(&[0; 10], 10, 3).is_err());
+    }
+
+    #[test]
+    fn cast_converts_between_float_types() {
+        // NOTE: exact in all three, so every cast between them is lossless
+        let values = [0.0f32, 1.0, -2.5, 0.15625];
+        let f32s: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let f16s: Vec<u8> = values
+            .iter()
+            .flat_map(|v| f16::from_f32(*v).to_le_bytes())
+            .collect();
+        let bf16s: Vec<u8> = values
+            .iter()
+            .flat_map(|v| bf16::from_f32(*v).to_le_bytes())
+            .collect();
+        assert_eq!(&f16s[2..4], [0x00, 0x3c]);
+        assert_eq!(&bf16s[2..4], [0x80, 0x3f]);
+
+        assert_eq!(cast(f32s.clone(), GgmlType::F32, GgmlType::F16), f16s);
+        assert_eq!(cast(f32s.clone(), GgmlType::F32, GgmlType::BF16), bf16s);
+        assert_eq!(cast(f16s.clone(), GgmlType::F16, GgmlType::F32), f32s);
+        assert_eq!(cast(f16s.clone(), GgmlType::F16, GgmlType::BF16), bf16s);
+        assert_eq!(cast(bf16s.clone(), GgmlType::BF16, GgmlType::F16), f16s);
+        assert_eq!(cast(bf16s.clone(), GgmlType::BF16, GgmlType::F32), f32s);
+        assert_eq!(cast(bf16s.clone(), GgmlType::BF16, GgmlType::BF16), bf16s);
+    }
+
+    #[test]
+    fn tensor_name_maps_each_module() {
+        let cases = [
+            ("model.embed_tokens.weight", "token_embd.weight"),
+            ("model.norm.weight", "output_norm.weight"),
+            ("lm_head.weight", "output.weight"),
+            (
+                "model.layers.0.self_attn.q_proj.weight",
+                "blk.0.attn_q.weight",
+            ),
+            (
+                "model.layers.1.self_attn.k_proj.weight",
+                "blk.1.attn_k.weight",
+            ),
+            (
+                "model.layers.2.self_attn.v_proj.weight",
+                "blk.2.attn_v.weight",
+            ),
+            (
+                "model.layers.3.self_attn.o_proj.weight",
+                "blk.3.attn_output.weight",
+            ),
+            (
+                "model.layers.4.mlp.gate_proj.weight",
+                "blk.4.ffn_gate.weight",
+            ),
+            ("model.layers.5.mlp.up_proj.weight", "blk.5.ffn_up.weight"),
+            (
+                "model.layers.6.mlp.down_proj.weight",
+                "blk.6.ffn_down.weight",
+            ),
+            (
+                "model.layers.7.input_layernorm.weight",
+                "blk.7.attn_norm.weight",
+            ),
+            (
+                "model.layers.31.post_attention_layernorm.weight",
+                "blk.31.ffn_norm.weight",
+            ),
+            ("model.layers.0.self_attn.q_proj.bias", "blk.0.attn_q.bias"),
+        ];
+        for (hf_name, name) in cases {
+            assert_eq!(tensor_name(hf_name).as_deref(), Some(name), "{hf_name}");
+        }
+    }
+
+    #[test]
+    fn tensor_name_skips_unknown_tensors() {
+        for hf_name in [
+            "model.layers.0.self_attn.rotary_emb.inv_freq",
+            "model.layers.x.mlp.up_proj.weight",
+            "vision_tower.blocks.0.attn.weight",
+            "weight",
+        ] {
+            assert_eq!(tensor_name(hf_name), None, "{hf_name}");
+        }
+    }
+}
@@ -0,0 +1,3212 @@
+use crate::{
+    audit::{self, QuantAudit},
+    card::{CardInfo, ImatrixSource, TensorTypes},
+    chat_template::{self, ChatTemplate, CHAT_TEMPLATE_KEY},
+    checksums::CHECKSUMS_FILE,
+    cleanup::{self, Cleanup},
+    context::{Context, LOG_DIR},
+    convert::{self, ImatrixParams},
+    doctor::{self, Check},
+    embedding::{self, Pooling},
+    error::Error,
+    eval::{self, EvalResults},
+    event::{self, Event, Stage, SPAN_TARGET},
+    gguf,
+    hf::{self, DownloadOptions, UploadTarget},
+    hub::{files_with_extensions, HubClient, ModelConfig, UploadFile},
+    llama::{self, LlamaLock},
+    llamafile,
+    lmstudio::{Manifest, MANIFEST_FILE},
+    lora::{self, LoraAdapter, LoraBase},
+    mirror::Mirror,
+    mmproj,
+    naming::{self, NameTemplate},
+    native,
+    plan::{human_bytes, Decision, Plan, PlannedStage},
+    run_lock::RunLock,
+    sign::{Signing, SigningKey},
+    state::PipelineState,
+    storage::Bucket,
+    summary::{RunSummary, StageStatus, SUMMARY_FILE},
+    timeout::{self, TimedStage},
+    torrent::MAGNETS_FILE,
+    verify::{self, Verification},
+    LlamaBackend, Precision, PythonInstaller, QuantLevel, TensorTypeOverride,
+};
+use serde_json::Value as Json;
+use shellexpand::tilde;
+use std::{
+    collections::{BTreeMap, HashSet},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, mpsc::UnboundedSender, Notify},
+    task::JoinHandle,
+};
+use tracing::{field::Empty, Instrument, Span};
+
+/// The source model downloaded from HuggingFace Hub.
+#[derive(Debug, Clone)]
+pub struct Downloaded {
+    pub model_id: String,
+    /// The commit the requested revision resolved to.
+    pub commit: String,
+    pub dir: PathBuf,
+}
+
+/// The full-precision GGUF converted from the source model.
+#[derive(Debug, Clone)]
+pub struct Converted {
+    pub precision: Precision,
+    pub path: PathBuf,
+}
+
+/// The importance matrix generated, or fetched from another repo, for imatrix quants.
+#[derive(Debug, Clone)]
+pub struct ImatrixGenerated {
+    pub path: PathBuf,
+}
+
+/// A single quantized GGUF.
+#[derive(Debug, Clone)]
+pub struct Quantized {
+    pub level: QuantLevel,
+    pub path: PathBuf,
+    /// The `-00001-of-0000N` shards `path` was split into for being too big to upload whole, in
+    /// which case `path` itself no longer exists.
+    pub shards: Vec<PathBuf>,
+}
+
+impl Quantized {
+    /// The files making up this quant: its shards if it was split, else just `path`.
+    pub fn files(&self) -> Vec<PathBuf> {
+        if self.shards.is_empty() {
+            vec![self.path.clone()]
+        } else {
+            self.shards.clone()
+        }
+    }
+}
+
+/// The file or directory a stage produced, if any.
+trait StageOutput {
+    fn output(&self) -> Option<PathBuf>;
+}
+
+impl StageOutput for () {
+    fn output(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+impl StageOutput for Downloaded {
+    fn output(&self) -> Option<PathBuf> {
+        Some(self.dir.clone())
+    }
+}
+
+impl StageOutput for Converted {
+    fn output(&self) -> Option<PathBuf> {
+        Some(self.path.clone())
+    }
+}
+
+impl StageOutput for ImatrixGenerated {
+    fn output(&self) -> Option<PathBuf> {
+        Some(self.path.clone())
+    }
+}
+
+impl StageOutput for Quantized {
+    fn output(&self) -> Option<PathBuf> {
+        self.files().into_iter().next()
+    }
+}
+
+impl StageOutput for EvalResults {
+    fn output(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// What a full pipeline run produced. Stages that were skipped are `None` or empty.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineReport {
+    pub download: Option<Downloaded>,
+    pub fp: Option<Converted>,
+    pub imatrix: Option<ImatrixGenerated>,
+    pub quants: Vec<Quantized>,
+    pub eval: Option<EvalResults>,
+    pub uploaded_to: Option<String>,
+}
+
+/// Download → convert → imatrix → quantize → upload, for a single HuggingFace model.
+///
+/// Build one with [`Pipeline::builder`], then either [`run`](Pipeline::run) every stage or
+/// call the stages individually.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    model_id: String,
+    model_name: String,
+    local_model: Option<PathBuf>,
+    work_dir: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    revision: Option<String>,
+    download_options: DownloadOptions,
+    quants: Vec<QuantLevel>,
+    precision: Precision,
+    name_template: NameTemplate,
+    chat_template: Option<ChatTemplate>,
+    /// Metadata to set in the full-precision GGUF, by key.
+    gguf_meta: BTreeMap<String, String>,
+    /// LoRA adapters of the model to convert alongside it.
+    loras: Vec<LoraAdapter>,
+    merge_lora: Option<LoraAdapter>,
+    /// An embedding or reranker model's pooling, found by [`PipelineBuilder::detect_embedding`].
+    pooling: Option<Pooling>,
+    /// The day the pipeline was built, for `{date}` in `name_template`.
+    date: String,
+    fp: Option<PathBuf>,
+    imatrix: Vec<PathBuf>,
+    imatrix_per_dataset: bool,
+    imatrix_repo: Option<String>,
+    skip_download: bool,
+    skip_upload: bool,
+    skip_mmproj: bool,
+    only_upload: bool,
+    update_llama: bool,
+    resume: bool,
+    force: bool,
+    force_unlock: bool,
+    ignore_disk_space: bool,
+    llama_path: PathBuf,
+    llama_ref: Option<String>,
+    llama_prebuilt: bool,
+    llama_build_dir: Option<PathBuf>,
+    llama_backend: Option<LlamaBackend>,
+    python_installer: Option<PythonInstaller>,
+    imatrix_params: ImatrixParams,
+    hf_user: String,
+    hf_token: String,
+    upload_logs: bool,
+    private: bool,
+    as_pr: bool,
+    repo_id: Option<String>,
+    collection: Option<String>,
+    upload_backend: Option<Bucket>,
+    mirrors: Vec<Mirror>,
+    /// What the target repo already has, when only adding to it.
+    published: Option<HashSet<String>>,
+    signing_key: Option<SigningKey>,
+    sign_files: bool,
+    smoke_test: bool,
+    lmstudio_manifest: bool,
+    /// The quant to package as a llamafile, and the runtime to package it with.
+    llamafile: Option<QuantLevel>,
+    llamafile_runtime: PathBuf,
+    torrent: bool,
+    ipfs: bool,
+    ipfs_pin_service: Option<String>,
+    eval_perplexity: bool,
+    eval_kl_divergence: bool,
+    eval_bench: bool,
+    eval_dataset: Option<String>,
+    calibration_data: Vec<String>,
+    output_tensor_types: Vec<TensorTypeOverride>,
+    token_embedding_types: Vec<TensorTypeOverride>,
+    allow_requantize: bool,
+    pure: bool,
+    quantize_args: Vec<String>,
+    convert_args: Vec<String>,
+    native_convert: bool,
+    cleanup: Vec<Cleanup>,
+    ctx: Context,
+}
+
+#[derive(Debug, Clone)]
+pub struct PipelineBuilder {
+    pipeline: Pipeline,
+}
+
+impl PipelineBuilder {
+    /// The quant levels to produce.
+    pub fn quants(mut self, quants: impl IntoIterator<Item = QuantLevel>) -> Self {
+        self.pipeline.quants = quants.into_iter().collect();
+        self
+    }
+
+    /// The full-precision GGUF format to convert to and quantize from.
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.pipeline.precision = precision;
+        self
+    }
+
+    /// Name quants by `template` instead of [`DEFAULT_NAME_TEMPLATE`](crate::DEFAULT_NAME_TEMPLATE),
+    /// e.g. `{model}-{quant}-imat.gguf` to match an existing repo's names.
+    pub fn name_template(mut self, template: NameTemplate) -> Self {
+        self.pipeline.name_template = template;
+        self
+    }
+
+    /// Write `template` into the full-precision GGUF before quantizing, replacing whatever chat
+    /// template conversion gave it, so every quant formats chats the same way.
+    pub fn chat_template(mut self, template: ChatTemplate) -> Self {
+        self.pipeline.chat_template = Some(template);
+        self
+    }
+
+    /// Set the metadata `key` to `value` in the full-precision GGUF before quantizing, e.g.
+    /// `general.name`, so every quant carries it. Keys it already has keep their type, so the
+    /// value must parse as it; new keys are strings.
+    pub fn gguf_meta(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.pipeline.gguf_meta.insert(key.into(), value.into());
+        self
+    }
+
+    /// Also convert this LoRA adapter of the model to a GGUF, uploaded with the quants to load on
+    /// top of them. Adapters on the Hub are downloaded into the model directory first.
+    pub fn lora(mut self, adapter: LoraAdapter) -> Self {
+        self.pipeline.loras.push(adapter);
+        self
+    }
+
+    /// Merge this LoRA adapter of the model into its full-precision GGUF before quantizing, so a
+    /// fine-tune only published as an adapter gets standalone quants. The model name, and so the
+    /// output directory and repo, comes from the adapter's.
+    pub fn merge_lora(mut self, adapter: LoraAdapter) -> Self {
+        self.pipeline.model_name = adapter.name();
+        self.pipeline.merge_lora = Some(adapter);
+        self
+    }
+
+    /// Quantize from an existing full-precision GGUF, skipping download and conversion. It's
+    /// never modified, so any `chat_template` or `gguf_meta` it lacks is an error.
+    pub fn fp(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pipeline.fp = Some(path.into());
+        self
+    }
+
+    /// Convert the HuggingFace-format model in `dir` instead of downloading one. The model name,
+    /// and so the output directory, comes from `dir`'s name; the model ID, if any, is only used
+    /// as the `base_model` in the model card.
+    pub fn local_model(mut self, dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let name = std::fs::canonicalize(&dir)
+            .unwrap_or_else(|_| dir.clone())
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.pipeline.model_name = name;
+        self.pipeline.local_model = Some(dir);
+        self
+    }
+
+    /// Download, convert and generate the imatrix in a folder named after the model in `dir`,
+    /// e.g. on a big scratch disk, instead of the output directory. `~` is expanded.
+    pub fn work_dir(mut self, dir: &str) -> Self {
+        self.pipeline.work_dir = Some(PathBuf::from(tilde(dir).into_owned()));
+        self
+    }
+
+    /// Write the quants, model card and checksums to a folder named after the model in `dir`,
+    /// instead of in the current directory. `~` is expanded.
+    pub fn output_dir(mut self, dir: &str) -> Self {
+        self.pipeline.output_dir = Some(PathBuf::from(tilde(dir).into_owned()));
+        self
+    }
+
+    /// Download the model at this branch, tag or commit instead of `main`.
+    pub fn revision(mut self, revision: impl Into<String>) -> Self {
+        self.pipeline.revision = Some(revision.into());
+        self
+    }
+
+    /// Only download files from the source repo matching one of these globs, e.g.
+    /// `*.safetensors`, instead of everything the converter might need.
+    pub fn download_include(mut self, globs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.pipeline.download_options.files.include = globs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Never download files from the source repo matching one of these globs.
+    pub fn download_exclude(mut self, globs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.pipeline.download_options.files.exclude = globs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Download every file in the source repo, including training artifacts and other checkpoint
+    /// formats that are skipped by default when there are safetensors.
+    pub fn download_all_files(mut self, all: bool) -> Self {
+        self.pipeline.download_options.files.all = all;
+        self
+    }
+
+    /// Download with parallel range requests on every core, which saturates fast links on big
+    /// machines but can bog down a desktop.
+    pub fn fast_download(mut self, fast: bool) -> Self {
+        self.pipeline.download_options.fast = fast;
+        self
+    }
+
+    /// Download into the shared HF cache (`~/.cache/huggingface/hub`, or under `$HF_HOME`) and
+    /// link the files into the model directory, so a model that's already cached isn't
+    /// downloaded or stored again.
+    pub fn shared_cache(mut self, shared: bool) -> Self {
+        self.pipeline.download_options.shared_cache = shared;
+        self
+    }
+
+    /// Use an existing imatrix file instead of generating one. Given more than once, the files are
+    /// combined into one with `llama-imatrix --in-file`.
+    pub fn imatrix(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pipeline.imatrix.push(path.into());
+        self
+    }
+
+    /// Generate a separate imatrix from each calibration dataset and combine them, rather than
+    /// one imatrix over all the datasets concatenated.
+    pub fn imatrix_per_dataset(mut self, per_dataset: bool) -> Self {
+        self.pipeline.imatrix_per_dataset = per_dataset;
+        self
+    }
+
+    /// Download the imatrix published in HuggingFace repo `repo_id` instead of generating one.
+    pub fn imatrix_repo(mut self, repo_id: impl Into<String>) -> Self {
+        self.pipeline.imatrix_repo = Some(repo_id.into());
+        self
+    }
+
+    pub fn skip_download(mut self, skip: bool) -> Self {
+        self.pipeline.skip_download = skip;
+        self
+    }
+
+    pub fn skip_upload(mut self, skip: bool) -> Self {
+        self.pipeline.skip_upload = skip;
+        self
+    }
+
+    /// Don't convert a vision-language model's projector, e.g. to only publish its text model.
+    pub fn skip_mmproj(mut self, skip: bool) -> Self {
+        self.pipeline.skip_mmproj = skip;
+        self
+    }
+
+    /// Only upload existing .gguf files in the output and work directories.
+    pub fn only_upload(mut self, only: bool) -> Self {
+        self.pipeline.only_upload = only;
+        self
+    }
+
+    /// Update (or install) and rebuild llama.cpp before converting.
+    pub fn update_llama(mut self, update: bool) -> Self {
+        self.pipeline.update_llama = update;
+        self
+    }
+
+    /// Skip stages recorded as finished in the model directory's state manifest. On by default.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.pipeline.resume = resume;
+        self
+    }
+
+    /// Requantize levels whose output file already exists instead of skipping them.
+    pub fn force(mut self, force: bool) -> Self {
+        self.pipeline.force = force;
+        self
+    }
+
+    /// Break the model directory's lock if another run holds it, rather than failing. Only for a
+    /// run that's hung, or a lock a crashed run left on a filesystem that didn't release it.
+    pub fn force_unlock(mut self, force: bool) -> Self {
+        self.pipeline.force_unlock = force;
+        self
+    }
+
+    /// Warn instead of failing when the disk space pre-flight check comes up short.
+    pub fn ignore_disk_space(mut self, ignore: bool) -> Self {
+        self.pipeline.ignore_disk_space = ignore;
+        self
+    }
+
+    /// Path to the llama.cpp repo. `~` is expanded.
+    pub fn llama_path(mut self, path: &str) -> Self {
+        self.pipeline.llama_path = PathBuf::from(tilde(path).into_owned());
+        self
+    }
+
+    /// Where CMake builds llama.cpp, instead of `build` in the llama.cpp repo. `~` is expanded.
+    pub fn llama_build_dir(mut self, dir: &str) -> Self {
+        self.pipeline.llama_build_dir = Some(PathBuf::from(tilde(dir).into_owned()));
+        self
+    }
+
+    /// Check out this llama.cpp tag or commit with `update_llama`, instead of pulling the latest,
+    /// so quants can be reproduced.
+    pub fn llama_ref(mut self, git_ref: impl Into<String>) -> Self {
+        self.pipeline.llama_ref = Some(git_ref.into());
+        self
+    }
+
+    /// Install llama.cpp's official release binaries with `update_llama`, instead of compiling
+    /// it; `llama_ref` then has to be a release tag, like `b4500`.
+    pub fn llama_prebuilt(mut self, prebuilt: bool) -> Self {
+        self.pipeline.llama_prebuilt = prebuilt;
+        self
+    }
+
+    /// Build llama.cpp for this backend with `update_llama`, instead of CMake's defaults.
+    pub fn llama_backend(mut self, backend: LlamaBackend) -> Self {
+        self.pipeline.llama_backend = Some(backend);
+        self
+    }
+
+    /// Install llama.cpp's Python deps with `installer` in `update_llama`, instead of uv if it's
+    /// on PATH, else pip.
+    pub fn python_installer(mut self, installer: PythonInstaller) -> Self {
+        self.pipeline.python_installer = Some(installer);
+        self
+    }
+
+    /// Number of threads for imatrix generation and evaluation. Defaults to every core available.
+    pub fn threads(mut self, threads: u32) -> Self {
+        self.pipeline.imatrix_params.threads = threads;
+        self
+    }
+
+    /// Number of layers to offload to the GPU for imatrix generation, evaluation and smoke
+    /// tests; 0 for CPU-only.
+    pub fn gpu_layers(mut self, layers: u32) -> Self {
+        self.pipeline.imatrix_params.gpu_layers = layers;
+        self
+    }
+
+    /// The llama.cpp devices to offload to, e.g. `CUDA1`, or several comma-separated. llama.cpp
+    /// picks when unset.
+    pub fn gpu_device(mut self, device: impl Into<String>) -> Self {
+        self.pipeline.imatrix_params.device = Some(device.into());
+        self
+    }
+
+    /// Number of calibration chunks to generate the imatrix over.
+    pub fn imatrix_chunks(mut self, chunks: u32) -> Self {
+        self.pipeline.imatrix_params.chunks = chunks;
+        self
+    }
+
+    /// Context size for each imatrix chunk, instead of llama-imatrix's default.
+    pub fn imatrix_ctx_size(mut self, ctx_size: u32) -> Self {
+        self.pipeline.imatrix_params.ctx_size = Some(ctx_size);
+        self
+    }
+
+    /// HuggingFace username and API token used for uploads.
+    pub fn hf_credentials(mut self, user: impl Into<String>, token: impl Into<String>) -> Self {
+        self.pipeline.hf_user = user.into();
+        self.pipeline.hf_token = token.into();
+        self
+    }
+
+    /// How many times a download, calibration or eval dataset fetch, or upload that fails
+    /// transiently (a dropped connection, a timeout, rate limiting or a server error) is
+    /// retried, with exponential backoff, before its stage fails. Defaults to 5.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.pipeline.ctx.retries = retries;
+        self
+    }
+
+    /// Fail `stage` if it runs longer than `limit`, killing whatever it's running. Every quant
+    /// level gets its own `limit`.
+    pub fn timeout(mut self, stage: TimedStage, limit: Duration) -> Self {
+        self.pipeline.ctx.timeouts.stages.insert(stage, limit);
+        self
+    }
+
+    /// Kill a llama.cpp subprocess that prints nothing for `limit`, failing its stage, rather
+    /// than waiting on it forever if it hangs.
+    pub fn stall_timeout(mut self, limit: Duration) -> Self {
+        self.pipeline.ctx.timeouts.stall = Some(limit);
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.pipeline.ctx.verbose = verbose;
+        self
+    }
+
+    /// Notified to kill whichever subprocess is running and abort the pipeline.
+    pub fn cancel(mut self, cancel: Arc<Notify>) -> Self {
+        self.pipeline.ctx.cancel = cancel;
+        self
+    }
+
+    /// Also upload the per-stage subprocess logs from `logs/` in the model directory.
+    pub fn upload_logs(mut self, upload: bool) -> Self {
+        self.pipeline.upload_logs = upload;
+        self
+    }
+
+    /// Create the target repo as private. A repo that already exists keeps its visibility.
+    pub fn private(mut self, private: bool) -> Self {
+        self.pipeline.private = private;
+        self
+    }
+
+    /// Upload to a new pull request on the target repo, which must already exist, instead of
+    /// committing to `main`, e.g. to contribute quants to someone else's repo.
+    pub fn as_pr(mut self, as_pr: bool) -> Self {
+        self.pipeline.as_pr = as_pr;
+        self
+    }
+
+    /// Sign `SHA256SUMS` with this minisign secret key file or GPG key ID, uploading the detached
+    /// signature next to it.
+    pub fn sign_key(mut self, key: &str) -> Self {
+        self.pipeline.signing_key = Some(SigningKey::parse(key));
+        self
+    }
+
+    /// Also upload a `.torrent` and magnet link for each quant, web-seeded from the repo, for
+    /// models too big to download comfortably over HTTP.
+    pub fn torrent(mut self, torrent: bool) -> Self {
+        self.pipeline.torrent = torrent;
+        self
+    }
+
+    /// Pin every GGUF to IPFS once it's uploaded, with kubo's `ipfs` CLI and whichever node it's
+    /// set up to use, listing their CIDs in the model card and run summary.
+    pub fn ipfs(mut self, ipfs: bool) -> Self {
+        self.pipeline.ipfs = ipfs;
+        self
+    }
+
+    /// With [`ipfs`](Self::ipfs), also pin to this remote pinning service, as configured with
+    /// `ipfs pin remote service add`.
+    pub fn ipfs_pin_service(mut self, service: impl Into<String>) -> Self {
+        self.pipeline.ipfs_pin_service = Some(service.into());
+        self
+    }
+
+    /// With a signing key, also sign every GGUF and imatrix.
+    pub fn sign_files(mut self, sign: bool) -> Self {
+        self.pipeline.sign_files = sign;
+        self
+    }
+
+    /// Generate a little text from each quant as it's made with `llama-cli`, through its chat
+    /// template if it has one, failing the quant if that errors or produces garbage.
+    pub fn smoke_test(mut self, smoke_test: bool) -> Self {
+        self.pipeline.smoke_test = smoke_test;
+        self
+    }
+
+    /// Write the manifest LM Studio reads to sideload a model into the output directory, listing
+    /// the quants with the model's context length and chat template.
+    pub fn lmstudio_manifest(mut self, write: bool) -> Self {
+        self.pipeline.lmstudio_manifest = write;
+        self
+    }
+
+    /// Once the quant for `level` is made, package it with the llamafile runtime into a single
+    /// executable that runs the model with nothing to install, uploaded alongside it.
+    pub fn llamafile(mut self, level: QuantLevel) -> Self {
+        self.pipeline.llamafile = Some(level);
+        self
+    }
+
+    /// The llamafile runtime to package with, by path or name on the PATH, with llamafile's
+    /// `zipalign` next to it or on the PATH. Defaults to [`llamafile::DEFAULT_RUNTIME`].
+    pub fn llamafile_runtime(mut self, runtime: impl AsRef<Path>) -> Self {
+        self.pipeline.llamafile_runtime =
+            PathBuf::from(tilde(&runtime.as_ref().to_string_lossy()).into_owned());
+        self
+    }
+
+    /// After quantizing, measure the perplexity of each quant and the full-precision GGUF with
+    /// `llama-perplexity`, for the model card.
+    pub fn eval_perplexity(mut self, eval: bool) -> Self {
+        self.pipeline.eval_perplexity = eval;
+        self
+    }
+
+    /// After quantizing, compare each quant's token probabilities to the full-precision GGUF's
+    /// with `llama-perplexity`'s KL-divergence mode, for the model card.
+    pub fn eval_kl_divergence(mut self, eval: bool) -> Self {
+        self.pipeline.eval_kl_divergence = eval;
+        self
+    }
+
+    /// After quantizing, measure how fast each quant and the full-precision GGUF process prompts
+    /// and generate text on this machine with `llama-bench`, for the model card.
+    pub fn bench(mut self, bench: bool) -> Self {
+        self.pipeline.eval_bench = bench;
+        self
+    }
+
+    /// Evaluate on this text, a local path or URL, instead of wikitext-2's test split.
+    pub fn eval_dataset(mut self, source: impl Into<String>) -> Self {
+        self.pipeline.eval_dataset = Some(source.into());
+        self
+    }
+
+    /// Generate the imatrix from these calibration datasets, local paths or URLs, concatenated in
+    /// order, instead of the default dataset.
+    pub fn calibration_data(
+        mut self,
+        sources: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.pipeline.calibration_data = sources.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Quantize the output tensor to this type, for one level or all of them. A level's own
+    /// override wins over a global one.
+    pub fn output_tensor_type(mut self, tensor_type: TensorTypeOverride) -> Self {
+        self.pipeline.output_tensor_types.push(tensor_type);
+        self
+    }
+
+    /// Quantize the token embeddings to this type, for one level or all of them. A level's own
+    /// override wins over a global one.
+    pub fn token_embedding_type(mut self, tensor_type: TensorTypeOverride) -> Self {
+        self.pipeline.token_embedding_types.push(tensor_type);
+        self
+    }
+
+    /// Let `llama-quantize` requantize already-quantized tensors, to quantize from e.g. a Q8_0
+    /// GGUF passed as [`fp`](Self::fp). Quality suffers compared to quantizing from full precision.
+    pub fn allow_requantize(mut self, allow: bool) -> Self {
+        self.pipeline.allow_requantize = allow;
+        self
+    }
+
+    /// Quantize every tensor to the requested level, without the higher-precision tensors k-quant
+    /// mixes normally keep.
+    pub fn pure(mut self, pure: bool) -> Self {
+        self.pipeline.pure = pure;
+        self
+    }
+
+    /// Extra arguments for every `llama-quantize` run, passed through verbatim, for flags autogguf
+    /// doesn't have an option for.
+    pub fn quantize_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.pipeline.quantize_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Extra arguments for `convert_hf_to_gguf.py`, passed through verbatim, e.g. `--model-name`
+    /// or `--use-temp-file`. `--outtype` and `--outfile` are already set from the pipeline. A
+    /// conversion split with `--split-max-size` is merged back with `llama-gguf-split` to quantize.
+    pub fn convert_args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.pipeline.convert_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Convert to the full-precision GGUF in-process instead of with `convert_hf_to_gguf.py`, so
+    /// no Python is needed. Experimental, and only for Llama, Mistral and Qwen2 models.
+    pub fn native_convert(mut self, native: bool) -> Self {
+        self.pipeline.native_convert = native;
+        self
+    }
+
+    /// Delete these as soon as the pipeline is done with them, to fit on smaller disks.
+    pub fn cleanup(mut self, cleanup: impl IntoIterator<Item = Cleanup>) -> Self {
+        self.pipeline.cleanup = cleanup.into_iter().collect();
+        self
+    }
+
+    /// Upload to `repo_id` (`namespace/name`) instead of `{hf_user}/{model_name}-GGUF`, e.g. to
+    /// push to an organization.
+    pub fn repo_id(mut self, repo_id: impl Into<String>) -> Self {
+        self.pipeline.repo_id = Some(repo_id.into());
+        self
+    }
+
+    /// Upload to `bucket` instead of the Hub, under a folder named like the repo would be.
+    pub fn upload_backend(mut self, bucket: Bucket) -> Self {
+        self.pipeline.upload_backend = Some(bucket);
+        self
+    }
+
+    /// Copy everything uploaded to `mirror` too, once the target repo has it. Repeatable.
+    pub fn mirror(mut self, mirror: Mirror) -> Self {
+        self.pipeline.mirrors.push(mirror);
+        self
+    }
+
+    /// Add the uploaded repo to the Hub collection `slug`, e.g. `user/llama-ggufs-66f1...`.
+    pub fn collection(mut self, slug: impl Into<String>) -> Self {
+        self.pipeline.collection = Some(slug.into());
+        self
+    }
+
+    /// Report progress as [`Event`]s on `events`. Subprocess output is captured and forwarded as
+    /// [`Event::Output`] instead of going straight to the terminal, and status messages are sent
+    /// as [`Event::Message`] instead of being printed.
+    pub fn events(mut self, events: UnboundedSender<Event>) -> Self {
+        self.pipeline.ctx.events = Some(events);
+        self
+    }
+
+    /// Add to the already published target repo instead of making it from scratch: drop the
+    /// quant levels it already has, and leave every file it has as it is when uploading. Call
+    /// once everything else is set, since it lists the repo then.
+    pub async fn update_existing(mut self) -> Result<Self, Box<dyn std::error::Error>> {
+        let pipeline = &mut self.pipeline;
+        let target = pipeline.upload_target();
+        target.check_credentials().map_err(|e| e.to_string())?;
+        let repo_id = target.repo_id();
+        let info = HubClient::new(pipeline.hf_token.clone(), pipeline.ctx.clone())
+            .model_info(&repo_id)
+            .await
+            .map_err(|e| {
+                format!(
+                    "couldn't list {repo_id} to update it; drop --update-existing if it's new: {e}"
+                )
+            })?;
+        let published: HashSet<String> = info.siblings.into_iter().map(|f| f.rfilename).collect();
+        let labels: HashSet<String> = published
+            .iter()
+            .filter_map(|path| naming::repo_label(path))
+            .collect();
+        let (present, missing): (Vec<QuantLevel>, Vec<QuantLevel>) = pipeline
+            .quants
+            .drain(..)
+            .partition(|level| labels.contains(&level.to_string().to_uppercase()));
+        let names = |levels: &[QuantLevel]| {
+            levels
+                .iter()
+                .map(|level| level.to_string().to_uppercase())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match (present.is_empty(), missing.is_empty()) {
+            (_, true) => pipeline.ctx.info(format!(
+                "🆗 {repo_id} already has every quant asked for; nothing to add."
+            )),
+            (true, false) => pipeline.ctx.info(format!(
+                "🆕 {repo_id} has none of the quants asked for; adding {}.",
+                names(&missing)
+            )),
+            (false, false) => pipeline.ctx.info(format!(
+                "🆕 {repo_id} already has {}; adding {}.",
+                names(&present),
+                names(&missing)
+            )),
+        }
+        pipeline.quants = missing;
+        pipeline.published = Some(published);
+        Ok(self)
+    }
+
+    /// Check the model's config for an embedding or reranker model, and if it's one, leave out
+    /// the quant levels that don't suit one, set its pooling in the full-precision GGUF, and
+    /// check its embeddings' dimensions once it's converted. Smoke tests are skipped, since it
+    /// doesn't generate text. Call once the quants are set, since it narrows them down; it fails
+    /// if none of them suit one.
+    pub async fn detect_embedding(mut self) -> Result<Self, Box<dyn std::error::Error>> {
+        let pipeline = &mut self.pipeline;
+        let Some(config) = pipeline.model_json("config.json").await else {
+            return Ok(self);
+        };
+        let pooling_config = pipeline.model_json(embedding::POOLING_CONFIG).await;
+        let Some(pooling) = embedding::detect(&config, pooling_config.as_ref()) else {
+            return Ok(self);
+        };
+        let kind = match pooling {
+            Pooling::Rank => "a reranker",
+            _ => "an embedding model",
+        };
+        pipeline.ctx.info(format!(
+            "🧬 {} is {kind}, pooling by {pooling}.",
+            pipeline.model_name
+        ));
+        let (kept, dropped): (Vec<QuantLevel>, Vec<QuantLevel>) = pipeline
+            .quants
+            .drain(..)
+            .partition(|level| embedding::QUANTS.contains(level));
+        let names = |levels: &[QuantLevel]| {
+            levels
+                .iter()
+                .map(|level| level.to_string().to_uppercase())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        if kept.is_empty() {
+            return Err(format!(
+                "none of the quants asked for suit {kind}; pick from {}",
+                names(embedding::QUANTS)
+            )
+            .into());
+        }
+        if !dropped.is_empty() {
+            pipeline.ctx.warn(format!(
+                "🧬 leaving out {}, which lose too much for {kind}",
+                names(&dropped)
+            ));
+        }
+        pipeline.quants = kept;
+        if pipeline.smoke_test {
+            pipeline.ctx.warn(format!(
+                "🧬 skipping smoke tests, since {kind} doesn't generate text"
+            ));
+            pipeline.smoke_test = false;
+        }
+        pipeline.pooling = Some(pooling);
+        Ok(self)
+    }
+
+    pub fn build(mut self) -> Pipeline {
+        self.pipeline.ctx.log_dir = Some(self.pipeline.model_dir().join(LOG_DIR));
+        self.pipeline
+    }
+}
+
+impl Pipeline {
+    pub fn builder(model_id: impl Into<String>) -> PipelineBuilder {
+        let model_id = model_id.into();
+        let model_name = model_id.split('/').nth(1).unwrap_or_default().to_string();
+        let config = crate::Config::default();
+        PipelineBuilder {
+            pipeline: Pipeline {
+                model_id,
+                model_name,
+                local_model: None,
+                work_dir: None,
+                output_dir: None,
+                revision: None,
+                download_options: DownloadOptions::default(),
+                quants: config.quants,
+                precision: config.full_precision,
+                name_template: NameTemplate::default(),
+                chat_template: None,
+                gguf_meta: BTreeMap::new(),
+                loras: vec![],
+                merge_lora: None,
+                pooling: None,
+                date: naming::today(),
+                fp: None,
+                imatrix: vec![],
+                imatrix_per_dataset: false,
+                imatrix_repo: None,
+                skip_download: false,
+                skip_upload: false,
+                skip_mmproj: false,
+                only_upload: false,
+                update_llama: false,
+                resume: true,
+                force: false,
+                force_unlock: false,
+                ignore_disk_space: false,
+                llama_path: PathBuf::from(tilde(&config.llama_path).into_owned()),
+                llama_ref: None,
+                llama_prebuilt: false,
+                llama_build_dir: config
+                    .llama_build_dir
+                    .as_deref()
+                    .map(|dir| PathBuf::from(tilde(dir).into_owned())),
+                llama_backend: config.llama_backend,
+                python_installer: config.python_installer,
+                imatrix_params: ImatrixParams {
+                    threads: config.threads.unwrap_or_else(convert::default_threads),
+                    gpu_layers: config.gpu_layers,
+                    device: config.gpu_device.clone(),
+                    chunks: config.imatrix_chunks,
+                    ctx_size: config.imatrix_ctx_size,
+                },
+                hf_user: String::new(),
+                hf_token: String::new(),
+                upload_logs: false,
+                private: false,
+                as_pr: false,
+                repo_id: None,
+                collection: None,
+                upload_backend: None,
+                mirrors: vec![],
+                published: None,
+                signing_key: config.sign_key.as_deref().map(SigningKey::parse),
+                sign_files: false,
+                smoke_test: false,
+                lmstudio_manifest: false,
+                llamafile: None,
+                llamafile_runtime: PathBuf::from(llamafile::DEFAULT_RUNTIME),
+                torrent: false,
+                ipfs: false,
+                ipfs_pin_service: None,
+                eval_perplexity: false,
+                eval_kl_divergence: false,
+                eval_bench: false,
+                eval_dataset: None,
+                calibration_data: vec![],
+                output_tensor_types: vec![],
+                token_embedding_types: vec![],
+                allow_requantize: false,
+                pure: false,
+                quantize_args: vec![],
+                convert_args: vec![],
+                native_convert: false,
+                cleanup: vec![],
+                ctx: Context {
+                    verbose: false,
+                    cancel: Arc::new(Notify::new()),
+                    interrupted: Default::default(),
+                    events: None,
+                    log_dir: None,
+                    summary: Default::default(),
+                    retries: config.retries,
+                    timeouts: Default::default(),
+                },
+            },
+        }
+    }
+
+    pub fn model_name(&self) -> &str {
+        &self.model_name
+    }
+
+    /// Every stage a run reports events for, in the order they run.
+    pub fn stages(&self) -> Vec<Stage> {
+        let mut stages = vec![];
+        if self.up_to_date() {
+            return stages;
+        }
+        if self.update_llama {
+            stages.push(Stage::UpdateLlama);
+        }
+        stages.extend([Stage::Download, Stage::Convert, Stage::Imatrix]);
+        stages.extend(self.quants.iter().cloned().map(Stage::Quantize));
+        if self.evaluates() {
+            stages.push(Stage::Eval);
+        }
+        stages.push(Stage::Upload);
+        stages
+    }
+
+    /// Whether the published repo being updated already has every quant asked for.
+    fn up_to_date(&self) -> bool {
+        self.published.is_some() && self.quants.is_empty()
+    }
+
+    /// Whether any evaluation of the quants was asked for.
+    fn evaluates(&self) -> bool {
+        self.eval_perplexity || self.eval_kl_divergence || self.eval_bench
+    }
+
+    /// Where the source model is downloaded, and the full-precision GGUF, imatrix, logs and
+    /// the state a run resumes from are written: the work directory if there is one, else the
+    /// output directory.
+    pub fn model_dir(&self) -> PathBuf {
+        match &self.work_dir {
+            Some(dir) => dir.join(&self.model_name),
+            None => self.output_dir(),
+        }
+    }
+
+    /// Where the quants are written, with the model card, checksums, evaluation results and run
+    /// summary.
+    pub fn output_dir(&self) -> PathBuf {
+        match &self.output_dir {
+            Some(dir) => dir.join(&self.model_name),
+            None => PathBuf::from(&self.model_name),
+        }
+    }
+
+    /// Where the HuggingFace-format model is converted from: the local model, or the download.
+    pub fn source_dir(&self) -> PathBuf {
+        self.local_model.clone().unwrap_or_else(|| self.model_dir())
+    }
+
+    /// The full-precision GGUF quantized from: the `fp` override, or where conversion writes it.
+    pub fn fp_path(&self) -> PathBuf {
+        self.fp.clone().unwrap_or_else(|| {
+            self.model_dir().join(format!(
+                "{}.{}.gguf",
+                self.model_name.to_lowercase(),
+                self.precision
+            ))
+        })
+    }
+
+    /// The imatrix used for imatrix quants: a single `imatrix` override, or where generation or
+    /// combining writes it.
+    pub fn imatrix_path(&self) -> PathBuf {
+        match self.imatrix.as_slice() {
+            [path] => path.clone(),
+            _ => self
+                .model_dir()
+                .join(format!("{}.imatrix", self.model_name.to_lowercase())),
+        }
+    }
+
+    /// Where the calibration dataset is written for generating the imatrix: the model's own work
+    /// directory, so runs on other models can't clobber it.
+    fn calibration_path(&self) -> PathBuf {
+        self.model_dir().join(convert::CALIBRATION_FILE)
+    }
+
+    /// Where the quantized GGUF for `level` is written, named by the name template.
+    pub fn quant_path(&self, level: &QuantLevel) -> PathBuf {
+        self.output_dir().join(self.name_template.render(
+            &self.model_name,
+            level,
+            &self.precision,
+            &self.date,
+        ))
+    }
+
+    /// Where CMake builds llama.cpp: the configured build dir, or `build` in the llama.cpp repo.
+    pub fn llama_build_dir(&self) -> PathBuf {
+        self.llama_build_dir
+            .clone()
+            .unwrap_or_else(|| self.llama_path.join("build"))
+    }
+
+    /// Where the llama.cpp binaries are: `bin` in the CMake build dir, or the repo itself for
+    /// llama.cpp built with the old Makefile.
+    pub fn llama_bin(&self) -> PathBuf {
+        let bin = self.llama_build_dir().join("bin");
+        match bin.is_dir() {
+            true => bin,
+            false => self.llama_path.clone(),
+        }
+    }
+
+    pub async fn update_llama(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.tracked(Stage::UpdateLlama, async {
+            let (path, build_dir) = (self.llama_path.clone(), self.llama_build_dir());
+            let (git_ref, backend) = (self.llama_ref.as_deref(), self.llama_backend);
+            let installer = self.python_installer;
+            match self.llama_prebuilt {
+                true => {
+                    llama::install_prebuilt(path, git_ref, build_dir, backend, installer, &self.ctx)
+                        .await
+                }
+                false => {
+                    llama::update_llama_cpp(path, git_ref, build_dir, backend, installer, &self.ctx)
+                        .await
+                }
+            }
+        })
+        .await
+    }
+
+    /// Download the source model. With `overwrite`, files already in the model directory are
+    /// replaced, e.g. because they're from a different revision.
+    pub async fn download(
+        &self,
+        overwrite: bool,
+    ) -> Result<Downloaded, Box<dyn std::error::Error>> {
+        self.tracked(Stage::Download, async {
+            let commit = hf::download_model(
+                &self.model_id,
+                self.revision.as_deref(),
+                &self.model_dir(),
+                &self.download_options,
+                overwrite,
+                &self.hf_token,
+                &self.ctx,
+            )
+            .await?;
+            Ok(Downloaded {
+                model_id: self.model_id.clone(),
+                commit,
+                dir: self.model_dir(),
+            })
+        })
+        .await
+    }
+
+    pub async fn convert(&self) -> Result<Converted, Box<dyn std::error::Error>> {
+        let path = self.fp_path();
+        self.tracked(Stage::Convert, async {
+            // NOTE: with an adapter to merge, the base model goes to the side, so the full-
+            // precision GGUF only exists once the merge finishes
+            let converted = match &self.merge_lora {
+                Some(_) => self.merge_base_path(),
+                None => path.clone(),
+            };
+            if self.native_convert {
+                self.convert_native(&converted).await?;
+            } else {
+                convert::convert_fp(
+                    self.precision.clone(),
+                    self.llama_path.clone(),
+                    converted.clone(),
+                    &self.source_dir(),
+                    &self.model_name,
+                    &self.convert_args,
+                    &self.ctx,
+                )
+                .await?;
+                if !tokio::fs::try_exists(&converted).await? {
+                    let shards = convert::find_shards(&converted).await?;
+                    convert::merge_shards(&self.llama_bin(), &shards, &converted, &self.ctx)
+                        .await?;
+                }
+            }
+            gguf::validate(&converted)
+                .await
+                .map_err(|e| format!("💥 {e}"))?;
+            if let Some(adapter) = &self.merge_lora {
+                self.merge_adapter(adapter, &converted, &path).await?;
+            }
+            gguf::validate(&path).await.map_err(|e| format!("💥 {e}"))?;
+            Ok(Converted {
+                precision: self.precision.clone(),
+                path,
+            })
+        })
+        .await
+    }
+
+    /// The JSON file at `path` in the HuggingFace-format model: where it was downloaded to or the
+    /// local model, or else on the Hub. `None` if it isn't in either.
+    async fn model_json(&self, path: &str) -> Option<Json> {
+        if let Ok(json) = tokio::fs::read_to_string(self.source_dir().join(path)).await {
+            return serde_json::from_str(&json).ok();
+        }
+        if self.local_model.is_some() || self.model_id.is_empty() {
+            return None;
+        }
+        let revision = self.revision.as_deref().unwrap_or("main");
+        HubClient::new(self.hf_token.clone(), self.ctx.clone())
+            .json_file(&self.model_id, revision, path)
+            .await
+            .ok()
+    }
+
+    /// Check an embedding or reranker model's full-precision GGUF embeds in as many dimensions
+    /// as its config's `hidden_size`, and for an embedding model, that `llama-embedding`'s
+    /// embeddings with it have that many too.
+    async fn check_embedding_dimensions(
+        &self,
+        pooling: Pooling,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let fp_path = self.fp_path();
+        let name = fp_path.file_name().unwrap_or_default().to_string_lossy();
+        let header = gguf::validate(&fp_path).await?;
+        let arch = header
+            .get("general.architecture")
+            .and_then(Json::as_str)
+            .unwrap_or_default();
+        let length = header
+            .get(&format!("{arch}.embedding_length"))
+            .and_then(Json::as_u64)
+            .ok_or_else(|| format!("💥 {name} has no {arch}.embedding_length"))?;
+        let hidden_size = self
+            .model_json("config.json")
+            .await
+            .and_then(|config| config.get("hidden_size").and_then(Json::as_u64));
+        if let Some(hidden_size) = hidden_size.filter(|size| *size != length) {
+            return Err(format!(
+                "💥 {name} embeds in {length} dimensions, but the model's hidden_size is \
+                 {hidden_size}"
+            )
+            .into());
+        }
+        if pooling != Pooling::Rank {
+            let dimensions = embedding::output_dimensions(&fp_path, &self.llama_bin(), &self.ctx)
+                .await
+                .map_err(|e| format!("💥 couldn't embed with {name}: {e}"))?;
+            if dimensions as u64 != length {
+                return Err(format!(
+                    "💥 {name}'s embeddings have {dimensions} dimensions rather than {length}"
+                )
+                .into());
+            }
+        }
+        self.ctx
+            .detail(format!("🧬 {name} embeds in {length} dimensions."));
+        Ok(())
+    }
+
+    /// Whether `convert_args` have the converter write shards, which need merging to quantize.
+    fn splits_conversion(&self) -> bool {
+        self.convert_args.iter().any(|arg| {
+            arg.starts_with("--split-max-size") || arg.starts_with("--split-max-tensors")
+        })
+    }
+
+    /// Where the base model is converted to before [`PipelineBuilder::merge_lora`]'s adapter is
+    /// merged into it.
+    fn merge_base_path(&self) -> PathBuf {
+        self.fp_path().with_extension("base.gguf")
+    }
+
+    /// Convert `adapter` and merge it into the base model's GGUF at `base`, writing the merged
+    /// model to `path`. Both intermediate GGUFs are deleted once it's written.
+    async fn merge_adapter(
+        &self,
+        adapter: &LoraAdapter,
+        base: &Path,
+        path: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let name = adapter.name();
+        let adapter_path = adapter.gguf_path(&self.model_dir(), &self.precision);
+        self.convert_lora(adapter, &adapter_path).await?;
+        self.ctx
+            .detail(format!("🧩 merging LoRA {name} into {}...", base.display()));
+        lora::merge(
+            &self.llama_bin(),
+            base,
+            &adapter_path,
+            path,
+            self.imatrix_params.threads,
+            &self.ctx,
+        )
+        .await
+        .map_err(|e| format!("merging LoRA {name} failed: {e}"))?;
+        tokio::fs::remove_file(base).await?;
+        tokio::fs::remove_file(&adapter_path).await?;
+        self.ctx
+            .info(format!("🧩 merged LoRA {name} into {}", path.display()));
+        Ok(())
+    }
+
+    async fn convert_native(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let precision = self.precision.clone();
+        let label = precision.to_string().to_uppercase();
+        self.ctx.detail(format!(
+            "🦀 converting {} to {label} natively...",
+            self.model_name
+        ));
+        let (source_dir, output, name) = (
+            self.source_dir(),
+            path.to_path_buf(),
+            self.model_name.clone(),
+        );
+        tokio::task::spawn_blocking(move || {
+            native::convert(&source_dir, &output, &precision, &name)
+        })
+        .await?
+        .map_err(|e| format!("💥 native conversion failed: {e}"))?;
+        self.ctx.detail(format!(
+            "🦀 {} conversion to {label} complete!",
+            self.model_name
+        ));
+        Ok(())
+    }
+
+    /// Delete the downloaded weights, once the full-precision GGUF converted from them looks
+    /// intact. Config and tokenizer files stay for the model card and imatrix generation.
+    pub async fn delete_sources(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let fp_path = self.fp_path();
+        if let Err(e) = gguf::validate(&fp_path).await {
+            return Err(format!("{e}, keeping the source weights").into());
+        }
+        let files = files_with_extensions(&self.model_dir(), cleanup::SOURCE_WEIGHT_EXTENSIONS)
+            .await
+            .map_err(|e| e as Box<dyn std::error::Error>)?;
+        let paths: Vec<PathBuf> = files.into_iter().map(|f| f.local_path).collect();
+        let (deleted, freed) = cleanup::delete_files(&paths).await?;
+        self.ctx.info(format!(
+            "🧹 deleted {deleted} source weight files, freeing {}.",
+            human_bytes(freed)
+        ));
+        Ok(())
+    }
+
+    /// Delete the uploaded outputs `cleanup` asks for. Only call this once the upload succeeded.
+    /// An `fp` or imatrix passed in from outside the model directory is never deleted.
+    pub async fn delete_uploaded(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let model_dir = self.model_dir();
+        let mut files = vec![];
+        if self.cleanup.contains(&Cleanup::Fp) {
+            files.push(self.fp_path());
+        }
+        if self.cleanup.contains(&Cleanup::Imatrix) {
+            files.push(self.imatrix_path());
+        }
+        files.retain(|path| path.parent() == Some(model_dir.as_path()));
+        if self.cleanup.contains(&Cleanup::Quants) {
+            for q in &self.quants {
+                let path = self.quant_path(q);
+                files.extend(convert::find_shards(&path).await?);
+                files.push(path);
+            }
+        }
+        let (deleted, freed) = cleanup::delete_files(&files).await?;
+        self.ctx.info(format!(
+            "🧹 deleted {deleted} uploaded files, freeing {}.",
+            human_bytes(freed)
+        ));
+        Ok(())
+    }
+
+    /// Check the chat template in the full-precision GGUF, which every quant inherits, and write
+    /// `chat_template` and the `gguf_meta` overrides into it where they're not there already.
+    /// Without a chat template, a missing or broken one is only reported, since base models
+    /// don't have one. An `fp` given to quantize is never rewritten, so that fails instead.
+    /// Returns whether it rewrote the GGUF.
+    pub async fn update_fp_metadata(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        let fp_path = self.fp_path();
+        let header = gguf::validate(&fp_path).await?;
+        let current = header
+            .get(CHAT_TEMPLATE_KEY)
+            .and_then(|value| value.as_str());
+        let name = fp_path.file_name().unwrap_or_default().to_string_lossy();
+        let entries = self.metadata_entries(&header);
+        match &self.chat_template {
+            None => match (current, chat_template::check(&header)) {
+                (_, None) => {}
+                (None, Some(problem)) => self.ctx.info(format!(
+                    "💬 {name} {problem}; fine for a base model, but pass --chat-template for a \
+                     chat model."
+                )),
+                (Some(_), Some(problem)) => self.ctx.warn(format!(
+                    "💬 {name} {problem}, so chat with its quants won't be formatted right; pass \
+                     --chat-template to replace it"
+                )),
+            },
+            Some(template) if entries.iter().any(|(key, _)| key == CHAT_TEMPLATE_KEY) => {
+                self.ctx.detail(format!(
+                    "💬 writing the {template} chat template into {name}..."
+                ));
+            }
+            Some(_) => {}
+        }
+        if entries.is_empty() {
+            return Ok(false);
+        }
+        let keys: Vec<&str> = entries.iter().map(|(key, _)| key.as_str()).collect();
+        if self.fp.is_some() {
+            return Err(format!(
+                "--fp {} would need {} set, but it's left as given; quantize a copy with them \
+                 set instead",
+                fp_path.display(),
+                keys.join(", ")
+            )
+            .into());
+        }
+        self.ctx
+            .detail(format!("🏷️ setting {} in {name}...", keys.join(", ")));
+        let path = fp_path.clone();
+        tokio::task::spawn_blocking(move || gguf::set_metadata(&path, &entries))
+            .await?
+            .map_err(|e| format!("couldn't set metadata in {name}: {e}"))?;
+        if self.force {
+            return Ok(true);
+        }
+        let mut stale = vec![];
+        for level in &self.quants {
+            if self.is_quantized(level).await {
+                stale.push(level.to_string().to_uppercase());
+            }
+        }
+        if !stale.is_empty() {
+            self.ctx.warn(format!(
+                "🏷️ quants made before it still have the old metadata ({}); rerun with --force to redo them",
+                stale.join(", ")
+            ));
+        }
+        Ok(true)
+    }
+
+    /// The metadata [`Pipeline::update_fp_metadata`] writes into the full-precision GGUF with
+    /// `header`: the `gguf_meta` overrides, the pooling and `chat_template`, wherever it doesn't
+    /// have them already.
+    fn metadata_entries(&self, header: &gguf::Header) -> Vec<(String, String)> {
+        // NOTE: the pooling goes with the `gguf_meta` overrides, keyed by the GGUF's architecture,
+        // unless they set it themselves
+        let pooling = self
+            .pooling
+            .zip(header.get("general.architecture").and_then(Json::as_str))
+            .map(|(pooling, arch)| (format!("{arch}.pooling_type"), (pooling as u32).to_string()))
+            .filter(|(key, _)| !self.gguf_meta.contains_key(key));
+        let mut entries: Vec<(String, String)> = self
+            .gguf_meta
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .chain(pooling)
+            .filter(|(key, value)| {
+                let current = header.get(key).map(|current| {
+                    current
+                        .as_str()
+                        .map_or_else(|| current.to_string(), str::to_string)
+                });
+                current.as_ref() != Some(value)
+            })
+            .collect();
+        let current = header
+            .get(CHAT_TEMPLATE_KEY)
+            .and_then(|value| value.as_str());
+        if let Some(template) = &self.chat_template {
+            if current != Some(template.template.as_str()) {
+                entries.retain(|(key, _)| key != CHAT_TEMPLATE_KEY);
+                entries.push((CHAT_TEMPLATE_KEY.to_string(), template.template.clone()));
+            }
+        }
+        entries
+    }
+
+    pub async fn generate_imatrix(&self) -> Result<ImatrixGenerated, Box<dyn std::error::Error>> {
+        let path = self.imatrix_path();
+        self.tracked(Stage::Imatrix, async {
+            let calibration = self.calibration_path();
+            if !self.imatrix_per_dataset || self.calibration_data.len() < 2 {
+                convert::prepare_calibration_data(&calibration, &self.calibration_data, &self.ctx)
+                    .await?;
+                convert::generate_imatrix(
+                    self.llama_bin(),
+                    self.fp_path(),
+                    path.clone(),
+                    &self.model_name,
+                    &self.imatrix_params,
+                    &calibration,
+                    &self.ctx,
+                )
+                .await?;
+                return Ok(ImatrixGenerated { path });
+            }
+
+            let mut parts = vec![];
+            for (i, source) in self.calibration_data.iter().enumerate() {
+                let part = path.with_extension(format!("{}.imatrix", i + 1));
+                let sources = std::slice::from_ref(source);
+                convert::prepare_calibration_data(&calibration, sources, &self.ctx).await?;
+                convert::generate_imatrix(
+                    self.llama_bin(),
+                    self.fp_path(),
+                    part.clone(),
+                    &self.model_name,
+                    &self.imatrix_params,
+                    &calibration,
+                    &self.ctx,
+                )
+                .await?;
+                parts.push(part);
+            }
+            convert::combine_imatrices(&self.llama_bin(), &parts, &path, &self.ctx).await?;
+            for part in parts {
+                tokio::fs::remove_file(part).await?;
+            }
+            Ok(ImatrixGenerated { path })
+        })
+        .await
+    }
+
+    /// Merge the imatrix files given with [`PipelineBuilder::imatrix`] into one.
+    pub async fn combine_imatrix(&self) -> Result<ImatrixGenerated, Box<dyn std::error::Error>> {
+        let path = self.imatrix_path();
+        self.tracked(Stage::Imatrix, async {
+            convert::combine_imatrices(&self.llama_bin(), &self.imatrix, &path, &self.ctx).await?;
+            Ok(ImatrixGenerated { path })
+        })
+        .await
+    }
+
+    /// Fetch the imatrix from `repo_id` to where generation would have written it.
+    pub async fn download_imatrix(
+        &self,
+        repo_id: &str,
+    ) -> Result<ImatrixGenerated, Box<dyn std::error::Error>> {
+        let path = self.imatrix_path();
+        self.tracked(Stage::Imatrix, async {
+            hf::download_imatrix(
+                repo_id,
+                &self.model_name,
+                &self.model_dir(),
+                &path,
+                &self.hf_token,
+                &self.ctx,
+            )
+            .await?;
+            Ok(ImatrixGenerated { path })
+        })
+        .await
+    }
+
+    fn quantize_job(&self, level: &QuantLevel) -> convert::QuantizeJob {
+        convert::QuantizeJob {
+            level: level.clone(),
+            fp: self.fp_path(),
+            imatrix: self.imatrix_path(),
+            output_path: self.quant_path(level),
+            output_tensor_type: TensorTypeOverride::resolve(&self.output_tensor_types, level)
+                .map(str::to_string),
+            token_embedding_type: TensorTypeOverride::resolve(&self.token_embedding_types, level)
+                .map(str::to_string),
+            allow_requantize: self.allow_requantize,
+            pure: self.pure,
+            extra_args: self.quantize_args.clone(),
+        }
+    }
+
+    pub async fn quantize(
+        &self,
+        level: QuantLevel,
+    ) -> Result<Quantized, Box<dyn std::error::Error>> {
+        self.tracked(Stage::Quantize(level.clone()), async {
+            convert::quantize(
+                self.quantize_job(&level),
+                self.llama_bin(),
+                &self.model_name,
+                &self.ctx,
+            )
+            .await?;
+            let quantized = self.split_quant(level).await?;
+            self.check_tensor_count(&quantized).await?;
+            if self.smoke_test {
+                self.smoke_test_quant(&quantized).await?;
+            }
+            Ok(quantized)
+        })
+        .await
+    }
+
+    /// The number of tensors in the full-precision GGUF, if it's still around to check.
+    async fn fp_tensor_count(&self) -> Option<usize> {
+        verify::tensor_count(&[self.fp_path()]).await.ok()
+    }
+
+    /// Check a fresh quant has every tensor the full-precision GGUF does.
+    async fn check_tensor_count(
+        &self,
+        quantized: &Quantized,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(expected) = self.fp_tensor_count().await else {
+            return Ok(());
+        };
+        let count = verify::tensor_count(&quantized.files()).await?;
+        if count != expected {
+            return Err(format!(
+                "💥 {} has {count} tensors, but {} has {expected}",
+                quantized.path.display(),
+                self.fp_path().display()
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Generate from a fresh quant, to catch tokenizer and metadata problems that only show up at
+    /// inference time.
+    async fn smoke_test_quant(
+        &self,
+        quantized: &Quantized,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let first = &quantized.files()[0];
+        let offload_args = self.imatrix_params.offload_args();
+        let text = verify::generate(first, &self.llama_bin(), &offload_args)
+            .await
+            .map_err(|e| format!("💥 {} failed its smoke test: {e}", first.display()))?;
+        self.ctx.detail(format!(
+            "💬 {} generated {text:?}",
+            quantized.level.to_string().to_uppercase()
+        ));
+        Ok(())
+    }
+
+    /// Re-check every quant in the output directory: headers intact, the same tensor count as
+    /// the full-precision GGUF, and, with `smoke_test`, that each generates a few tokens. Quants
+    /// that weren't produced are left out.
+    pub async fn verify(
+        &self,
+        smoke_test: bool,
+    ) -> Result<Vec<Verification>, Box<dyn std::error::Error>> {
+        let expected = self.fp_tensor_count().await;
+        let llama_bin = self.llama_bin();
+        let offload_args = self.imatrix_params.offload_args();
+        let smoke_test = smoke_test.then_some((llama_bin.as_path(), offload_args.as_slice()));
+        let mut verifications = vec![];
+        for level in &self.quants {
+            let path = self.quant_path(level);
+            let files = match tokio::fs::try_exists(&path).await? {
+                true => vec![path],
+                false => convert::find_shards(&path).await?,
+            };
+            if files.is_empty() {
+                continue;
+            }
+            self.ctx.detail(format!(
+                "🔎 verifying {}...",
+                level.to_string().to_uppercase()
+            ));
+            verifications.push(verify::verify(level.clone(), &files, expected, smoke_test).await);
+        }
+        if verifications.is_empty() {
+            return Err(format!("no quants found in {}", self.output_dir().display()).into());
+        }
+        Ok(verifications)
+    }
+
+    /// Check each requested quant here against the target repo: that it's been made, isn't
+    /// corrupted, has been published, and is the same size on the Hub.
+    pub async fn audit(&self) -> Result<Vec<QuantAudit>, Box<dyn std::error::Error>> {
+        let target = self.upload_target();
+        target.check_credentials().map_err(|e| e.to_string())?;
+        let repo_id = target.repo_id();
+        let remote = HubClient::new(self.hf_token.clone(), self.ctx.clone())
+            .files(&repo_id)
+            .await
+            .map_err(|e| format!("couldn't list {repo_id}: {e}"))?;
+        let mut audits = vec![];
+        for level in &self.quants {
+            let label = level.to_string().to_uppercase();
+            let published: Vec<_> = remote
+                .iter()
+                .filter(|f| naming::repo_label(&f.rfilename).as_ref() == Some(&label))
+                .collect();
+            let remote_bytes = (!published.is_empty())
+                .then(|| published.iter().map(|f| f.size.unwrap_or_default()).sum());
+            let path = self.quant_path(level);
+            let files = match tokio::fs::try_exists(&path).await? {
+                true => vec![path],
+                false => convert::find_shards(&path).await?,
+            };
+            self.ctx.detail(format!("🔎 auditing {label}..."));
+            audits.push(audit::audit(level.clone(), &files, remote_bytes).await);
+        }
+        Ok(audits)
+    }
+
+    /// Convert each LoRA adapter to a GGUF in the output directory, against the base model's
+    /// config where it was downloaded to or, failing that, on the Hub. Adapters a previous run
+    /// converted are left as they are.
+    pub async fn convert_loras(&self) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let mut converted = vec![];
+        for adapter in &self.loras {
+            let name = adapter.name();
+            let path = adapter.gguf_path(&self.output_dir(), &self.precision);
+            if is_non_empty(&path).await {
+                self.ctx
+                    .info(format!("🧩 LoRA {name} already converted, skipping."));
+                converted.push(path);
+                continue;
+            }
+            self.convert_lora(adapter, &path).await?;
+            self.ctx
+                .info(format!("🧩 converted LoRA {name} to {}", path.display()));
+            converted.push(path);
+        }
+        Ok(converted)
+    }
+
+    /// Convert a vision-language model's projector to GGUFs at full precision and `Q8_0`,
+    /// uploaded with the quants for llama.cpp to load alongside them. Text-only models have none.
+    pub async fn convert_mmproj(&self) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let source_dir = self.source_dir();
+        if self.skip_mmproj || !mmproj::is_multimodal(&source_dir).await {
+            return Ok(vec![]);
+        }
+        let mut converted = vec![];
+        for (outtype, label) in mmproj::types(&self.precision) {
+            let path = mmproj::path(&self.output_dir(), &self.model_name, &label);
+            if is_non_empty(&path).await {
+                self.ctx
+                    .info(format!("🖼️ mmproj {label} already converted, skipping."));
+                converted.push(path);
+                continue;
+            }
+            self.ctx.detail(format!(
+                "🖼️ converting {}'s projector to {label}...",
+                self.model_name
+            ));
+            mmproj::convert(&self.llama_path, &source_dir, &outtype, &path, &self.ctx)
+                .await
+                .map_err(|e| format!("converting the mmproj to {label} failed: {e}"))?;
+            gguf::validate(&path).await.map_err(|e| format!("💥 {e}"))?;
+            self.ctx
+                .info(format!("🖼️ converted the projector to {}", path.display()));
+            converted.push(path);
+        }
+        Ok(converted)
+    }
+
+    /// Convert `adapter` to a GGUF at `path`, downloading it into the model directory first if
+    /// it's on the Hub.
+    async fn convert_lora(
+        &self,
+        adapter: &LoraAdapter,
+        path: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let name = adapter.name();
+        let dir = adapter.dir(&self.model_dir());
+        if let LoraAdapter::Hub(repo_id) = adapter {
+            let options = DownloadOptions {
+                files: Default::default(),
+                ..self.download_options.clone()
+            };
+            hf::download_model(
+                repo_id,
+                None,
+                &dir,
+                &options,
+                false,
+                &self.hf_token,
+                &self.ctx,
+            )
+            .await?;
+        }
+        self.ctx.detail(format!("🧩 converting LoRA {name}..."));
+        lora::convert_lora(
+            &self.precision,
+            &self.llama_path,
+            &dir,
+            &self.lora_base(),
+            path,
+            &self.ctx,
+        )
+        .await
+        .map_err(|e| format!("converting LoRA {name} failed: {e}").into())
+    }
+
+    /// The base model LoRA adapters are converted against: its config where it was downloaded
+    /// to, or else on the Hub.
+    fn lora_base(&self) -> LoraBase {
+        let source_dir = self.source_dir();
+        match source_dir.join("config.json").exists() {
+            true => LoraBase::Local(source_dir),
+            false => LoraBase::Hub(self.model_id.clone()),
+        }
+    }
+
+    /// Package the quant for `level` as a llamafile, unless a previous run already did. `None` if
+    /// there's no quant to package: it failed, or it's split into shards, which a llamafile can't
+    /// hold.
+    async fn package_llamafile(
+        &self,
+        level: &QuantLevel,
+    ) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+        let label = level.to_string().to_uppercase();
+        let gguf = self.quant_path(level);
+        if !tokio::fs::try_exists(&gguf).await? {
+            if !convert::find_shards(&gguf).await?.is_empty() {
+                self.ctx.warn(format!(
+                    "📦 {label} is split into shards, which a llamafile can't hold; skipping it."
+                ));
+            }
+            return Ok(None);
+        }
+        let path = llamafile::path(&gguf);
+        if is_non_empty(&path).await {
+            self.ctx.info(format!(
+                "📦 {label} already packaged as a llamafile, skipping."
+            ));
+            return Ok(Some(path));
+        }
+        let path = llamafile::package(
+            &self.llamafile_runtime,
+            &gguf,
+            &Stage::Quantize(level.clone()),
+            &self.ctx,
+        )
+        .await?;
+        self.ctx
+            .info(format!("📦 packaged {label} as {}", path.display()));
+        Ok(Some(path))
+    }
+
+    /// Write [`MANIFEST_FILE`] for LM Studio into the output directory, listing every quant
+    /// that's been made. The model's metadata comes from the full-precision GGUF, or the first
+    /// quant if that's been cleaned up.
+    async fn write_lmstudio_manifest(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut quants = vec![];
+        for level in &self.quants {
+            let path = self.quant_path(level);
+            let files = match tokio::fs::try_exists(&path).await? {
+                true => vec![path],
+                false => convert::find_shards(&path).await?,
+            };
+            if !files.is_empty() {
+                quants.push((level.to_string().to_uppercase(), files));
+            }
+        }
+        let fp_path = self.fp_path();
+        let source = match tokio::fs::try_exists(&fp_path).await? {
+            true => Some(fp_path),
+            false => quants.first().map(|(_, files)| files[0].clone()),
+        };
+        let header = match source {
+            Some(path) => tokio::task::spawn_blocking(move || gguf::read_header(&path))
+                .await?
+                .ok(),
+            None => None,
+        };
+        let mut manifest = Manifest::new(&self.upload_target().repo_id(), header.as_ref());
+        for (label, files) in &quants {
+            manifest.add(label, files).await;
+        }
+        let path = manifest.save(&self.output_dir()).await?;
+        self.ctx.detail(format!(
+            "🎛️ wrote LM Studio's {MANIFEST_FILE} listing {} quants to {}",
+            quants.len(),
+            path.display()
+        ));
+        Ok(())
+    }
+
+    /// Split the quant for `level` into shards if it's too big to upload whole.
+    async fn split_quant(
+        &self,
+        level: QuantLevel,
+    ) -> Result<Quantized, Box<dyn std::error::Error>> {
+        let path = self.quant_path(&level);
+        let files = convert::split_if_oversized(
+            &path,
+            &self.llama_bin(),
+            &Stage::Quantize(level.clone()),
+            &self.ctx,
+        )
+        .await?;
+        let shards = if files == [path.clone()] {
+            vec![]
+        } else {
+            files
+        };
+        Ok(Quantized {
+            level,
+            path,
+            shards,
+        })
+    }
+
+    /// The full-precision GGUF and each quant that's been made, labelled for the eval results.
+    /// A split quant is evaluated from its first shard, which llama.cpp loads the rest from.
+    async fn eval_targets(&self) -> Result<Vec<(String, PathBuf)>, Box<dyn std::error::Error>> {
+        let mut targets = vec![];
+        let fp_path = self.fp_path();
+        if tokio::fs::try_exists(&fp_path).await? {
+            targets.push((self.precision.to_string().to_uppercase(), fp_path));
+        }
+        for level in &self.quants {
+            let path = self.quant_path(level);
+            let first = match tokio::fs::try_exists(&path).await? {
+                true => Some(path),
+                false => convert::find_shards(&path).await?.into_iter().next(),
+            };
+            if let Some(first) = first {
+                targets.push((level.to_string().to_uppercase(), first));
+            }
+        }
+        Ok(targets)
+    }
+
+    /// Measure each quant against the full-precision GGUF, saving results to the output directory
+    /// as they come in. GGUFs measured by a previous run aren't measured again.
+    pub async fn evaluate(&self) -> Result<EvalResults, Box<dyn std::error::Error>> {
+        self.tracked(Stage::Eval, async {
+            let (model_dir, output_dir) = (self.model_dir(), self.output_dir());
+            let mut results = EvalResults::load(&output_dir).await;
+            if self.eval_perplexity || self.eval_kl_divergence {
+                results.use_dataset(self.eval_dataset.as_deref());
+            }
+            let targets = self.eval_targets().await?;
+            let fp_label = self.precision.to_string().to_uppercase();
+            let ppl_pending: Vec<_> = targets
+                .iter()
+                .filter(|(label, _)| self.eval_perplexity && !results.has_perplexity(label))
+                .cloned()
+                .collect();
+            let kld_pending: Vec<_> = targets
+                .iter()
+                .filter(|(label, _)| {
+                    self.eval_kl_divergence
+                        && *label != fp_label
+                        && !results.has_kl_divergence(label)
+                })
+                .cloned()
+                .collect();
+            for (label, path) in &targets {
+                if !self.eval_bench || results.has_bench(label) {
+                    continue;
+                }
+                let (bench, hardware) = eval::bench(
+                    label,
+                    path,
+                    &self.llama_bin(),
+                    &self.imatrix_params,
+                    &self.ctx,
+                )
+                .await?;
+                results.record_bench(bench, hardware);
+                results.save(&output_dir).await?;
+            }
+            if ppl_pending.is_empty() && kld_pending.is_empty() {
+                return Ok(results);
+            }
+            let (dataset, downloaded) =
+                eval::prepare_dataset(self.eval_dataset.as_deref(), &model_dir, &self.ctx).await?;
+            for (label, path) in ppl_pending {
+                let perplexity = eval::perplexity(
+                    &label,
+                    label == fp_label,
+                    &path,
+                    &dataset,
+                    &self.llama_bin(),
+                    &self.imatrix_params,
+                    &self.ctx,
+                )
+                .await?;
+                results.perplexity.push(perplexity);
+                results.save(&output_dir).await?;
+            }
+            if !kld_pending.is_empty() {
+                let fp_path = self.fp_path();
+                if !tokio::fs::try_exists(&fp_path).await? {
+                    return Err(format!(
+                        "KL-divergence compares against {}, which doesn't exist",
+                        fp_path.display()
+                    )
+                    .into());
+                }
+                let logits = eval::kld_base(
+                    &fp_path,
+                    &dataset,
+                    &model_dir,
+                    &self.llama_bin(),
+                    &self.imatrix_params,
+                    &self.ctx,
+                )
+                .await?;
+                for (label, path) in kld_pending {
+                    let kld = eval::kl_divergence(
+                        &label,
+                        &path,
+                        &logits,
+                        &self.llama_bin(),
+                        &self.imatrix_params,
+                        &self.ctx,
+                    )
+                    .await?;
+                    results.kl_divergence.push(kld);
+                    results.save(&output_dir).await?;
+                }
+                tokio::fs::remove_file(&logits).await?;
+            }
+            if downloaded {
+                tokio::fs::remove_file(&dataset).await?;
+            }
+            Ok(results)
+        })
+        .await
+    }
+
+    /// Upload every .gguf and .imatrix in the output and work directories, returning the target
+    /// repo ID.
+    pub async fn upload(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        hf::upload_ggufs_to_hf(&self.upload_target(), &self.ctx).await
+    }
+
+    fn upload_target(&self) -> UploadTarget {
+        UploadTarget {
+            hf_user: self.hf_user.clone(),
+            hf_token: self.hf_token.clone(),
+            model_name: self.model_name.clone(),
+            work_dir: self.model_dir(),
+            output_dir: self.output_dir(),
+            include_logs: self.upload_logs,
+            private: self.private,
+            as_pr: self.as_pr,
+            repo_id: self.repo_id.clone(),
+            collection: self.collection.clone(),
+            backend: self.upload_backend.clone(),
+            mirrors: self.mirrors.clone(),
+            published: self.published.clone().unwrap_or_default(),
+            signing: self.signing_key.clone().map(|key| Signing {
+                key,
+                all_files: self.sign_files,
+            }),
+            torrent: self.torrent,
+            ipfs: self.ipfs,
+            ipfs_pin_service: self.ipfs_pin_service.clone(),
+            card: CardInfo {
+                model_id: self.model_id.clone(),
+                revision: self.revision.clone(),
+                quantized_by: self.hf_user.clone(),
+                llama_path: self.llama_path.clone(),
+                imatrix: self.needs_imatrix().then(|| {
+                    if !self.imatrix.is_empty() {
+                        ImatrixSource::Provided(self.imatrix.clone())
+                    } else if let Some(repo_id) = &self.imatrix_repo {
+                        ImatrixSource::Downloaded(repo_id.clone())
+                    } else {
+                        ImatrixSource::Generated {
+                            sources: self.calibration_data.clone(),
+                            chunks: self.imatrix_params.chunks,
+                            per_dataset: self.imatrix_per_dataset
+                                && self.calibration_data.len() > 1,
+                        }
+                    }
+                }),
+                tensor_types: self
+                    .quants
+                    .iter()
+                    .map(|level| {
+                        let job = self.quantize_job(level);
+                        TensorTypes {
+                            level: level.clone(),
+                            output: job.output_tensor_type,
+                            token_embedding: job.token_embedding_type,
+                        }
+                    })
+                    .filter(|t| t.output.is_some() || t.token_embedding.is_some())
+                    .collect(),
+                loras: self
+                    .loras
+                    .iter()
+                    .map(|adapter| {
+                        let path = adapter.gguf_path(&self.output_dir(), &self.precision);
+                        let file = path.file_name().unwrap_or_default().to_string_lossy();
+                        (adapter.clone(), file.into_owned())
+                    })
+                    .collect(),
+                merged_lora: self.merge_lora.clone(),
+                pooling: self.pooling,
+                torrents: vec![],
+                pins: vec![],
+            },
+        }
+    }
+
+    /// Run one stage, reporting when it starts and how it ends.
+    async fn tracked<T: StageOutput>(
+        &self,
+        stage: Stage,
+        work: impl Future<Output = Result<T, Box<dyn std::error::Error>>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        self.ctx.emit(Event::StageStarted {
+            stage: stage.clone(),
+        });
+        let span = stage.span(&self.model_name);
+        let work = async {
+            let Some(limit) = self.ctx.timeouts.stage(&stage) else {
+                return work.await;
+            };
+            match tokio::time::timeout(limit, work).await {
+                Ok(result) => result,
+                Err(_) => {
+                    let error = format!("{stage} timed out after {}", timeout::describe(limit));
+                    self.ctx.log(&stage, &format!("# {error}")).await;
+                    Err(error.into())
+                }
+            }
+        };
+        match work.instrument(span.clone()).await {
+            Ok(result) => {
+                let output = result.output();
+                let bytes = match &output {
+                    Some(path) => tokio::fs::metadata(path)
+                        .await
+                        .ok()
+                        .filter(|m| m.is_file())
+                        .map(|m| m.len()),
+                    None => None,
+                };
+                if let Some(bytes) = bytes {
+                    span.record("bytes", bytes);
+                }
+                self.ctx.emit(Event::StageFinished {
+                    stage,
+                    output,
+                    bytes,
+                });
+                Ok(result)
+            }
+            Err(e) => {
+                tracing::debug!("{stage} failed: {e}");
+                event::record_error(&span, &e);
+                self.ctx.emit(Event::StageFailed {
+                    stage,
+                    error: e.to_string(),
+                });
+                Err(e)
+            }
+        }
+    }
+
+    /// Report a stage that won't run.
+    fn skipped(&self, stage: Stage, reason: String) {
+        tracing::info!("{reason}");
+        self.ctx.emit(Event::StageSkipped { stage, reason });
+    }
+
+    async fn load_state(&self) -> Result<PipelineState, Box<dyn std::error::Error>> {
+        if self.resume {
+            PipelineState::load(&self.model_dir()).await
+        } else {
+            Ok(PipelineState::default())
+        }
+    }
+
+    async fn download_decision(&self, state: &PipelineState) -> Decision {
+        if self.skip_download || self.local_model.is_some() || self.fp.is_some() || self.only_upload
+        {
+            Decision::Skip
+        } else if state.downloaded
+            && state.revision == self.revision
+            && (!state.sources_deleted || self.convert_decision(state).await == Decision::Done)
+        {
+            Decision::Done
+        } else {
+            Decision::Run
+        }
+    }
+
+    async fn convert_decision(&self, state: &PipelineState) -> Decision {
+        let fp_path = self.fp_path();
+        if self.fp.is_some() || self.only_upload {
+            Decision::Skip
+        } else if state.fp.as_ref() == Some(&fp_path) && is_non_empty(&fp_path).await {
+            Decision::Done
+        } else {
+            Decision::Run
+        }
+    }
+
+    fn needs_imatrix(&self) -> bool {
+        self.quants.iter().any(QuantLevel::requires_imatrix)
+    }
+
+    async fn imatrix_decision(&self, state: &PipelineState) -> Decision {
+        let imatrix_path = self.imatrix_path();
+        if self.only_upload || self.imatrix.len() == 1 || !self.needs_imatrix() {
+            Decision::Skip
+        } else if state.imatrix.as_ref() == Some(&imatrix_path) && is_non_empty(&imatrix_path).await
+        {
+            Decision::Done
+        } else {
+            Decision::Run
+        }
+    }
+
+    async fn quantize_decision(&self, level: &QuantLevel) -> Decision {
+        if self.only_upload {
+            Decision::Skip
+        } else if !self.force && self.is_quantized(level).await {
+            Decision::Done
+        } else {
+            Decision::Run
+        }
+    }
+
+    /// Remove `level`'s quant, whether whole or split, made from a checkpoint since replaced.
+    async fn remove_quant(&self, level: &QuantLevel) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.quant_path(level);
+        let mut files = convert::find_shards(&path).await?;
+        files.push(path);
+        for file in files {
+            match tokio::fs::remove_file(&file).await {
+                Ok(()) => self.ctx.detail(format!(
+                    "🗑️ removed {}, made from an earlier checkpoint",
+                    file.display()
+                )),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    async fn is_quantized(&self, level: &QuantLevel) -> bool {
+        let path = self.quant_path(level);
+        is_non_empty(&path).await
+            || convert::find_shards(&path)
+                .await
+                .is_ok_and(|s| !s.is_empty())
+    }
+
+    /// Best-effort parameter count: from the Hub, or from the size of an existing fp GGUF or
+    /// local model's safetensors.
+    async fn parameter_count(&self) -> Option<u64> {
+        if let Ok(metadata) = tokio::fs::metadata(self.fp_path()).await {
+            return Some((metadata.len() as f64 * 8.0 / self.precision.bits_per_weight()) as u64);
+        }
+        if let Some(dir) = &self.local_model {
+            let weights = files_with_extensions(dir, &[".safetensors"]).await.ok()?;
+            let mut bytes = 0;
+            for file in weights {
+                bytes += tokio::fs::metadata(&file.local_path).await.ok()?.len();
+            }
+            // NOTE: assumes 16-bit weights, by far the most common for safetensors checkpoints
+            return (bytes > 0).then_some(bytes / 2);
+        }
+        if self.model_id.is_empty() {
+            return None;
+        }
+        HubClient::new(self.hf_token.clone(), self.ctx.clone())
+            .parameter_count(&self.model_id)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Resolve every path and decide which stages would run, without executing anything.
+    pub async fn plan(&self) -> Result<Plan, Box<dyn std::error::Error>> {
+        let mut state = self.load_state().await?;
+        let download = self.download_decision(&state).await;
+        // NOTE: downloading another revision replaces everything made from the recorded one
+        let stale = match download == Decision::Run && state.downloaded {
+            true if state.revision != self.revision => state.forget_outputs(),
+            _ => vec![],
+        };
+        let parameters = self.parameter_count().await;
+        let estimate =
+            |bits_per_weight: f64| parameters.map(|n| (n as f64 * bits_per_weight / 8.0) as u64);
+        let (model_dir, output_dir) = (self.model_dir(), self.output_dir());
+        let mut stages = vec![];
+
+        if self.update_llama {
+            stages.push(
+                PlannedStage::new("update llama.cpp", Decision::Run).detail(format!(
+                    "{} in {}, {} {}{}",
+                    match &self.llama_ref {
+                        Some(git_ref) => format!("git checkout {git_ref}"),
+                        None => "git pull".to_string(),
+                    },
+                    self.llama_path.display(),
+                    match self.llama_prebuilt {
+                        true => "release binaries into",
+                        false => "cmake --build",
+                    },
+                    self.llama_build_dir().display(),
+                    self.llama_backend
+                        .map(|b| format!(" for {b}"))
+                        .unwrap_or_default()
+                )),
+            );
+        }
+
+        let source = match (&self.local_model, &self.revision) {
+            (Some(dir), _) => format!("local model at {}", dir.display()),
+            (None, Some(revision)) => format!("huggingface.co/{} at {revision}", self.model_id),
+            (None, None) => format!("huggingface.co/{}", self.model_id),
+        };
+        stages.push(
+            PlannedStage::new("download", download)
+                .detail(source)
+                .output(
+                    model_dir.clone(),
+                    estimate(Precision::BF16.bits_per_weight()),
+                ),
+        );
+
+        let fp_path = self.fp_path();
+        let convert_decision = self.convert_decision(&state).await;
+        let convert = PlannedStage::new(
+            format!("convert to {}", self.precision.to_string().to_uppercase()),
+            convert_decision,
+        )
+        .output(fp_path.clone(), estimate(self.precision.bits_per_weight()));
+        let converted = match self.merge_lora {
+            Some(_) => self.merge_base_path(),
+            None => fp_path.clone(),
+        };
+        let mut details = vec![];
+        let convert = match self.native_convert {
+            true => {
+                details.push(format!(
+                    "in-process with the native converter, from {}",
+                    self.source_dir().display()
+                ));
+                convert
+            }
+            false => convert.command(&convert::convert_command(
+                &self.precision,
+                &self.llama_path,
+                &self.source_dir(),
+                &converted,
+                &self.convert_args,
+            )),
+        };
+        if !self.native_convert && self.splits_conversion() {
+            details.push("then merges the shards with llama-gguf-split".to_string());
+        }
+        if let Some(adapter) = &self.merge_lora {
+            details.push(format!(
+                "then merges the LoRA adapter {} in with llama-export-lora",
+                adapter.name()
+            ));
+        }
+        match self.pooling {
+            Some(Pooling::Rank) => {
+                details.push("then sets rank pooling and checks the embedding length".to_string())
+            }
+            Some(pooling) => details.push(format!(
+                "then sets {pooling} pooling and checks the embeddings' dimensions with \
+                 llama-embedding"
+            )),
+            None => {}
+        }
+        if self.cleanup.contains(&Cleanup::Sources) && self.local_model.is_none() {
+            details.push("then deletes the downloaded weights".to_string());
+        }
+        stages.push(match details.is_empty() {
+            true => convert,
+            false => convert.detail(details.join(", ")),
+        });
+
+        // NOTE: what's already in a GGUF that won't be reconverted is known; a new one may have
+        // any of it
+        let header = match convert_decision {
+            Decision::Run => None,
+            _ => gguf::validate(&fp_path).await.ok(),
+        };
+        let keys: Vec<String> = match &header {
+            Some(header) => self
+                .metadata_entries(header)
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect(),
+            None => self
+                .gguf_meta
+                .keys()
+                .cloned()
+                .chain(self.pooling.map(|_| "{arch}.pooling_type".to_string()))
+                .chain(
+                    self.chat_template
+                        .as_ref()
+                        .map(|_| CHAT_TEMPLATE_KEY.to_string()),
+                )
+                .collect(),
+        };
+        let metadata = match self.only_upload || keys.is_empty() {
+            true => PlannedStage::new("set metadata", Decision::Skip),
+            // NOTE: the GGUF is rewritten through a copy beside it, as big as it is
+            false => PlannedStage::new("set metadata", Decision::Run)
+                .detail(match self.fp {
+                    Some(_) => format!("{}; fails, since --fp is never modified", keys.join(", ")),
+                    None => keys.join(", "),
+                })
+                .output(fp_path.clone(), estimate(self.precision.bits_per_weight())),
+        };
+        stages.push(metadata);
+        for adapter in &self.loras {
+            let path = adapter.gguf_path(&output_dir, &self.precision);
+            let decision = match is_non_empty(&path).await {
+                true => Decision::Done,
+                false => Decision::Run,
+            };
+            let mut stage = PlannedStage::new(format!("convert LoRA {}", adapter.name()), decision)
+                .command(&lora::convert_lora_command(
+                    &self.precision,
+                    &self.llama_path,
+                    &adapter.dir(&model_dir),
+                    &self.lora_base(),
+                    &path,
+                ))
+                .output(path, None);
+            if let LoraAdapter::Hub(repo_id) = adapter {
+                stage = stage.detail(format!("downloaded from huggingface.co/{repo_id}"));
+            }
+            stages.push(stage);
+        }
+        if !self.skip_mmproj && mmproj::is_multimodal(&self.source_dir()).await {
+            for (outtype, label) in mmproj::types(&self.precision) {
+                let path = mmproj::path(&output_dir, &self.model_name, &label);
+                let decision = match is_non_empty(&path).await {
+                    true => Decision::Done,
+                    false => Decision::Run,
+                };
+                stages.push(
+                    PlannedStage::new(format!("convert mmproj {label}"), decision)
+                        .command(&mmproj::convert_command(
+                            &self.llama_path,
+                            &self.source_dir(),
+                            &outtype,
+                            &path,
+                        ))
+                        .output(path, None),
+                );
+            }
+        }
+
+        let imatrix_path = self.imatrix_path();
+        let imatrix = PlannedStage::new("imatrix", self.imatrix_decision(&state).await);
+        let imatrix = match &self.imatrix_repo {
+            Some(repo_id) => imatrix.detail(format!("fetched from huggingface.co/{repo_id}")),
+            None if self.imatrix.len() > 1 => imatrix.command(&convert::combine_imatrix_command(
+                &self.llama_bin(),
+                &self.imatrix,
+                &imatrix_path,
+            )),
+            None => {
+                let calibration = if self.calibration_data.is_empty() {
+                    convert::CALIBRATION_URL.to_string()
+                } else {
+                    self.calibration_data.join(" + ")
+                };
+                imatrix
+                    .detail(format!("calibration data: {calibration}"))
+                    .command(&convert::imatrix_command(
+                        &self.llama_bin(),
+                        &fp_path,
+                        &self.calibration_path(),
+                        &imatrix_path,
+                        &self.imatrix_params,
+                    ))
+            }
+        };
+        stages.push(imatrix.output(imatrix_path, None));
+
+        for q in &self.quants {
+            let job = self.quantize_job(q);
+            let estimated_bytes = estimate(q.bits_per_weight());
+            let mut stage = PlannedStage::new(
+                format!("quantize {}", q.to_string().to_uppercase()),
+                match stale.contains(q) {
+                    true => Decision::Run,
+                    false => self.quantize_decision(q).await,
+                },
+            )
+            .command(&convert::quantize_command(&job, &self.llama_bin()));
+            let mut details = vec![];
+            if estimated_bytes.is_some_and(|bytes| bytes > convert::SPLIT_THRESHOLD) {
+                details.push(format!(
+                    "over {}, so it'll be split into shards with llama-gguf-split",
+                    human_bytes(convert::SPLIT_THRESHOLD)
+                ));
+            }
+            if self.smoke_test {
+                details.push("then smoke-tested with llama-cli".to_string());
+            }
+            if self.llamafile.as_ref() == Some(q) {
+                details.push(format!(
+                    "then packaged with {} into {}",
+                    self.llamafile_runtime.display(),
+                    llamafile::path(&job.output_path).display()
+                ));
+            }
+            if !details.is_empty() {
+                stage = stage.detail(details.join("; "));
+            }
+            stages.push(stage.output(job.output_path, estimated_bytes));
+        }
+
+        if self.evaluates() {
+            let dataset = self
+                .eval_dataset
+                .clone()
+                .unwrap_or_else(|| eval::WIKITEXT_URL.to_string());
+            let measures = match (self.eval_perplexity, self.eval_kl_divergence) {
+                (true, true) => Some("perplexity and KL-divergence"),
+                (true, false) => Some("perplexity"),
+                (false, true) => Some("KL-divergence"),
+                (false, false) => None,
+            };
+            let mut details = vec![];
+            if let Some(measures) = measures {
+                details.push(format!(
+                    "{measures} of each quant vs. {} on {dataset}",
+                    self.precision.to_string().to_uppercase()
+                ));
+            }
+            if self.eval_bench {
+                details.push("speed of each GGUF on this machine".to_string());
+            }
+            let command = match measures {
+                Some(_) => eval::perplexity_command(
+                    &self.llama_bin(),
+                    &fp_path,
+                    Path::new("<dataset>"),
+                    &self.imatrix_params,
+                ),
+                None => eval::bench_command(&self.llama_bin(), &fp_path, &self.imatrix_params),
+            };
+            stages.push(
+                PlannedStage::new("eval", Decision::Run)
+                    .detail(details.join("; "))
+                    .command(&command),
+            );
+        }
+
+        let upload = if self.skip_upload {
+            PlannedStage::new("upload", Decision::Skip)
+        } else {
+            let logs = if self.upload_logs { ", logs/*.log" } else { "" };
+            let visibility = match (self.as_pr, self.private) {
+                (true, _) => " as a pull request",
+                (false, true) => " (private)",
+                (false, false) => "",
+            };
+            let signatures = match &self.signing_key {
+                Some(key) if self.sign_files => format!(", *.{}", key.extension()),
+                Some(key) => format!(", {CHECKSUMS_FILE}.{}", key.extension()),
+                None => String::new(),
+            };
+            let torrents = match self.torrent {
+                true => format!(", *.torrent, {MAGNETS_FILE}"),
+                false => String::new(),
+            };
+            let collection = match &self.collection {
+                Some(slug) => format!(", added to the collection {slug}"),
+                None => String::new(),
+            };
+            let ipfs = match (self.ipfs, &self.ipfs_pin_service) {
+                (false, _) => String::new(),
+                (true, None) => ", pinned to IPFS".to_string(),
+                (true, Some(service)) => format!(", pinned to IPFS and {service}"),
+            };
+            let repo_name = self.upload_target().repo_id();
+            let repo_name = repo_name.rsplit('/').next().unwrap_or_default();
+            let mirrors = match self.mirrors.as_slice() {
+                [] => String::new(),
+                mirrors => format!(
+                    ", mirrored to {}",
+                    mirrors
+                        .iter()
+                        .map(|m| m.destination(repo_name))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            };
+            let deleted: Vec<String> = Cleanup::UPLOADED
+                .iter()
+                .filter(|c| self.cleanup.contains(c))
+                .map(ToString::to_string)
+                .collect();
+            let cleanup = match deleted.as_slice() {
+                [] => String::new(),
+                deleted => format!(", then deletes {}", deleted.join(", ")),
+            };
+            PlannedStage::new("upload", Decision::Run).detail(format!(
+                "*.gguf, *.imatrix{logs}, {CHECKSUMS_FILE}{signatures}{torrents}, README.md in {} → \
+                 {}{visibility}{collection}{ipfs}{mirrors}{cleanup}",
+                match model_dir == output_dir {
+                    true => model_dir.display().to_string(),
+                    false => format!("{} and {}", output_dir.display(), model_dir.display()),
+                },
+                self.upload_target().destination()
+            ))
+        };
+        stages.push(upload);
+
+        Ok(Plan {
+            model_id: match &self.local_model {
+                Some(dir) if self.model_id.is_empty() => dir.display().to_string(),
+                _ => self.model_id.clone(),
+            },
+            model_dir,
+            output_dir,
+            parameters,
+            stages,
+        })
+    }
+
+    /// Compare the estimated size of everything the run will write against the free space on the
+    /// filesystems of the work and output directories, failing early rather than halfway through
+    /// with ENOSPC.
+    pub async fn check_disk_space(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let plan = self.plan().await?;
+        if plan.parameters.is_none() {
+            self.ctx
+                .info("💾 couldn't determine the parameter count, skipping the disk space check.");
+            return Ok(());
+        }
+        let dirs = match plan.model_dir == plan.output_dir {
+            true => vec![(&plan.model_dir, plan.estimated_bytes())],
+            false => [&plan.model_dir, &plan.output_dir]
+                .into_iter()
+                .map(|dir| (dir, plan.estimated_bytes_in(dir)))
+                .collect(),
+        };
+        for (dir, required) in dirs {
+            let available = fs4::available_space(dir)?;
+            self.ctx.detail(format!(
+                "💾 estimated {} needed in {}, {} available.",
+                human_bytes(required),
+                dir.display(),
+                human_bytes(available)
+            ));
+            if required <= available {
+                continue;
+            }
+            let message = format!(
+                "not enough disk space in {}: ~{} needed, {} available",
+                dir.display(),
+                human_bytes(required),
+                human_bytes(available)
+            );
+            if self.ignore_disk_space {
+                self.ctx.warn(format!("⚠️ {message}; continuing anyway."));
+            } else {
+                return Err(format!("{message} (pass --ignore-disk-space to try anyway)").into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the llama.cpp commit in use in the output dir's `llama::LOCK_FILE`, or warn if
+    /// earlier outputs there were made with a different one. A fresh run (no `resume`) replaces
+    /// the lock instead.
+    pub async fn lock_llama(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(commit) = llama::head_commit(&self.llama_path).await else {
+            return Ok(());
+        };
+        let output_dir = self.output_dir();
+        match LlamaLock::load(&output_dir).await {
+            Some(lock) if self.resume && lock.commit != commit => {
+                self.ctx.warn(format!(
+                    "🐪 llama.cpp is at {commit}, but outputs in {} were made with {} ({}); \
+                     rerun with --update-llama --llama-ref {} to match them, or --no-resume to \
+                     redo them all",
+                    output_dir.display(),
+                    lock.commit,
+                    llama::LOCK_FILE,
+                    lock.git_ref.as_deref().unwrap_or(&lock.commit),
+                ));
+                Ok(())
+            }
+            Some(_) if self.resume => Ok(()),
+            _ => {
+                LlamaLock {
+                    git_ref: self.llama_ref.clone(),
+                    commit,
+                }
+                .save(&output_dir)
+                .await
+            }
+        }
+    }
+
+    /// Check the machine for everything a run can need, from git to a GPU, each with a fix if
+    /// it's missing. Unlike the other checks, this doesn't depend on the model or stages.
+    pub async fn doctor(&self) -> Vec<Check> {
+        let mut checks = vec![doctor::git().await];
+        checks.extend(doctor::python(&self.llama_path).await);
+        checks.push(doctor::converter(&self.llama_path));
+        checks.push(doctor::binaries(&self.llama_bin()));
+        checks.push(doctor::huggingface(&self.hf_user, &self.hf_token, &self.ctx).await);
+        checks.push(doctor::gpu().await);
+        checks.push(doctor::disk_space(Path::new(".")));
+        checks
+    }
+
+    /// Check that llama.cpp has the script and binaries the stages that'll run need, so a missing
+    /// or half-built install fails up front rather than with a cryptic subprocess error.
+    pub async fn check_llama_install(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let bin = self.llama_bin();
+        let mut required = vec![];
+        if self.fp.is_none() && !self.native_convert {
+            required.push(self.llama_path.join(convert::CONVERT_SCRIPT));
+            if self.splits_conversion() {
+                required.push(llama::binary(&bin, "llama-gguf-split"));
+            }
+        }
+        if !self.loras.is_empty() || self.merge_lora.is_some() {
+            required.push(self.llama_path.join(lora::CONVERT_LORA_SCRIPT));
+        }
+        if self.merge_lora.is_some() {
+            required.push(llama::binary(&bin, "llama-export-lora"));
+        }
+        required.push(llama::binary(&bin, "llama-quantize"));
+        if self.eval_perplexity || self.eval_kl_divergence {
+            required.push(llama::binary(&bin, "llama-perplexity"));
+        }
+        if self.eval_bench {
+            required.push(llama::binary(&bin, "llama-bench"));
+        }
+        if self.smoke_test {
+            required.push(llama::binary(&bin, "llama-cli"));
+        }
+        if self.pooling.is_some_and(|pooling| pooling != Pooling::Rank) {
+            required.push(llama::binary(&bin, "llama-embedding"));
+        }
+        if self.needs_imatrix() && self.imatrix.len() != 1 && self.imatrix_repo.is_none() {
+            required.push(llama::binary(&bin, "llama-imatrix"));
+        }
+        let mut missing = vec![];
+        for path in required {
+            if !tokio::fs::try_exists(&path).await? {
+                missing.push(path.display().to_string());
+            }
+        }
+        if !missing.is_empty() {
+            return Err(format!(
+                "llama.cpp at {} is missing {}; install or build it with --update-llama, or \
+                 point --llama-path at a llama.cpp checkout",
+                self.llama_path.display(),
+                missing.join(", ")
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Check the model's architecture against the ones the installed converter supports, before
+    /// downloading tens of GB only to fail converting. Skipped if either is unknown.
+    pub async fn check_architecture(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let config = match &self.local_model {
+            Some(dir) => tokio::fs::read_to_string(dir.join("config.json"))
+                .await
+                .ok()
+                .and_then(|json| serde_json::from_str::<ModelConfig>(&json).ok()),
+            None => HubClient::new(self.hf_token.clone(), self.ctx.clone())
+                .model_info(&self.model_id)
+                .await
+                .ok()
+                .and_then(|info| info.config),
+        };
+        let architectures = config.map(|c| c.architectures).unwrap_or_default();
+        if self.native_convert {
+            if !architectures.is_empty()
+                && !architectures
+                    .iter()
+                    .any(|a| native::ARCHITECTURES.iter().any(|(hf, _)| hf == a))
+            {
+                return Err(format!(
+                    "the native converter doesn't support {}; it only handles {}, so drop \
+                     --native-convert",
+                    architectures.join(", "),
+                    native::supported()
+                )
+                .into());
+            }
+            return Ok(());
+        }
+        let Some(supported) = llama::supported_architectures(&self.llama_path).await else {
+            self.ctx
+                .detail("🐪 couldn't list the converter's architectures, skipping the check.");
+            return Ok(());
+        };
+        if architectures.is_empty() {
+            self.ctx
+                .detail("🐪 couldn't determine the model's architecture, skipping the check.");
+            return Ok(());
+        }
+        if !architectures.iter().any(|a| supported.contains(a)) {
+            return Err(format!(
+                "{} isn't supported by {} in {}; update llama.cpp with --update-llama in case \
+                 support was added recently",
+                architectures.join(", "),
+                convert::CONVERT_SCRIPT,
+                self.llama_path.display()
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Check the requested quant levels against what the installed `llama-quantize` accepts, so an
+    /// outdated llama.cpp fails up front instead of after conversion, and note any types it has
+    /// that autogguf doesn't know about yet.
+    pub async fn check_quant_support(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(supported) = llama::supported_quant_types(&self.llama_bin()).await else {
+            self.ctx
+                .detail("🐪 couldn't list llama-quantize's quant types, skipping the check.");
+            return Ok(());
+        };
+        let unsupported: Vec<String> = self
+            .quants
+            .iter()
+            .map(QuantLevel::to_string)
+            .filter(|q| !supported.contains(q))
+            .collect();
+        if !unsupported.is_empty() {
+            return Err(format!(
+                "llama-quantize in {} doesn't support {}; update llama.cpp with --update-llama",
+                self.llama_bin().display(),
+                unsupported.join(", ")
+            )
+            .into());
+        }
+        let unknown: Vec<&str> = supported
+            .iter()
+            .map(String::as_str)
+            .filter(|q| q.parse::<QuantLevel>().is_err())
+            .collect();
+        if !unknown.is_empty() {
+            tracing::debug!(
+                "llama-quantize supports types autogguf doesn't know about: {}",
+                unknown.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Run every stage that isn't skipped, uploading eagerly as quants finish. How each stage
+    /// went is summarized in [`summary`](Self::summary) afterwards, and in [`SUMMARY_FILE`] in
+    /// the output directory.
+    pub async fn run(&self) -> Result<PipelineReport, Error> {
+        // NOTE: before anything's written to the model dir, so a run that's locked out doesn't
+        // touch the outputs, logs or summary of the one holding it
+        let _lock = self.lock_model_dir().await.map_err(Error::Setup)?;
+        *self.ctx.summary.lock().unwrap() = RunSummary::new(
+            self.stages()
+                .into_iter()
+                .map(|stage| {
+                    let output = match &stage {
+                        Stage::Convert => Some(self.fp_path()),
+                        Stage::Imatrix => Some(self.imatrix_path()),
+                        Stage::Quantize(level) => Some(self.quant_path(level)),
+                        _ => None,
+                    };
+                    (stage, output)
+                })
+                .collect(),
+        );
+        let quants: Vec<String> = self.quants.iter().map(ToString::to_string).collect();
+        let span = tracing::info_span!(
+            target: SPAN_TARGET,
+            "run",
+            model = %self.model_name,
+            model_id = %self.model_id,
+            quants = %quants.join(","),
+            otel.status_code = Empty,
+            otel.status_message = Empty,
+        );
+        let result = self.run_stages().instrument(span.clone()).await;
+        if let Err(e) = &result {
+            event::record_error(&span, e);
+        }
+        if self.ctx.is_interrupted() {
+            self.remove_partial_outputs().await;
+        }
+        self.write_summary().await;
+        result.map_err(|e| self.classify(e))
+    }
+
+    async fn lock_model_dir(&self) -> Result<RunLock, Box<dyn std::error::Error>> {
+        let model_dir = self.model_dir();
+        tokio::fs::create_dir_all(&model_dir).await?;
+        // NOTE: only ever tries the lock, so it's quick enough not to need a blocking thread
+        RunLock::acquire(&model_dir, self.force_unlock)
+    }
+
+    /// Attribute the `error` a run ended in to the stage it came from.
+    fn classify(&self, error: Box<dyn std::error::Error>) -> Error {
+        let error = match error.downcast::<Error>() {
+            Ok(error) => return *error,
+            Err(error) => error,
+        };
+        if self.ctx.is_interrupted() {
+            return Error::Interrupted(error);
+        }
+        Error::in_stage(self.summary().last_failed(), error)
+    }
+
+    /// How each stage of the last [`run`](Self::run) went, so far as it got.
+    pub fn summary(&self) -> RunSummary {
+        self.ctx.summary.lock().unwrap().clone()
+    }
+
+    /// Delete what a cancelled run left half-written: quants still `.pending`, and the output of a
+    /// conversion or imatrix cut short. Partial downloads are kept, since the next run resumes
+    /// them.
+    async fn remove_partial_outputs(&self) {
+        let mut partial: Vec<PathBuf> = self
+            .quants
+            .iter()
+            .map(|level| self.quantize_job(level).pending_path())
+            .collect();
+        partial.extend(
+            self.summary()
+                .stages
+                .into_iter()
+                .filter(|s| s.status == StageStatus::Failed)
+                .filter(|s| matches!(s.stage, Stage::Convert | Stage::Imatrix))
+                .filter_map(|s| s.output),
+        );
+        match cleanup::delete_files(&partial).await {
+            Ok((0, _)) => {}
+            Ok((deleted, freed)) => self.ctx.info(format!(
+                "🧹 deleted {deleted} partial files, freeing {}.",
+                human_bytes(freed)
+            )),
+            Err(e) => self.ctx.warn(format!("couldn't delete partial files: {e}")),
+        }
+    }
+
+    async fn write_summary(&self) {
+        let mut summary = self.summary();
+        summary.finish().await;
+        let path = self.output_dir().join(SUMMARY_FILE);
+        let written = match serde_json::to_string_pretty(&summary) {
+            Ok(json) => tokio::fs::write(&path, json)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = written {
+            // NOTE: the summary isn't worth failing an otherwise finished run over
+            tracing::warn!("couldn't write {}: {e}", path.display());
+        }
+        *self.ctx.summary.lock().unwrap() = summary;
+    }
+
+    async fn run_stages(&self) -> Result<PipelineReport, Box<dyn std::error::Error>> {
+        let mut report = PipelineReport::default();
+        if self.up_to_date() {
+            return Ok(report);
+        }
+
+        if self.update_llama {
+            self.update_llama().await?;
+        }
+
+        let model_dir = self.model_dir();
+        tokio::fs::create_dir_all(&model_dir).await?;
+        tokio::fs::create_dir_all(self.output_dir()).await?;
+        if !self.only_upload {
+            self.check_llama_install().await?;
+            if self.fp.is_none() {
+                self.check_architecture().await?;
+            }
+            self.check_quant_support().await?;
+            self.check_disk_space().await?;
+            self.lock_llama().await?;
+        }
+        let mut state = self.load_state().await?;
+
+        match self.download_decision(&state).await {
+            Decision::Skip => self.skipped(
+                Stage::Download,
+                "🤗 skipping download from HuggingFace Hub.".to_string(),
+            ),
+            Decision::Done => self.skipped(
+                Stage::Download,
+                format!("🤗 {} already downloaded, skipping.", self.model_name),
+            ),
+            Decision::Run => {
+                let downloaded = self.download(state.downloaded).await?;
+                let stale = state.record_download(self.revision.clone(), downloaded.commit.clone());
+                // NOTE: before saving, so an interrupted run still finds them to remove
+                for level in &stale {
+                    self.remove_quant(level).await?;
+                }
+                state.save(&model_dir).await?;
+                report.download = Some(downloaded);
+            }
+        }
+
+        let convert_decision = self.convert_decision(&state).await;
+        match convert_decision {
+            Decision::Skip => self.skipped(
+                Stage::Convert,
+                format!(
+                    "skipping {} conversion.",
+                    self.precision.to_string().to_uppercase()
+                ),
+            ),
+            Decision::Done => self.skipped(
+                Stage::Convert,
+                format!(
+                    "🪄 {} already converted to {}, skipping.",
+                    self.model_name,
+                    self.precision.to_string().to_uppercase()
+                ),
+            ),
+            Decision::Run => {
+                let converted = self.convert().await?;
+                state.fp = Some(converted.path.clone());
+                // NOTE: quants made from the old fp no longer match it
+                state.quants.clear();
+                state.save(&model_dir).await?;
+                report.fp = Some(converted);
+            }
+        }
+        // NOTE: before the source weights are deleted, since the projector's are among them
+        let mmproj = match self.only_upload {
+            true => vec![],
+            false => self.convert_mmproj().await?,
+        };
+        if self.cleanup.contains(&Cleanup::Sources)
+            && convert_decision != Decision::Skip
+            && !state.sources_deleted
+            && self.local_model.is_none()
+        {
+            self.delete_sources().await?;
+            state.sources_deleted = true;
+            state.save(&model_dir).await?;
+        }
+        if !self.only_upload && self.update_fp_metadata().await? {
+            state.quants.clear();
+            state.save(&model_dir).await?;
+        }
+        if let Some(pooling) = self.pooling.filter(|_| report.fp.is_some()) {
+            self.check_embedding_dimensions(pooling).await?;
+        }
+
+        match self.imatrix_decision(&state).await {
+            Decision::Skip => self.ctx.emit(Event::StageSkipped {
+                stage: Stage::Imatrix,
+                reason: "not needed".to_string(),
+            }),
+            Decision::Done => self.skipped(
+                Stage::Imatrix,
+                format!(
+                    "⚖️ imatrix for {} already generated, skipping.",
+                    self.model_name
+                ),
+            ),
+            Decision::Run => {
+                let imatrix = match &self.imatrix_repo {
+                    Some(repo_id) => self.download_imatrix(repo_id).await?,
+                    None if self.imatrix.len() > 1 => self.combine_imatrix().await?,
+                    None => self.generate_imatrix().await?,
+                };
+                state.imatrix = Some(imatrix.path.clone());
+                state.save(&model_dir).await?;
+                report.imatrix = Some(imatrix);
+            }
+        }
+
+        let (upload_tx, upload_rx) = mpsc::unbounded_channel();
+        let mut upload_handle: Option<JoinHandle<_>> = None;
+        if !self.skip_upload {
+            upload_handle = Some(tokio::task::spawn(
+                hf::upload_worker(upload_rx, self.upload_target(), self.ctx.clone())
+                    .instrument(Span::current()),
+            ));
+        } else {
+            self.ctx.emit(Event::StageSkipped {
+                stage: Stage::Upload,
+                reason: "--skip-upload".to_string(),
+            });
+        }
+        // NOTE: a failed upload worker has hung up; its error is reported once it's joined below
+        let enqueue = |file: UploadFile| {
+            let _ = upload_tx.send(file);
+        };
+        let mut failed = vec![];
+
+        if self.only_upload {
+            if !self.skip_upload {
+                let outputs = self.upload_target().outputs().await;
+                for file in outputs.map_err(|e| e as Box<dyn std::error::Error>)? {
+                    enqueue(file);
+                }
+            }
+        } else {
+            for path in [self.fp_path(), self.imatrix_path()] {
+                if path.parent() == Some(model_dir.as_path()) && is_non_empty(&path).await {
+                    enqueue(hf::repo_file(path));
+                }
+            }
+            for path in mmproj {
+                enqueue(hf::repo_file(path));
+            }
+            for path in self.convert_loras().await? {
+                enqueue(hf::repo_file(path));
+            }
+            for q in &self.quants {
+                if self.quantize_decision(q).await == Decision::Done {
+                    self.skipped(
+                        Stage::Quantize(q.clone()),
+                        format!(
+                            "🪄 {} already quantized to {}, skipping.",
+                            self.model_name,
+                            q.to_string().to_uppercase()
+                        ),
+                    );
+                    // NOTE: finishes a split a previous run was interrupted during
+                    for file in self.split_quant(q.clone()).await?.files() {
+                        enqueue(hf::repo_file(file));
+                    }
+                    continue;
+                }
+                let quantized = match self.quantize(q.clone()).await {
+                    Ok(quantized) => quantized,
+                    Err(e) if self.ctx.is_interrupted() => return Err(e),
+                    // NOTE: one quant failing, e.g. an IQ type the installed llama.cpp can't make,
+                    // shouldn't cost the rest; they're all reported once the others are uploaded
+                    Err(e) => {
+                        let level = q.to_string().to_uppercase();
+                        self.ctx
+                            .warn(format!("💥 {level} failed, carrying on with the rest: {e}"));
+                        failed.push(format!("{level}: {e}"));
+                        continue;
+                    }
+                };
+                if !state.has_quant(q) {
+                    state.quants.push(q.clone());
+                }
+                state.save(&model_dir).await?;
+                for file in quantized.files() {
+                    enqueue(hf::repo_file(file));
+                }
+                report.quants.push(quantized);
+            }
+            if self.evaluates() {
+                match self.evaluate().await {
+                    Ok(results) => report.eval = Some(results),
+                    // NOTE: the quants are fine either way, so don't hold up their upload
+                    Err(e) => self.ctx.warn(format!("📏 evaluation failed: {e}")),
+                }
+            }
+            if let Some(level) = &self.llamafile {
+                match self.package_llamafile(level).await {
+                    Ok(Some(path)) => enqueue(hf::repo_file(path)),
+                    Ok(None) => {}
+                    // NOTE: like an evaluation, it's an extra the quants shouldn't wait on
+                    Err(e) => self
+                        .ctx
+                        .warn(format!("📦 packaging the llamafile failed: {e}")),
+                }
+            }
+            if self.lmstudio_manifest {
+                self.write_lmstudio_manifest().await?;
+            }
+        }
+
+        drop(upload_tx);
+        if let Some(handle) = upload_handle {
+            let repo_id = handle.await?.map_err(|e| {
+                let error = format!("upload failed: {e}").into();
+                match self.ctx.is_interrupted() {
+                    true => Error::Interrupted(error),
+                    false => Error::Upload(error),
+                }
+            })?;
+            report.uploaded_to = Some(repo_id);
+            if self.cleanup.iter().any(|c| Cleanup::UPLOADED.contains(c)) {
+                self.delete_uploaded().await?;
+            }
+        }
+
+        if !failed.is_empty() {
+            return Err(Error::Quantize(
+                format!(
+                    "{} of {} quants failed: {}",
+                    failed.len(),
+                    self.quants.len(),
+                    failed.join("; ")
+                )
+                .into(),
+            )
+            .into());
+        }
+        Ok(report)
+    }
+}
+
+async fn is_non_empty(path: &Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|m| m.is_file() && m.len() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn an_existing_quant_is_skipped_unless_forced() {
+        let dir = std::env::temp_dir().join(format!("autogguf-{}-quantized", std::process::id()));
+        let pipeline = |force| {
+            Pipeline::builder("org/Model")
+                .output_dir(&dir.to_string_lossy())
+                .quants([QuantLevel::Q8_0])
+                .force(force)
+                .build()
+        };
+        let path = pipeline(false).quant_path(&QuantLevel::Q8_0);
+        tokio::fs::create_dir_all(path.parent().unwrap())
+            .await
+            .unwrap();
+
+        tokio::fs::write(&path, b"").await.unwrap();
+        let empty = pipeline(false).quantize_decision(&QuantLevel::Q8_0).await;
+        tokio::fs::write(&path, b"GGUF").await.unwrap();
+        let existing = pipeline(false).quantize_decision(&QuantLevel::Q8_0).await;
+        let forced = pipeline(true).quantize_decision(&QuantLevel::Q8_0).await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        assert_eq!(empty, Decision::Run);
+        assert_eq!(existing, Decision::Done);
+        assert_eq!(forced, Decision::Run);
+    }
+}
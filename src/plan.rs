@@ -0,0 +1,148 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+/// Whether a stage will run, and if not, why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Run,
+    /// Skipped by a flag or option.
+    Skip,
+    /// Already finished by a previous run.
+    Done,
+}
+
+/// One stage of a [`Plan`].
+#[derive(Debug, Clone)]
+pub struct PlannedStage {
+    pub name: String,
+    pub decision: Decision,
+    /// The subprocess command line, for stages that shell out.
+    pub command: Option<String>,
+    /// Free-form detail, e.g. the source or destination of a transfer.
+    pub detail: Option<String>,
+    pub output: Option<PathBuf>,
+    pub estimated_bytes: Option<u64>,
+}
+
+impl PlannedStage {
+    pub(crate) fn new(name: impl Into<String>, decision: Decision) -> Self {
+        Self {
+            name: name.into(),
+            decision,
+            command: None,
+            detail: None,
+            output: None,
+            estimated_bytes: None,
+        }
+    }
+
+    pub(crate) fn command(mut self, command: &tokio::process::Command) -> Self {
+        self.command = Some(format!("{:?}", command.as_std()));
+        self
+    }
+
+    pub(crate) fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub(crate) fn output(mut self, output: PathBuf, estimated_bytes: Option<u64>) -> Self {
+        self.output = Some(output);
+        self.estimated_bytes = estimated_bytes;
+        self
+    }
+}
+
+/// Everything a pipeline run would do, resolved without executing anything.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub model_id: String,
+    /// Where the source model, full-precision GGUF and imatrix go.
+    pub model_dir: PathBuf,
+    /// Where the quants go; the same as `model_dir` unless a work directory was set.
+    pub output_dir: PathBuf,
+    pub parameters: Option<u64>,
+    pub stages: Vec<PlannedStage>,
+}
+
+impl Plan {
+    /// Estimated bytes written by the stages that will run.
+    pub fn estimated_bytes(&self) -> u64 {
+        self.stages
+            .iter()
+            .filter(|s| s.decision == Decision::Run)
+            .filter_map(|s| s.estimated_bytes)
+            .sum()
+    }
+
+    /// Estimated bytes written into `dir` by the stages that will run.
+    pub fn estimated_bytes_in(&self, dir: &Path) -> u64 {
+        self.stages
+            .iter()
+            .filter(|s| s.decision == Decision::Run)
+            .filter(|s| {
+                s.output
+                    .as_ref()
+                    .is_some_and(|output| output.starts_with(dir))
+            })
+            .filter_map(|s| s.estimated_bytes)
+            .sum()
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "📋 plan for {}", self.model_id)?;
+        match self.model_dir == self.output_dir {
+            true => writeln!(f, "   model dir: {}", self.model_dir.display())?,
+            false => {
+                writeln!(f, "   work dir: {}", self.model_dir.display())?;
+                writeln!(f, "   output dir: {}", self.output_dir.display())?;
+            }
+        }
+        match self.parameters {
+            Some(n) => writeln!(f, "   parameters: {:.2}B", n as f64 / 1e9)?,
+            None => writeln!(f, "   parameters: unknown")?,
+        }
+        for stage in &self.stages {
+            let marker = match stage.decision {
+                Decision::Run => "▶️",
+                Decision::Skip => "⏭️ skip",
+                Decision::Done => "✅ done",
+            };
+            writeln!(f, "{marker} {}", stage.name)?;
+            if let Some(detail) = &stage.detail {
+                writeln!(f, "   {detail}")?;
+            }
+            if let Some(command) = &stage.command {
+                writeln!(f, "   $ {command}")?;
+            }
+            if let Some(output) = &stage.output {
+                match stage.estimated_bytes {
+                    Some(bytes) => {
+                        writeln!(f, "   → {} (~{})", output.display(), human_bytes(bytes))?
+                    }
+                    None => writeln!(f, "   → {}", output.display())?,
+                }
+            }
+        }
+        write!(
+            f,
+            "total estimated output: ~{}",
+            human_bytes(self.estimated_bytes())
+        )
+    }
+}
+
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
@@ -0,0 +1,485 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt::Display, str::FromStr};
+
+/// The quant levels converted when none are requested: all non-imatrix quants.
+pub const DEFAULT_QUANTS: &str =
+    "q2_k,q3_k_s,q3_k_m,q3_k_l,q4_0,q4_1,q4_k_s,q4_k_m,q5_0,q5_1,q5_k_s,q5_k_m,q6_k,q8_0";
+
+/// The full-precision GGUF format converted to and quantized from.
+#[derive(Debug, Clone, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    F16,
+    BF16,
+    F32,
+}
+
+impl Display for Precision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            Precision::F16 => "f16",
+            Precision::BF16 => "bf16",
+            Precision::F32 => "f32",
+        };
+        write!(f, "{label}")
+    }
+}
+
+macro_rules! quant_level_enum {
+    ($($variant:ident => $str:expr),* $(,)?) => {
+        /// A `llama-quantize` output type.
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub enum QuantLevel {
+            $($variant),*
+        }
+
+        impl QuantLevel {
+            /// Every level, in declaration order.
+            pub const ALL: &'static [QuantLevel] = &[$(QuantLevel::$variant),*];
+        }
+
+        impl FromStr for QuantLevel {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s.to_lowercase().as_str() {
+                    $($str => Ok(QuantLevel::$variant),)*
+                    _ => Err(format!("'{s}' is not a valid quant level")),
+                }
+            }
+        }
+
+        impl Display for QuantLevel {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let label = match self {
+                    $(QuantLevel::$variant => $str,)*
+                };
+                write!(f, "{label}")
+            }
+        }
+
+        impl Serialize for QuantLevel {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for QuantLevel {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer)?
+                    .parse()
+                    .map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+quant_level_enum! {
+    Q2K => "q2_k",
+    Q3KS => "q3_k_s",
+    Q3KM => "q3_k_m",
+    Q3KL => "q3_k_l",
+    Q4_0 => "q4_0",
+    Q4_1 => "q4_1",
+    Q4KS => "q4_k_s",
+    Q4KM => "q4_k_m",
+    Q5_0 => "q5_0",
+    Q5_1 => "q5_1",
+    Q5KS => "q5_k_s",
+    Q5KM => "q5_k_m",
+    Q6K => "q6_k",
+    Q8_0 => "q8_0",
+    BF16 => "bf16",
+    IQ1S => "iq1_s",
+    IQ1M => "iq1_m",
+    IQ2XXS => "iq2_xxs",
+    IQ2XS => "iq2_xs",
+    IQ2S => "iq2_s",
+    IQ2M => "iq2_m",
+    Q2KS => "q2_k_s",
+    IQ3XXS => "iq3_xxs",
+    IQ3XS => "iq3_xs",
+    IQ3S => "iq3_s",
+    IQ3M => "iq3_m",
+    IQ4XS => "iq4_xs",
+    IQ4NL => "iq4_nl",
+    TQ1_0 => "tq1_0",
+    TQ2_0 => "tq2_0",
+    MXFP4MOE => "mxfp4_moe",
+    F16 => "f16",
+    F32 => "f32",
+    Copy => "copy",
+}
+
+impl Precision {
+    pub fn bits_per_weight(&self) -> f64 {
+        match self {
+            Precision::F16 | Precision::BF16 => 16.0,
+            Precision::F32 => 32.0,
+        }
+    }
+}
+
+impl QuantLevel {
+    /// Approximate effective bits per weight, including the higher-precision tensors k-quant
+    /// mixes keep, as reported by `llama-quantize --help` for Llama-3-8B.
+    pub fn bits_per_weight(&self) -> f64 {
+        match self {
+            QuantLevel::Q2K => 3.17,
+            QuantLevel::Q3KS => 3.65,
+            QuantLevel::Q3KM => 4.00,
+            QuantLevel::Q3KL => 4.31,
+            QuantLevel::Q4_0 => 4.64,
+            QuantLevel::Q4_1 => 5.11,
+            QuantLevel::Q4KS => 4.67,
+            QuantLevel::Q4KM => 4.90,
+            QuantLevel::Q5_0 => 5.57,
+            QuantLevel::Q5_1 => 6.04,
+            QuantLevel::Q5KS => 5.57,
+            QuantLevel::Q5KM => 5.70,
+            QuantLevel::Q6K => 6.57,
+            QuantLevel::Q8_0 => 8.51,
+            QuantLevel::BF16 => 16.0,
+            QuantLevel::IQ1S => 1.56,
+            QuantLevel::IQ1M => 1.75,
+            QuantLevel::IQ2XXS => 2.06,
+            QuantLevel::IQ2XS => 2.31,
+            QuantLevel::IQ2S => 2.5,
+            QuantLevel::IQ2M => 2.7,
+            QuantLevel::Q2KS => 3.17,
+            QuantLevel::IQ3XXS => 3.06,
+            QuantLevel::IQ3XS => 3.3,
+            QuantLevel::IQ3S => 3.44,
+            QuantLevel::IQ3M => 3.66,
+            QuantLevel::IQ4XS => 4.25,
+            QuantLevel::IQ4NL => 4.5,
+            QuantLevel::TQ1_0 => 1.69,
+            QuantLevel::TQ2_0 => 2.06,
+            QuantLevel::MXFP4MOE => 4.25,
+            QuantLevel::F16 => 16.0,
+            QuantLevel::F32 => 32.0,
+            // NOTE: tensors are copied as-is, so assume the usual 16-bit source
+            QuantLevel::Copy => 16.0,
+        }
+    }
+
+    /// Whether this level leaves weights at full precision: the float types and `copy`.
+    pub fn is_unquantized(&self) -> bool {
+        matches!(
+            self,
+            QuantLevel::BF16 | QuantLevel::F16 | QuantLevel::F32 | QuantLevel::Copy
+        )
+    }
+
+    /// Whether `llama-quantize` needs an importance matrix to produce this level.
+    pub fn requires_imatrix(&self) -> bool {
+        matches!(
+            self,
+            QuantLevel::IQ1S
+                | QuantLevel::IQ1M
+                | QuantLevel::IQ2XXS
+                | QuantLevel::IQ2XS
+                | QuantLevel::IQ2S
+                | QuantLevel::IQ2M
+                | QuantLevel::Q2KS
+                | QuantLevel::IQ3XXS
+                | QuantLevel::IQ3XS
+                | QuantLevel::IQ3S
+                | QuantLevel::IQ3M
+                | QuantLevel::IQ4XS
+                | QuantLevel::IQ4NL
+        )
+    }
+}
+
+/// A named, curated list of quant levels, usable anywhere a quant level is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum QuantPreset {
+    /// Every quant level (but not the float and copy-through types, which aren't quants).
+    All,
+    /// Every level that needs an importance matrix.
+    Imatrix,
+    /// The handful most people download.
+    Common,
+    /// The spread bartowski publishes, from IQ2_XXS up to Q8_0.
+    Bartowski,
+}
+
+impl QuantPreset {
+    pub fn levels(&self) -> Vec<QuantLevel> {
+        match self {
+            QuantPreset::All => QuantLevel::ALL
+                .iter()
+                .filter(|q| !q.is_unquantized())
+                .cloned()
+                .collect(),
+            QuantPreset::Imatrix => QuantLevel::ALL
+                .iter()
+                .filter(|q| q.requires_imatrix())
+                .cloned()
+                .collect(),
+            QuantPreset::Common => level_list("q3_k_m,q4_k_m,q5_k_m,q6_k,q8_0"),
+            QuantPreset::Bartowski => level_list(
+                "iq2_xxs,iq2_xs,iq2_s,iq2_m,q2_k,iq3_xxs,iq3_xs,q3_k_s,iq3_m,q3_k_m,q3_k_l,iq4_xs,\
+                 iq4_nl,q4_0,q4_k_s,q4_k_m,q5_k_s,q5_k_m,q6_k,q8_0",
+            ),
+        }
+    }
+}
+
+fn level_list(names: &str) -> Vec<QuantLevel> {
+    names
+        .split(',')
+        .map(|q| q.parse().expect("preset quants are valid"))
+        .collect()
+}
+
+/// One entry in a list of quants: a level, or a preset standing for several.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuantSpec {
+    Level(QuantLevel),
+    Preset(QuantPreset),
+}
+
+impl FromStr for QuantSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(preset) = QuantPreset::from_str(s, true) {
+            return Ok(QuantSpec::Preset(preset));
+        }
+        s.parse().map(QuantSpec::Level).map_err(|_| {
+            let presets: Vec<_> = QuantPreset::value_variants()
+                .iter()
+                .filter_map(|p| p.to_possible_value())
+                .map(|p| p.get_name().to_string())
+                .collect();
+            format!(
+                "'{s}' is not a valid quant level or preset ({})",
+                presets.join(", ")
+            )
+        })
+    }
+}
+
+impl QuantSpec {
+    /// Levels that more than one of `specs` stands for, e.g. `q4_k_m` alongside `common`.
+    pub fn duplicates(specs: &[QuantSpec]) -> Vec<QuantLevel> {
+        let mut seen = vec![];
+        let mut duplicates = vec![];
+        for spec in specs {
+            for level in QuantSpec::expand(std::slice::from_ref(spec)) {
+                if seen.contains(&level) {
+                    if !duplicates.contains(&level) {
+                        duplicates.push(level);
+                    }
+                } else {
+                    seen.push(level);
+                }
+            }
+        }
+        duplicates
+    }
+
+    /// The levels `specs` stand for, presets expanded, in order and without duplicates.
+    pub fn expand(specs: &[QuantSpec]) -> Vec<QuantLevel> {
+        let mut levels = vec![];
+        for spec in specs {
+            let expanded = match spec {
+                QuantSpec::Level(level) => vec![level.clone()],
+                QuantSpec::Preset(preset) => preset.levels(),
+            };
+            for level in expanded {
+                if !levels.contains(&level) {
+                    levels.push(level);
+                }
+            }
+        }
+        levels
+    }
+}
+
+/// A ggml type to force for one kind of tensor, for one quant level or, without a level, all of
+/// them. Parses from `q8_0` or `q4_k_m=q8_0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TensorTypeOverride {
+    pub level: Option<QuantLevel>,
+    /// A ggml type name like `q8_0` or `f16`, as `llama-quantize` takes it.
+    pub ggml_type: String,
+}
+
+impl FromStr for TensorTypeOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (level, ggml_type) = match s.split_once('=') {
+            Some((level, ggml_type)) => (Some(level.parse()?), ggml_type),
+            None => (None, s),
+        };
+        if ggml_type.is_empty()
+            || !ggml_type
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(format!("'{ggml_type}' is not a valid ggml type"));
+        }
+        Ok(Self {
+            level,
+            ggml_type: ggml_type.to_lowercase(),
+        })
+    }
+}
+
+impl TensorTypeOverride {
+    /// The type `overrides` set for `level`: its own override if it has one, else the global one.
+    pub fn resolve<'a>(overrides: &'a [TensorTypeOverride], level: &QuantLevel) -> Option<&'a str> {
+        overrides
+            .iter()
+            .rev()
+            .find(|o| o.level.as_ref() == Some(level))
+            .or_else(|| overrides.iter().rev().find(|o| o.level.is_none()))
+            .map(|o| o.ggml_type.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specs(list: &str) -> Vec<QuantSpec> {
+        list.split(',').map(|s| s.parse().unwrap()).collect()
+    }
+
+    fn names(levels: &[QuantLevel]) -> Vec<String> {
+        levels.iter().map(QuantLevel::to_string).collect()
+    }
+
+    #[test]
+    fn expand_keeps_the_order_presets_list_levels_in() {
+        assert_eq!(
+            names(&QuantSpec::expand(&specs("q2_k,common,iq4_xs"))),
+            ["q2_k", "q3_k_m", "q4_k_m", "q5_k_m", "q6_k", "q8_0", "iq4_xs"]
+        );
+        assert_eq!(
+            QuantSpec::expand(&specs("bartowski")),
+            QuantPreset::Bartowski.levels()
+        );
+    }
+
+    #[test]
+    fn expand_drops_duplicates_keeping_the_first() {
+        assert_eq!(
+            names(&QuantSpec::expand(&specs("q8_0,common,q4_k_m,q8_0"))),
+            ["q8_0", "q3_k_m", "q4_k_m", "q5_k_m", "q6_k"]
+        );
+    }
+
+    #[test]
+    fn duplicates_names_levels_given_twice() {
+        assert_eq!(
+            QuantSpec::duplicates(&specs("q4_k_m,common")),
+            [QuantLevel::Q4KM]
+        );
+        assert_eq!(
+            QuantSpec::duplicates(&specs("q8_0,q8_0,common,common")),
+            [
+                QuantLevel::Q8_0,
+                QuantLevel::Q3KM,
+                QuantLevel::Q4KM,
+                QuantLevel::Q5KM,
+                QuantLevel::Q6K
+            ]
+        );
+        assert!(QuantSpec::duplicates(&specs("q2_k,common")).is_empty());
+    }
+
+    #[test]
+    fn presets_cover_their_levels() {
+        let all = QuantPreset::All.levels();
+        assert!(all.contains(&QuantLevel::IQ1S) && !all.contains(&QuantLevel::BF16));
+        assert!(QuantPreset::Imatrix
+            .levels()
+            .iter()
+            .all(QuantLevel::requires_imatrix));
+        assert_eq!(QuantPreset::Bartowski.levels().len(), 20);
+    }
+
+    #[test]
+    fn spec_parses_levels_and_presets_case_insensitively() {
+        assert_eq!(
+            "Q4_K_M".parse::<QuantSpec>(),
+            Ok(QuantSpec::Level(QuantLevel::Q4KM))
+        );
+        assert_eq!(
+            "Common".parse::<QuantSpec>(),
+            Ok(QuantSpec::Preset(QuantPreset::Common))
+        );
+    }
+
+    #[test]
+    fn a_bad_spec_lists_the_presets() {
+        assert_eq!(
+            "q4_k_x".parse::<QuantSpec>(),
+            Err(
+                "'q4_k_x' is not a valid quant level or preset (all, imatrix, common, bartowski)"
+                    .to_string()
+            )
+        );
+    }
+
+    fn overrides(list: &[&str]) -> Vec<TensorTypeOverride> {
+        list.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn tensor_type_override_parses_global_and_per_level() {
+        assert_eq!(
+            "Q8_0".parse::<TensorTypeOverride>(),
+            Ok(TensorTypeOverride {
+                level: None,
+                ggml_type: "q8_0".to_string()
+            })
+        );
+        assert_eq!(
+            "q4_k_m=f16".parse::<TensorTypeOverride>(),
+            Ok(TensorTypeOverride {
+                level: Some(QuantLevel::Q4KM),
+                ggml_type: "f16".to_string()
+            })
+        );
+        assert!("q4_k_m=".parse::<TensorTypeOverride>().is_err());
+        assert!("q9_k=q8_0".parse::<TensorTypeOverride>().is_err());
+        assert!("q8_0;rm".parse::<TensorTypeOverride>().is_err());
+    }
+
+    #[test]
+    fn a_per_level_override_beats_the_global_one() {
+        let overrides = overrides(&["q4_k_m=f16", "q8_0"]);
+        assert_eq!(
+            TensorTypeOverride::resolve(&overrides, &QuantLevel::Q4KM),
+            Some("f16")
+        );
+        assert_eq!(
+            TensorTypeOverride::resolve(&overrides, &QuantLevel::Q6K),
+            Some("q8_0")
+        );
+        assert_eq!(
+            TensorTypeOverride::resolve(&overrides[..1], &QuantLevel::Q6K),
+            None
+        );
+    }
+
+    #[test]
+    fn the_last_override_wins() {
+        let overrides = overrides(&["q6_k", "q4_k_m=q5_k", "q8_0", "q4_k_m=f16"]);
+        assert_eq!(
+            TensorTypeOverride::resolve(&overrides, &QuantLevel::Q4KM),
+            Some("f16")
+        );
+        assert_eq!(
+            TensorTypeOverride::resolve(&overrides, &QuantLevel::Q2K),
+            Some("q8_0")
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Retrying work that failed for reasons that tend to go away by themselves: dropped
+//! connections, timeouts, rate limiting and server errors.
+
+use crate::{context::Context, event::Stage, hub::is_transient};
+use std::{
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{select, time::sleep};
+
+/// How many times a transient failure is retried before its stage gives up, unless configured
+/// otherwise.
+pub(crate) const DEFAULT_RETRIES: u32 = 5;
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+
+/// Exponential backoff before retry `attempt`, capped at [`MAX_BACKOFF`], plus up to a second of
+/// jitter so parallel runs don't retry in lockstep.
+fn backoff(attempt: u32) -> Duration {
+    let jitter = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_millis();
+    Duration::from_secs(2u64.saturating_pow(attempt)).min(MAX_BACKOFF)
+        + Duration::from_millis(jitter.into())
+}
+
+/// Run `attempt` until it succeeds, retrying [transient](is_transient) failures with [`backoff`]
+/// up to `ctx.retries` times. `action` describes it in warnings and `stage`'s log, e.g.
+/// "upload model.gguf".
+pub(crate) async fn with_retries<T, F>(
+    stage: &Stage,
+    action: &str,
+    ctx: &Context,
+    mut attempt: impl FnMut() -> F,
+) -> Result<T, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: Future<Output = Result<T, Box<dyn std::error::Error + Send + Sync>>>,
+{
+    let attempts = ctx.retries + 1;
+    let mut n = 1;
+    loop {
+        match attempt().await {
+            Ok(result) => return Ok(result),
+            Err(e) if n < attempts && is_transient(e.as_ref()) => {
+                let delay = backoff(n);
+                ctx.warn(format!(
+                    "🔁 failed to {action} (attempt {n}/{attempts}), retrying in {}s: {e}",
+                    delay.as_secs()
+                ));
+                ctx.log(stage, &format!("# attempt {n} to {action} failed: {e}"))
+                    .await;
+                select! {
+                    _ = sleep(delay) => {}
+                    _ = ctx.cancel.notified() => {
+                        ctx.mark_interrupted();
+                        return Err(format!("{action} cancelled due to interrupt").into());
+                    }
+                }
+                n += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hub::HubError;
+    use reqwest::StatusCode;
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+    use tokio::sync::Notify;
+
+    fn context(retries: u32) -> Context {
+        Context {
+            verbose: false,
+            cancel: Arc::new(Notify::new()),
+            interrupted: Default::default(),
+            events: None,
+            log_dir: None,
+            summary: Default::default(),
+            retries,
+            timeouts: Default::default(),
+        }
+    }
+
+    /// Run `with_retries` on an attempt that always fails with `error`, returning how many times
+    /// it was tried.
+    async fn attempts_until_failure(
+        retries: u32,
+        error: fn() -> Box<dyn std::error::Error + Send + Sync>,
+    ) -> u32 {
+        let tries = AtomicU32::new(0);
+        let result: Result<(), _> =
+            with_retries(&Stage::Upload, "upload", &context(retries), || {
+                tries.fetch_add(1, Ordering::SeqCst);
+                async move { Err(error()) }
+            })
+            .await;
+        assert!(result.is_err());
+        tries.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transient_failures_are_tried_retries_plus_one_times() {
+        let unavailable = || HubError::new(StatusCode::SERVICE_UNAVAILABLE, "down").into();
+        assert_eq!(attempts_until_failure(3, unavailable).await, 4);
+        assert_eq!(attempts_until_failure(0, unavailable).await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn permanent_failures_are_not_retried() {
+        let not_found = || HubError::new(StatusCode::NOT_FOUND, "missing").into();
+        assert_eq!(attempts_until_failure(3, not_found).await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_success_after_a_transient_failure_is_returned() {
+        let tries = AtomicU32::new(0);
+        let result = with_retries(&Stage::Upload, "upload", &context(3), || {
+            let n = tries.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match n {
+                    0 => Err(HubError::new(StatusCode::BAD_GATEWAY, "blip").into()),
+                    _ => Ok(n),
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 1);
+    }
+}
@@ -0,0 +1,88 @@
+//! Keeping two runs off the same model directory, where they'd overwrite each other's outputs.
+
+use fs4::fs_std::FileExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{Read, Seek, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The file a run locks in the model directory. It's left behind afterwards, since deleting it
+/// could let two later runs each lock a different one.
+pub const RUN_LOCK_FILE: &str = ".autogguf.lock";
+
+/// Who holds the lock, written into [`RUN_LOCK_FILE`] for the error the next run gets.
+#[derive(Debug, Deserialize, Serialize)]
+struct Holder {
+    pid: u32,
+    /// When the run started, in seconds since the Unix epoch.
+    started: u64,
+}
+
+/// An exclusive advisory lock on a model directory, released when dropped or when the process
+/// exits, however it exits.
+#[derive(Debug)]
+pub(crate) struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Lock `model_dir`, or fail naming the run that already has. With `force`, a lock another
+    /// run holds is broken instead, for a run that's hung or a filesystem that kept a crashed
+    /// run's lock.
+    pub fn acquire(model_dir: &Path, force: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = model_dir.join(RUN_LOCK_FILE);
+        if force {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if !file.try_lock_exclusive()? {
+            let holder = match holder(&mut file) {
+                Some(Holder { pid, started }) => {
+                    let minutes = now().saturating_sub(started) / 60;
+                    format!("another autogguf run (pid {pid}, started {minutes} minutes ago)")
+                }
+                None => "another autogguf run".to_string(),
+            };
+            return Err(format!(
+                "{} is locked by {holder}; wait for it to finish, or pass --force-unlock if it's \
+                stuck",
+                model_dir.display()
+            )
+            .into());
+        }
+        let holder = Holder {
+            pid: std::process::id(),
+            started: now(),
+        };
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(serde_json::to_string(&holder)?.as_bytes())?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// The run holding `file`'s lock, if it says.
+fn holder(file: &mut File) -> Option<Holder> {
+    let mut contents = String::new();
+    // NOTE: Windows locks are mandatory, so the holder can't be read there
+    file.read_to_string(&mut contents).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,382 @@
+//! `autogguf serve`: a long-lived worker that converts the models queued through a small HTTP
+//! API one at a time, every job running with the flags the server was started with.
+//!
+//! - `POST /jobs` with `{"model_id": "..."}` queues a model, returning its job.
+//! - `GET /jobs` lists every job, and `GET /jobs/{id}` returns one, with each stage's progress.
+//! - `DELETE /jobs/{id}` cancels a job, whether it's still queued or already running.
+
+use autogguf::{Event, Notification, PipelineBuilder, Stage, Webhook};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{
+    mpsc::{self, UnboundedSender},
+    Notify,
+};
+
+/// Status messages and subprocess output kept per job.
+const LOG_LINES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum StageStatus {
+    Pending,
+    Running,
+    Done,
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct StageProgress {
+    stage: Stage,
+    status: StageStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Job {
+    id: u64,
+    model_id: String,
+    status: JobStatus,
+    /// Filled in once the job starts, when its pipeline knows what stages it'll run.
+    stages: Vec<StageProgress>,
+    /// The latest messages and subprocess output, oldest first.
+    log: VecDeque<String>,
+    error: Option<String>,
+    /// Unix timestamps, in seconds.
+    queued_at: u64,
+    started_at: Option<u64>,
+    finished_at: Option<u64>,
+    #[serde(skip)]
+    cancel: Arc<Notify>,
+    #[serde(skip)]
+    cancel_requested: bool,
+}
+
+impl Job {
+    fn push_log(&mut self, line: String) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+
+    fn stage(&mut self, stage: &Stage) -> &mut StageProgress {
+        match self.stages.iter().position(|s| &s.stage == stage) {
+            Some(i) => &mut self.stages[i],
+            None => {
+                self.stages.push(StageProgress {
+                    stage: stage.clone(),
+                    status: StageStatus::Pending,
+                });
+                self.stages.last_mut().unwrap()
+            }
+        }
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::StageStarted { stage } => self.stage(&stage).status = StageStatus::Running,
+            Event::StageFinished { stage, .. } => self.stage(&stage).status = StageStatus::Done,
+            Event::StageSkipped { stage, .. } => self.stage(&stage).status = StageStatus::Skipped,
+            Event::StageFailed { stage, error } => {
+                self.stage(&stage).status = StageStatus::Failed;
+                self.push_log(format!("❌ {stage}: {error}"));
+            }
+            Event::Uploaded { repo_id, files } => {
+                self.push_log(format!("🤗 uploaded {} file(s) to {repo_id}", files.len()))
+            }
+            Event::Pinned { cids } => {
+                self.push_log(format!("📌 pinned {} file(s) to IPFS", cids.len()))
+            }
+            Event::Output { stage, line } => self.push_log(format!("[{stage}] {line}")),
+            Event::Message { text } => self.push_log(text),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Jobs {
+    next_id: u64,
+    jobs: BTreeMap<u64, Job>,
+}
+
+#[derive(Debug, Clone)]
+struct AppState {
+    jobs: Arc<Mutex<Jobs>>,
+    queue: UnboundedSender<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Enqueue {
+    model_id: String,
+}
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn api_error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (status, Json(serde_json::json!({ "error": message.into() })))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Serve the API on `listen` and work through its queue until `shutdown` is notified, which
+/// also cancels the running job. `build` makes each job's pipeline for its model ID, cancelled
+/// through the given [`Notify`]. Each job's milestones are also sent to `webhook`, if given.
+pub async fn serve(
+    listen: SocketAddr,
+    build: impl AsyncFn(&str, &Arc<Notify>) -> Result<PipelineBuilder, Box<dyn std::error::Error>>,
+    webhook: Option<Webhook>,
+    shutdown: Arc<Notify>,
+    interrupted: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (queue, mut queued) = mpsc::unbounded_channel();
+    let state = AppState {
+        jobs: Arc::new(Mutex::new(Jobs::default())),
+        queue,
+    };
+    let app = Router::new()
+        .route("/jobs", get(list_jobs).post(enqueue))
+        .route("/jobs/{id}", get(get_job).delete(cancel_job))
+        .with_state(state.clone());
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| format!("couldn't listen on {listen}: {e}"))?;
+    tracing::info!("🛰️ listening on http://{}", listener.local_addr()?);
+    let stopped = shutdown.clone();
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async move { stopped.notified().await })
+            .await
+    });
+
+    let jobs = state.jobs.clone();
+    let stopping = shutdown.clone();
+    tokio::spawn(async move {
+        stopping.notified().await;
+        let jobs = jobs.lock().unwrap();
+        for job in jobs.jobs.values() {
+            if job.status == JobStatus::Running {
+                job.cancel.notify_waiters();
+            }
+        }
+    });
+
+    loop {
+        let id = tokio::select! {
+            id = queued.recv() => id,
+            _ = shutdown.notified() => None,
+        };
+        let Some(id) = id else { break };
+        run_job(id, &state.jobs, &build, webhook.as_ref()).await;
+        if interrupted.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+    server.await??;
+    Ok(())
+}
+
+/// Run job `id`'s pipeline to the end, recording its progress as it goes, unless it was
+/// cancelled while queued.
+async fn run_job(
+    id: u64,
+    jobs: &Arc<Mutex<Jobs>>,
+    build: &impl AsyncFn(&str, &Arc<Notify>) -> Result<PipelineBuilder, Box<dyn std::error::Error>>,
+    webhook: Option<&Webhook>,
+) {
+    let (model_id, cancel) = {
+        let mut jobs = jobs.lock().unwrap();
+        let Some(job) = jobs
+            .jobs
+            .get_mut(&id)
+            .filter(|j| j.status == JobStatus::Queued)
+        else {
+            return;
+        };
+        job.status = JobStatus::Running;
+        job.started_at = Some(now());
+        (job.model_id.clone(), job.cancel.clone())
+    };
+    tracing::info!("🚀 job {id}: converting {model_id}");
+    if let Some(webhook) = webhook {
+        let model_id = model_id.clone();
+        webhook.send(&Notification::RunStarted { model_id }).await;
+    }
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let result = match build(&model_id, &cancel).await {
+        Ok(pipeline) => {
+            let pipeline = pipeline.events(events_tx).build();
+            if let Some(job) = jobs.lock().unwrap().jobs.get_mut(&id) {
+                job.stages = pipeline
+                    .stages()
+                    .into_iter()
+                    .map(|stage| StageProgress {
+                        stage,
+                        status: StageStatus::Pending,
+                    })
+                    .collect();
+            }
+            let run = async move {
+                let result = pipeline.run().await.map_err(|e| e.to_string());
+                // NOTE: hanging up the event channel is what ends the progress loop
+                drop(pipeline);
+                result
+            };
+            let progress = async {
+                while let Some(event) = events.recv().await {
+                    let notification = Notification::from_event(&model_id, &event);
+                    if let Some(job) = jobs.lock().unwrap().jobs.get_mut(&id) {
+                        job.handle(event);
+                    }
+                    if let (Some(webhook), Some(notification)) = (webhook, notification) {
+                        webhook.send(&notification).await;
+                    }
+                }
+            };
+            tokio::join!(run, progress).0
+        }
+        Err(e) => Err(e.to_string()),
+    };
+
+    if let Some(webhook) = webhook {
+        let notification = match &result {
+            Ok(_) => Notification::RunFinished {
+                model_id: model_id.clone(),
+            },
+            Err(error) => Notification::RunFailed {
+                model_id: model_id.clone(),
+                error: error.clone(),
+            },
+        };
+        webhook.send(&notification).await;
+    }
+    let mut jobs = jobs.lock().unwrap();
+    let Some(job) = jobs.jobs.get_mut(&id) else {
+        return;
+    };
+    job.finished_at = Some(now());
+    match result {
+        Ok(_) => {
+            job.status = JobStatus::Succeeded;
+            tracing::info!("🎉 job {id}: {model_id} done!");
+        }
+        Err(_) if job.cancel_requested => {
+            job.status = JobStatus::Cancelled;
+            tracing::info!("🛑 job {id}: {model_id} cancelled");
+        }
+        Err(e) => {
+            tracing::error!("💥 job {id}: {model_id} failed: {e}");
+            job.status = JobStatus::Failed;
+            job.error = Some(e);
+        }
+    }
+}
+
+async fn list_jobs(State(state): State<AppState>) -> Json<Vec<Job>> {
+    Json(state.jobs.lock().unwrap().jobs.values().cloned().collect())
+}
+
+async fn enqueue(
+    State(state): State<AppState>,
+    Json(request): Json<Enqueue>,
+) -> Result<(StatusCode, Json<Job>), ApiError> {
+    let model_id = crate::parse_repo_id(request.model_id.trim())
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, format!("bad model_id: {e}")))?;
+    let job = {
+        let mut jobs = state.jobs.lock().unwrap();
+        jobs.next_id += 1;
+        let job = Job {
+            id: jobs.next_id,
+            model_id: model_id.clone(),
+            status: JobStatus::Queued,
+            stages: vec![],
+            log: VecDeque::new(),
+            error: None,
+            queued_at: now(),
+            started_at: None,
+            finished_at: None,
+            cancel: Arc::new(Notify::new()),
+            cancel_requested: false,
+        };
+        jobs.jobs.insert(job.id, job.clone());
+        job
+    };
+    tracing::info!("📥 job {}: queued {model_id}", job.id);
+    state.queue.send(job.id).map_err(|_| {
+        api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "the server is shutting down",
+        )
+    })?;
+    Ok((StatusCode::CREATED, Json(job)))
+}
+
+async fn get_job(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<Json<Job>, ApiError> {
+    match state.jobs.lock().unwrap().jobs.get(&id) {
+        Some(job) => Ok(Json(job.clone())),
+        None => Err(api_error(StatusCode::NOT_FOUND, format!("no job {id}"))),
+    }
+}
+
+/// Cancel a queued job outright, or ask a running one's pipeline to stop, which it does by
+/// killing whatever subprocess it's waiting on.
+async fn cancel_job(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<Json<Job>, ApiError> {
+    let mut jobs = state.jobs.lock().unwrap();
+    let Some(job) = jobs.jobs.get_mut(&id) else {
+        return Err(api_error(StatusCode::NOT_FOUND, format!("no job {id}")));
+    };
+    match job.status {
+        JobStatus::Queued => {
+            job.status = JobStatus::Cancelled;
+            job.finished_at = Some(now());
+        }
+        JobStatus::Running => {
+            job.cancel_requested = true;
+            job.cancel.notify_waiters();
+            // NOTE: a permit for the next wait too, in case it's between subprocesses right now
+            job.cancel.notify_one();
+        }
+        _ => {
+            return Err(api_error(
+                StatusCode::CONFLICT,
+                format!("job {id} has already finished"),
+            ))
+        }
+    }
+    tracing::info!("🛑 job {id}: cancelling {}", job.model_id);
+    Ok(Json(job.clone()))
+}
@@ -0,0 +1,132 @@
+//! Detached signatures for the uploaded files, made with minisign or GPG, so downloaders can
+//! check who made them as well as that they're intact.
+
+use crate::hub::UploadFile;
+use shellexpand::tilde;
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tokio::process::Command;
+
+/// How uploads are signed.
+#[derive(Debug, Clone)]
+pub(crate) struct Signing {
+    pub key: SigningKey,
+    /// Sign every GGUF and imatrix too, not just the checksums that cover them.
+    pub all_files: bool,
+}
+
+/// What signs the checksums, and with `--sign-files`, every GGUF and imatrix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SigningKey {
+    /// A minisign secret key file, and its public key, if it's next to it as a `.pub`.
+    Minisign {
+        secret_key: PathBuf,
+        public_key: Option<String>,
+    },
+    /// A GPG key ID, fingerprint or email in the default keyring.
+    Gpg(String),
+}
+
+impl SigningKey {
+    /// A minisign secret key if `key` is a file (or ends in `.key`, minisign's default), else a
+    /// GPG key.
+    pub fn parse(key: &str) -> Self {
+        let path = PathBuf::from(tilde(key).into_owned());
+        if !key.ends_with(".key") && !path.is_file() {
+            return Self::Gpg(key.to_string());
+        }
+        // NOTE: a minisign .pub file is an untrusted comment line, then the key
+        let public_key = std::fs::read_to_string(path.with_extension("pub"))
+            .ok()
+            .and_then(|contents| contents.lines().nth(1).map(|key| key.trim().to_string()));
+        Self::Minisign {
+            secret_key: path,
+            public_key,
+        }
+    }
+
+    /// The signature's extension, appended to the signed file's name.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Minisign { .. } => "minisig",
+            Self::Gpg(_) => "asc",
+        }
+    }
+
+    /// How to check `file`'s signature, for the model card.
+    pub fn verify_command(&self, file: &str) -> String {
+        match self {
+            Self::Minisign {
+                public_key: Some(public_key),
+                ..
+            } => format!("minisign -Vm {file} -P {public_key}"),
+            Self::Minisign { .. } => format!("minisign -Vm {file} -p <public key file>"),
+            Self::Gpg(_) => format!("gpg --verify {file}.{} {file}", self.extension()),
+        }
+    }
+
+    fn command(&self, file: &Path, signature: &Path) -> Command {
+        match self {
+            Self::Minisign { secret_key, .. } => {
+                let mut command = Command::new("minisign");
+                command
+                    .arg("-S")
+                    .arg("-s")
+                    .arg(secret_key)
+                    .arg("-m")
+                    .arg(file)
+                    .arg("-x")
+                    .arg(signature);
+                command
+            }
+            Self::Gpg(key) => {
+                let mut command = Command::new("gpg");
+                command
+                    .args(["--batch", "--yes", "--armor", "--detach-sign"])
+                    .args(["--local-user", key])
+                    .arg("--output")
+                    .arg(signature)
+                    .arg(file);
+                command
+            }
+        }
+    }
+
+    /// Sign `file`, returning its signature to upload next to it in the repo.
+    pub async fn sign(
+        &self,
+        file: &UploadFile,
+    ) -> Result<UploadFile, Box<dyn std::error::Error + Send + Sync>> {
+        let mut signature = file.local_path.clone().into_os_string();
+        signature.push(format!(".{}", self.extension()));
+        let signature = PathBuf::from(signature);
+        // NOTE: nothing can answer a passphrase prompt mid-run, so minisign keys must be
+        // passwordless; GPG can still ask through gpg-agent's pinentry
+        let output = self
+            .command(&file.local_path, &signature)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| format!("couldn't run the signer for {}: {e}", file.path_in_repo))?;
+        if !output.status.success() {
+            let hint = match self {
+                Self::Minisign { .. } => {
+                    " (minisign keys must be passwordless, e.g. made with `minisign -G -W`)"
+                }
+                Self::Gpg(_) => "",
+            };
+            return Err(format!(
+                "signing {} failed{hint}: {}",
+                file.path_in_repo,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(UploadFile {
+            local_path: signature,
+            path_in_repo: format!("{}.{}", file.path_in_repo, self.extension()),
+        })
+    }
+}
@@ -0,0 +1,137 @@
+use crate::QuantLevel;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const STATE_FILE: &str = ".autogguf-state.json";
+
+/// Which stages of a pipeline have finished, persisted in the model directory so an interrupted
+/// run can pick up where it left off.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PipelineState {
+    pub downloaded: bool,
+    /// The revision asked for when downloading, if not the default branch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    /// The commit the download resolved to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// The downloaded weights were deleted after conversion, so converting again means
+    /// downloading again.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub sources_deleted: bool,
+    pub fp: Option<PathBuf>,
+    pub imatrix: Option<PathBuf>,
+    /// The quant levels made from `fp` as it is now. Quants on disk are skipped either way; these
+    /// are the ones known to be stale once the checkpoint changes.
+    pub quants: Vec<QuantLevel>,
+}
+
+impl PipelineState {
+    pub fn path(model_dir: &Path) -> PathBuf {
+        model_dir.join(STATE_FILE)
+    }
+
+    /// Load the state for `model_dir`, or a fresh state if there is none yet.
+    pub async fn load(model_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = Self::path(model_dir);
+        if !tokio::fs::try_exists(&path).await? {
+            return Ok(Self::default());
+        }
+        let contents = tokio::fs::read_to_string(&path).await?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("invalid state file {}: {e}", path.display()).into())
+    }
+
+    /// Write the state atomically, so a crash mid-write can't leave a corrupt manifest behind.
+    pub async fn save(&self, model_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        tokio::fs::create_dir_all(model_dir).await?;
+        let path = Self::path(model_dir);
+        let pending = model_dir.join(format!("{STATE_FILE}.pending"));
+        tokio::fs::write(&pending, serde_json::to_string_pretty(self)?).await?;
+        tokio::fs::rename(&pending, &path).await?;
+        Ok(())
+    }
+
+    pub fn has_quant(&self, level: &QuantLevel) -> bool {
+        self.quants.contains(level)
+    }
+
+    /// Record a download of `revision` that resolved to `commit`. If that's a different
+    /// checkpoint than the recorded one, whatever was made from the old one is stale: the
+    /// full-precision GGUF and imatrix are forgotten, and the quants are returned to be removed.
+    pub fn record_download(&mut self, revision: Option<String>, commit: String) -> Vec<QuantLevel> {
+        let changed = self.revision != revision
+            || self
+                .commit
+                .as_ref()
+                .is_some_and(|recorded| *recorded != commit);
+        self.downloaded = true;
+        self.revision = revision;
+        self.commit = Some(commit);
+        self.sources_deleted = false;
+        match changed {
+            true => self.forget_outputs(),
+            false => vec![],
+        }
+    }
+
+    /// Forget the full-precision GGUF and imatrix, returning the quants made from them.
+    pub fn forget_outputs(&mut self) -> Vec<QuantLevel> {
+        self.fp = None;
+        self.imatrix = None;
+        std::mem::take(&mut self.quants)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn converted() -> PipelineState {
+        PipelineState {
+            downloaded: true,
+            revision: None,
+            commit: Some("aaaa".to_string()),
+            sources_deleted: true,
+            fp: Some(PathBuf::from("Model/model.f16.gguf")),
+            imatrix: Some(PathBuf::from("Model/model.imatrix")),
+            quants: vec![QuantLevel::Q4KM, QuantLevel::Q8_0],
+        }
+    }
+
+    #[tokio::test]
+    async fn resuming_with_a_different_revision_forgets_the_outputs() {
+        let dir = std::env::temp_dir().join(format!("autogguf-{}-state", std::process::id()));
+        converted().save(&dir).await.unwrap();
+        let mut state = PipelineState::load(&dir).await.unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        let stale = state.record_download(Some("v2".to_string()), "bbbb".to_string());
+        assert_eq!(stale, [QuantLevel::Q4KM, QuantLevel::Q8_0]);
+        assert_eq!(state.revision.as_deref(), Some("v2"));
+        assert_eq!(state.commit.as_deref(), Some("bbbb"));
+        assert!(!state.sources_deleted);
+        assert_eq!(state.fp, None);
+        assert_eq!(state.imatrix, None);
+        assert!(state.quants.is_empty());
+    }
+
+    #[test]
+    fn a_new_commit_on_the_same_revision_forgets_the_outputs() {
+        let mut state = converted();
+        let stale = state.record_download(None, "bbbb".to_string());
+        assert_eq!(stale.len(), 2);
+        assert_eq!(state.fp, None);
+    }
+
+    #[test]
+    fn the_same_checkpoint_keeps_the_outputs() {
+        let mut state = converted();
+        assert!(state.record_download(None, "aaaa".to_string()).is_empty());
+        assert_eq!(state.fp, converted().fp);
+        assert_eq!(state.imatrix, converted().imatrix);
+        assert_eq!(state.quants, converted().quants);
+        assert!(!state.sources_deleted);
+    }
+}
@@ -0,0 +1,136 @@
+//! Object storage buckets to upload to instead of, or as well as, the Hub, for setups that
+//! don't publish there. Files are copied with each cloud's own CLI, so its usual credentials
+//! apply.
+
+use crate::{context::Context, event::Stage, hub::UploadFile};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
+use tokio::process::Command;
+
+/// The bucket URL schemes understood, and the CLI each is copied to with.
+const SCHEMES: &[(&str, &str)] = &[("s3", "aws"), ("gs", "gcloud"), ("az", "az")];
+
+/// A bucket and optional prefix: `s3://bucket/prefix` for S3, `gs://bucket/prefix` for Google
+/// Cloud Storage, or `az://container/prefix` for Azure Blob Storage in the account
+/// `AZURE_STORAGE_ACCOUNT` names.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Bucket {
+    scheme: String,
+    bucket: String,
+    /// Without leading or trailing slashes; empty for the bucket's root.
+    prefix: String,
+}
+
+impl FromStr for Bucket {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let schemes: Vec<String> = SCHEMES.iter().map(|(s, _)| format!("{s}://")).collect();
+        let Some((scheme, rest)) = s.split_once("://") else {
+            return Err(format!(
+                "'{s}' isn't a bucket URL, expected one starting {}",
+                schemes.join(", ")
+            ));
+        };
+        if !SCHEMES.iter().any(|(known, _)| *known == scheme) {
+            return Err(format!(
+                "unknown storage '{scheme}://' in '{s}', expected {}",
+                schemes.join(", ")
+            ));
+        }
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(format!(
+                "'{s}' has no bucket, expected {scheme}://bucket/prefix"
+            ));
+        }
+        Ok(Self {
+            scheme: scheme.to_string(),
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for Bucket {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Bucket> for String {
+    fn from(bucket: Bucket) -> Self {
+        bucket.to_string()
+    }
+}
+
+impl Display for Bucket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}", self.scheme, self.bucket)?;
+        if !self.prefix.is_empty() {
+            write!(f, "/{}", self.prefix)?;
+        }
+        Ok(())
+    }
+}
+
+impl Bucket {
+    /// Where the files of the repo named `repo_name` go, e.g.
+    /// `s3://bucket/ggufs/Llama-3.1-8B-GGUF`.
+    pub fn destination(&self, repo_name: &str) -> String {
+        format!("{self}/{repo_name}")
+    }
+
+    /// The CLI files are copied with.
+    pub fn cli(&self) -> &'static str {
+        SCHEMES
+            .iter()
+            .find(|(scheme, _)| *scheme == self.scheme)
+            .map_or("aws", |(_, cli)| *cli)
+    }
+
+    /// Copy each of `files` under [`destination`](Self::destination), at its path in the repo,
+    /// replacing whatever's there.
+    pub(crate) async fn copy(
+        &self,
+        repo_name: &str,
+        files: &[UploadFile],
+        ctx: &Context,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for file in files {
+            let key = match self.prefix.as_str() {
+                "" => format!("{repo_name}/{}", file.path_in_repo),
+                prefix => format!("{prefix}/{repo_name}/{}", file.path_in_repo),
+            };
+            let mut command = Command::new(self.cli());
+            match self.scheme.as_str() {
+                "az" => command
+                    .args([
+                        "storage",
+                        "blob",
+                        "upload",
+                        "--overwrite",
+                        "--only-show-errors",
+                    ])
+                    .args(["--container-name", &self.bucket, "--name", &key, "--file"])
+                    .arg(&file.local_path),
+                "gs" => command
+                    .args(["storage", "cp"])
+                    .arg(&file.local_path)
+                    .arg(format!("gs://{}/{key}", self.bucket)),
+                _ => command
+                    .args(["s3", "cp", "--only-show-errors"])
+                    .arg(&file.local_path)
+                    .arg(format!("s3://{}/{key}", self.bucket)),
+            };
+            let description = format!("{} copy of {}", self.cli(), file.path_in_repo);
+            ctx.run(&Stage::Upload, command, &description)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
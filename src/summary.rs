@@ -0,0 +1,226 @@
+//! What a run got done, stage by stage, gathered from the events it emits along the way.
+
+use crate::{event::Event, plan::human_bytes, Stage};
+use serde::Serialize;
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+/// Where the summary of the last run is written, in the output directory.
+pub const SUMMARY_FILE: &str = "summary.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    /// The run ended before getting to it.
+    NotRun,
+    Running,
+    Done,
+    Skipped,
+    Failed,
+}
+
+impl Display for StageStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StageStatus::NotRun => write!(f, "not run"),
+            StageStatus::Running => write!(f, "running"),
+            StageStatus::Done => write!(f, "✅ done"),
+            StageStatus::Skipped => write!(f, "⏭️ skipped"),
+            StageStatus::Failed => write!(f, "❌ failed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageSummary {
+    pub stage: Stage,
+    pub status: StageStatus,
+    /// How long it ran for, in seconds.
+    pub duration_secs: Option<f64>,
+    /// The file it produces, whether this run or an earlier one did.
+    pub output: Option<PathBuf>,
+    pub bytes: Option<u64>,
+    /// Whether `output` was uploaded to the HuggingFace Hub by this run.
+    pub uploaded: bool,
+    /// The IPFS CIDs of `output`, or of each of its shards, if this run pinned it.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cids: Vec<String>,
+    /// Why it was skipped, or how it failed.
+    pub reason: Option<String>,
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+/// Every stage of a run, in order, with how it went.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunSummary {
+    pub stages: Vec<StageSummary>,
+    /// The stage that most recently failed, which is usually the one that ended the run.
+    #[serde(skip)]
+    last_failed: Option<Stage>,
+}
+
+impl RunSummary {
+    /// A summary of `stages` yet to run, each with the output it's expected to produce.
+    pub(crate) fn new(stages: Vec<(Stage, Option<PathBuf>)>) -> Self {
+        let stages = stages
+            .into_iter()
+            .map(|(stage, output)| StageSummary {
+                stage,
+                status: StageStatus::NotRun,
+                duration_secs: None,
+                output,
+                bytes: None,
+                uploaded: false,
+                cids: vec![],
+                reason: None,
+                started: None,
+            })
+            .collect();
+        Self {
+            stages,
+            last_failed: None,
+        }
+    }
+
+    pub(crate) fn last_failed(&self) -> Option<&Stage> {
+        self.last_failed.as_ref()
+    }
+
+    fn stage(&mut self, stage: &Stage) -> &mut StageSummary {
+        match self.stages.iter().position(|s| &s.stage == stage) {
+            Some(i) => &mut self.stages[i],
+            None => {
+                self.stages
+                    .extend(Self::new(vec![(stage.clone(), None)]).stages);
+                self.stages.last_mut().unwrap()
+            }
+        }
+    }
+
+    pub(crate) fn record(&mut self, event: &Event) {
+        match event {
+            Event::StageStarted { stage } => {
+                let summary = self.stage(stage);
+                summary.status = StageStatus::Running;
+                summary.started = Some(Instant::now());
+            }
+            Event::StageFinished {
+                stage,
+                output,
+                bytes,
+            } => {
+                let summary = self.stage(stage);
+                summary.status = StageStatus::Done;
+                summary.duration_secs = summary.started.map(|t| t.elapsed().as_secs_f64());
+                if output.is_some() {
+                    summary.output.clone_from(output);
+                    summary.bytes = *bytes;
+                }
+            }
+            Event::StageSkipped { stage, reason } => {
+                let summary = self.stage(stage);
+                summary.status = StageStatus::Skipped;
+                summary.reason = Some(reason.clone());
+            }
+            Event::StageFailed { stage, error } => {
+                let summary = self.stage(stage);
+                summary.status = StageStatus::Failed;
+                summary.duration_secs = summary.started.map(|t| t.elapsed().as_secs_f64());
+                summary.reason = Some(error.clone());
+                self.last_failed = Some(stage.clone());
+            }
+            Event::Uploaded { files, .. } => {
+                for summary in &mut self.stages {
+                    if let Some(output) = &summary.output {
+                        summary.uploaded |= files.iter().any(|file| is_upload_of(file, output));
+                    }
+                }
+            }
+            Event::Pinned { cids } => {
+                for summary in &mut self.stages {
+                    if let Some(output) = &summary.output {
+                        summary.cids = cids
+                            .iter()
+                            .filter(|(file, _)| is_upload_of(file, output))
+                            .map(|(_, cid)| cid.clone())
+                            .collect();
+                    }
+                }
+            }
+            Event::Output { .. } | Event::Message { .. } => {}
+        }
+    }
+
+    /// Fill in the sizes of outputs left by earlier runs, and mark anything still running as
+    /// cut short.
+    pub(crate) async fn finish(&mut self) {
+        for summary in &mut self.stages {
+            if summary.status == StageStatus::Running {
+                summary.status = StageStatus::Failed;
+            }
+            if summary.bytes.is_some()
+                || matches!(summary.status, StageStatus::Failed | StageStatus::NotRun)
+            {
+                continue;
+            }
+            if let Some(output) = &summary.output {
+                summary.bytes = tokio::fs::metadata(output)
+                    .await
+                    .ok()
+                    .filter(|m| m.is_file())
+                    .map(|m| m.len());
+            }
+        }
+    }
+}
+
+/// Whether the uploaded `file` is `output`, or one of the shards it was split into.
+fn is_upload_of(file: &str, output: &Path) -> bool {
+    let (Some(name), Some(stem)) = (output.file_name(), output.file_stem()) else {
+        return false;
+    };
+    let file = Path::new(file).file_name().unwrap_or_default();
+    let shard_prefix = format!("{}-0", stem.to_string_lossy());
+    file == name || file.to_string_lossy().starts_with(&shard_prefix)
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+impl Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "| Stage | Status | Time | Output | Size | Uploaded |")?;
+        writeln!(f, "| ----- | ------ | ---: | ------ | ---: | -------- |")?;
+        for summary in &self.stages {
+            let output = match (&summary.output, summary.status) {
+                (_, StageStatus::NotRun | StageStatus::Failed) | (None, _) => String::new(),
+                // NOTE: skipped stages only have an output if an earlier run left one behind
+                (Some(_), StageStatus::Skipped) if summary.bytes.is_none() => String::new(),
+                (Some(output), _) => output
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            writeln!(
+                f,
+                "| {} | {} | {} | {output} | {} | {} |",
+                summary.stage,
+                summary.status,
+                summary
+                    .duration_secs
+                    .map(format_duration)
+                    .unwrap_or_default(),
+                summary.bytes.map(human_bytes).unwrap_or_default(),
+                if summary.uploaded { "✅" } else { "" },
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+//! Exporting each run's spans over OTLP, so conversions across a fleet of runners can be timed
+//! end to end in Jaeger, Tempo, Honeycomb and the like.
+
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use tracing::Subscriber;
+use tracing_subscriber::{filter::Targets, registry::LookupSpan, Layer};
+
+/// Batches spans up for an OTLP collector, flushing whatever's left when dropped.
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Telemetry {
+    /// Export to the OTLP/HTTP collector at `endpoint`, e.g. `http://localhost:4318`.
+    pub fn new(endpoint: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()
+            .map_err(|e| format!("couldn't export traces to {endpoint}: {e}"))?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name("autogguf").build())
+            .build();
+        Ok(Self { provider })
+    }
+
+    /// A layer recording the pipeline's spans, and nothing else, to the collector.
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer()
+            .with_tracer(self.provider.tracer("autogguf"))
+            .with_filter(Targets::new().with_target(autogguf::SPAN_TARGET, tracing::Level::INFO))
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        let provider = self.provider.clone();
+        // NOTE: the blocking exporter can't be shut down from inside the async runtime
+        let flushed = std::thread::spawn(move || provider.shutdown()).join();
+        if let Ok(Err(e)) = flushed {
+            eprintln!("couldn't flush traces: {e}");
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Limits on how long a stage may take, and on how long a subprocess may go without printing
+//! anything before it's presumed hung, like an imatrix run wedged on a GPU driver.
+
+use crate::Stage;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Display, str::FromStr, time::Duration};
+
+/// A stage a time limit can be set for. Every quant level shares `quantize`'s.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum, Deserialize, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimedStage {
+    UpdateLlama,
+    Download,
+    Convert,
+    Imatrix,
+    Quantize,
+    Eval,
+}
+
+impl Display for TimedStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            TimedStage::UpdateLlama => "update-llama",
+            TimedStage::Download => "download",
+            TimedStage::Convert => "convert",
+            TimedStage::Imatrix => "imatrix",
+            TimedStage::Quantize => "quantize",
+            TimedStage::Eval => "eval",
+        };
+        write!(f, "{label}")
+    }
+}
+
+impl TimedStage {
+    fn of(stage: &Stage) -> Option<Self> {
+        match stage {
+            Stage::UpdateLlama => Some(TimedStage::UpdateLlama),
+            Stage::Download => Some(TimedStage::Download),
+            Stage::Convert => Some(TimedStage::Convert),
+            Stage::Imatrix => Some(TimedStage::Imatrix),
+            Stage::Quantize(_) => Some(TimedStage::Quantize),
+            Stage::Eval => Some(TimedStage::Eval),
+            // NOTE: uploads run alongside the other stages, and are already retried
+            Stage::Upload => None,
+        }
+    }
+}
+
+/// A `STAGE=MINUTES` time limit, e.g. `imatrix=240`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageTimeout {
+    pub stage: TimedStage,
+    pub minutes: u64,
+}
+
+impl FromStr for StageTimeout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (stage, minutes) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected STAGE=MINUTES, got '{s}'"))?;
+        let stage = TimedStage::from_str(stage, true).map_err(|_| {
+            let stages = TimedStage::value_variants()
+                .iter()
+                .map(TimedStage::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("unknown stage '{stage}', expected one of {stages}")
+        })?;
+        let minutes = minutes
+            .parse()
+            .ok()
+            .filter(|&m| m > 0)
+            .ok_or_else(|| format!("'{minutes}' is not a positive number of minutes"))?;
+        Ok(Self { stage, minutes })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Timeouts {
+    pub stages: HashMap<TimedStage, Duration>,
+    /// How long a subprocess may go without printing anything before it's killed.
+    pub stall: Option<Duration>,
+}
+
+impl Timeouts {
+    /// How long `stage` may run for, if it's limited at all.
+    pub fn stage(&self, stage: &Stage) -> Option<Duration> {
+        TimedStage::of(stage).and_then(|timed| self.stages.get(&timed).copied())
+    }
+}
+
+/// `limit` for messages, in whole minutes where it's set in minutes.
+pub(crate) fn describe(limit: Duration) -> String {
+    match limit.as_secs() {
+        60 => "1 minute".to_string(),
+        secs if secs % 60 == 0 => format!("{} minutes", secs / 60),
+        secs => format!("{secs}s"),
+    }
+}
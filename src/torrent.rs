@@ -0,0 +1,240 @@
+//! A `.torrent` and magnet link per quant, web-seeded from the repo so they download even with no
+//! other peers, for models too big to comfortably fetch over a single HTTP connection.
+
+use crate::{hub::UploadFile, mmproj, naming};
+use sha1::{Digest, Sha1};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Read,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Every quant's magnet link and `.torrent`, one per line.
+pub(crate) const MAGNETS_FILE: &str = "MAGNETS";
+
+/// Pieces are sized for about this many per torrent, within the sizes clients handle well.
+const TARGET_PIECES: u64 = 1500;
+const MIN_PIECE_LENGTH: u64 = 256 * 1024;
+const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+
+/// The torrent of one quant: its GGUF, or the folder of its shards.
+#[derive(Debug, Clone)]
+pub(crate) struct Torrent {
+    /// The quant or precision in capitals, e.g. `Q4_K_M`.
+    pub label: String,
+    /// The `.torrent`'s name, at the repo's root.
+    pub path: String,
+    pub magnet: String,
+}
+
+/// A bencoded value. Dictionaries are kept sorted by key, as bencoding requires.
+enum Bencode {
+    Int(u64),
+    Bytes(Vec<u8>),
+    List(Vec<Bencode>),
+    Dict(BTreeMap<&'static str, Bencode>),
+}
+
+impl Bencode {
+    fn text(s: &str) -> Self {
+        Self::Bytes(s.as_bytes().to_vec())
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Int(n) => out.extend(format!("i{n}e").as_bytes()),
+            Self::Bytes(bytes) => {
+                out.extend(format!("{}:", bytes.len()).as_bytes());
+                out.extend(bytes);
+            }
+            Self::List(items) => {
+                out.push(b'l');
+                items.iter().for_each(|item| item.encode(out));
+                out.push(b'e');
+            }
+            Self::Dict(entries) => {
+                out.push(b'd');
+                for (key, value) in entries {
+                    Self::text(key).encode(out);
+                    value.encode(out);
+                }
+                out.push(b'e');
+            }
+        }
+    }
+}
+
+/// Write a `.torrent` into `dir` for each quant among `files`, the uploads, web-seeded from
+/// `web_seed`, the URL files in the repo are downloaded from, ending in a `/`. Their magnet
+/// links go in `MAGNETS`. Like checksums, torrents of files that haven't changed since
+/// `MAGNETS` was written are reused rather than hashed again.
+pub(crate) async fn write(
+    dir: &Path,
+    files: &[UploadFile],
+    web_seed: &str,
+) -> Result<Vec<Torrent>, Box<dyn std::error::Error + Send + Sync>> {
+    let magnets_path = dir.join(MAGNETS_FILE);
+    let written = tokio::fs::metadata(&magnets_path)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    let previous = match written {
+        Some(_) => read(&magnets_path).await?,
+        None => HashMap::new(),
+    };
+    // NOTE: a split quant's shards are in a folder named for it, which becomes the torrent's
+    // name, so a client appending it to the web seed finds them where they are in the repo
+    let mut quants: BTreeMap<String, Vec<&UploadFile>> = BTreeMap::new();
+    for file in files.iter().filter(|f| f.path_in_repo.ends_with(".gguf")) {
+        let name = file.path_in_repo.split('/').next().unwrap_or_default();
+        quants.entry(name.to_string()).or_default().push(file);
+    }
+    let mut torrents = vec![];
+    for (name, mut files) in quants {
+        files.sort_by(|a, b| a.path_in_repo.cmp(&b.path_in_repo));
+        let path = format!("{name}.torrent");
+        let mut unchanged = written.is_some() && tokio::fs::try_exists(dir.join(&path)).await?;
+        for file in &files {
+            let modified = tokio::fs::metadata(&file.local_path).await?.modified().ok();
+            unchanged &= matches!((written, modified), (Some(w), Some(m)) if m < w);
+        }
+        let magnet = match previous.get(&path) {
+            Some(magnet) if unchanged => magnet.clone(),
+            _ => create(&dir.join(&path), &name, &files, web_seed).await?,
+        };
+        let label = naming::repo_label(&files[0].path_in_repo).unwrap_or(name);
+        torrents.push(Torrent {
+            label: match mmproj::is_mmproj(&files[0].path_in_repo) {
+                true => format!("mmproj {label}"),
+                false => label,
+            },
+            path,
+            magnet,
+        });
+    }
+    let contents: String = torrents
+        .iter()
+        .map(|t| format!("{}  {}\n", t.magnet, t.path))
+        .collect();
+    tokio::fs::write(&magnets_path, contents).await?;
+    Ok(torrents)
+}
+
+/// Hash `files` into the torrent `name` at `path`, returning its magnet link.
+async fn create(
+    path: &Path,
+    name: &str,
+    files: &[&UploadFile],
+    web_seed: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut lengths = vec![];
+    for file in files {
+        lengths.push(tokio::fs::metadata(&file.local_path).await?.len());
+    }
+    let total: u64 = lengths.iter().sum();
+    let mut piece_length = MIN_PIECE_LENGTH;
+    while piece_length < MAX_PIECE_LENGTH && total / piece_length > TARGET_PIECES {
+        piece_length *= 2;
+    }
+    let local_paths: Vec<PathBuf> = files.iter().map(|f| f.local_path.clone()).collect();
+    let pieces =
+        tokio::task::spawn_blocking(move || hash_pieces(&local_paths, piece_length)).await??;
+
+    let mut info = BTreeMap::from([
+        ("name", Bencode::text(name)),
+        ("piece length", Bencode::Int(piece_length)),
+        ("pieces", Bencode::Bytes(pieces)),
+    ]);
+    match files {
+        [_] => {
+            info.insert("length", Bencode::Int(total));
+        }
+        _ => {
+            let entries = files
+                .iter()
+                .zip(&lengths)
+                .map(|(file, length)| {
+                    let (_, file_name) = file.path_in_repo.split_once('/').unwrap_or_default();
+                    Bencode::Dict(BTreeMap::from([
+                        ("length", Bencode::Int(*length)),
+                        ("path", Bencode::List(vec![Bencode::text(file_name)])),
+                    ]))
+                })
+                .collect();
+            info.insert("files", Bencode::List(entries));
+        }
+    }
+    let info = Bencode::Dict(info);
+    let mut info_bytes = vec![];
+    info.encode(&mut info_bytes);
+    let info_hash: String = Sha1::digest(&info_bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let torrent = Bencode::Dict(BTreeMap::from([
+        ("created by", Bencode::text("autogguf")),
+        ("creation date", Bencode::Int(created)),
+        ("info", info),
+        ("url-list", Bencode::List(vec![Bencode::text(web_seed)])),
+    ]));
+    let mut bytes = vec![];
+    torrent.encode(&mut bytes);
+    tokio::fs::write(path, bytes).await?;
+    Ok(format!(
+        "magnet:?xt=urn:btih:{info_hash}&dn={}&ws={}",
+        escape(name),
+        escape(web_seed)
+    ))
+}
+
+/// The SHA-1 of each `piece_length` bytes of `files` end to end, concatenated.
+fn hash_pieces(files: &[PathBuf], piece_length: u64) -> std::io::Result<Vec<u8>> {
+    let mut pieces = vec![];
+    let mut piece = Vec::with_capacity(piece_length as usize);
+    for path in files {
+        let mut file = std::fs::File::open(path)?;
+        loop {
+            let wanted = piece_length - piece.len() as u64;
+            let read = (&mut file).take(wanted).read_to_end(&mut piece)?;
+            if piece.len() as u64 == piece_length {
+                pieces.extend(Sha1::digest(&piece));
+                piece.clear();
+            }
+            if read == 0 {
+                break;
+            }
+        }
+    }
+    if !piece.is_empty() {
+        pieces.extend(Sha1::digest(&piece));
+    }
+    Ok(pieces)
+}
+
+/// `s` escaped for a URL's query string.
+fn escape(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// The magnet links in an existing `MAGNETS`, by `.torrent`.
+async fn read(path: &Path) -> std::io::Result<HashMap<String, String>> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(magnet, path)| (path.to_string(), magnet.to_string()))
+        .collect())
+}
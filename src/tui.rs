@@ -0,0 +1,277 @@
+use autogguf::{Event, Stage};
+use ratatui::{
+    crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Table},
+    DefaultTerminal, Frame,
+};
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{
+    mpsc::{error::TryRecvError, UnboundedReceiver},
+    Notify,
+};
+
+const LOG_LINES: usize = 500;
+const TICK: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Status {
+    Pending,
+    Running,
+    Done,
+    Skipped(String),
+    Failed(String),
+}
+
+#[derive(Debug)]
+struct StageRow {
+    stage: Stage,
+    status: Status,
+    started: Option<Instant>,
+    elapsed: Option<Duration>,
+}
+
+impl StageRow {
+    fn elapsed(&self) -> Option<Duration> {
+        self.elapsed.or_else(|| self.started.map(|s| s.elapsed()))
+    }
+}
+
+#[derive(Debug)]
+struct App {
+    model_id: String,
+    rows: Vec<StageRow>,
+    log: VecDeque<String>,
+    started: Instant,
+    cancelled: bool,
+}
+
+impl App {
+    fn new(model_id: String, stages: Vec<Stage>) -> Self {
+        Self {
+            model_id,
+            rows: stages
+                .into_iter()
+                .map(|stage| StageRow {
+                    stage,
+                    status: Status::Pending,
+                    started: None,
+                    elapsed: None,
+                })
+                .collect(),
+            log: VecDeque::with_capacity(LOG_LINES),
+            started: Instant::now(),
+            cancelled: false,
+        }
+    }
+
+    fn row(&mut self, stage: &Stage) -> &mut StageRow {
+        match self.rows.iter().position(|r| &r.stage == stage) {
+            Some(i) => &mut self.rows[i],
+            None => {
+                self.rows.push(StageRow {
+                    stage: stage.clone(),
+                    status: Status::Pending,
+                    started: None,
+                    elapsed: None,
+                });
+                self.rows.last_mut().unwrap()
+            }
+        }
+    }
+
+    fn push_log(&mut self, line: String) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::StageStarted { stage } => {
+                let row = self.row(&stage);
+                row.status = Status::Running;
+                row.started = Some(Instant::now());
+                row.elapsed = None;
+            }
+            Event::StageFinished { stage, .. } => {
+                let row = self.row(&stage);
+                row.status = Status::Done;
+                row.elapsed = row.started.map(|s| s.elapsed());
+            }
+            Event::StageSkipped { stage, reason } => {
+                self.row(&stage).status = Status::Skipped(reason);
+            }
+            Event::StageFailed { stage, error } => {
+                let row = self.row(&stage);
+                row.status = Status::Failed(error.clone());
+                row.elapsed = row.started.map(|s| s.elapsed());
+                self.push_log(format!("❌ {stage}: {error}"));
+            }
+            Event::Uploaded { repo_id, files } => {
+                self.push_log(format!("🤗 uploaded {} file(s) to {repo_id}", files.len()))
+            }
+            Event::Pinned { cids } => {
+                self.push_log(format!("📌 pinned {} file(s) to IPFS", cids.len()))
+            }
+            Event::Output { stage, line } => self.push_log(format!("[{stage}] {line}")),
+            Event::Message { text } => self.push_log(text),
+        }
+    }
+
+    /// Remaining time for the quant queue, extrapolated from the average of finished quants.
+    fn eta(&self) -> Option<Duration> {
+        let quants = self
+            .rows
+            .iter()
+            .filter(|r| matches!(r.stage, Stage::Quantize(_)));
+        let finished: Vec<Duration> = quants
+            .clone()
+            .filter(|r| r.status == Status::Done)
+            .filter_map(|r| r.elapsed)
+            .collect();
+        if finished.is_empty() {
+            return None;
+        }
+        let average = finished.iter().sum::<Duration>() / finished.len() as u32;
+        let remaining = quants
+            .map(|r| match r.status {
+                Status::Pending => average,
+                Status::Running => average.saturating_sub(r.elapsed().unwrap_or_default()),
+                _ => Duration::ZERO,
+            })
+            .sum();
+        Some(remaining)
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [header, body] =
+            Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).areas(frame.area());
+        let [stages, log] =
+            Layout::horizontal([Constraint::Length(48), Constraint::Fill(1)]).areas(body);
+
+        let eta = match self.eta() {
+            Some(eta) => format_duration(eta),
+            None => "--".to_string(),
+        };
+        let mut title = format!(
+            "elapsed {}   quant ETA {eta}",
+            format_duration(self.started.elapsed())
+        );
+        if self.cancelled {
+            title.push_str("   cancelling... (q again to force quit)");
+        }
+        frame.render_widget(
+            Paragraph::new(title).block(
+                Block::bordered()
+                    .title(format!(" autogguf · {} ", self.model_id))
+                    .title_bottom(Line::from(" q: cancel ").right_aligned()),
+            ),
+            header,
+        );
+
+        let rows = self.rows.iter().map(|r| {
+            let (status, color) = match &r.status {
+                Status::Pending => ("pending".to_string(), Color::DarkGray),
+                Status::Running => ("running".to_string(), Color::Yellow),
+                Status::Done => ("done".to_string(), Color::Green),
+                Status::Skipped(reason) => (format!("skipped ({reason})"), Color::Blue),
+                Status::Failed(_) => ("failed".to_string(), Color::Red),
+            };
+            Row::new([
+                r.stage.to_string(),
+                status,
+                r.elapsed().map(format_duration).unwrap_or_default(),
+            ])
+            .style(Style::new().fg(color))
+        });
+        frame.render_widget(
+            Table::new(
+                rows,
+                [
+                    Constraint::Length(16),
+                    Constraint::Fill(1),
+                    Constraint::Length(8),
+                ],
+            )
+            .header(Row::new(["stage", "status", "time"]).bold())
+            .block(Block::bordered().title(" stages ")),
+            stages,
+        );
+
+        let height = log.height.saturating_sub(2) as usize;
+        let tail: Vec<Line> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(height))
+            .map(|l| Line::raw(l.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(tail).block(Block::bordered().title(" log ")),
+            log,
+        );
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Draw the dashboard until the pipeline hangs up its end of `events`. Pressing `q` or Ctrl-C
+/// cancels the pipeline, and pressing it again quits right away; the terminal is in raw mode, so
+/// the usual SIGINT never arrives.
+///
+/// Blocks, so run it with [`tokio::task::spawn_blocking`].
+pub fn run(
+    model_id: String,
+    stages: Vec<Stage>,
+    events: UnboundedReceiver<Event>,
+    cancel: Arc<Notify>,
+) -> std::io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, App::new(model_id, stages), events, cancel);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    mut app: App,
+    mut events: UnboundedReceiver<Event>,
+    cancel: Arc<Notify>,
+) -> std::io::Result<()> {
+    loop {
+        loop {
+            match events.try_recv() {
+                Ok(event) => app.handle(event),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        terminal.draw(|frame| app.draw(frame))?;
+
+        if event::poll(TICK)? {
+            if let TermEvent::Key(key) = event::read()? {
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (key.code == KeyCode::Char('q') || ctrl_c) {
+                    if app.cancelled {
+                        ratatui::restore();
+                        autogguf::kill_subprocesses();
+                        std::process::exit(crate::EXIT_INTERRUPTED.into());
+                    }
+                    app.cancelled = true;
+                    cancel.notify_waiters();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! `autogguf verify`: re-checks the quants a run produced, so a broken one is caught before
+//! anyone downloads it.
+
+use crate::{gguf, llama, QuantLevel};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tokio::process::Command;
+
+/// Tokens the smoke test generates: enough to tell text from garbage.
+const SMOKE_TEST_TOKENS: u32 = 32;
+const SMOKE_TEST_PROMPT: &str = "The capital of France is";
+/// Asked instead when the model has a chat template, so the template gets applied too.
+const SMOKE_TEST_QUESTION: &str = "What is the capital of France?";
+
+/// The outcome of verifying one quant.
+#[derive(Debug, Clone)]
+pub struct Verification {
+    pub level: QuantLevel,
+    /// The quant, or its first shard if it was split.
+    pub path: PathBuf,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let icon = match self.passed {
+            true => "✅",
+            false => "❌",
+        };
+        write!(f, "{icon} {}: {}", self.path.display(), self.detail)
+    }
+}
+
+/// Validate every file of a quant, returning how many tensors they hold between them.
+pub(crate) async fn tensor_count(files: &[PathBuf]) -> Result<usize, String> {
+    let mut count = 0;
+    for file in files {
+        count += gguf::validate(file).await?.tensors.len();
+    }
+    Ok(count)
+}
+
+/// Check a quant's headers and tensor count against the full-precision GGUF's, and, given
+/// `llama-cli` in `smoke_test`, that it loads and generates text.
+pub(crate) async fn verify(
+    level: QuantLevel,
+    files: &[PathBuf],
+    expected_tensors: Option<usize>,
+    smoke_test: Option<(&Path, &[String])>,
+) -> Verification {
+    let path = files[0].clone();
+    let fail = |detail: String| Verification {
+        level: level.clone(),
+        path: path.clone(),
+        passed: false,
+        detail,
+    };
+    let count = match tensor_count(files).await {
+        Ok(count) => count,
+        Err(e) => return fail(e),
+    };
+    if let Some(expected) = expected_tensors.filter(|expected| *expected != count) {
+        return fail(format!(
+            "{count} tensors, but the full-precision GGUF has {expected}"
+        ));
+    }
+    let mut detail = format!("{count} tensors");
+    if let Some((llama_bin, offload_args)) = smoke_test {
+        match generate(&path, llama_bin, offload_args).await {
+            Ok(text) => detail.push_str(&format!(", generated {text:?}")),
+            Err(e) => return fail(e),
+        }
+    }
+    Verification {
+        level,
+        path,
+        passed: true,
+        detail,
+    }
+}
+
+/// Generate [`SMOKE_TEST_TOKENS`] tokens from `model` with `llama-cli`, checking they read like
+/// text. A model with a chat template is asked a question through it in a single conversation
+/// turn, so a template llama.cpp can't apply fails too; one without just continues a prompt.
+pub(crate) async fn generate(
+    model: &Path,
+    llama_bin: &Path,
+    offload_args: &[String],
+) -> Result<String, String> {
+    let cli = llama::binary(llama_bin, "llama-cli");
+    if !cli.exists() {
+        return Err(format!(
+            "{} not found; run with --update-llama to build it",
+            cli.display()
+        ));
+    }
+    let header = gguf::validate(model).await?;
+    let chat = header.get("tokenizer.chat_template").is_some();
+    let prompt = match chat {
+        true => SMOKE_TEST_QUESTION,
+        false => SMOKE_TEST_PROMPT,
+    };
+    let mut command = Command::new(&cli);
+    command
+        .arg("-m")
+        .arg(model)
+        .args(["-p", prompt])
+        .args(["-n", &SMOKE_TEST_TOKENS.to_string()])
+        .args(offload_args)
+        .args(["--temp", "0", "--no-warmup"]);
+    match chat {
+        true => command.args(["--jinja", "-cnv", "--single-turn"]),
+        false => command.args(["-no-cnv", "--no-display-prompt"]),
+    };
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("couldn't run {}: {e}", cli.display()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or("no output");
+        return Err(format!("generation failed ({}): {reason}", output.status));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // NOTE: in conversation mode llama-cli echoes the user's turn as "> {prompt}"
+    let text: Vec<&str> = stdout
+        .lines()
+        .filter(|line| !line.starts_with('>'))
+        .collect();
+    let text = text.join("\n");
+    let generated = text
+        .trim()
+        .strip_prefix(prompt)
+        .unwrap_or(text.trim())
+        .trim()
+        .to_string();
+    if generated.is_empty() {
+        return Err("generation produced no text".to_string());
+    }
+    if let Some(problem) = garbage(&generated) {
+        return Err(format!("generated {problem}: {generated:?}"));
+    }
+    Ok(generated)
+}
+
+/// What's wrong with `text`, if it looks like a broken model's output rather than language:
+/// undecodable bytes, control characters, or one token over and over.
+fn garbage(text: &str) -> Option<&'static str> {
+    let chars = text.chars().count();
+    let unreadable = text
+        .chars()
+        .filter(|c| *c == char::REPLACEMENT_CHARACTER || (c.is_control() && !c.is_whitespace()))
+        .count();
+    if unreadable * 10 > chars {
+        return Some("unreadable characters");
+    }
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let distinct: std::collections::HashSet<&str> = words.iter().copied().collect();
+    if words.len() >= 8 && distinct.len() <= 2 {
+        return Some("the same token over and over");
+    }
+    None
+}
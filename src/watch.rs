@@ -0,0 +1,159 @@
+//! Watching a HuggingFace user or org for new and updated models to convert, remembering which
+//! commit of each was last converted so every poll only turns up what changed.
+
+use crate::{
+    context::Context,
+    hub::{HubClient, ListedModel},
+    retry::DEFAULT_RETRIES,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::Notify;
+
+/// Where the commits converted so far are kept, in the directory models are converted in.
+pub const WATCH_FILE: &str = ".autogguf-watch.json";
+
+/// A model in the watched namespace with a commit that hasn't been converted yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changed {
+    pub model_id: String,
+    pub commit: String,
+    /// An earlier commit of it was converted, rather than it being new.
+    pub updated: bool,
+}
+
+/// The commit of each watched model last converted, or seen when the watch started.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct WatchState {
+    /// Namespaces whose models were all recorded when first watched.
+    namespaces: BTreeSet<String>,
+    models: BTreeMap<String, String>,
+}
+
+/// Polls `namespace` for models matching any of `patterns` (or any model, with none).
+#[derive(Debug)]
+pub struct Watcher {
+    namespace: String,
+    patterns: Vec<glob::Pattern>,
+    /// Convert the models already there when the watch starts, not just later changes.
+    backfill: bool,
+    client: HubClient,
+    ctx: Context,
+    path: PathBuf,
+    state: WatchState,
+}
+
+impl Watcher {
+    /// Pick up where an earlier watch left off, from [`WATCH_FILE`] in `dir`.
+    pub async fn new(
+        namespace: &str,
+        patterns: &[String],
+        backfill: bool,
+        hf_token: &str,
+        dir: &Path,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let patterns = patterns
+            .iter()
+            .map(|p| glob::Pattern::new(p).map_err(|e| format!("invalid glob '{p}': {e}")))
+            .collect::<Result<_, _>>()?;
+        let path = dir.join(WATCH_FILE);
+        let state = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("invalid watch state {}: {e}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => WatchState::default(),
+            Err(e) => return Err(e.into()),
+        };
+        let ctx = Context {
+            verbose: false,
+            cancel: Arc::new(Notify::new()),
+            interrupted: Default::default(),
+            events: None,
+            log_dir: None,
+            summary: Default::default(),
+            retries: DEFAULT_RETRIES,
+            timeouts: Default::default(),
+        };
+        Ok(Self {
+            namespace: namespace.to_string(),
+            patterns,
+            backfill,
+            client: HubClient::new(hf_token, ctx.clone()),
+            ctx,
+            path,
+            state,
+        })
+    }
+
+    /// The matching models whose latest commit hasn't been converted, oldest change first.
+    /// GGUF repos are left out, so watching a namespace that's also the target doesn't loop.
+    ///
+    /// The first poll of a namespace records what's already there as seen instead, unless
+    /// backfilling.
+    pub async fn poll(&mut self) -> Result<Vec<Changed>, Box<dyn std::error::Error>> {
+        let mut models: Vec<ListedModel> = self
+            .client
+            .list_models(&self.namespace)
+            .await
+            .map_err(|e| format!("couldn't list {}'s models: {e}", self.namespace))?;
+        models.retain(|model| self.matches(model));
+        models.sort_by(|a, b| a.last_modified.cmp(&b.last_modified));
+        let changed: Vec<Changed> = models
+            .into_iter()
+            .filter_map(|model| {
+                let commit = model.sha?;
+                let converted = self.state.models.get(&model.id);
+                (converted != Some(&commit)).then(|| Changed {
+                    updated: converted.is_some(),
+                    model_id: model.id,
+                    commit,
+                })
+            })
+            .collect();
+        if self.state.namespaces.contains(&self.namespace) {
+            return Ok(changed);
+        }
+        self.state.namespaces.insert(self.namespace.clone());
+        if self.backfill {
+            self.save().await?;
+            return Ok(changed);
+        }
+        for model in &changed {
+            self.state
+                .models
+                .insert(model.model_id.clone(), model.commit.clone());
+        }
+        self.save().await?;
+        self.ctx.info(format!(
+            "👀 {} matching models in {} already, converting only new commits from here on",
+            changed.len(),
+            self.namespace
+        ));
+        Ok(vec![])
+    }
+
+    fn matches(&self, model: &ListedModel) -> bool {
+        let name = model.id.rsplit('/').next().unwrap_or(&model.id);
+        let gguf = name.to_lowercase().ends_with("-gguf") || model.tags.iter().any(|t| t == "gguf");
+        !gguf && (self.patterns.is_empty() || self.patterns.iter().any(|p| p.matches(name)))
+    }
+
+    /// Record `changed` as converted, so it only counts as changed again with a new commit.
+    pub async fn converted(&mut self, changed: &Changed) -> Result<(), Box<dyn std::error::Error>> {
+        self.state
+            .models
+            .insert(changed.model_id.clone(), changed.commit.clone());
+        self.save().await
+    }
+
+    async fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let pending = self.path.with_extension("json.pending");
+        tokio::fs::write(&pending, serde_json::to_string_pretty(&self.state)?).await?;
+        tokio::fs::rename(&pending, &self.path).await?;
+        Ok(())
+    }
+}
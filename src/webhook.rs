@@ -0,0 +1,167 @@
+//! Reporting a run's milestones to a webhook, so a long unattended conversion can post into a
+//! Slack or Discord channel, or anything else that takes JSON.
+
+use crate::{event::Event, plan::human_bytes, Stage};
+use reqwest::Url;
+use serde::Serialize;
+use std::{fmt::Display, time::Duration};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// How long a webhook gets to answer before the notification is given up on.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A milestone worth interrupting someone for.
+///
+/// Generic webhooks get it as an object tagged by `"event"`, e.g.
+/// `{"event":"quant_finished","model_id":"...","quant":"Q4_K_M","bytes":4683073952}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notification {
+    RunStarted {
+        model_id: String,
+    },
+    QuantFinished {
+        model_id: String,
+        quant: String,
+        bytes: Option<u64>,
+    },
+    Uploaded {
+        model_id: String,
+        repo_id: String,
+        files: Vec<String>,
+    },
+    RunFinished {
+        model_id: String,
+    },
+    RunFailed {
+        model_id: String,
+        error: String,
+    },
+}
+
+impl Notification {
+    /// The notification a pipeline event for `model_id` calls for, if any.
+    pub fn from_event(model_id: &str, event: &Event) -> Option<Self> {
+        match event {
+            Event::StageFinished {
+                stage: Stage::Quantize(level),
+                bytes,
+                ..
+            } => Some(Self::QuantFinished {
+                model_id: model_id.to_string(),
+                quant: level.to_string().to_uppercase(),
+                bytes: *bytes,
+            }),
+            Event::Uploaded { repo_id, files } => Some(Self::Uploaded {
+                model_id: model_id.to_string(),
+                repo_id: repo_id.clone(),
+                files: files.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Notification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RunStarted { model_id } => write!(f, "🚀 converting {model_id}"),
+            Self::QuantFinished {
+                model_id,
+                quant,
+                bytes,
+            } => {
+                write!(f, "🧮 {model_id}: {quant} quantized")?;
+                match bytes {
+                    Some(bytes) => write!(f, " ({})", human_bytes(*bytes)),
+                    None => Ok(()),
+                }
+            }
+            Self::Uploaded {
+                model_id,
+                repo_id,
+                files,
+            } => write!(
+                f,
+                "🤗 {model_id}: uploaded {} file(s) to https://huggingface.co/{repo_id}",
+                files.len()
+            ),
+            Self::RunFinished { model_id } => write!(f, "🎉 {model_id} done!"),
+            Self::RunFailed { model_id, error } => write!(f, "💥 {model_id} failed: {error}"),
+        }
+    }
+}
+
+/// Where notifications are POSTed. Slack and Discord webhooks get a message in the shape they
+/// expect; any other URL gets the [`Notification`] as JSON.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: Url,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(url: &str) -> Result<Self, String> {
+        let url = Url::parse(url).map_err(|e| format!("invalid webhook URL '{url}': {e}"))?;
+        Ok(Self {
+            url,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn body(&self, notification: &Notification) -> serde_json::Value {
+        let host = self.url.host_str().unwrap_or_default();
+        if host == "hooks.slack.com" {
+            serde_json::json!({ "text": notification.to_string() })
+        } else if host.ends_with("discord.com") || host.ends_with("discordapp.com") {
+            serde_json::json!({ "content": notification.to_string() })
+        } else {
+            serde_json::json!(notification)
+        }
+    }
+
+    /// POST `notification`, only warning when that fails: a chat being down shouldn't take a
+    /// conversion with it.
+    pub async fn send(&self, notification: &Notification) {
+        let sent = self
+            .client
+            .post(self.url.clone())
+            .timeout(TIMEOUT)
+            .json(&self.body(notification))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = sent {
+            tracing::warn!("couldn't send webhook notification: {e}");
+        }
+    }
+
+    /// Pass `events` from `model_id`'s pipeline on to `next`, if anyone's listening there,
+    /// notifying of any milestones among them. Returns once the pipeline hangs up and every
+    /// notification is sent.
+    pub async fn forward(
+        self,
+        model_id: String,
+        mut events: UnboundedReceiver<Event>,
+        next: Option<UnboundedSender<Event>>,
+    ) {
+        // NOTE: queued separately so a slow webhook doesn't hold up the dashboard
+        let (queue, mut queued) = mpsc::unbounded_channel();
+        let tee = async move {
+            while let Some(event) = events.recv().await {
+                if let Some(notification) = Notification::from_event(&model_id, &event) {
+                    let _ = queue.send(notification);
+                }
+                if let Some(next) = &next {
+                    let _ = next.send(event);
+                }
+            }
+        };
+        let sender = async {
+            while let Some(notification) = queued.recv().await {
+                self.send(&notification).await;
+            }
+        };
+        tokio::join!(tee, sender);
+    }
+}